@@ -0,0 +1,146 @@
+use crate::{make, unmake, Arg, ByteCode, Bytes, CompileError, Constants, Object, OpCode, SourceMap};
+
+/// Builds bytecode an instruction at a time, to run the VM on bytecode without going through the parser and the
+/// compiler.
+#[derive(Debug, Default)]
+pub struct ByteCodeBuilder {
+    bytes: Bytes,
+    constants: Constants,
+    globals: usize,
+}
+
+impl ByteCodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `obj` to the constants, returning the index `CONST` (or `CONST_WIDE` past 65535) loads it with.
+    pub fn constant(&mut self, obj: Object) -> u32 {
+        self.constants.push(obj);
+        (self.constants.len() - 1) as u32
+    }
+
+    /// Where the next instruction starts, what a jump to it takes.
+    pub fn offset(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn emit(&mut self, opcode: OpCode, args: &[u32]) -> Result<&mut Self, CompileError> {
+        let widths = opcode.get_arg_widths();
+        let too_wide = |width: &str, arg: &u32| CompileError(format!("{} takes a {width} byte arg, {arg} doesn't fit", opcode.name()));
+        let args = args.iter().zip(widths.iter().chain(std::iter::repeat(&2))).map(|(arg, width)| match width {
+            1 => u8::try_from(*arg).map(Arg::U8).map_err(|_| too_wide("one", arg)),
+            2 => u16::try_from(*arg).map(Arg::U16).map_err(|_| too_wide("two", arg)),
+            _ => Ok(Arg::U32(*arg)),
+        }).collect::<Result<Vec<Arg>, CompileError>>()?;
+        self.bytes.extend(make(opcode, &args)?);
+        if let (OpCode::GetGlobal | OpCode::SetGlobal, Some(Arg::U16(idx))) = (opcode, args.first()) {
+            self.globals = self.globals.max(*idx as usize + 1);
+        }
+        Ok(self)
+    }
+
+    /// The bytecode built, with as many globals as its instructions use.
+    pub fn build(self) -> ByteCode {
+        ByteCode {
+            bytes: self.bytes,
+            constants: self.constants,
+            constants_base: 0,
+            globals: self.globals,
+            global_names: Vec::new(),
+            source_map: SourceMap::default(),
+        }
+    }
+}
+
+/// Assembles `text`, instructions separated by `;` or newlines, each an opcode's mnemonic (in any case) followed by
+/// its args, e.g. `CONST 0; CONST 1; ADD; POP`. `CONST` args index `constants`, jump args are byte offsets.
+pub fn assemble(text: &str, constants: Constants) -> Result<ByteCode, CompileError> {
+    let mut builder = ByteCodeBuilder { constants, ..ByteCodeBuilder::new() };
+    for (n, instruction) in text.split([';', '\n']).map(str::trim).filter(|instruction| !instruction.is_empty()).enumerate() {
+        let mut words = instruction.split_whitespace();
+        let mnemonic = words.next().unwrap_or_default();
+        let opcode = OpCode::ALL
+            .iter()
+            .find(|opcode| opcode.name().eq_ignore_ascii_case(mnemonic))
+            .ok_or_else(|| CompileError(format!("Unknown mnemonic `{mnemonic}` in instruction {}", n + 1)))?;
+        let args = words
+            .map(|word| word.parse::<u32>().map_err(|_| CompileError(format!("Invalid arg `{word}` in instruction {}, expected a number up to {}", n + 1, u32::MAX))))
+            .collect::<Result<Vec<u32>, CompileError>>()?;
+        if args.len() != opcode.get_arg_widths().len() {
+            return Err(CompileError(format!("{} takes {} args, instruction {} has {}", opcode.name(), opcode.get_arg_widths().len(), n + 1, args.len())));
+        }
+        builder.emit(*opcode, &args)?;
+    }
+    Ok(builder.build())
+}
+
+/// Disassembles `bytes` into the text [`assemble`] reads, an instruction per line.
+pub fn disassemble(bytes: &Bytes) -> Result<String, CompileError> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (opcode, args, len) = unmake(bytes, offset)?;
+        let args: String = args.iter().map(|arg| match arg {
+            Arg::U8(val) => format!(" {val}"),
+            Arg::U16(val) => format!(" {val}"),
+            Arg::U32(val) => format!(" {val}"),
+        }).collect();
+        lines.push(format!("{}{args}", opcode.name()));
+        offset += len;
+    }
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use crate::{vm::VM, Compiler};
+
+    use super::*;
+
+    fn run(bytecode: ByteCode) -> Object {
+        let vm = VM::load(bytecode).unwrap();
+        vm.run().unwrap();
+        vm.last_popped()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let constants = vec![Object::Integer(1), Object::Integer(2)];
+        assert_eq!(run(assemble("CONST 0; CONST 1; ADD; POP", constants.clone()).unwrap()), Object::Integer(3));
+        // if (true) { 1 } else { null }
+        let text = "true\njump_false 10\nconst 0\njump 11\nnull\npop";
+        assert_eq!(run(assemble(text, constants.clone()).unwrap()), Object::Integer(1));
+        assert_eq!(run(assemble("CONST 1; SET_GLOBAL 3; GET_GLOBAL 3; POP", constants.clone()).unwrap()), Object::Integer(2));
+
+        assert_eq!(assemble("CONST 0; FOO", Vec::new()).unwrap_err().0, "Unknown mnemonic `FOO` in instruction 2");
+        assert_eq!(assemble("CONST", Vec::new()).unwrap_err().0, "CONST takes 1 args, instruction 1 has 0");
+        assert_eq!(assemble("JUMP -1", Vec::new()).unwrap_err().0, "Invalid arg `-1` in instruction 1, expected a number up to 4294967295");
+        assert_eq!(assemble("JUMP 65536", Vec::new()).unwrap_err().0, "JUMP takes a two byte arg, 65536 doesn't fit");
+        // Assembling doesn't validate, loading does
+        assert!(VM::load(assemble("CONST 2; POP", constants).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let program = Parser::new(Lexer::new("let x = [1, 2][0]; if (x > 0) { x ?? 3 } else { -x }".to_string())).parse_program().unwrap();
+        let bytecode = Compiler::new().compile_program(&program).unwrap();
+        let text = disassemble(&bytecode.bytes).unwrap();
+        assert!(text.starts_with("CONST 0\nCONST 1\nARRAY 2\n"), "{text}");
+        let assembled = assemble(&text, bytecode.constants.clone()).unwrap();
+        assert_eq!((&assembled.bytes, assembled.globals), (&bytecode.bytes, bytecode.globals));
+        assert_eq!(run(assembled), run(bytecode));
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = ByteCodeBuilder::new();
+        let two = builder.constant(Object::Integer(2));
+        builder.emit(OpCode::Constant, &[two]).unwrap().emit(OpCode::Minus, &[]).unwrap().emit(OpCode::Pop, &[]).unwrap();
+        assert_eq!(builder.offset(), 5);
+        assert!(builder.emit(OpCode::Add, &[1]).is_err());
+        assert_eq!(run(builder.build()), Object::Integer(-2));
+    }
+}
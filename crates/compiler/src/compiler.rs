@@ -1,8 +1,10 @@
-use crate::{helpers::{self, binary_helpers}, symbol_table::SymbolTable};
+use std::collections::HashMap;
+
+use crate::{helpers::binary_helpers, peephole, symbol_table::SymbolTable};
 
 pub use crate::types::*;
 
-use parser::{ast::{self, Statement}, Program};
+use parser::{analysis::{find_const_redeclarations, find_returns_outside_functions, find_undefined}, optimize, ast::{self, walk_expression, walk_statement, Visitor}, lexer::token::Span, Program};
 
 pub fn unmake(bytes: &Bytes, offset: usize) -> Result<(OpCode, Vec<Arg>, usize), CompileError> {
     if bytes.len() <= offset {
@@ -16,7 +18,7 @@ pub fn unmake(bytes: &Bytes, offset: usize) -> Result<(OpCode, Vec<Arg>, usize),
     let total_len = arg_widths.iter().sum::<u8>() + 1;
 
     // println!("total_len: {total_len}, offset: {offset}, len: {}", bytes.len());
-    if total_len as usize + offset - 1 >= bytes.len() {
+    if total_len as usize + offset > bytes.len() {
         return Err(CompileError("unmake: args length greater than bytes size!".to_string()))
     }
 
@@ -25,6 +27,7 @@ pub fn unmake(bytes: &Bytes, offset: usize) -> Result<(OpCode, Vec<Arg>, usize),
         match width {
             1 => args.push(Arg::read_u8(bytes, offset + bytes_read)?.0),
             2 => args.push(Arg::read_u16(bytes, offset + bytes_read)?.0),
+            4 => args.push(Arg::read_u32(bytes, offset + bytes_read)?.0),
             _ => return  Err(CompileError(format!("Invalid arg width: {}", width))),
         }
         bytes_read += width as usize;
@@ -33,7 +36,7 @@ pub fn unmake(bytes: &Bytes, offset: usize) -> Result<(OpCode, Vec<Arg>, usize),
     Ok((opcode, args, bytes_read))
 }
 
-pub fn make(opcode: OpCode, args: &Vec<Arg>) -> Result<Vec<u8>, CompileError> {
+pub fn make(opcode: OpCode, args: &[Arg]) -> Result<Vec<u8>, CompileError> {
     let arg_widths = opcode.get_arg_widths();
 
     if args.len() != arg_widths.len() {
@@ -52,6 +55,7 @@ pub fn make(opcode: OpCode, args: &Vec<Arg>) -> Result<Vec<u8>, CompileError> {
                 let (h, l) = binary_helpers::split_u16(val);
                 bytes.extend_from_slice(&[h, l]);
             },
+            (4, Arg::U32(val)) => bytes.extend_from_slice(&val.to_be_bytes()),
             _ => return Err(CompileError(format!("Cannot parse arg: {:?} for opcode: {:?}, expected size: {} byte(s)", arg, opcode, width))),
         }
     }
@@ -64,69 +68,333 @@ pub fn make(opcode: OpCode, args: &Vec<Arg>) -> Result<Vec<u8>, CompileError> {
     Ok(bytes)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmittedInstruction {
+    pub opcode: OpCode,
+    pub position: usize,
+}
+
+/// The instructions of one function body (or the top level) being compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompilationScope {
+    pub bytes: Bytes,
+    pub last_instruction: Option<EmittedInstruction>,
+    pub previous_instruction: Option<EmittedInstruction>,
+    pub source_map: SourceMap,
+}
+
+/// How many constants the pool can hold, as many as `CONST_WIDE` can index.
+pub const MAX_CONSTANTS: usize = u32::MAX as usize + 1;
+
 pub struct Compiler {
-    bytes: Bytes,
+    scopes: Vec<CompilationScope>, // the top level first, the scope being emitted into last
     constants: Constants,
+    shipped_constants: usize, // how many constants earlier deltas handed out
+    max_constants: usize,
     symbol_table: SymbolTable,
+    consts: HashMap<String, (Option<usize>, usize)>, // globals bound by a `const`, with the pool index of their value when it's a literal and the block depth they're bound at
+    span: Option<Span>, // of the innermost node being compiled
+    optimize: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
         Self {
-            bytes: Vec::new(),
+            scopes: vec![CompilationScope::default()],
             constants: Vec::new(),
+            shipped_constants: 0,
+            max_constants: MAX_CONSTANTS,
             symbol_table: SymbolTable::new(),
+            consts: HashMap::new(),
+            span: None,
+            optimize: true,
         }
     }
 
-    fn add_constant(&mut self, obj: Object) -> usize {
+    /// Whether `compile_program` simplifies the AST first and runs the peephole pass after, on by default.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Caps the constant pool at `max` constants (at most [`MAX_CONSTANTS`]), compiling a program that needs more
+    /// fails. The pool is shared by every delta compiled, so a REPL session counts all of its lines.
+    pub fn set_max_constants(&mut self, max: usize) {
+        self.max_constants = max.min(MAX_CONSTANTS);
+    }
+
+    fn add_constant(&mut self, obj: Object) -> Result<usize, CompileError> {
+        if self.constants.len() >= self.max_constants {
+            return Err(CompileError(format!("Too many constants, the pool is full at {} before the literal {obj}", self.max_constants)));
+        }
         self.constants.push(obj);
-        self.constants.len() - 1
+        Ok(self.constants.len() - 1)
+    }
+
+    /// Loads the constant at `idx`, with `CONST_WIDE` once the pool has outgrown the two byte index of `CONST`.
+    fn emit_constant(&mut self, idx: usize) -> Result<usize, CompileError> {
+        match u16::try_from(idx) {
+            Ok(idx) => self.emit(OpCode::Constant, &[Arg::U16(idx)]),
+            Err(_) => self.emit(OpCode::ConstantWide, &[Arg::U32(idx as u32)]),
+        }
     }
 
-    fn emit(&mut self, opcode: OpCode, args: &Vec<Arg>) -> Result<usize, CompileError> {
+    fn emit(&mut self, opcode: OpCode, args: &[Arg]) -> Result<usize, CompileError> {
         let bytes = make(opcode, args)?;
-        let start = self.bytes.len();
-        self.bytes.extend(bytes);
-        Ok(start)
+        let span = self.span;
+        let scope = self.current_scope_mut();
+        let position = scope.bytes.len();
+        scope.bytes.extend(bytes);
+        if let Some(span) = span {
+            scope.source_map.add(position, span);
+        }
+        scope.previous_instruction = scope.last_instruction.replace(EmittedInstruction { opcode, position });
+        Ok(position)
+    }
+
+    fn current_scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("the top level scope is never left")
+    }
+
+    /// The scope currently being emitted into.
+    pub fn scope(&self) -> &CompilationScope {
+        self.scopes.last().expect("the top level scope is never left")
+    }
+
+    /// How many scopes are open, 1 at the top level.
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Starts emitting into a fresh instruction buffer, e.g. for a function body.
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+    }
+
+    /// Goes back to the enclosing scope, returning the instructions emitted since the matching `enter_scope`.
+    pub fn leave_scope(&mut self) -> Result<Bytes, CompileError> {
+        if self.scopes.len() == 1 {
+            return Err(CompileError("Cannot leave the top level scope".to_string()));
+        }
+        Ok(self.scopes.pop().expect("more than one scope is open").bytes)
     }
 
     fn emit_no_args(&mut self, opcode: OpCode) -> Result<usize, CompileError> {
-        self.emit(opcode, &Vec::new())
+        self.emit(opcode, &[])
     }
 
     pub fn compile_program(&mut self, program: &Program) -> Result<ByteCode, CompileError> {
-        for statement in &program.statements {
-            self.compile_statement(statement)?;
-        }
+        self.compile_top_level(program)?;
         Ok(self.get_byte_code())
     }
 
-    fn parse_statements(&mut self, statements: &Vec<Statement>) -> Result<(), CompileError> {
-        for statement in statements {
-            self.compile_statement(statement)?;
+    /// Compiles `program` to run on its own against the globals compiled so far: the names and consts it binds are
+    /// forgotten once it's compiled, so the programs compiled after it don't see them.
+    pub fn compile_isolated(&mut self, program: &Program) -> Result<ByteCode, CompileError> {
+        self.reset();
+        let (symbol_table, consts) = (self.symbol_table.clone(), self.consts.clone());
+        let bytecode = self.compile_program(program);
+        (self.symbol_table, self.consts) = (symbol_table, consts);
+        bytecode
+    }
+
+    /// Compiles `program` after the ones compiled before it (e.g. REPL lines), returning only the constants added
+    /// since the previous delta. The VM appends them to the pool it kept, see [`crate::vm::VM::new_with_pool`].
+    pub fn compile_delta(&mut self, program: &Program) -> Result<ByteCode, CompileError> {
+        self.scopes = vec![CompilationScope::default()];
+        let base = self.shipped_constants;
+        if let Err(err) = self.compile_top_level(program) {
+            self.constants.truncate(base);
+            return Err(err);
+        }
+        self.shipped_constants = self.constants.len();
+
+        Ok(ByteCode {
+            bytes: self.scopes[0].bytes.clone(),
+            constants: self.constants[base..].to_vec(),
+            constants_base: base,
+            globals: self.symbol_table.num_defs() as usize,
+            global_names: self.symbol_table.slot_names(),
+            source_map: self.scopes[0].source_map.clone(),
+        })
+    }
+
+    fn compile_top_level(&mut self, program: &Program) -> Result<(), CompileError> {
+        // Report every misplaced return and unknown variable at once rather than stopping at the first one
+        let known = self.globals().into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+        let mut errors = find_returns_outside_functions(program).iter().map(ToString::to_string).collect::<Vec<String>>();
+        errors.extend(find_const_redeclarations(program).iter().map(ToString::to_string));
+        errors.extend(find_undefined(program, &known).iter().map(ToString::to_string));
+        if !errors.is_empty() {
+            return Err(CompileError(errors.join("\n")));
+        }
+        if self.optimize {
+            let mut program = program.clone();
+            optimize::optimize(&mut program);
+            self.visit_program(&program)?;
+        } else {
+            self.visit_program(program)?;
+        }
+        if self.optimize {
+            let scope = &mut self.scopes[0];
+            (scope.bytes, scope.source_map) = peephole::optimize(&scope.bytes, &scope.source_map)?;
+            // Positions no longer line up with the optimized bytes
+            scope.last_instruction = None;
+            scope.previous_instruction = None;
         }
         Ok(())
     }
 
-    fn compile_statement(&mut self, statement: &ast::Statement) -> Result<(), CompileError> {
+    fn remove_last_pop(&mut self) {
+        let scope = self.current_scope_mut();
+        if let Some(EmittedInstruction { opcode: OpCode::Pop, position }) = scope.last_instruction {
+            scope.bytes.truncate(position);
+            scope.source_map.truncate(position);
+            scope.last_instruction = scope.previous_instruction.take();
+        }
+    }
+
+    /// Compiles the block of an `if`, leaving its value on the stack like the interpreter: that of its last statement,
+    /// the bound value for a `let`, null when empty.
+    fn compile_branch(&mut self, branch: &ast::Statement) -> Result<(), CompileError> {
+        // The block's lets go out of scope with it, and may hide consts until then
+        let consts = self.consts.clone();
+        self.symbol_table.enter_block();
+        let result = self.compile_block(branch);
+        self.symbol_table.leave_block();
+        self.consts = consts;
+        result
+    }
+
+    fn compile_block(&mut self, branch: &ast::Statement) -> Result<(), CompileError> {
+        self.visit_statement(branch)?;
+        let last = match branch {
+            ast::Statement::Block { statements, .. } => statements.last(),
+            branch => Some(branch),
+        };
+        match last {
+            Some(ast::Statement::ExpressionStatement { .. }) => self.remove_last_pop(),
+            Some(ast::Statement::Let { name, .. }) => self.visit_expression(name)?,
+            _ => { self.emit(OpCode::Null, &[])?; },
+        }
+        Ok(())
+    }
+
+    fn overwrite_instruction(&mut self, addr_idx: usize, new_instruction: &[u8]) {
+        self.current_scope_mut().bytes[addr_idx..addr_idx + new_instruction.len()].copy_from_slice(new_instruction);
+        // let (h, l) = binary_helpers::split_u16(addr);
+        // self.bytes[addr_idx] = h;
+        // self.bytes[addr_idx + 1] = l;
+    }
+
+    pub fn get_byte_code(&self) -> ByteCode {
+        ByteCode {
+            bytes: self.scopes[0].bytes.clone(),
+            constants: self.constants.clone(),
+            constants_base: 0,
+            globals: self.symbol_table.num_defs() as usize,
+            global_names: self.symbol_table.slot_names(),
+            source_map: self.scopes[0].source_map.clone(),
+        }
+    }
+
+    pub fn define_global(&mut self, name: &str) -> u16 {
+        self.symbol_table.define(name)
+    }
+
+    pub fn resolve_global(&self, name: &str) -> Option<u16> {
+        self.symbol_table.resolve(name)
+    }
+
+    /// Names of the globals defined so far and their slots in the VM's globals, in definition order.
+    pub fn globals(&self) -> Vec<(String, u16)> {
+        self.symbol_table.symbols()
+    }
+
+    pub fn reset(&mut self) {
+        self.scopes = vec![CompilationScope::default()];
+        self.constants.clear();
+        self.shipped_constants = 0;
+        // The pool the folded consts pointed into is gone, they're read from their globals again
+        self.consts.values_mut().for_each(|(folded, _)| *folded = None);
+    }
+
+    /// Consts can't be bound again in their block, by this program or an earlier one compiled by this compiler.
+    fn check_not_const(&self, name: &str) -> Result<(), CompileError> {
+        match self.consts.get(name) {
+            Some((_, depth)) if *depth == self.symbol_table.block_depth() => Err(CompileError(format!("Cannot redeclare const {name}"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records `name` as just bound in the current block, a const or a let hiding any const of an outer block.
+    fn bind_const(&mut self, name: &str, constant: bool, folded: Option<usize>) {
+        match constant {
+            true => { self.consts.insert(name.to_string(), (folded, self.symbol_table.block_depth())); },
+            false => { self.consts.remove(name); },
+        }
+    }
+
+    pub fn decompile(&self) -> Result<(), CompileError> {
+        println!("**************Decompile*****************");
+        let bytes = &self.scopes[0].bytes;
+        let mut i = 0;
+        while i < bytes.len() {
+            let (opcode, args, bytes_read) = unmake(bytes, i)?;
+            let args: String = args.iter().map(|arg| match arg {
+                Arg::U8(val) => format!(" {val}"),
+                Arg::U16(val) => format!(" {val}"),
+                Arg::U32(val) => format!(" {val}"),
+            }).collect();
+            println!("{i:04} {}{args}", opcode.name());
+            i += bytes_read;
+        }
+        println!("****************************************");
+        Ok(())
+    }
+}
+
+impl Visitor for Compiler {
+    type Error = CompileError;
+
+    fn visit_statement(&mut self, statement: &ast::Statement) -> Result<(), CompileError> {
         match statement {
             ast::Statement::ExpressionStatement { expression, .. } => {
-                self.compile_expression(expression)?;
-                self.emit(OpCode::Pop, &Vec::new())?;
-            },
-            ast::Statement::Block { statements, .. } => {
-                for statement in statements {
-                    self.compile_statement(statement)?;
-                }
+                self.visit_expression(expression)?;
+                self.emit(OpCode::Pop, &[])?;
             },
+            ast::Statement::Block { .. } => walk_statement(self, statement)?,
             ast::Statement::Let { name, value, .. } => {
-                if let ast::Expression::Identifier { value: name, .. } = name {
-                    self.compile_expression(value)?;
-                    let idx = self.symbol_table.define(&name);
-                    self.emit(OpCode::SetGlobal, &vec![Arg::U16(idx)])?;
-                } else {
-                    return Err(CompileError(format!("Invalie Let statement, expected identifier, got: {:?}", name)))
+                match name {
+                    ast::Expression::Identifier { value: name, .. } => {
+                        self.check_not_const(name)?;
+                        self.visit_expression(value)?;
+                        let idx = self.symbol_table.define(name);
+                        self.emit(OpCode::SetGlobal, &[Arg::U16(idx)])?;
+                        // The literal just added to the pool is the const's value for good, reads load it from there
+                        let folded = matches!(value, ast::Expression::Integer { .. } | ast::Expression::String { .. }).then(|| self.constants.len() - 1);
+                        self.bind_const(name, statement.is_const(), folded);
+                    },
+                    ast::Expression::Tuple { elements: names, .. } => {
+                        self.visit_expression(value)?;
+                        // Destructure leaves the elements on the stack in order, so the last name is set first
+                        self.emit(OpCode::Destructure, &[u16_arg(names.len(), "Destructuring into")?])?;
+                        for name in names.iter().rev() {
+                            let ast::Expression::Identifier { value: name, .. } = name else {
+                                return Err(CompileError(format!("Invalie Let statement, expected identifier, got: {:?}", name)))
+                            };
+                            self.check_not_const(name)?;
+                            let idx = self.symbol_table.define(name);
+                            self.emit(OpCode::SetGlobal, &[Arg::U16(idx)])?;
+                            self.bind_const(name, statement.is_const(), None);
+                        }
+                    },
+                    _ => return Err(CompileError(format!("Invalie Let statement, expected identifier, got: {:?}", name))),
                 }
             },
             // ast::Statement::Block { statements, .. } => self.
@@ -136,11 +404,56 @@ impl Compiler {
         Ok(())
     }
 
+    fn visit_expression(&mut self, expression: &ast::Expression) -> Result<(), CompileError> {
+        let outer = self.span;
+        self.span = expression.span().or(outer);
+        let result = self.compile_expression(expression);
+        self.span = outer;
+        result
+    }
+}
+
+/// The operand of an instruction taking `len` values off the stack, `what` says what they are when there are too many.
+fn u16_arg(len: usize, what: &str) -> Result<Arg, CompileError> {
+    u16::try_from(len).map(Arg::U16).map_err(|_| CompileError(format!("{what} {len} values is more than the {} an instruction takes", u16::MAX)))
+}
+
+/// Jumps take a two byte offset, so code past 64KB can't be jumped to.
+fn jump_arg(offset: usize) -> Result<Arg, CompileError> {
+    u16::try_from(offset).map(Arg::U16).map_err(|_| CompileError(format!("Jump to offset {offset} is past the {} bytes jumps reach, the program is too large", u16::MAX)))
+}
+
+impl Compiler {
     fn compile_expression(&mut self, expression: &ast::Expression) -> Result<(), CompileError> {
         match expression {
-            ast::Expression::Infix { left, operator, right, .. } => {
-                self.compile_expression(left)?;
-                self.compile_expression(right)?;
+            ast::Expression::Infix { left, operator, right, .. } if operator == "??" => {
+                self.visit_expression(left)?;
+                let jp_not_null_addr_idx = self.emit(OpCode::JPNotNull, &[Arg::U16(0)])?;
+                self.visit_expression(right)?;
+                let end = self.scope().bytes.len();
+                self.overwrite_instruction(jp_not_null_addr_idx, &make(OpCode::JPNotNull, &[jump_arg(end)?])?);
+            },
+            // `a && b` jumps to false as soon as a side is falsy, `a || b` to true as soon as one is truthy
+            ast::Expression::Infix { left, operator, right, .. } if operator == "&&" || operator == "||" => {
+                let (jump, decided, undecided) = match operator.as_str() {
+                    "&&" => (OpCode::JPFalse, OpCode::False, OpCode::True),
+                    _ => (OpCode::JPTrue, OpCode::True, OpCode::False),
+                };
+                self.visit_expression(left)?;
+                let left_jump_idx = self.emit(jump, &[Arg::U16(0)])?;
+                self.visit_expression(right)?;
+                let right_jump_idx = self.emit(jump, &[Arg::U16(0)])?;
+                self.emit_no_args(undecided)?;
+                let jp_end_idx = self.emit(OpCode::JP, &[Arg::U16(0)])?;
+                let decided_addr = self.scope().bytes.len();
+                self.emit_no_args(decided)?;
+                let end = self.scope().bytes.len();
+                self.overwrite_instruction(left_jump_idx, &make(jump, &[jump_arg(decided_addr)?])?);
+                self.overwrite_instruction(right_jump_idx, &make(jump, &[jump_arg(decided_addr)?])?);
+                self.overwrite_instruction(jp_end_idx, &make(OpCode::JP, &[jump_arg(end)?])?);
+            },
+            ast::Expression::Infix { operator, .. } => {
+                walk_expression(self, expression)?;
                 match operator.as_str() {
                     "+" => { self.emit_no_args(OpCode::Add)?; },
                     "-" => { self.emit_no_args(OpCode::Sub)?; },
@@ -150,109 +463,142 @@ impl Compiler {
                     "!=" => { self.emit_no_args(OpCode::NEq)?; },
                     ">" => { self.emit_no_args(OpCode::GT)?; },
                     "<" => { self.emit_no_args(OpCode::LT)?; },
-                    op @ _ => return Err(CompileError(format!("Cannot compile infix operator: {}", op))),
+                    "&" => { self.emit_no_args(OpCode::BitAnd)?; },
+                    "|" => { self.emit_no_args(OpCode::BitOr)?; },
+                    "^" => { self.emit_no_args(OpCode::BitXor)?; },
+                    "**" => { self.emit_no_args(OpCode::Pow)?; },
+                    "<<" => { self.emit_no_args(OpCode::ShiftL)?; },
+                    ">>" => { self.emit_no_args(OpCode::ShiftR)?; },
+                    ".." => { self.emit_no_args(OpCode::Range)?; },
+                    op => return Err(CompileError(format!("Cannot compile infix operator: {}", op))),
                 }
             },
             ast::Expression::Integer { value, .. } => {
-                let idx = self.add_constant(Object::Integer(*value));
-                self.emit(OpCode::Constant, &vec![Arg::U16(idx as u16)])?;
+                let idx = self.add_constant(Object::Integer(*value))?;
+                self.emit_constant(idx)?;
+            },
+            ast::Expression::String { value, .. } => {
+                let idx = self.add_constant(Object::String(value.clone()))?;
+                self.emit_constant(idx)?;
+            },
+            ast::Expression::Array { elements, .. } => {
+                walk_expression(self, expression)?;
+                self.emit(OpCode::Array, &[u16_arg(elements.len(), "An array of")?])?;
+            },
+            ast::Expression::Tuple { elements, .. } => {
+                walk_expression(self, expression)?;
+                self.emit(OpCode::Tuple, &[u16_arg(elements.len(), "A tuple of")?])?;
+            },
+            ast::Expression::Index { .. } => {
+                walk_expression(self, expression)?;
+                self.emit_no_args(OpCode::Index)?;
+            },
+            ast::Expression::Slice { name, start, end, .. } => {
+                self.visit_expression(name)?;
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => self.visit_expression(bound)?,
+                        None => { self.emit_no_args(OpCode::Null)?; },
+                    }
+                }
+                self.emit_no_args(OpCode::Slice)?;
             },
             ast::Expression::Boolean { value, .. } => {
                 let opcode = if *value { OpCode::True } else { OpCode::False };
-                self.emit(opcode, &Vec::new())?;
+                self.emit(opcode, &[])?;
+            },
+            ast::Expression::Null { .. } => {
+                self.emit_no_args(OpCode::Null)?;
             },
-            ast::Expression::Prefix { operator, right, .. } => {
-                self.compile_expression(&right)?;
+            ast::Expression::Prefix { operator, .. } => {
+                walk_expression(self, expression)?;
                 
                 match operator.as_str() {
                     "-" => { self.emit_no_args(OpCode::Minus)?; },
                     "!" => { self.emit_no_args(OpCode::Exclam)?; },
-                    op @ _ => return Err(CompileError(format!("Cannot compile prefix operator: {}", op))),
+                    op => return Err(CompileError(format!("Cannot compile prefix operator: {}", op))),
                 }
             },
             ast::Expression::If { condition, consequence, alternative, .. } => {
-                self.compile_expression(&condition)?;
+                self.visit_expression(condition)?;
 
-                let jp_false_addr_idx = self.emit(OpCode::JPFalse, &vec![Arg::U16(0)])?;
+                let jp_false_addr_idx = self.emit(OpCode::JPFalse, &[Arg::U16(0)])?;
 
-                self.compile_statement(&consequence)?;
-                self.remove_last_pop();
+                self.compile_branch(consequence)?;
 
                 // let mut jp_false_addr = self.bytes.len();
 
-                let jp_addr_idx = self.emit(OpCode::JP, &vec![Arg::U16(0)])?;
-                let jp_false_addr = self.bytes.len();
+                let jp_addr_idx = self.emit(OpCode::JP, &[Arg::U16(0)])?;
+                let jp_false_addr = self.scope().bytes.len();
 
                 if let Some(alternative) = alternative {
-                    self.compile_statement(&alternative)?;
+                    self.compile_branch(alternative)?;
                 }else {
-                    self.emit(OpCode::Null, &vec![])?;
+                    self.emit(OpCode::Null, &[])?;
                 }
-                
-                self.remove_last_pop();
 
-                let jp_addr = self.bytes.len();
+                let jp_addr = self.scope().bytes.len();
 
-                self.overwrite_instruction(jp_addr_idx, &make(OpCode::JP, &vec![Arg::U16(jp_addr as u16)])?);
-                self.overwrite_instruction(jp_false_addr_idx, &make(OpCode::JPFalse, &vec![Arg::U16(jp_false_addr as u16)])?);
+                self.overwrite_instruction(jp_addr_idx, &make(OpCode::JP, &[jump_arg(jp_addr)?])?);
+                self.overwrite_instruction(jp_false_addr_idx, &make(OpCode::JPFalse, &[jump_arg(jp_false_addr)?])?);
             },
             ast::Expression::Identifier { value, .. } => {
-                let idx = self.symbol_table.resolve(&value).ok_or(CompileError(format!("Cannot resolve symbol: {}", value)))?;
-                self.emit(OpCode::GetGlobal, &vec![Arg::U16(idx)])?;
+                if let Some((Some(idx), _)) = self.consts.get(value) {
+                    self.emit_constant(*idx)?;
+                    return Ok(());
+                }
+                let idx = self.symbol_table.resolve(value).ok_or(CompileError(format!("Cannot resolve symbol: {}", value)))?;
+                self.emit(OpCode::GetGlobal, &[Arg::U16(idx)])?;
             }
             _ => return Err(CompileError(format!("Compilation not implemented for: {:?}", expression))),
         }
         Ok(())
     }
+}
 
-    fn remove_last_pop(&mut self) {
-        if let Some(val) = self.bytes.last() {
-            if *val == OpCode::Pop as u8 {
-                self.bytes.pop();
-            }
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
 
-    fn overwrite_instruction(&mut self, addr_idx: usize, new_instruction: &Vec<u8>) {
-        for i in 0..new_instruction.len() {
-            self.bytes[addr_idx + i] = new_instruction[i];
-        }
-        // let (h, l) = binary_helpers::split_u16(addr);
-        // self.bytes[addr_idx] = h;
-        // self.bytes[addr_idx + 1] = l;
-    }
+    use crate::vm::VM;
 
-    pub fn get_byte_code(&self) -> ByteCode {
-        ByteCode {
-            bytes: self.bytes.clone(),
-            constants: self.constants.clone(),
-        }
-    }
+    use super::*;
 
-    pub fn reset(&mut self) {
-        self.bytes.clear();
-        self.constants.clear();
-    }
+    #[test]
+    fn test_make_constant() -> Result<(), CompileError> {
+        assert_eq!(make(OpCode::Constant, &[Arg::U16(0xfffe)])?, vec![OpCode::Constant as u8, 0xff, 0xfe]);
 
-    pub fn decompile(&self) -> Result<(), CompileError> {
-        println!("**************Decompile*****************");
-        let mut i = 0;
-        while i < self.bytes.len() {
-            let (opcode, args, bytes_read) = unmake(&self.bytes, i)?;
-            println!("{:?} ({:?})", opcode, args);
-            i += bytes_read;
-        }
-        println!("****************************************");
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
     #[test]
-    fn test_make_constant() -> Result<(), CompileError> {
-        assert_eq!(make(OpCode::Constant, &vec![Arg::U16(0xfffe)])?, vec![OpCode::Constant as u8, 0xff, 0xfe]);
+    fn test_scopes() -> Result<(), CompileError> {
+        let mut compiler = Compiler::new();
+        compiler.emit_no_args(OpCode::Mul)?;
+
+        compiler.enter_scope();
+        assert_eq!(compiler.scope_depth(), 2);
+        compiler.emit_no_args(OpCode::Sub)?;
+        compiler.emit_no_args(OpCode::Pop)?;
+        assert_eq!(compiler.scope().last_instruction, Some(EmittedInstruction { opcode: OpCode::Pop, position: 1 }));
+        compiler.remove_last_pop();
+        assert_eq!(compiler.scope().last_instruction, Some(EmittedInstruction { opcode: OpCode::Sub, position: 0 }));
+        assert_eq!(compiler.leave_scope()?, vec![OpCode::Sub as u8]);
+
+        assert_eq!(compiler.scope_depth(), 1);
+        compiler.emit_no_args(OpCode::Add)?;
+        assert_eq!(compiler.scope(), &CompilationScope {
+            bytes: vec![OpCode::Mul as u8, OpCode::Add as u8],
+            last_instruction: Some(EmittedInstruction { opcode: OpCode::Add, position: 1 }),
+            previous_instruction: Some(EmittedInstruction { opcode: OpCode::Mul, position: 0 }),
+            source_map: SourceMap::default(),
+        });
+        assert!(compiler.leave_scope().is_err());
+
+        // An operand byte that happens to equal Pop's opcode is not an instruction
+        compiler.emit(OpCode::Constant, &[Arg::U16(OpCode::Pop as u16)])?;
+        compiler.remove_last_pop();
+        assert_eq!(compiler.scope().bytes.len(), 5);
 
         Ok(())
     }
@@ -260,6 +606,152 @@ mod tests {
     #[test]
     fn test_unmake_constant() -> Result<(), CompileError> {
         assert_eq!(unmake(&vec![0, 0xab, 0xcd], 0)?, (OpCode::Constant, vec![Arg::U16(0xabcd)], 3));
+        assert_eq!(unmake(&vec![32, 0, 1, 0, 2], 0)?, (OpCode::ConstantWide, vec![Arg::U32(0x10002)], 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_pool_limit() -> Result<(), CompileError> {
+        let program = Parser::new(Lexer::new("[1][0] + 2".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_max_constants(3);
+        compiler.compile_program(&program)?;
+        let mut compiler = Compiler::new();
+        compiler.set_max_constants(2);
+        assert_eq!(compiler.compile_program(&program).unwrap_err().0, "Too many constants, the pool is full at 2 before the literal 2");
+
+        // Past what CONST indexes, constants are loaded with CONST_WIDE
+        let mut compiler = Compiler::new();
+        compiler.constants = vec![Object::Null; u16::MAX as usize + 1];
+        let bytecode = compiler.compile_program(&program)?;
+        assert_eq!(unmake(&bytecode.bytes, 0)?.0, OpCode::ConstantWide);
+        let vm = VM::load(bytecode).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(3));
         Ok(())
     }
+
+    #[test]
+    fn test_operands_too_large() {
+        let compile = |src: String| Compiler::new().compile_program(&Parser::new(Lexer::new(src)).parse_program().unwrap());
+        // A jump past 64KB of bytecode can't be encoded, rather than jumping somewhere else
+        let src = (0..17_000).map(|i| format!("{i};")).collect::<String>() + "if ([false][0]) { 111 } else { 222 }";
+        let err = compile(src).unwrap_err();
+        assert!(err.0.starts_with("Jump to offset ") && err.0.ends_with("is past the 65535 bytes jumps reach, the program is too large"), "{}", err.0);
+
+        let src = format!("[{}]", vec!["0"; u16::MAX as usize + 1].join(", "));
+        assert_eq!(compile(src).unwrap_err().0, "An array of 65536 values is more than the 65535 an instruction takes");
+    }
+
+    /// Args of the widths `opcode` takes, out of arbitrary values.
+    fn args_for(opcode: OpCode, values: &[u32]) -> Vec<Arg> {
+        opcode.get_arg_widths().iter().zip(values).map(|(width, val)| match width {
+            1 => Arg::U8(*val as u8),
+            2 => Arg::U16(*val as u16),
+            _ => Arg::U32(*val),
+        }).collect()
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_make_unmake_round_trip(
+            opcode in proptest::sample::select(OpCode::ALL),
+            values in proptest::collection::vec(proptest::arbitrary::any::<u32>(), 2),
+            prefix in proptest::collection::vec(proptest::arbitrary::any::<u8>(), 0..8),
+            suffix in proptest::collection::vec(proptest::arbitrary::any::<u8>(), 0..8),
+        ) {
+            let args = args_for(opcode, &values);
+            let made = make(opcode, &args).unwrap();
+            proptest::prop_assert_eq!(made.len(), 1 + opcode.get_arg_widths().iter().map(|width| *width as usize).sum::<usize>());
+            let bytes = [prefix.as_slice(), &made, &suffix].concat();
+            proptest::prop_assert_eq!(unmake(&bytes, prefix.len()).unwrap(), (opcode, args, made.len()));
+
+            // Cut short anywhere, the instruction doesn't decode
+            for len in prefix.len()..prefix.len() + made.len() {
+                proptest::prop_assert!(unmake(&bytes[..len].to_vec(), prefix.len()).is_err());
+            }
+        }
+
+        #[test]
+        fn prop_make_rejects_bad_args(opcode in proptest::sample::select(OpCode::ALL), val in proptest::arbitrary::any::<u32>()) {
+            let mut args = args_for(opcode, &[val, val]);
+            // Args of the wrong width
+            let mismatched: Vec<Arg> = args.iter().map(|arg| match arg {
+                Arg::U8(val) => Arg::U16(*val as u16),
+                Arg::U16(val) => Arg::U8(*val as u8),
+                Arg::U32(val) => Arg::U16(*val as u16),
+            }).collect();
+            if !mismatched.is_empty() {
+                proptest::prop_assert!(make(opcode, &mismatched).is_err());
+            }
+            // One arg too many, and one too few
+            args.push(Arg::U16(val as u16));
+            proptest::prop_assert!(make(opcode, &args).is_err());
+            args.truncate(args.len().saturating_sub(2));
+            proptest::prop_assert_eq!(make(opcode, &args).is_err(), !opcode.get_arg_widths().is_empty());
+        }
+
+        #[test]
+        fn prop_unmake_arbitrary_bytes(bytes in proptest::collection::vec(proptest::arbitrary::any::<u8>(), 0..16), offset in 0usize..20) {
+            // Errors, never panics, and whatever decodes encodes back to the same bytes
+            if let Ok((opcode, args, len)) = unmake(&bytes, offset) {
+                proptest::prop_assert!(offset + len <= bytes.len());
+                proptest::prop_assert_eq!(make(opcode, &args).unwrap(), bytes[offset..offset + len].to_vec());
+            } else {
+                let len = OpCode::from_byte(bytes.get(offset).copied().unwrap_or_default())
+                    .map_or(0, |opcode| 1 + opcode.get_arg_widths().iter().map(|width| *width as usize).sum::<usize>());
+                proptest::prop_assert!(offset >= bytes.len() || OpCode::from_byte(bytes[offset]).is_err() || offset + len > bytes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_const_bindings() {
+        use parser::{lexer::Lexer, Parser};
+
+        let parse = |src: &str| Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(false);
+        let bytecode = compiler.compile_program(&parse(r#"const name = "monkey"; const xs = [1]; [name, xs]"#)).unwrap();
+        // The string const is read from the pool, the array one from its global
+        let opcodes = crate::assembler::disassemble(&bytecode.bytes).unwrap();
+        assert_eq!(opcodes.lines().filter(|line| line.starts_with("GET_GLOBAL")).collect::<Vec<&str>>(), ["GET_GLOBAL 1"]);
+        assert_eq!(opcodes.lines().filter(|line| *line == "CONST 0").count(), 2);
+
+        assert_eq!(compiler.compile_program(&parse("let name = 1")).unwrap_err().0, "Cannot redeclare const name");
+        assert_eq!(compiler.compile_program(&parse("let (a, xs) = (1, 2)")).unwrap_err().0, "Cannot redeclare const xs");
+        assert_eq!(compiler.compile_program(&parse("const a = 1; const a = 2;")).unwrap_err().0, "Cannot redeclare const a at line 1, column 20");
+    }
+
+    #[test]
+    fn test_block_scope() {
+        use parser::{lexer::Lexer, Parser};
+
+        let run = |src: &str| -> Result<Object, CompileError> {
+            let bytecode = Compiler::new().compile_program(&Parser::new(Lexer::new(src.to_string())).parse_program().unwrap())?;
+            let vm = VM::load(bytecode).unwrap();
+            vm.run().unwrap();
+            Ok(vm.last_popped())
+        };
+        // Block lets get slots of their own, hiding the outer bindings (consts included) until the block ends
+        let src = "let x = [1][0]; const c = 10; let y = if (x > 0) { let x = x + 1; const c = 3; x + c }; [x, y, c]";
+        assert_eq!(run(src).unwrap().to_string(), "[1, 5, 10]");
+        assert_eq!(run("let t = [true][0]; if (t) { let y = 2; }").unwrap(), Object::Integer(2));
+        assert_eq!(run("let t = [true][0]; if (t) { let y = 2; }; y").unwrap_err().0, "Unknown variable: y at line 1, column 43");
+        assert_eq!(run("if ([true][0]) { const c = 1; let c = 2; }").unwrap_err().0, "Cannot redeclare const c at line 1, column 35");
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        use parser::{lexer::Lexer, Parser};
+
+        let mut compiler = Compiler::new();
+        let program = Parser::new(Lexer::new("let total = 1; totl + count".to_string())).parse_program().unwrap();
+        let CompileError(msg) = compiler.compile_program(&program).unwrap_err();
+        assert_eq!(msg, "Unknown variable: totl at line 1, column 16, did you mean `total`?\nUnknown variable: count at line 1, column 23");
+
+        let program = Parser::new(Lexer::new("if (true) { return 1; }".to_string())).parse_program().unwrap();
+        let CompileError(msg) = compiler.compile_program(&program).unwrap_err();
+        assert_eq!(msg, "Return outside of a function at line 1, column 13");
+    }
 }
@@ -1,7 +1,11 @@
+pub mod assembler;
 mod helpers;
 mod types;
-mod symbol_table;
+pub mod symbol_table;
 pub mod compiler;
+pub mod peephole;
+pub mod profile;
+pub mod report;
 pub mod vm;
 
 pub use compiler::*;
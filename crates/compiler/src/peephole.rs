@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use parser::lexer::token::Span;
+
+use crate::{make, unmake, Arg, Bytes, CompileError, OpCode, SourceMap};
+
+struct Instruction {
+    opcode: OpCode,
+    args: Vec<Arg>,
+    target: Option<usize>, // index of the instruction a jump lands on, the instruction count for the end
+    span: Option<Span>,
+}
+
+fn decode(bytes: &Bytes, source_map: &SourceMap) -> Result<Vec<Instruction>, CompileError> {
+    let mut addrs = Vec::new();
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (opcode, args, bytes_read) = unmake(bytes, offset)?;
+        addrs.push(offset);
+        decoded.push((opcode, args, source_map.lookup(offset)));
+        offset += bytes_read;
+    }
+    addrs.push(offset);
+
+    decoded
+        .into_iter()
+        .map(|(opcode, args, span)| {
+            let target = match (opcode.is_jump(), args.as_slice()) {
+                (true, [Arg::U16(addr)]) => Some(addrs.binary_search(&(*addr as usize))
+                    .map_err(|_| CompileError(format!("Jump to {addr} doesn't land on an instruction")))?),
+                _ => None,
+            };
+            Ok(Instruction { opcode, args, target, span })
+        })
+        .collect()
+}
+
+fn encode(instructions: &[Instruction]) -> Result<(Bytes, SourceMap), CompileError> {
+    let mut addrs = Vec::with_capacity(instructions.len() + 1);
+    let mut addr = 0;
+    for instruction in instructions {
+        addrs.push(addr);
+        addr += 1 + instruction.opcode.get_arg_widths().iter().map(|width| *width as usize).sum::<usize>();
+    }
+    addrs.push(addr);
+
+    let mut bytes = Vec::with_capacity(addr);
+    let mut source_map = SourceMap::default();
+    for instruction in instructions {
+        if let Some(span) = instruction.span {
+            source_map.add(bytes.len(), span);
+        }
+        match instruction.target {
+            Some(target) => bytes.extend(make(instruction.opcode, &[Arg::U16(addrs[target] as u16)])?),
+            None => bytes.extend(make(instruction.opcode, &instruction.args)?),
+        }
+    }
+    Ok((bytes, source_map))
+}
+
+/// Which instructions can go, found in one pass: jumps to the next instruction and nulls popped right away.
+fn find_removable(instructions: &[Instruction]) -> Vec<bool> {
+    let targets: HashSet<usize> = instructions.iter().filter_map(|instruction| instruction.target).collect();
+    let mut removable = vec![false; instructions.len()];
+    let mut idx = 0;
+    while idx < instructions.len() {
+        match instructions[idx].opcode {
+            // A jump to the next instruction does nothing
+            OpCode::JP if instructions[idx].target == Some(idx + 1) => removable[idx] = true,
+            // Pushing null only to pop it does nothing either, unless another path jumps to the pop with a value of
+            // its own. The program's final pop is kept, it sets the VM's last popped value.
+            OpCode::Null if idx + 2 < instructions.len() && instructions[idx + 1].opcode == OpCode::Pop && !targets.contains(&(idx + 1)) => {
+                removable[idx] = true;
+                removable[idx + 1] = true;
+                idx += 1;
+            },
+            _ => {},
+        }
+        idx += 1;
+    }
+    removable
+}
+
+/// Post-compilation pass dropping jumps to the next instruction and nulls that are popped right away, with every
+/// jump target and source position adjusted for the removed bytes.
+pub fn optimize(bytes: &Bytes, source_map: &SourceMap) -> Result<(Bytes, SourceMap), CompileError> {
+    let instructions = decode(bytes, source_map)?;
+    let removable = find_removable(&instructions);
+
+    // New index of each instruction, a removed one maps to the instruction after it so jumps to it land there
+    let mut new_idx = Vec::with_capacity(instructions.len() + 1);
+    let mut kept = 0;
+    for removed in &removable {
+        new_idx.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    new_idx.push(kept);
+
+    let instructions: Vec<Instruction> = instructions
+        .into_iter()
+        .zip(removable)
+        .filter(|(_, removed)| !removed)
+        .map(|(instruction, _)| Instruction { target: instruction.target.map(|target| new_idx[target]), ..instruction })
+        .collect();
+    encode(&instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(instructions: &[(OpCode, &[Arg])]) -> Bytes {
+        instructions.iter().flat_map(|(opcode, args)| make(*opcode, args).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_optimize() {
+        // if (true) { 1 } else { }; 2
+        let bytes = assemble(&[
+            (OpCode::True, &[]),
+            (OpCode::JPFalse, &[Arg::U16(10)]),
+            (OpCode::Constant, &[Arg::U16(0)]),
+            (OpCode::JP, &[Arg::U16(10)]),
+            (OpCode::Pop, &[]),
+            (OpCode::Null, &[]),
+            (OpCode::Pop, &[]),
+            (OpCode::Constant, &[Arg::U16(1)]),
+            (OpCode::Pop, &[]),
+        ]);
+        let span = Span { line: 1, col: 31 };
+        let mut source_map = SourceMap::default();
+        source_map.add(13, span);
+        let (optimized, source_map) = optimize(&bytes, &source_map).unwrap();
+        assert_eq!(optimized, assemble(&[
+            (OpCode::True, &[]),
+            (OpCode::JPFalse, &[Arg::U16(7)]),
+            (OpCode::Constant, &[Arg::U16(0)]),
+            (OpCode::Pop, &[]),
+            (OpCode::Constant, &[Arg::U16(1)]),
+            (OpCode::Pop, &[]),
+        ]));
+        assert_eq!((source_map.lookup(7), source_map.lookup(8)), (None, Some(span)));
+
+        // The null's pop is also the landing spot of the consequence, and the final pop always stays
+        let bytes = assemble(&[
+            (OpCode::True, &[]),
+            (OpCode::JPFalse, &[Arg::U16(10)]),
+            (OpCode::Constant, &[Arg::U16(0)]),
+            (OpCode::JP, &[Arg::U16(11)]),
+            (OpCode::Null, &[]),
+            (OpCode::Pop, &[]),
+            (OpCode::Null, &[]),
+            (OpCode::Pop, &[]),
+        ]);
+        assert_eq!(optimize(&bytes, &SourceMap::default()).unwrap().0, bytes);
+
+        assert!(optimize(&assemble(&[(OpCode::JP, &[Arg::U16(1)]), (OpCode::Pop, &[])]), &SourceMap::default()).is_err());
+    }
+}
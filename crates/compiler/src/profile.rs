@@ -0,0 +1,112 @@
+use std::{collections::BTreeMap, fmt};
+
+use parser::lexer::token::Span;
+
+use crate::{ByteCode, OpCode};
+
+/// How many hot spots a profile keeps.
+const HOT_SPOTS: usize = 10;
+
+/// An instruction that ran often, and the source it was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub offset: usize,
+    pub opcode: OpCode,
+    pub count: usize,
+    pub span: Option<Span>,
+}
+
+/// What a VM run with profiling on executed, from [`crate::vm::VM::profile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub total: usize, // instructions executed
+    pub opcodes: Vec<(OpCode, usize)>, // (opcode, times executed), most executed first
+    pub hot_spots: Vec<HotSpot>, // most executed offsets first, at most `HOT_SPOTS`
+}
+
+impl Profile {
+    /// `counts` holds the opcode at each offset executed and how many times it was.
+    pub fn new(bytecode: &ByteCode, counts: &BTreeMap<usize, (OpCode, usize)>) -> Self {
+        let mut opcodes: Vec<(OpCode, usize)> = Vec::new();
+        for (opcode, count) in counts.values() {
+            match opcodes.iter_mut().find(|(seen, _)| seen == opcode) {
+                Some((_, total)) => *total += count,
+                None => opcodes.push((*opcode, *count)),
+            }
+        }
+        opcodes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        // Sorting is stable, so equally hot offsets stay in bytecode order
+        let mut hot_spots = counts
+            .iter()
+            .map(|(offset, (opcode, count))| HotSpot { offset: *offset, opcode: *opcode, count: *count, span: bytecode.source_map.lookup(*offset) })
+            .collect::<Vec<HotSpot>>();
+        hot_spots.sort_by_key(|hot_spot| std::cmp::Reverse(hot_spot.count));
+        hot_spots.truncate(HOT_SPOTS);
+
+        Self { total: counts.values().map(|(_, count)| count).sum(), opcodes, hot_spots }
+    }
+
+    /// The report `Display` prints, with the line of `src` each hot spot was compiled from when it's given.
+    pub fn report(&self, src: Option<&str>) -> String {
+        let mut out = format!("executed: {} instructions\nopcodes:\n", self.total);
+        for (opcode, count) in &self.opcodes {
+            out += &format!("  {:<12} {count:>8}\n", format!("{opcode:?}"));
+        }
+        out += "hot spots:\n";
+        for hot_spot in &self.hot_spots {
+            out += &format!("  {:04} {:<12} {:>8}", hot_spot.offset, format!("{:?}", hot_spot.opcode), hot_spot.count);
+            if let Some(span) = hot_spot.span {
+                out += &format!("  {span}");
+                if let Some(line) = src.and_then(|src| src.lines().nth(span.line as usize - 1)) {
+                    out += &format!(": {}", line.trim());
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.report(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use crate::{vm::VM, Compiler};
+
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let src = "let x = [1, 2][0];\nlet y = x + x;\ny * 2";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let vm = VM::new(Compiler::new().compile_program(&program).unwrap());
+        assert_eq!(vm.profile(), None);
+
+        vm.set_profiling(true);
+        vm.run().unwrap();
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.total, 14);
+        assert_eq!(profile.opcodes[0], (OpCode::Constant, 4));
+        assert_eq!(profile.opcodes.iter().map(|(_, count)| count).sum::<usize>(), profile.total);
+        assert_eq!(profile.hot_spots.len(), 10);
+        assert_eq!(profile.hot_spots[0], HotSpot { offset: 0, opcode: OpCode::Constant, count: 1, span: Some(Span { line: 1, col: 10 }) });
+
+        let report = profile.report(Some(src));
+        assert!(report.starts_with("executed: 14 instructions\nopcodes:\n  Constant            4\n"), "{report}");
+        assert!(report.contains("  0000 Constant            1  line 1, column 10: let x = [1, 2][0];\n"), "{report}");
+        assert!(!profile.to_string().contains("let x"));
+
+        // Turning profiling back on starts counting afresh
+        vm.set_profiling(true);
+        assert_eq!(vm.profile().unwrap().total, 0);
+        vm.set_profiling(false);
+        assert_eq!(vm.profile(), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::{unmake, ByteCode, CompileError, OpCode};
+
+/// Where the bytes of compiled bytecode go, to find what makes a program large.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeReport {
+    pub total_bytes: usize,
+    pub unoptimized_bytes: Option<usize>, // size before the peephole pass, when it was compiled both ways
+    pub opcodes: Vec<(OpCode, usize, usize)>, // (opcode, count, bytes), largest first
+    pub constants: Vec<(&'static str, usize)>, // (type, count), most common first
+    pub constant_string_bytes: usize,
+}
+
+impl SizeReport {
+    pub fn new(bytecode: &ByteCode) -> Result<Self, CompileError> {
+        let mut opcodes: Vec<(OpCode, usize, usize)> = Vec::new();
+        let mut offset = 0;
+        while offset < bytecode.bytes.len() {
+            let (opcode, _, len) = unmake(&bytecode.bytes, offset)?;
+            match opcodes.iter_mut().find(|(seen, ..)| *seen == opcode) {
+                Some((_, count, bytes)) => {
+                    *count += 1;
+                    *bytes += len;
+                },
+                None => opcodes.push((opcode, 1, len)),
+            }
+            offset += len;
+        }
+        opcodes.sort_by_key(|(_, _, bytes)| std::cmp::Reverse(*bytes));
+
+        let mut constants: Vec<(&'static str, usize)> = Vec::new();
+        let mut constant_string_bytes = 0;
+        for constant in &bytecode.constants {
+            if let object::Object::String(s) = constant {
+                constant_string_bytes += s.len();
+            }
+            match constants.iter_mut().find(|(typ, _)| *typ == constant.type_name()) {
+                Some((_, count)) => *count += 1,
+                None => constants.push((constant.type_name(), 1)),
+            }
+        }
+        constants.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        Ok(Self { total_bytes: bytecode.bytes.len(), unoptimized_bytes: None, opcodes, constants, constant_string_bytes })
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Function bodies aren't compiled yet, so the top level is the only unit
+        writeln!(f, "functions:")?;
+        writeln!(f, "  <top level>  {} bytes", self.total_bytes)?;
+
+        writeln!(f, "instructions:")?;
+        for (opcode, count, bytes) in &self.opcodes {
+            writeln!(f, "  {:<12} {count:>6} x {bytes:>8} bytes", format!("{opcode:?}"))?;
+        }
+
+        let total_constants = self.constants.iter().map(|(_, count)| count).sum::<usize>();
+        writeln!(f, "constants: {total_constants}")?;
+        for (typ, count) in &self.constants {
+            writeln!(f, "  {typ:<12} {count:>6}")?;
+        }
+        writeln!(f, "  string data  {} bytes", self.constant_string_bytes)?;
+
+        write!(f, "total: {} bytes", self.total_bytes)?;
+        if let Some(unoptimized) = self.unoptimized_bytes {
+            write!(f, " ({} saved by optimization)", unoptimized.saturating_sub(self.total_bytes))?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use crate::Compiler;
+
+    use super::*;
+
+    #[test]
+    fn test_size_report() {
+        let src = r#"let a = "abc"; let b = [1, 2, "de"]; if (true) { a } else { }"#;
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let report = SizeReport::new(&Compiler::new().compile_program(&program).unwrap()).unwrap();
+
+        // `a` is propagated into the folded `if`, so "abc" is a constant twice
+        assert_eq!(report.constants, [("str", 3), ("int", 2)]);
+        assert_eq!(report.constant_string_bytes, 8);
+        assert_eq!(report.opcodes.iter().map(|(_, _, bytes)| bytes).sum::<usize>(), report.total_bytes);
+        assert_eq!(report.opcodes[0], (OpCode::Constant, 5, 15));
+
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(false);
+        let unoptimized = compiler.compile_program(&program).unwrap();
+        assert!(unoptimized.bytes.len() > report.total_bytes);
+
+        let report = SizeReport { unoptimized_bytes: Some(unoptimized.bytes.len()), ..report };
+        assert!(report.to_string().contains(&format!("total: {} bytes (8 saved by optimization)", report.total_bytes)), "{report}");
+    }
+}
@@ -1,9 +1,17 @@
-use std::{cell::{Cell, RefCell}, collections::HashMap};
+use std::{cell::{Cell, RefCell}, collections::{hash_map::Entry, HashMap}, rc::Rc};
 
-#[derive(Debug, Clone)]
-pub struct SymbolScope(String);
+use parser::intern;
 
-#[derive(Debug, Clone)]
+/// Where a name's value lives: a global slot, a slot of the current function's frame, or the captured variables of
+/// the closure being run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Free,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Symbol {
     pub name: String,
     pub scope: SymbolScope,
@@ -11,39 +19,235 @@ pub struct Symbol {
 }
 
 impl Symbol {
-    pub fn new(name: &str, scope: &SymbolScope, idx: u16) -> Self {
+    pub fn new(name: &str, scope: SymbolScope, idx: u16) -> Self {
         Self {
             name: name.to_string(),
-            scope: scope.clone(),
+            scope,
             idx,
         }
     }
 }
 
-#[derive(Debug)]
+/// The names of a scope: the globals for the outermost table, a function's locals for a table enclosed in the table
+/// of the code around the function.
+#[derive(Debug, Clone)]
 pub struct SymbolTable {
-    store: RefCell<HashMap<String, Symbol>>,
+    outer: Option<Rc<SymbolTable>>,
+    store: RefCell<HashMap<intern::Symbol, Symbol>>, // keyed by name
     num_defs: Cell<u16>,
+    slot_names: RefCell<Vec<String>>, // the name each slot was defined for, by index
+    free_symbols: RefCell<Vec<Symbol>>, // the enclosing scopes' symbols this one captures, by free index
+    blocks: RefCell<Vec<HashMap<intern::Symbol, Option<Symbol>>>>, // for each block entered, what its names hid
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
+            outer: None,
             store: RefCell::new(HashMap::new()),
             num_defs: Cell::new(0),
+            slot_names: RefCell::new(Vec::new()),
+            free_symbols: RefCell::new(Vec::new()),
+            blocks: RefCell::new(Vec::new()),
         }
     }
 
+    /// The table of a function defined in the scope of `outer`.
+    pub fn new_enclosed(outer: Rc<SymbolTable>) -> Self {
+        Self { outer: Some(outer), ..Self::new() }
+    }
+
+    pub fn outer(&self) -> Option<&Rc<SymbolTable>> {
+        self.outer.as_ref()
+    }
+
+    /// Defines `name` in this scope, a global in the outermost table and a local otherwise. Defining a name again
+    /// keeps its slot, unless it's the first definition in a block, which takes a new one.
     pub fn define(&self, name: &str) -> u16 {
+        match self.outer {
+            Some(_) => self.define_local(name).idx,
+            None => self.define_in(name, SymbolScope::Global).idx,
+        }
+    }
+
+    /// Defines `name` as a local of the function this table is for.
+    pub fn define_local(&self, name: &str) -> Symbol {
+        self.define_in(name, SymbolScope::Local)
+    }
+
+    fn define_in(&self, name: &str, scope: SymbolScope) -> Symbol {
         let mut store = self.store.borrow_mut();
+        let key = intern::Symbol::from(name);
+        let first_in_block = self.blocks.borrow_mut().last_mut().is_some_and(|hidden| match hidden.entry(key.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(store.get(name).cloned());
+                true
+            },
+            Entry::Occupied(_) => false,
+        });
+        if let Some(symbol) = store.get(name).filter(|symbol| symbol.scope == scope && !first_in_block) {
+            return symbol.clone();
+        }
         let num_defs = self.num_defs.get();
-        store.insert(name.to_string(), Symbol::new(name, &SymbolScope("Global".to_string()), num_defs));
+        let symbol = Symbol::new(name, scope, num_defs);
+        store.insert(key, symbol.clone());
         self.num_defs.set(num_defs + 1);
+        self.slot_names.borrow_mut().push(name.to_string());
+
+        symbol
+    }
 
-        num_defs
+    /// Captures `original`, a local (or free variable) of an enclosing function, as the next free variable of this
+    /// one. Free variables don't take a slot of the frame, so `num_defs` doesn't count them.
+    pub fn define_free(&self, original: Symbol) -> Symbol {
+        let mut free_symbols = self.free_symbols.borrow_mut();
+        let symbol = Symbol::new(&original.name, SymbolScope::Free, free_symbols.len() as u16);
+        free_symbols.push(original);
+        self.store.borrow_mut().insert(intern::Symbol::from(symbol.name.as_str()), symbol.clone());
+        symbol
+    }
+
+    /// Starts a block, the names defined until it's left get slots of their own and go out of scope with it.
+    pub fn enter_block(&self) {
+        self.blocks.borrow_mut().push(HashMap::new());
+    }
+
+    /// Ends the innermost block, the names it defined resolve to what they did before it again.
+    pub fn leave_block(&self) {
+        let Some(hidden) = self.blocks.borrow_mut().pop() else { return };
+        let mut store = self.store.borrow_mut();
+        for (key, symbol) in hidden {
+            match symbol {
+                Some(symbol) => store.insert(key, symbol),
+                None => store.remove(&key),
+            };
+        }
+    }
+
+    /// How many blocks deep the names being defined are, 0 outside any.
+    pub fn block_depth(&self) -> usize {
+        self.blocks.borrow().len()
+    }
+
+    /// Looks `name` up in this scope then the enclosing ones. Locals of an enclosing function are captured on the
+    /// way, so they resolve as free variables of this one.
+    pub fn resolve_symbol(&self, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.store.borrow().get(name) {
+            return Some(symbol.clone());
+        }
+        let symbol = self.outer.as_ref()?.resolve_symbol(name)?;
+        match symbol.scope {
+            SymbolScope::Global => Some(symbol),
+            SymbolScope::Local | SymbolScope::Free => Some(self.define_free(symbol)),
+        }
     }
 
     pub fn resolve(&self, name: &str) -> Option<u16> {
-        Some(self.store.borrow().get(name)?.idx)
+        Some(self.resolve_symbol(name)?.idx)
+    }
+
+    /// How many names are defined in this scope, one past the highest index: the globals of the outermost table, the
+    /// frame slots of a function's.
+    pub fn num_defs(&self) -> u16 {
+        self.num_defs.get()
+    }
+
+    /// The name each slot was defined for, by index, including the slots of blocks that have ended.
+    pub fn slot_names(&self) -> Vec<String> {
+        self.slot_names.borrow().clone()
+    }
+
+    /// The symbols of the enclosing scopes captured so far, in the order of their free indexes: what a closure over
+    /// this function has to load.
+    pub fn free_symbols(&self) -> Vec<Symbol> {
+        self.free_symbols.borrow().clone()
+    }
+
+    /// Every defined name with its index, in definition order.
+    pub fn symbols(&self) -> Vec<(String, u16)> {
+        let store = self.store.borrow();
+        let mut symbols: Vec<(String, u16)> = store.values().filter(|symbol| symbol.scope != SymbolScope::Free).map(|symbol| (symbol.name.clone(), symbol.idx)).collect();
+        symbols.sort_by_key(|(_, idx)| *idx);
+        symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_define_and_resolve() {
+        let global = Rc::new(SymbolTable::new());
+        assert_eq!(global.define("a"), 0);
+        assert_eq!(global.define("b"), 1);
+        assert_eq!(global.define("a"), 0);
+        assert_eq!(global.resolve_symbol("b"), Some(Symbol::new("b", SymbolScope::Global, 1)));
+        assert_eq!(global.resolve("c"), None);
+
+        let local = SymbolTable::new_enclosed(Rc::clone(&global));
+        assert_eq!(local.define("c"), 0);
+        assert_eq!(local.define_local("a"), Symbol::new("a", SymbolScope::Local, 1));
+        assert_eq!(local.resolve_symbol("a").map(|symbol| symbol.scope), Some(SymbolScope::Local));
+        assert_eq!(local.resolve_symbol("b"), Some(Symbol::new("b", SymbolScope::Global, 1)));
+        assert_eq!(local.num_defs(), 2);
+        assert_eq!(global.num_defs(), 2);
+        assert!(local.free_symbols().is_empty());
+    }
+
+    #[test]
+    fn test_free_variables() {
+        // let g = 1; fn(a) { let b = 2; fn(c) { fn(d) { g + a + b + c + d } } }
+        let global = Rc::new(SymbolTable::new());
+        global.define("g");
+        let outer = Rc::new(SymbolTable::new_enclosed(Rc::clone(&global)));
+        outer.define("a");
+        outer.define("b");
+        let middle = Rc::new(SymbolTable::new_enclosed(Rc::clone(&outer)));
+        middle.define("c");
+        let inner = SymbolTable::new_enclosed(Rc::clone(&middle));
+        inner.define("d");
+
+        let resolved = ["g", "a", "b", "c", "d"].map(|name| inner.resolve_symbol(name).unwrap());
+        assert_eq!(resolved, [
+            Symbol::new("g", SymbolScope::Global, 0),
+            Symbol::new("a", SymbolScope::Free, 0),
+            Symbol::new("b", SymbolScope::Free, 1),
+            Symbol::new("c", SymbolScope::Free, 2),
+            Symbol::new("d", SymbolScope::Local, 0),
+        ]);
+        // The inner fn captures from the middle one, which captures from the outer one in turn
+        assert_eq!(inner.free_symbols(), [
+            Symbol::new("a", SymbolScope::Free, 0),
+            Symbol::new("b", SymbolScope::Free, 1),
+            Symbol::new("c", SymbolScope::Local, 0),
+        ]);
+        assert_eq!(middle.free_symbols(), [Symbol::new("a", SymbolScope::Local, 0), Symbol::new("b", SymbolScope::Local, 1)]);
+        assert!(outer.free_symbols().is_empty());
+
+        // Captured once, resolving again finds the free variable
+        assert_eq!(inner.resolve_symbol("a"), Some(Symbol::new("a", SymbolScope::Free, 0)));
+        assert_eq!(inner.free_symbols().len(), 3);
+        assert_eq!((inner.num_defs(), inner.symbols()), (1, vec![("d".to_string(), 0)]));
+        assert_eq!(inner.resolve("missing"), None);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let global = SymbolTable::new();
+        assert_eq!(global.define("a"), 0);
+        global.enter_block();
+        assert_eq!((global.define("a"), global.define("b"), global.define("a")), (1, 2, 1));
+        assert_eq!(global.block_depth(), 1);
+        global.leave_block();
+        assert_eq!((global.resolve("a"), global.resolve("b")), (Some(0), None));
+        assert_eq!((global.num_defs(), global.symbols()), (3, vec![("a".to_string(), 0)]));
+        assert_eq!(global.slot_names(), ["a", "a", "b"]);
     }
 }
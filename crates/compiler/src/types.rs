@@ -1,6 +1,10 @@
-use std::ops::{Add, Div, Mul, Sub};
+use std::collections::HashSet;
 
-use crate::helpers::binary_helpers;
+use crate::{helpers::binary_helpers, unmake};
+
+pub use object::Object;
+use object::EvalError;
+use parser::lexer::token::Span;
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -10,13 +14,30 @@ pub struct CompileError(pub String);
 #[derive(Debug)]
 pub struct RuntimeError(pub String);
 
+impl From<EvalError> for RuntimeError {
+    fn from(err: EvalError) -> Self {
+        RuntimeError(err.0)
+    }
+}
+
+impl RuntimeError {
+    pub fn is_budget_exceeded(&self) -> bool {
+        EvalError(self.0.clone()).is_budget_exceeded()
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        EvalError(self.0.clone()).is_interrupted()
+    }
+}
+
 pub type Bytes = Vec<u8>;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Arg {
     U8(u8),
-    U16(u16)
+    U16(u16),
+    U32(u32),
 }
 
 impl Arg {
@@ -24,6 +45,7 @@ impl Arg {
         match self {
             Self::U8(_) => 8,
             Self::U16(_) => 16,
+            Self::U32(_) => 32,
         }
     }
 
@@ -42,6 +64,14 @@ impl Arg {
         let val = binary_helpers::combine_bytes(bytes[offset], bytes[offset + 1]);
         Ok((Arg::U16(val), val))
     }
+
+    pub fn read_u32(bytes: &Bytes, offset: usize) -> Result<(Arg, u32), CompileError> {
+        let Some(val) = bytes.get(offset..offset + 4) else {
+            return Err(CompileError(format!("read_u32: offset: {} larger than bytes size: {}", offset, bytes.len())))
+        };
+        let val = u32::from_be_bytes([val[0], val[1], val[2], val[3]]);
+        Ok((Arg::U32(val), val))
+    }
 }
 
 // impl Add for Arg {
@@ -56,155 +86,172 @@ impl Arg {
 //     }
 // }
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum OpCode {
-    Constant = 0,
-    Pop = 1,
-    Add = 2,
-    Sub = 3,
-    Mul = 4,
-    Div = 5,
-    True = 6,
-    False = 7,
-    Eq = 8,
-    NEq = 9,
-    GT = 10,
-    LT = 11,
-    Minus = 12,
-    Exclam = 13,
-    JP = 14,
-    JPTrue = 15,
-    JPFalse = 16,
-    Null = 17,
-    GetGlobal = 18,
-    SetGlobal = 19,
-}
+/// Declares the opcodes from one table of `Variant = byte, "name", [arg widths]` rows, so the enum, the decoding of
+/// bytes and the arg widths can't drift apart when an opcode is added.
+macro_rules! opcodes {
+    ($($variant:ident = $byte:literal, $name:literal, [$($width:literal),*];)*) => {
+        #[repr(u8)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum OpCode {
+            $($variant = $byte,)*
+        }
 
-impl OpCode {
-    pub fn get_arg_widths(&self) -> Vec<u8> {
-        match self {
-            Self::Constant => vec![2],
-            Self::Pop => vec![],
-            Self::Add => vec![],
-            Self::Sub => vec![],
-            Self::Mul => vec![],
-            Self::Div => vec![],
-            Self::True => vec![],
-            Self::False => vec![],
-            Self::Eq => vec![],
-            Self::NEq => vec![],
-            Self::GT => vec![],
-            Self::LT => vec![],
-            Self::Minus => vec![],
-            Self::Exclam => vec![],
-            Self::JP => vec![2],
-            Self::JPTrue => vec![2],
-            Self::JPFalse => vec![2],
-            Self::Null => vec![],
-            Self::SetGlobal => vec![2],
-            Self::GetGlobal => vec![2],
+        impl OpCode {
+            pub const ALL: &[OpCode] = &[$(Self::$variant,)*];
 
-        }
-    }
+            /// The mnemonic disassembly shows the opcode with, and the assembler reads.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
 
-    pub fn from_byte(opcode: u8) -> Result<Self, CompileError> {
-        match opcode {
-            _ if opcode == Self::Constant as u8 => Ok(Self::Constant),
-            _ if opcode == Self::Pop as u8 => Ok(Self::Pop),
-            _ if opcode == Self::Add as u8 => Ok(Self::Add),
-            _ if opcode == Self::Sub as u8 => Ok(Self::Sub),
-            _ if opcode == Self::Mul as u8 => Ok(Self::Mul),
-            _ if opcode == Self::Div as u8 => Ok(Self::Div),
-            _ if opcode == Self::True as u8 => Ok(Self::True),
-            _ if opcode == Self::False as u8 => Ok(Self::False),
-            _ if opcode == Self::Eq as u8 => Ok(Self::Eq),
-            _ if opcode == Self::NEq as u8 => Ok(Self::NEq),
-            _ if opcode == Self::GT as u8 => Ok(Self::GT),
-            _ if opcode == Self::LT as u8 => Ok(Self::LT),
-            _ if opcode == Self::Minus as u8 => Ok(Self::Minus),
-            _ if opcode == Self::Exclam as u8 => Ok(Self::Exclam),
-            _ if opcode == Self::JP as u8 => Ok(Self::JP),
-            _ if opcode == Self::JPTrue as u8 => Ok(Self::JPTrue),
-            _ if opcode == Self::JPFalse as u8 => Ok(Self::JPFalse),
-            _ if opcode == Self::Null as u8 => Ok(Self::Null),
-            _ if opcode == Self::SetGlobal as u8 => Ok(Self::SetGlobal),
-            _ if opcode == Self::GetGlobal as u8 => Ok(Self::GetGlobal),
-            _ => Err(CompileError(format!("Unknown opcode: {opcode}")))
+            pub fn get_arg_widths(&self) -> Vec<u8> {
+                match self {
+                    $(Self::$variant => vec![$($width),*],)*
+                }
+            }
+
+            pub fn from_byte(opcode: u8) -> Result<Self, CompileError> {
+                match opcode {
+                    $($byte => Ok(Self::$variant),)*
+                    _ => Err(CompileError(format!("Unknown opcode: {opcode}")))
+                }
+            }
         }
-    }
+    };
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Hash)]
-pub enum Object {
-    Integer(isize),
-    Boolean(bool),
-    String(String),
-    Array(Vec<Self>),
-    KVPair(Box<Self>, Box<Self>),
-    Return(Box<Self>),
-    Null,
-
-    BuiltIn(fn(Vec<Object>) -> Result<Object, CompileError>)
+opcodes! {
+    Constant = 0, "CONST", [2];
+    Pop = 1, "POP", [];
+    Add = 2, "ADD", [];
+    Sub = 3, "SUB", [];
+    Mul = 4, "MUL", [];
+    Div = 5, "DIV", [];
+    True = 6, "TRUE", [];
+    False = 7, "FALSE", [];
+    Eq = 8, "EQ", [];
+    NEq = 9, "NEQ", [];
+    GT = 10, "GT", [];
+    LT = 11, "LT", [];
+    Minus = 12, "NEG", [];
+    Exclam = 13, "NOT", [];
+    JP = 14, "JUMP", [2];
+    JPTrue = 15, "JUMP_TRUE", [2];
+    JPFalse = 16, "JUMP_FALSE", [2];
+    Null = 17, "NULL", [];
+    GetGlobal = 18, "GET_GLOBAL", [2];
+    SetGlobal = 19, "SET_GLOBAL", [2];
+    Array = 20, "ARRAY", [2];
+    Index = 21, "INDEX", [];
+    Slice = 22, "SLICE", [];
+    Tuple = 23, "TUPLE", [2];
+    Destructure = 24, "DESTRUCTURE", [2];
+    BitAnd = 25, "BIT_AND", [];
+    BitOr = 26, "BIT_OR", [];
+    BitXor = 27, "BIT_XOR", [];
+    ShiftL = 28, "SHL", [];
+    ShiftR = 29, "SHR", [];
+    Range = 30, "RANGE", [];
+    JPNotNull = 31, "JUMP_NOT_NULL", [2];
+    ConstantWide = 32, "CONST_WIDE", [4];
+    Pow = 33, "POW", [];
 }
 
-impl Object {
-    pub fn is_truthy(&self) -> bool {
-        match self {
-            Self::Boolean(val) => *val,
-            Self::Integer(val) => *val != 0,
-            _ => false,
-        }
+impl OpCode {
+    pub fn is_jump(&self) -> bool {
+        matches!(self, Self::JP | Self::JPTrue | Self::JPFalse | Self::JPNotNull)
     }
 }
 
-impl Add for Object {
-    type Output = Result<Self, RuntimeError>;
-    
-    fn add(self, rhs: Self) -> Self::Output {
-        match (&self, &rhs) {
-            (Self::Integer(x), Self::Integer(y)) => Ok(Self::Integer(x + y)),
-            _ => Err(RuntimeError(format!("Invalid addition: {:?} + {:?}", self, rhs))),
+pub type Constants = Vec<Object>;
+
+/// Source positions of instructions as (byte offset, span) pairs sorted by offset, each covering the instructions
+/// up to the next entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap(Vec<(usize, Span)>);
+
+impl SourceMap {
+    pub fn add(&mut self, offset: usize, span: Span) {
+        if self.0.last().is_none_or(|(_, last)| *last != span) {
+            self.0.push((offset, span));
         }
     }
-}
 
-impl Sub for Object {
-    type Output = Result<Self, RuntimeError>;
+    /// Forgets the positions of everything from `offset` on.
+    pub fn truncate(&mut self, offset: usize) {
+        self.0.retain(|(start, _)| *start < offset);
+    }
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        match (&self, &rhs) {
-            (Self::Integer(x), Self::Integer(y)) => Ok(Self::Integer(x - y)),
-            _ => Err(RuntimeError(format!("Invalid subtraction: {:?} - {:?}", self, rhs))),
-        }
+    pub fn lookup(&self, offset: usize) -> Option<Span> {
+        let idx = self.0.partition_point(|(start, _)| *start <= offset);
+        idx.checked_sub(1).map(|idx| self.0[idx].1)
     }
 }
 
-impl Mul for Object {
-    type Output = Result<Self, RuntimeError>;
+#[derive(Debug, Clone)]
+pub struct ByteCode {
+    pub bytes: Bytes,
+    pub constants: Constants,
+    pub constants_base: usize, // pool index of `constants[0]`, non-zero for deltas that extend an earlier pool
+    pub globals: usize, // global slots the bytecode uses, the VM allocates at least as many
+    pub global_names: Vec<String>, // the name bound in each global slot, for errors, empty when not compiled
+    pub source_map: SourceMap,
+}
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        match (&self, &rhs) {
-            (Self::Integer(x), Self::Integer(y)) => Ok(Self::Integer(x * y)),
-            _ => Err(RuntimeError(format!("Invalid multiplication: {:?} * {:?}", self, rhs))),
+impl ByteCode {
+    /// Checks bytecode that may not come from the compiler before it's run: every instruction must decode, constant
+    /// indexes must be in the pool, global indexes below `globals` and jumps must land on an instruction or the end.
+    pub fn validate(&self) -> Result<(), CompileError> {
+        let pool = self.constants_base + self.constants.len();
+        let mut starts = HashSet::new();
+        let mut jumps = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let (opcode, args, len) = unmake(&self.bytes, offset).map_err(|err| CompileError(format!("Invalid instruction at {offset:04}: {}", err.0)))?;
+            starts.insert(offset);
+            let arg = match args.first() {
+                Some(Arg::U8(arg)) => *arg as usize,
+                Some(Arg::U16(arg)) => *arg as usize,
+                Some(Arg::U32(arg)) => *arg as usize,
+                None => 0,
+            };
+            match opcode {
+                OpCode::Constant | OpCode::ConstantWide if arg >= pool => {
+                    return Err(CompileError(format!("Constant {arg} at {offset:04} is past the {pool} constants of the pool")));
+                },
+                OpCode::SetGlobal | OpCode::GetGlobal if arg >= self.globals => {
+                    return Err(CompileError(format!("Global {arg} at {offset:04} is past the {} globals the bytecode declares", self.globals)));
+                },
+                _ if opcode.is_jump() => jumps.push((offset, arg)),
+                _ => {},
+            }
+            offset += len;
+        }
+
+        match jumps.into_iter().find(|(_, target)| *target != self.bytes.len() && !starts.contains(target)) {
+            Some((offset, target)) => Err(CompileError(format!("Jump at {offset:04} to {target:04}, which isn't the start of an instruction"))),
+            None => Ok(()),
         }
     }
 }
 
-impl Div for Object {
-    type Output = Result<Self, RuntimeError>;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn div(self, rhs: Self) -> Self::Output {
-        match (&self, &rhs) {
-            (Self::Integer(x), Self::Integer(y)) => Ok(Self::Integer(x / y)),
-            _ => Err(RuntimeError(format!("Invalid division: {:?} / {:?}", self, rhs))),
+    #[test]
+    fn test_opcode_table() {
+        for opcode in OpCode::ALL {
+            assert_eq!(OpCode::from_byte(*opcode as u8).unwrap(), *opcode);
         }
+        assert_eq!(OpCode::ALL.len(), OpCode::Pow as usize + 1);
+        assert!(OpCode::from_byte(OpCode::ALL.len() as u8).is_err());
+        let names: HashSet<&str> = OpCode::ALL.iter().map(|opcode| opcode.name()).collect();
+        assert_eq!(names.len(), OpCode::ALL.len());
+        assert_eq!((OpCode::LT.name(), OpCode::LT.get_arg_widths()), ("LT", vec![]));
+        assert_eq!((OpCode::JPNotNull.name(), OpCode::JPNotNull.get_arg_widths()), ("JUMP_NOT_NULL", vec![2]));
+        assert_eq!(OpCode::ConstantWide.get_arg_widths(), vec![4]);
     }
 }
-
-pub type Constants = Vec<Object>;
-#[derive(Debug)]
-pub struct ByteCode {
-    pub bytes: Bytes,
-    pub constants: Constants
-}
@@ -1,6 +1,9 @@
-use std::cell::{Cell, RefCell};
+use std::{cell::{Cell, RefCell}, collections::{BTreeMap, BTreeSet}, rc::Rc, sync::{atomic::{AtomicBool, Ordering}, Arc}};
 
-use crate::{Arg, ByteCode, CompileError, Object, OpCode, RuntimeError};
+use object::{log_event, normalize_index, EvalError, HashKey, Level, Logger, FALSE, NULL, TRUE};
+use parser::lexer::token::Span;
+
+use crate::{profile::Profile, unmake, Arg, ByteCode, CompileError, Object, OpCode, RuntimeError};
 
 static STACK_SIZE: usize = 10; //2048;
 
@@ -8,157 +11,464 @@ fn map_compile_err(err: CompileError) -> RuntimeError {
     RuntimeError(format!("{:?}", err))
 }
 
+
+fn invalid_bytecode(err: CompileError) -> RuntimeError {
+    RuntimeError(format!("Invalid bytecode: {}", err.0))
+}
+
+/// An instruction executed by [`VM::step`], and the state it left the VM in. The VM has no call frames yet, so
+/// the instruction pointer and the stack are all there is to show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub offset: usize,
+    pub opcode: OpCode,
+    pub args: Vec<Arg>,
+    pub span: Option<Span>, // source of the instruction, from the source map
+    pub next: usize,        // offset of the instruction to run next
+    pub stack: Vec<Object>, // bottom first
+}
+
 pub struct VM {
     bytecode: ByteCode,
+    constants: Vec<Object>, // the bytecode's constants, after the pool of earlier deltas
     stack: RefCell<Vec<Object>>,
     sp: Cell<usize>,
     ip: Cell<usize>,
     globals: RefCell<Vec<Object>>,
-
+    last_popped: RefCell<Object>,
+    step_limit: Cell<Option<usize>>,
+    steps: Cell<usize>,
+    interrupt: RefCell<Option<Arc<AtomicBool>>>,
+    breakpoints: RefCell<BTreeSet<usize>>,
+    profile: RefCell<Option<BTreeMap<usize, (OpCode, usize)>>>, // the opcode at each offset executed and how often
+    logger: RefCell<Option<Rc<dyn Logger>>>,
 }
 
 impl VM {
     pub fn new(bytecode: ByteCode) -> Self {
+        Self::new_with_globals(bytecode, Vec::new())
+    }
+
+    /// Starts with `globals` in the first global slots, made up to as many slots as the bytecode uses. More are added
+    /// when an instruction sets one past the end.
+    pub fn new_with_globals(mut bytecode: ByteCode, mut globals: Vec<Object>) -> Self {
+        if globals.len() < bytecode.globals {
+            globals.resize(bytecode.globals, Object::Null);
+        }
         let stack = vec![Object::Null; STACK_SIZE];
         Self {
+            constants: std::mem::take(&mut bytecode.constants),
             bytecode,
             stack: RefCell::new(stack),
             sp: Cell::new(0),
             ip: Cell::new(0),
-            globals: RefCell::new(vec![Object::Null; STACK_SIZE]),
+            globals: RefCell::new(globals),
+            last_popped: RefCell::new(Object::Null),
+            step_limit: Cell::new(None),
+            steps: Cell::new(0),
+            interrupt: RefCell::new(None),
+            breakpoints: RefCell::new(BTreeSet::new()),
+            profile: RefCell::new(None),
+            logger: RefCell::new(None),
+        }
+    }
+
+    /// Like `new`, for bytecode that may not come from the compiler: it's checked with [`ByteCode::validate`] first.
+    /// Running unchecked bytecode fails with an error rather than panicking too, but only once it gets to the fault.
+    pub fn load(bytecode: ByteCode) -> Result<Self, RuntimeError> {
+        if bytecode.constants_base != 0 {
+            return Err(RuntimeError("Invalid bytecode: a delta needs the constant pool of the deltas before it, see VM::new_with_pool".to_string()));
+        }
+        bytecode.validate().map_err(invalid_bytecode)?;
+        Ok(Self::new(bytecode))
+    }
+
+    /// Runs a delta from [`crate::Compiler::compile_delta`], `pool` being the constants of the deltas run before it
+    /// (from [`VM::into_state`]), so they aren't compiled or copied again. The delta is validated like in `load`.
+    pub fn new_with_pool(mut bytecode: ByteCode, globals: Vec<Object>, mut pool: Vec<Object>) -> Result<Self, RuntimeError> {
+        if pool.len() != bytecode.constants_base {
+            return Err(RuntimeError(format!("Constant pool holds {} constants, the bytecode continues from {}", pool.len(), bytecode.constants_base)));
+        }
+        bytecode.validate().map_err(invalid_bytecode)?;
+        pool.append(&mut bytecode.constants);
+        let mut vm = Self::new_with_globals(bytecode, globals);
+        vm.constants = pool;
+        Ok(vm)
+    }
+
+    /// Receives a trace event for each instruction executed, and the stack it left. `None` (the default) logs nothing.
+    pub fn set_logger(&self, logger: Option<Rc<dyn Logger>>) {
+        *self.logger.borrow_mut() = logger;
+    }
+
+    fn trace(&self, message: impl FnOnce() -> String) {
+        log_event(self.logger.borrow().as_ref(), Level::Trace, "vm", message);
+    }
+
+    /// Caps the number of instructions `run` may execute, `None` (the default) is unlimited.
+    pub fn set_step_limit(&self, limit: Option<usize>) {
+        self.step_limit.set(limit);
+    }
+
+    /// A flag another thread can raise, a signal handler say, to stop the VM before its next instruction with
+    /// [`EvalError::interrupted`]. The VM lowers it again when it stops.
+    pub fn set_interrupt(&self, interrupt: Option<Arc<AtomicBool>>) {
+        *self.interrupt.borrow_mut() = interrupt;
+    }
+
+    /// Counts the instructions executed from now on, per offset, for [`VM::profile`]. Turning it on again starts
+    /// the counts afresh.
+    pub fn set_profiling(&self, on: bool) {
+        *self.profile.borrow_mut() = on.then(BTreeMap::new);
+    }
+
+    /// The hot-spot summary of what ran since profiling was turned on, `None` when it's off.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile.borrow().as_ref().map(|counts| Profile::new(&self.bytecode, counts))
+    }
+
+    pub fn into_globals(self) -> Vec<Object> {
+        self.globals.into_inner()
+    }
+
+    /// The globals and the constant pool, to carry over to the VM running the next delta.
+    pub fn into_state(self) -> (Vec<Object>, Vec<Object>) {
+        (self.globals.into_inner(), self.constants)
+    }
+
+    /// Rewinds to the start of the bytecode with fresh state: an empty stack, every global null and no step taken,
+    /// to run the same bytecode again without compiling or loading it again. Limits, the logger, breakpoints and
+    /// profiling are kept.
+    pub fn reset(&self) {
+        self.stack.borrow_mut().fill(Object::Null);
+        self.sp.set(0);
+        self.ip.set(0);
+        self.globals.borrow_mut().fill(Object::Null);
+        *self.last_popped.borrow_mut() = Object::Null;
+        self.steps.set(0);
+    }
+
+    /// A global read before any slot up to it was set, named when the bytecode was compiled.
+    fn unknown_global(&self, idx: u16, slots: usize) -> RuntimeError {
+        match self.bytecode.global_names.get(idx as usize) {
+            Some(name) => RuntimeError(format!("Global {name} (slot {idx}) is past the {slots} global slots")),
+            None => RuntimeError(format!("Global {idx} is past the {slots} global slots")),
+        }
+    }
+
+    /// Binds the first globals to `globals`, e.g. the host's bindings after a [`VM::reset`].
+    pub fn set_globals(&self, globals: &[Object]) {
+        let mut slots = self.globals.borrow_mut();
+        if slots.len() < globals.len() {
+            slots.resize(globals.len(), Object::Null);
         }
+        slots[..globals.len()].clone_from_slice(globals);
+    }
+
+    pub fn last_popped(&self) -> Object {
+        self.last_popped.borrow().clone()
     }
 
+    /// Runs to the end of the bytecode, ignoring breakpoints.
     pub fn run(&self) -> Result<(), RuntimeError> {
-         loop {
-            let mut ip = self.ip.get();
-            // println!("IP: {}", ip);
-            if ip >= self.bytecode.bytes.len() { break; }
-
-            let opcode = OpCode::from_byte(self.bytecode.bytes[ip]).map_err(|err| map_compile_err(err))?;
-
-            println!("Dbg: Executing opcode: {:?}", opcode);
-
-            match opcode {
-                OpCode::Constant => {
-                    // let idx = match Arg::read_u16(&self.bytecode.bytes, ip) {
-                    //     Ok(arg) => {
-                    //         if let Arg::U16(x) = arg { x } else { unreachable!("Arg::read_u16 must return the Arg:U16 varient!"); }
-                    //     },
-                    //     Err(err) => return Err(map_compile_err(err))
-                    // } as usize;
-                    ip += 1;
-                    let (_, idx) = Arg::read_u16(&self.bytecode.bytes, ip).map_err(map_compile_err)?;
-                    let idx = idx as usize;
-                    if idx >= self.bytecode.constants.len() {
-                        return Err(RuntimeError(format!("Attempted to access object at index {}, but objects len is {}", idx, self.bytecode.constants.len())))
-                    }
-
-                    self.push_stack(self.bytecode.constants[idx].clone())?;
-
-                    self.ip.set(ip + 2);
-                },
-                OpCode::Add => {
-                    self.perform_infix_operation(|x, y| x + y, "+")?;
-                },
-                OpCode::Sub => {
-                    self.perform_infix_operation(|x, y| x - y, "-")?;
-                },
-                OpCode::Mul => {
-                    self.perform_infix_operation(|x, y| x * y, "*")?;
-                },
-                OpCode::Div => {
-                    self.perform_infix_operation(|x, y| x / y, "/")?;
-                },
-                OpCode::Eq => {
-                    self.perform_infix_operation(|x, y| Ok(Object::Boolean(x == y)), "==")?;
-                },
-                OpCode::NEq => {
-                    self.perform_infix_operation(|x, y| Ok(Object::Boolean(x != y)), "!=")?;
-                },
-                OpCode::GT => {
-                    self.perform_infix_operation(|x, y| Ok(Object::Boolean(x > y)), ">")?;
-                },
-                OpCode::LT => {
-                    self.perform_infix_operation(|x, y| Ok(Object::Boolean(x < y)), "<")?;
-                },
-                OpCode::Minus => {
-                    let val = self.pop_stack()?;
-                    if let Object::Integer(val) = val {
-                        self.push_stack(Object::Integer(-val))?;
-                    } else {
-                        return Err(RuntimeError(format!("`-` can only be applied to Integers, got: {val:?}")));
-                    }
-
-                    self.ip.set(ip + 1);
-                },
-                OpCode::Exclam => {
-                    let val = self.pop_stack()?;
-                    match val {
-                        Object::Boolean(val) => self.push_stack(Object::Boolean(!val))?,
-                        Object::Integer(val) => self.push_stack(Object::Boolean(val == 0))?,
-                        Object::Null => self.push_stack(Object::Boolean(true))?,
-                        _ => return Err(RuntimeError(format!("`!` can only be applied to Booleans and Integers got: {val:?}"))),
-                    };
-
-                    self.ip.set(ip + 1);
-                }
-                OpCode::Pop => {
-                    self.pop_stack()?;
+        while !self.finished() {
+            self.execute_next().map_err(|err| self.locate(err))?;
+        }
+        Ok(())
+    }
+
+    /// Runs until the next breakpoint, returning its offset with the instruction there not yet executed, or `None`
+    /// once the bytecode is done. Called again it resumes from the breakpoint it stopped at.
+    pub fn run_to_breakpoint(&self) -> Result<Option<usize>, RuntimeError> {
+        let mut first = true;
+        while !self.finished() {
+            let ip = self.ip.get();
+            if !first && self.breakpoints.borrow().contains(&ip) {
+                return Ok(Some(ip));
+            }
+            first = false;
+            self.execute_next().map_err(|err| self.locate(err))?;
+        }
+        Ok(None)
+    }
 
-                    self.ip.set(ip + 1);
-                },
-                OpCode::True => {
-                    self.push_stack(Object::Boolean(true))?;
+    /// Executes exactly one instruction, `None` when there are none left.
+    pub fn step(&self) -> Result<Option<Step>, RuntimeError> {
+        if self.finished() {
+            return Ok(None);
+        }
+        let offset = self.ip.get();
+        let (opcode, args, _) = unmake(&self.bytecode.bytes, offset).map_err(map_compile_err)?;
+        self.execute_next().map_err(|err| self.locate(err))?;
+
+        Ok(Some(Step {
+            offset,
+            opcode,
+            args,
+            span: self.bytecode.source_map.lookup(offset),
+            next: self.ip.get(),
+            stack: self.stack(),
+        }))
+    }
 
-                    self.ip.set(ip + 1);
-                },
-                OpCode::False => {
-                    self.push_stack(Object::Boolean(false))?;
+    /// The values on the stack, bottom first.
+    pub fn stack(&self) -> Vec<Object> {
+        self.stack.borrow()[..self.sp.get()].to_vec()
+    }
 
-                    self.ip.set(ip + 1);
-                },
-                OpCode::Null => {
-                    self.push_stack(Object::Null)?;
+    /// Makes `run_to_breakpoint` stop before the instruction at `offset`, which must start an instruction.
+    pub fn set_breakpoint(&self, offset: usize) -> Result<(), RuntimeError> {
+        if !self.instructions().iter().any(|(start, ..)| *start == offset) {
+            return Err(RuntimeError(format!("No instruction starts at offset {offset}")));
+        }
+        self.breakpoints.borrow_mut().insert(offset);
+        Ok(())
+    }
 
-                    self.ip.set(ip + 1);
-                },
-                OpCode::JP => {
-                    self.jump()?;
-                },
-                OpCode::JPTrue => {
-                    let condition = self.pop_stack()?;
-                    if condition.is_truthy() {
-                        self.jump()?;
-                    }else {
-                        self.ip.set(ip + 3);
-                    }
-                },
-                OpCode::JPFalse => {
-                    let condition = self.pop_stack()?;
-                    if !condition.is_truthy() {
-                        self.jump()?;
-                    }else {
-                        self.ip.set(ip + 3);
-                    }
-                },
-                OpCode::SetGlobal => {
-                    let (_, idx) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
-                    self.globals.borrow_mut()[idx as usize] = self.pop_stack()?;
+    /// Whether there was a breakpoint at `offset`.
+    pub fn clear_breakpoint(&self, offset: usize) -> bool {
+        self.breakpoints.borrow_mut().remove(&offset)
+    }
 
-                    self.ip.set(ip + 3);
-                },
-                OpCode::GetGlobal => {
-                    let (_, idx) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
-                    self.push_stack(self.globals.borrow()[idx as usize].clone())?;
+    /// Every instruction of the bytecode with its offset, in order.
+    pub fn instructions(&self) -> Vec<(usize, OpCode, Vec<Arg>)> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while let Ok((opcode, args, len)) = unmake(&self.bytecode.bytes, offset) {
+            instructions.push((offset, opcode, args));
+            offset += len;
+        }
+        instructions
+    }
 
-                    self.ip.set(ip + 3);
+    pub fn breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.borrow().iter().copied().collect()
+    }
+
+    /// Offset of the next instruction to execute.
+    pub fn ip(&self) -> usize {
+        self.ip.get()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.ip.get() >= self.bytecode.bytes.len()
+    }
+
+    fn locate(&self, err: RuntimeError) -> RuntimeError {
+        match self.bytecode.source_map.lookup(self.ip.get()) {
+            Some(span) => RuntimeError(format!("{} at {span}", err.0)),
+            None => err,
+        }
+    }
+
+    fn execute_next(&self) -> Result<(), RuntimeError> {
+        let mut ip = self.ip.get();
+
+        self.steps.set(self.steps.get() + 1);
+        if let Some(limit) = self.step_limit.get().filter(|limit| self.steps.get() > *limit) {
+            return Err(EvalError::budget_exceeded(limit).into());
+        }
+        if self.interrupt.borrow().as_ref().is_some_and(|interrupt| interrupt.swap(false, Ordering::Relaxed)) {
+            return Err(EvalError::interrupted().into());
+        }
+
+        let byte = *self.bytecode.bytes.get(ip).ok_or_else(|| RuntimeError(format!("No instruction at offset {ip}, the bytecode ends at {}", self.bytecode.bytes.len())))?;
+        let opcode = OpCode::from_byte(byte).map_err(map_compile_err)?;
+        if let Some(counts) = self.profile.borrow_mut().as_mut() {
+            counts.entry(ip).or_insert((opcode, 0)).1 += 1;
+        }
+
+        self.trace(|| format!("executing {ip:04} {opcode:?}"));
+
+        match opcode {
+            OpCode::Constant | OpCode::ConstantWide => {
+                // let idx = match Arg::read_u16(&self.bytecode.bytes, ip) {
+                //     Ok(arg) => {
+                //         if let Arg::U16(x) = arg { x } else { unreachable!("Arg::read_u16 must return the Arg:U16 varient!"); }
+                //     },
+                //     Err(err) => return Err(map_compile_err(err))
+                // } as usize;
+                ip += 1;
+                let (idx, width) = match opcode {
+                    OpCode::Constant => (Arg::read_u16(&self.bytecode.bytes, ip).map_err(map_compile_err)?.1 as usize, 2),
+                    _ => (Arg::read_u32(&self.bytecode.bytes, ip).map_err(map_compile_err)?.1 as usize, 4),
+                };
+                if idx >= self.constants.len() {
+                    return Err(RuntimeError(format!("Attempted to access object at index {}, but objects len is {}", idx, self.constants.len())))
                 }
+
+                self.push_stack(self.constants[idx].clone())?;
+
+                self.ip.set(ip + width);
+            },
+            OpCode::Add => {
+                self.perform_infix_operation("+")?;
+            },
+            OpCode::Sub => {
+                self.perform_infix_operation("-")?;
+            },
+            OpCode::Mul => {
+                self.perform_infix_operation("*")?;
+            },
+            OpCode::Div => {
+                self.perform_infix_operation("/")?;
+            },
+            OpCode::Eq => {
+                self.perform_infix_operation("==")?;
+            },
+            OpCode::NEq => {
+                self.perform_infix_operation("!=")?;
+            },
+            OpCode::GT => {
+                self.perform_infix_operation(">")?;
+            },
+            OpCode::LT => {
+                self.perform_infix_operation("<")?;
+            },
+            OpCode::BitAnd => {
+                self.perform_infix_operation("&")?;
+            },
+            OpCode::BitOr => {
+                self.perform_infix_operation("|")?;
+            },
+            OpCode::BitXor => {
+                self.perform_infix_operation("^")?;
+            },
+            OpCode::Pow => {
+                self.perform_infix_operation("**")?;
+            },
+            OpCode::ShiftL => {
+                self.perform_infix_operation("<<")?;
+            },
+            OpCode::ShiftR => {
+                self.perform_infix_operation(">>")?;
+            },
+            OpCode::Range => {
+                self.perform_infix_operation("..")?;
+            },
+            OpCode::Minus => {
+                let val = self.pop_stack()?;
+                self.push_stack(val.prefix("-")?)?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::Exclam => {
+                let val = self.pop_stack()?;
+                self.push_stack(val.prefix("!")?)?;
+
+                self.ip.set(ip + 1);
             }
+            OpCode::Pop => {
+                self.pop_stack()?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::True => {
+                self.push_stack(TRUE)?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::False => {
+                self.push_stack(FALSE)?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::Null => {
+                self.push_stack(NULL)?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::JP => {
+                self.jump()?;
+            },
+            OpCode::JPTrue => {
+                let condition = self.pop_stack()?;
+                if condition.is_truthy() {
+                    self.jump()?;
+                }else {
+                    self.ip.set(ip + 3);
+                }
+            },
+            OpCode::JPFalse => {
+                let condition = self.pop_stack()?;
+                if !condition.is_truthy() {
+                    self.jump()?;
+                }else {
+                    self.ip.set(ip + 3);
+                }
+            },
+            // Keeps the value when jumping past the fallback of `??`, drops the null otherwise
+            OpCode::JPNotNull => {
+                if matches!(self.stack_top()?, Object::Null) {
+                    self.pop_stack()?;
+                    self.ip.set(ip + 3);
+                } else {
+                    self.jump()?;
+                }
+            },
+            OpCode::SetGlobal => {
+                let (_, idx) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
+                let val = self.pop_stack()?;
+                let mut globals = self.globals.borrow_mut();
+                if globals.len() <= idx as usize {
+                    globals.resize(idx as usize + 1, Object::Null);
+                }
+                globals[idx as usize] = val;
+
+                self.ip.set(ip + 3);
+            },
+            OpCode::GetGlobal => {
+                let (_, idx) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
+                let globals = self.globals.borrow();
+                let val = globals.get(idx as usize).cloned().ok_or_else(|| self.unknown_global(idx, globals.len()))?;
+                drop(globals);
+                self.push_stack(val)?;
+
+                self.ip.set(ip + 3);
+            },
+            OpCode::Array | OpCode::Tuple => {
+                let (_, len) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
+                let mut elements = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    elements.push(self.pop_stack()?);
+                }
+                elements.reverse();
+                self.push_stack(if opcode == OpCode::Array { Object::Array(elements) } else { Object::Tuple(elements) })?;
+
+                self.ip.set(ip + 3);
+            },
+            OpCode::Destructure => {
+                let (_, len) = Arg::read_u16(&self.bytecode.bytes, ip + 1).map_err(map_compile_err)?;
+                match self.pop_stack()?.thaw() {
+                    Object::Tuple(elements) if elements.len() == len as usize => {
+                        for element in elements {
+                            self.push_stack(element)?;
+                        }
+                    },
+                    val => return Err(RuntimeError(format!("Cannot destructure {val}, expected a tuple of {len} values"))),
+                }
 
-            println!("Dbg: stack: {:?}", self.stack.borrow());
+                self.ip.set(ip + 3);
+            },
+            OpCode::Index => {
+                let i = self.pop_stack()?;
+                let left = self.pop_stack()?;
+                self.push_stack(index(left, i)?)?;
+
+                self.ip.set(ip + 1);
+            },
+            OpCode::Slice => {
+                let end = self.pop_stack()?;
+                let start = self.pop_stack()?;
+                let left = self.pop_stack()?;
+                self.push_stack(left.slice(&start, &end)?)?;
+
+                self.ip.set(ip + 1);
+            },
         }
 
+        self.trace(|| format!("stack: {:?}", &self.stack.borrow()[..self.sp.get()]));
+
         Ok(())
     }
 
@@ -171,15 +481,18 @@ impl VM {
         // } as usize;
         let (_, addr) = Arg::read_u16(&self.bytecode.bytes, self.ip.get() + 1).map_err(map_compile_err)?;
         let addr = addr as usize;
+        if addr > self.bytecode.bytes.len() {
+            return Err(RuntimeError(format!("Jump to offset {addr}, past the end of the bytecode at {}", self.bytecode.bytes.len())));
+        }
         self.ip.set(addr);
         Ok(())
     }
 
-    fn perform_infix_operation(&self, operator: fn(Object, Object) -> Result<Object, RuntimeError>, op_str: &str) -> Result<(), RuntimeError> {
+    fn perform_infix_operation(&self, op_str: &str) -> Result<(), RuntimeError> {
         let y = self.pop_stack()?;
         let x = self.pop_stack()?;
-        let res = operator(x.clone(), y.clone())?;
-        println!("Dbg: {x:?} {op_str} {y:?} = {res:?}");
+        let res = x.infix(op_str, &y)?;
+        self.trace(|| format!("{x:?} {op_str} {y:?} = {res:?}"));
         self.push_stack(res)?;
 
         self.ip.set(self.ip.get() + 1);
@@ -210,16 +523,36 @@ impl VM {
         let val = self.stack_top()?;
         self.sp.set(self.sp.get() - 1);
         self.stack.borrow_mut()[self.sp.get()] = Object::Null;
+        *self.last_popped.borrow_mut() = val.clone();
         Ok(val)
     }
 }
 
+/// Indexes like the interpreter's default lenient index mode: missing elements are `null`.
+fn index(left: Object, i: Object) -> Result<Object, RuntimeError> {
+    match (left.thaw(), i) {
+        (Object::Array(arr), Object::Integer(i)) => {
+            Ok(normalize_index(i, arr.len()).map_or(Object::Null, |i| arr[i].clone()))
+        },
+        (Object::String(string), Object::Integer(i)) => {
+            let c = normalize_index(i, string.chars().count()).and_then(|i| string.chars().nth(i));
+            Ok(c.map_or(Object::Null, |c| Object::String(c.to_string())))
+        },
+        // The VM can't build hashes yet, but hosts can bind them
+        (Object::HashMap(hash_map), i) => match hash_map.get(&HashKey::get_hash_key(&i)?) {
+            Some(Object::KVPair(_, val)) => Ok(*val.clone()),
+            _ => Ok(Object::Null),
+        },
+        (left, i) => Err(RuntimeError(format!("Invalid index expression: ({left:?})[{i:?}]"))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use parser::{lexer::Lexer, Parser};
 
-    use crate::Compiler;
+    use crate::{assembler::assemble, Compiler};
 
     use super::*;
 
@@ -243,4 +576,341 @@ mod tests {
         println!("stack: {:#?}", vm.stack)
 
     }
+
+    #[test]
+    fn test_index_and_slice() {
+        let cases = [
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("[1, 2, 3][-1]", Object::Integer(3)),
+            ("[1, 2, 3][3]", Object::Null),
+            ("[1, 2, 3, 4][1:3]", Object::Array(vec![Object::Integer(2), Object::Integer(3)])),
+            ("[1, 2, 3][:-2]", Object::Array(vec![Object::Integer(1)])),
+            ("[1, 2, 3][5:]", Object::Array(vec![])),
+            (r#""hello"[-1]"#, Object::String("o".to_string())),
+            (r#""hello"[1:]"#, Object::String("ello".to_string())),
+        ];
+
+        for (src, expected) in cases {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            let mut compiler = Compiler::new();
+            let vm = VM::new(compiler.compile_program(&program).unwrap());
+            vm.run().unwrap();
+            assert_eq!(vm.last_popped(), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let cases = [
+            ("1 < 2", Ok(Object::Boolean(true))),
+            ("2 < 1", Ok(Object::Boolean(false))),
+            ("2 > 1", Ok(Object::Boolean(true))),
+            (r#""abc" < "abd""#, Ok(Object::Boolean(true))),
+            (r#""b" > "abc""#, Ok(Object::Boolean(true))),
+            ("true > false", Err("Cannot compare bool > bool, only two ints or two strings can be ordered at line 1, column 6")),
+            (r#"1 < "2""#, Err("Cannot compare int < str, only two ints or two strings can be ordered at line 1, column 3")),
+        ];
+
+        for (src, expected) in cases {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            let mut compiler = Compiler::new();
+            let vm = VM::new(compiler.compile_program(&program).unwrap());
+            match (vm.run(), expected) {
+                (Ok(()), Ok(expected)) => assert_eq!(vm.last_popped(), expected, "{src}"),
+                (Err(err), Err(expected)) => assert_eq!(err.0, expected, "{src}"),
+                (result, expected) => panic!("{src}: expected {expected:?}, got {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let program = Parser::new(Lexer::new("let h = 0x811C; [h ^ 0b1010, h & 0xFF, h | 1, 1 << 4, -16 >> 2]".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(false);
+        let vm = VM::new(compiler.compile_program(&program).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Array([0x8116, 0x1C, 0x811D, 16, -4].map(Object::Integer).to_vec()));
+
+        let program = Parser::new(Lexer::new("let n = [1][0]; n << -1".to_string())).parse_program().unwrap();
+        let vm = VM::new(compiler.compile_program(&program).unwrap());
+        assert_eq!(vm.run().unwrap_err().0, "shift amount out of range: 1 << -1 at line 1, column 19");
+    }
+
+    #[test]
+    fn test_power() {
+        let program = Parser::new(Lexer::new("let n = [3][0]; [2 * n ** 2, 2 ** n ** 2, n ** 0]".to_string())).parse_program().unwrap();
+        let vm = VM::new(Compiler::new().compile_program(&program).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Array([18, 512, 1].map(Object::Integer).to_vec()));
+
+        let program = Parser::new(Lexer::new("let n = [3][0]; 10 ** n ** n".to_string())).parse_program().unwrap();
+        let vm = VM::new(Compiler::new().compile_program(&program).unwrap());
+        assert_eq!(vm.run().unwrap_err().0, "integer overflow: 10 ** 27 at line 1, column 20");
+    }
+
+    #[test]
+    fn test_null_coalescing() {
+        let program = Parser::new(Lexer::new("let n = [null][0]; [n ?? 1, 2 ?? n, n ?? n, 3 ?? 1 / 0, n == null]".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(false);
+        let vm = VM::load(compiler.compile_program(&program).unwrap()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped().to_string(), "[1, 2, null, 3, true]");
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        let src = "let t = [true][0]; let f = !t; [f && 1 / 0, t || 1 / 0, t && f, f || t, t && [1][0], [0][0] || f, f || [] && 1 / 0]";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let vm = VM::load(Compiler::new().compile_program(&program).unwrap()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped().to_string(), "[false, true, false, true, true, false, false]");
+    }
+
+    #[test]
+    fn test_structural_equality() {
+        let program = Parser::new(Lexer::new("let a = [[1][0], [2]]; [a == [1, [2]], a != [1, [2]], a == [1], [] == []]".to_string())).parse_program().unwrap();
+        let vm = VM::load(Compiler::new().compile_program(&program).unwrap()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped().to_string(), "[true, false, false, true]");
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let program = Parser::new(Lexer::new("1 + 1 + 1 + 1 + 1".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_optimize(false); // keep the additions to step through
+        let bytecode = compiler.compile_program(&program).unwrap();
+
+        let vm = VM::new(bytecode.clone());
+        vm.set_step_limit(Some(5));
+        let err = vm.run().unwrap_err();
+        assert!(err.is_budget_exceeded(), "{err:?}");
+
+        let vm = VM::new(bytecode);
+        vm.set_step_limit(Some(10));
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(5));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let program = Parser::new(Lexer::new("1 + 1".to_string())).parse_program().unwrap();
+        let vm = VM::new(Compiler::new().compile_program(&program).unwrap());
+        let interrupt = Arc::new(AtomicBool::new(true));
+        vm.set_interrupt(Some(Arc::clone(&interrupt)));
+
+        let err = vm.run().unwrap_err();
+        assert!(err.is_interrupted(), "{err:?}");
+        assert!(!interrupt.load(Ordering::Relaxed));
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_reset() {
+        let program = Parser::new(Lexer::new("let x = base + [1][0]; x".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.define_global("base");
+        let vm = VM::new(compiler.compile_program(&program).unwrap());
+        // Enough steps for one run, each run starts counting afresh
+        vm.set_step_limit(Some(10));
+        for base in [41, 9] {
+            vm.set_globals(&[Object::Integer(base)]);
+            vm.run().unwrap();
+            assert_eq!(vm.last_popped(), Object::Integer(base + 1));
+            vm.reset();
+            assert_eq!((vm.ip(), vm.last_popped(), vm.stack()), (0, Object::Null, vec![]));
+        }
+        // Globals are null again, so the host has to bind them again
+        assert!(vm.run().is_err());
+        assert_eq!(vm.into_globals()[..2], [Object::Null, Object::Null]);
+    }
+
+    #[test]
+    fn test_tuples() {
+        let src = "let t = (1, 2 + 3); let (a, b) = t; [b, a, t]";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        let vm = VM::new(compiler.compile_program(&program).unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Array(vec![
+            Object::Integer(5),
+            Object::Integer(1),
+            Object::Tuple(vec![Object::Integer(1), Object::Integer(5)]),
+        ]));
+
+        let program = Parser::new(Lexer::new("let (a, b) = (1, 2, 3);".to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        assert!(VM::new(compiler.compile_program(&program).unwrap()).run().is_err());
+    }
+
+    #[test]
+    fn test_error_location() {
+        let src = "let x = 1;\nlet y = [x][0] + 2;\n\"a\" -\n  y";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        let vm = VM::new(compiler.compile_program(&program).unwrap());
+        match vm.run() {
+            Err(RuntimeError(msg)) => assert!(msg.ends_with(" at line 3, column 5"), "{msg}"),
+            Ok(()) => panic!("Expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        let run = |src: &str| {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            VM::new(Compiler::new().compile_program(&program).unwrap()).run().unwrap_err().0
+        };
+        assert_eq!(run("9223372036854775807 + 1"), "integer overflow: 9223372036854775807 + 1 at line 1, column 21");
+        assert_eq!(run("let zero = [0][0];\n10 / zero"), "division by zero at line 2, column 4");
+    }
+
+    #[test]
+    fn test_deltas() {
+        let mut compiler = Compiler::new();
+        let (mut globals, mut pool) = (Vec::new(), Vec::new());
+        let lines = [
+            (r#"let greeting = "hi";"#, 1, Object::String("hi".to_string())),
+            (r#"let n = 40 + 2;"#, 1, Object::Integer(42)), // folded
+            (r#"[greeting, n + 1]"#, 1, Object::Array(vec![Object::String("hi".to_string()), Object::Integer(43)])),
+        ];
+        for (src, new_constants, expected) in lines {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            let delta = compiler.compile_delta(&program).unwrap();
+            assert_eq!((delta.constants_base, delta.constants.len()), (pool.len(), new_constants), "{src}");
+
+            let vm = VM::new_with_pool(delta, globals, pool).unwrap();
+            vm.run().unwrap();
+            assert_eq!(vm.last_popped(), expected, "{src}");
+            (globals, pool) = vm.into_state();
+        }
+        assert_eq!(pool.len(), 3);
+
+        // A failed compile hands out nothing, the next delta still lines up with the pool
+        let program = Parser::new(Lexer::new("[5, missing]".to_string())).parse_program().unwrap();
+        assert!(compiler.compile_delta(&program).is_err());
+        let program = Parser::new(Lexer::new("n".to_string())).parse_program().unwrap();
+        let delta = compiler.compile_delta(&program).unwrap();
+        assert_eq!(delta.constants_base, 3);
+        assert!(VM::new_with_pool(delta.clone(), Vec::new(), Vec::new()).is_err());
+        let vm = VM::new_with_pool(delta, globals, pool).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(42));
+    }
+
+    #[test]
+    fn test_step_and_breakpoints() {
+        let program = Parser::new(Lexer::new("let x = [1][0];\nx + 2".to_string())).parse_program().unwrap();
+        let bytecode = Compiler::new().compile_program(&program).unwrap();
+
+        let vm = VM::new(bytecode.clone());
+        let first = vm.step().unwrap().unwrap();
+        assert_eq!((first.offset, first.opcode, first.args.clone(), first.next), (0, OpCode::Constant, vec![Arg::U16(0)], 3));
+        assert_eq!(first.stack, vec![Object::Integer(1)]);
+        assert_eq!(first.span.map(|span| (span.line, span.col)), Some((1, 10)));
+
+        let mut steps = vec![first];
+        while let Some(step) = vm.step().unwrap() {
+            steps.push(step);
+        }
+        assert_eq!(steps.iter().map(|step| step.opcode).collect::<Vec<OpCode>>(), [
+            OpCode::Constant, OpCode::Array, OpCode::Constant, OpCode::Index, OpCode::SetGlobal,
+            OpCode::GetGlobal, OpCode::Constant, OpCode::Add, OpCode::Pop,
+        ]);
+        assert_eq!(steps[7].stack, vec![Object::Integer(3)]);
+        assert!(vm.finished() && vm.step().unwrap().is_none());
+        assert_eq!(vm.last_popped(), Object::Integer(3));
+
+        let vm = VM::new(bytecode);
+        let offsets = vm.instructions().into_iter().map(|(offset, ..)| offset).collect::<Vec<usize>>();
+        assert_eq!(offsets.len(), 9);
+        assert!(vm.set_breakpoint(1).is_err());
+        vm.set_breakpoint(offsets[5]).unwrap();
+        vm.set_breakpoint(offsets[7]).unwrap();
+        assert_eq!(vm.run_to_breakpoint().unwrap(), Some(offsets[5]));
+        assert_eq!(vm.globals.borrow()[0], Object::Integer(1));
+        assert_eq!(vm.run_to_breakpoint().unwrap(), Some(offsets[7]));
+        assert!(vm.clear_breakpoint(offsets[7]));
+        assert_eq!(vm.step().unwrap().map(|step| step.opcode), Some(OpCode::Add));
+        assert_eq!(vm.run_to_breakpoint().unwrap(), None);
+        assert_eq!(vm.last_popped(), Object::Integer(3));
+    }
+
+    #[test]
+    fn test_load_validates() {
+        let program = Parser::new(Lexer::new("let x = [1][0]; if (x > 0) { x + 2 } else { 0 }".to_string())).parse_program().unwrap();
+        let bytecode = Compiler::new().compile_program(&program).unwrap();
+        let vm = VM::load(bytecode.clone()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(3));
+
+        let mut bad = bytecode.clone();
+        bad.constants.clear();
+        assert!(VM::load(bad).err().unwrap().0.starts_with("Invalid bytecode: Constant 0 at 0000"));
+        let mut bad = bytecode.clone();
+        bad.globals = 0;
+        assert!(VM::load(bad).err().unwrap().0.contains("past the 0 globals"));
+        let (jump, ..) = VM::new(bytecode.clone()).instructions().into_iter().find(|(_, opcode, _)| *opcode == OpCode::JPFalse).unwrap();
+        let mut bad = bytecode.clone();
+        bad.bytes[jump + 2] += 1;
+        assert!(VM::load(bad).err().unwrap().0.contains("isn't the start of an instruction"));
+        let mut bad = bytecode;
+        bad.bytes.push(255);
+        assert!(VM::load(bad).err().unwrap().0.starts_with("Invalid bytecode: Invalid instruction"));
+    }
+
+    #[test]
+    fn test_malformed_bytecode_never_panics() {
+        let src = "let a = [1, 2][0]; let t = (a, 3); let (b, c) = t; if (b < c) { [a, b][1:] } else { \"s\"[0] }; -a * 2";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let bytecode = Compiler::new().compile_program(&program).unwrap();
+        for offset in 0..bytecode.bytes.len() {
+            for byte in 0..=255 {
+                let mut bad = bytecode.clone();
+                bad.bytes[offset] = byte;
+                bad.globals = 0;
+                let vm = VM::new(bad);
+                vm.set_step_limit(Some(1000));
+                let _ = vm.run();
+            }
+            let mut truncated = bytecode.clone();
+            truncated.bytes.truncate(offset);
+            let _ = VM::new(truncated).run();
+        }
+    }
+
+    #[test]
+    fn test_many_globals() {
+        let src = (0..40).map(|i| format!("let {} = {i};", "g".repeat(i + 1))).collect::<String>() + &"g".repeat(40);
+        let program = Parser::new(Lexer::new(src)).parse_program().unwrap();
+        let vm = VM::load(Compiler::new().compile_program(&program).unwrap()).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.last_popped(), Object::Integer(39));
+    }
+
+    #[test]
+    fn test_globals_grow() {
+        let mut compiler = Compiler::new();
+        compiler.define_global("base");
+        let program = Parser::new(Lexer::new("let x = base + [1][0]; x".to_string())).parse_program().unwrap();
+        let bytecode = compiler.compile_program(&program).unwrap();
+        assert_eq!(bytecode.global_names, ["base", "x"]);
+        let vm = VM::new(bytecode.clone());
+        assert_eq!(vm.globals.borrow().len(), 2);
+
+        // Slots past what the bytecode says it uses are added when set, reading one first names it
+        let mut short = bytecode;
+        short.globals = 0;
+        let vm = VM::new_with_globals(short.clone(), vec![Object::Integer(2)]);
+        vm.run().unwrap();
+        assert_eq!(vm.into_globals(), [Object::Integer(2), Object::Integer(3)]);
+        assert_eq!(VM::new(short).run().unwrap_err().0, "Global base (slot 0) is past the 0 global slots at line 1, column 9");
+        let mut unnamed = assemble("GET_GLOBAL 3; POP", Vec::new()).unwrap();
+        unnamed.globals = 0;
+        let vm = VM::new(unnamed);
+        assert_eq!(vm.run().unwrap_err().0, "Global 3 is past the 0 global slots");
+    }
 }
@@ -0,0 +1,69 @@
+//! Walks through the embedding workflows of the `engine` crate, printing what each one produces.
+//!
+//! cargo run -p engine --example gallery
+
+use engine::{Backend, Bindings, Engine, EngineError, Object, SharedBuffer};
+
+fn eval_on_both_backends() -> Result<(), EngineError> {
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let mut engine = Engine::new(backend);
+        engine.eval("let prices = [3, 5, 8];")?;
+        let total = engine.eval("prices[0] + prices[1] + prices[2]")?;
+        println!("{backend:?}: total = {total}");
+    }
+    Ok(())
+}
+
+fn bindings_in_outputs_out() -> Result<(), EngineError> {
+    let mut engine = Engine::new(Backend::Interpreter);
+    let script = engine
+        .compile("let discounted = price - price / 10; let label = name;")?
+        .with_outputs(&["discounted", "label"]);
+
+    for (name, price) in [("lamp", 40), ("desk", 250)] {
+        let vars = Bindings::from([
+            ("name".to_string(), Object::String(name.to_string())),
+            ("price".to_string(), Object::Integer(price)),
+        ]);
+        let output = script.run_with(&mut engine, vars)?;
+        println!("{} -> {}", output.outputs["label"], output.outputs["discounted"]);
+    }
+    Ok(())
+}
+
+fn limited_untrusted_script() {
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_step_limit(Some(1_000));
+    match engine.eval("let spin = fn(n) { spin(n + 1) }; spin(0)") {
+        Ok(value) => println!("finished with {value}"),
+        Err(err) => println!("stopped: {err}"),
+    }
+}
+
+fn captured_output() -> Result<(), EngineError> {
+    let output = SharedBuffer::new();
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_output(output.clone());
+    engine.eval(r#"println("hello from Monkey"); println(6 * 7);"#)?;
+    print!("captured:\n{}", output.take());
+    Ok(())
+}
+
+fn hot_reload() -> Result<(), EngineError> {
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.eval("let greet = fn(who) { \"hi \" + who };")?;
+    println!("{}", engine.eval(r#"greet("there")"#)?);
+    engine.redefine("greet", "fn(who) { \"hello \" + who }")?;
+    println!("{}", engine.eval(r#"greet("there")"#)?);
+    Ok(())
+}
+
+fn main() -> Result<(), EngineError> {
+    eval_on_both_backends()?;
+    bindings_in_outputs_out()?;
+    limited_untrusted_script();
+    captured_output()?;
+    hot_reload()?;
+    println!("{}", engine::run("let xs = [1, 2, 3]; len(xs)").trim_end());
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+use crate::{Backend, Engine, EngineError, Object, SharedBuffer};
+
+/// What the same source evaluated to on each backend.
+#[derive(Debug)]
+pub struct BackendResults {
+    pub interpreter: Result<Object, EngineError>,
+    pub vm: Result<Object, EngineError>,
+}
+
+impl BackendResults {
+    /// Both produced equal values, or both failed the same way: to parse, or at run time. Error messages aren't
+    /// compared, the backends word them differently, but the VM failing to compile what the interpreter ran until
+    /// it failed is a disagreement.
+    pub fn agree(&self) -> bool {
+        match (&self.interpreter, &self.vm) {
+            (Ok(interpreted), Ok(run)) => interpreted == run,
+            (Err(EngineError::Parse(_)), Err(EngineError::Parse(_))) => true,
+            (Err(EngineError::Eval(_)), Err(EngineError::Runtime(_))) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for BackendResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |result: &Result<Object, EngineError>| match result {
+            Ok(value) => format!("{value}"),
+            Err(err) => format!("error: {err}"),
+        };
+        writeln!(f, "interpreter: {}", show(&self.interpreter))?;
+        writeln!(f, "vm:          {}", show(&self.vm))?;
+        write!(f, "{}", if self.agree() { "backends agree" } else { "backends differ" })
+    }
+}
+
+/// Evaluates `src` with a fresh engine per backend, discarding what it prints.
+pub fn run_backends(src: &str) -> BackendResults {
+    let run = |backend| {
+        let mut engine = Engine::new(backend);
+        engine.set_output(SharedBuffer::new());
+        engine.eval(src)
+    };
+    BackendResults { interpreter: run(Backend::Interpreter), vm: run(Backend::Vm) }
+}
@@ -0,0 +1,598 @@
+use std::{cell::RefCell, collections::{HashMap, VecDeque}, fmt, hash::{DefaultHasher, Hash, Hasher}, rc::{Rc, Weak}, sync::atomic::{AtomicU64, Ordering}};
+
+use compiler::{vm::VM, ByteCode, CompileError, Compiler, RuntimeError};
+use interpreter::{bindings_from_json, Environment, EvalError, Interpreter};
+pub use interpreter::{from_json, to_json, Caller, Diagnostic, Level, LogBuffer, Logger, Object, OutputSink, Record, SharedBuffer, StderrLogger};
+use parser::{ast::{Expression, Statement}, lexer::{token::Token, Lexer}, ParseError, Parser, Program};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+static DEFAULT_CACHE_CAPACITY: usize = 64;
+static NEXT_ENGINE_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Vm,
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Parse(ParseError),
+    Eval(EvalError),
+    Compile(CompileError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "parse error: {err:?}"),
+            Self::Eval(err) => write!(f, "{}", err.0),
+            Self::Compile(err) => write!(f, "compile error: {}", err.0),
+            Self::Runtime(err) => write!(f, "{}", err.0),
+        }
+    }
+}
+
+pub type Bindings = HashMap<String, Object>;
+
+/// String in, string out: evaluates `src` with a fresh interpreter and returns what it printed, any diagnostics,
+/// then the value of the last expression or the error. Nothing is written to stdout, so it can be exported to
+/// JavaScript with wasm-bindgen as is.
+pub fn run(src: &str) -> String {
+    let output = SharedBuffer::new();
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_output(output.clone());
+
+    let result = engine.eval(src);
+    let mut text = output.take();
+    for diagnostic in engine.take_diagnostics() {
+        text.push_str(&format!("{diagnostic}\n"));
+    }
+    match result {
+        Ok(Object::Null) => {},
+        Ok(value) => text.push_str(&format!("{value}\n")),
+        Err(err) => text.push_str(&format!("error: {err}\n")),
+    }
+    text
+}
+
+#[derive(Debug)]
+pub struct RunOutput {
+    pub value: Object,
+    pub outputs: Bindings, // only the designated outputs that were bound after the run
+}
+
+struct CompiledSource {
+    source: String,
+    program: Program,
+    // Bytecode refers to globals by the slots of the engine that compiled it, so both are kept with that engine's id
+    bytecode: RefCell<Option<(u64, ByteCode)>>,
+    vm: RefCell<Option<(u64, VM)>>, // loaded with the bytecode for isolated runs, reset before each
+}
+
+/// Runs Monkey source for a host program, keeping globals between runs.
+///
+/// The interpreter backend recurses on the native stack for every Monkey call. The default call depth takes more
+/// stack than a main thread has, see `interpreter::DEFAULT_MAX_CALL_DEPTH`: run the engine on a thread with a
+/// larger stack, or lower the depth with [`Engine::set_max_call_depth`].
+///
+/// ```
+/// use engine::{Backend, Engine, Object, SharedBuffer};
+///
+/// let mut engine = Engine::new(Backend::Interpreter);
+/// let output = SharedBuffer::new();
+/// engine.set_output(output.clone());
+/// engine.set_step_limit(Some(10_000));
+///
+/// engine.eval("let double = fn(x) { x * 2 };").unwrap();
+/// let value = engine.eval(r#"println("doubling"); double(21)"#).unwrap();
+///
+/// assert_eq!(value, Object::Integer(42));
+/// assert_eq!(output.take(), "doubling\n");
+/// assert!(engine.get_global("double").is_some());
+/// ```
+pub struct Engine {
+    id: u64, // tells the bytecode of scripts compiled by this engine from that of others
+    backend: Backend,
+    interpreter: Interpreter,
+    compiler: Compiler,
+    globals: Vec<Object>,
+    // Parsed sources stay reachable through `cache` for as long as something holds them; `recent` keeps the
+    // last few alive so the per-request pattern of re-running the same script never re-parses.
+    cache: HashMap<u64, Weak<CompiledSource>>,
+    recent: VecDeque<Rc<CompiledSource>>,
+    cache_capacity: usize,
+    step_limit: Option<usize>,
+    logger: Option<Rc<dyn Logger>>, // handed to the VM of each run
+}
+
+impl Engine {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            id: NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed),
+            backend,
+            interpreter: Interpreter::new(Environment::new(None)),
+            compiler: Compiler::new(),
+            globals: Vec::new(),
+            cache: HashMap::new(),
+            recent: VecDeque::new(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            step_limit: None,
+            logger: None,
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache_capacity = capacity;
+        while self.recent.len() > capacity {
+            self.recent.pop_front();
+        }
+        self.cache.retain(|_, entry| entry.strong_count() > 0);
+    }
+
+    pub fn cached_sources(&self) -> usize {
+        self.cache.values().filter(|entry| entry.strong_count() > 0).count()
+    }
+
+    pub fn eval(&mut self, src: &str) -> Result<Object, EngineError> {
+        let compiled = self.parse(src)?;
+        self.run_compiled(&compiled)
+    }
+
+    pub fn eval_cached(&mut self, src: &str) -> Result<Object, EngineError> {
+        let compiled = self.lookup_or_parse(src)?;
+        self.run_compiled(&compiled)
+    }
+
+    /// Evaluates `src` against fresh state: it sees the globals bound so far, but what it binds is dropped after the
+    /// run. Like [`Engine::eval_cached`], the source is only parsed (and compiled) once, so a server can run the same
+    /// script for every request without one request seeing what another left behind.
+    pub fn eval_isolated(&mut self, src: &str) -> Result<Object, EngineError> {
+        let compiled = self.lookup_or_parse(src)?;
+        self.run_isolated(&compiled)
+    }
+
+    /// Parses `src` once so it can be run many times; VM bytecode is compiled on the first run, after that run's
+    /// bindings are known to the symbol table.
+    pub fn compile(&mut self, src: &str) -> Result<Script, EngineError> {
+        Ok(Script { compiled: self.lookup_or_parse(src)?, outputs: Vec::new() })
+    }
+
+    pub fn bind(&mut self, bindings: &Bindings) -> Result<(), EngineError> {
+        for (name, value) in bindings {
+            match self.backend {
+                Backend::Interpreter => self.interpreter.global_env().borrow_mut().set(name, value.clone()),
+                Backend::Vm => {
+                    let idx = self.compiler.define_global(name) as usize;
+                    if idx >= self.globals.len() {
+                        self.globals.resize(idx + 1, Object::Null);
+                    }
+                    self.globals[idx] = value.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates `src` with the fields of `scope`, a JSON object, bound as globals and returns its value as JSON, to
+    /// use Monkey as an expression or rules language over host data. Like [`Engine::bind`], the fields stay bound.
+    ///
+    /// ```
+    /// use engine::{Backend, Engine};
+    /// use serde_json::json;
+    ///
+    /// let mut engine = Engine::new(Backend::Interpreter);
+    /// let order = json!({"total": 120, "items": ["book", "pen"], "customer": {"vip": true}});
+    /// let free_shipping = engine.eval_json(&order, r#"total > 100 || customer["vip"]"#).unwrap();
+    /// assert_eq!(free_shipping, json!(true));
+    /// ```
+    pub fn eval_json(&mut self, scope: &Value, src: &str) -> Result<Value, EngineError> {
+        self.bind(&bindings_from_json(scope).map_err(EngineError::Eval)?)?;
+        let value = self.eval(src)?;
+        to_json(&value).map_err(EngineError::Eval)
+    }
+
+    /// Like [`Engine::eval_json`], reading the value into a `T`.
+    pub fn eval_json_as<T: DeserializeOwned>(&mut self, scope: &Value, src: &str) -> Result<T, EngineError> {
+        let value = self.eval_json(scope, src)?;
+        serde_json::from_value(value).map_err(|err| EngineError::Eval(EvalError(format!("Unable to read the value as {}: {err}", std::any::type_name::<T>()))))
+    }
+
+    /// Sends what scripts print to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl OutputSink + 'static) {
+        self.interpreter.set_output(output);
+    }
+
+    /// Caps how deep calls nest, interpreter backend only since the VM can't call functions yet. Each level takes
+    /// native stack, see `interpreter::DEFAULT_MAX_CALL_DEPTH` for how much.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.interpreter.set_max_call_depth(depth);
+    }
+
+    /// Caps each run at `limit` evaluated expressions (interpreter) or executed instructions (VM).
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+        self.interpreter.set_step_limit(limit);
+    }
+
+    /// Sends the events of both backends to `logger`, see `Interpreter::set_logger` and `VM::set_logger`.
+    pub fn set_logger(&mut self, logger: Option<Rc<dyn Logger>>) {
+        self.interpreter.set_logger(logger.clone());
+        self.logger = logger;
+    }
+
+    /// Exposes a host function to scripts, interpreter backend only since the VM can't call functions yet.
+    pub fn register_builtin(&mut self, name: &str, f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) {
+        self.interpreter.register_builtin(name, f);
+    }
+
+    /// Exposes a host function that takes Monkey functions as arguements and calls them through the `Caller`.
+    pub fn register_builtin_with_caller(&mut self, name: &str, f: impl Fn(Vec<Object>, &dyn Caller) -> Result<Object, EvalError> + 'static) {
+        self.interpreter.register_builtin_with_caller(name, f);
+    }
+
+    /// Deprecation warnings are reported by the interpreter backend; the VM has no builtins to deprecate yet.
+    pub fn deprecate_builtin(&mut self, name: &str, note: &str) -> Result<(), EngineError> {
+        self.interpreter.deprecate_builtin(name, note).map_err(EngineError::Eval)
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.interpreter.take_diagnostics()
+    }
+
+    /// Reads a global after execution: an environment lookup for the interpreter, the symbol table slot for the VM.
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        match self.backend {
+            Backend::Interpreter => self.interpreter.global_env().borrow().get(name),
+            Backend::Vm => {
+                let idx = self.compiler.resolve_global(name)?;
+                self.globals.get(idx as usize).cloned()
+            },
+        }
+    }
+
+    /// Re-parses a single `fn(...) { ... }` literal and swaps it into the existing global `name`, leaving every
+    /// other binding untouched. Callers that look `name` up at call time pick up the new definition.
+    pub fn redefine(&mut self, name: &str, src: &str) -> Result<(), EngineError> {
+        if self.get_global(name).is_none() {
+            return Err(EngineError::Eval(EvalError(format!("Cannot redefine `{name}`, it is not defined"))));
+        }
+
+        let mut parser = Parser::new(Lexer::new_borrowed(src));
+        parser.set_interner(self.interpreter.interner().clone());
+        let mut program = parser.parse_program().map_err(EngineError::Parse)?;
+        let function = match program.statements.pop() {
+            Some(Statement::ExpressionStatement { expression: function @ Expression::Function { .. }, .. }) if program.statements.is_empty() => function,
+            _ => return Err(EngineError::Parse(ParseError::Syntax(format!("Cannot redefine `{name}`, expected a single fn literal, got: {src}")))),
+        };
+
+        let program = Program {
+            statements: vec![Statement::Let {
+                token: Token::new_let(),
+                name: Expression::construct_identifier_expression(name),
+                value: function,
+            }],
+        };
+        self.run_compiled(&CompiledSource { source: src.to_string(), program, bytecode: RefCell::new(None), vm: RefCell::new(None) })?;
+
+        Ok(())
+    }
+
+    fn parse(&self, src: &str) -> Result<CompiledSource, EngineError> {
+        let mut parser = Parser::new(Lexer::new_borrowed(src));
+        parser.set_interner(self.interpreter.interner().clone());
+        let program = parser.parse_program().map_err(EngineError::Parse)?;
+        Ok(CompiledSource { source: src.to_string(), program, bytecode: RefCell::new(None), vm: RefCell::new(None) })
+    }
+
+    fn hash_source(src: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn lookup_or_parse(&mut self, src: &str) -> Result<Rc<CompiledSource>, EngineError> {
+        let key = Self::hash_source(src);
+        if let Some(compiled) = self.cache.get(&key).and_then(Weak::upgrade) {
+            if compiled.source == src {
+                return Ok(compiled);
+            }
+        }
+
+        let compiled = Rc::new(self.parse(src)?);
+        self.cache.retain(|_, entry| entry.strong_count() > 0);
+        self.cache.insert(key, Rc::downgrade(&compiled));
+        if self.cache_capacity > 0 {
+            if self.recent.len() == self.cache_capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(Rc::clone(&compiled));
+        }
+
+        Ok(compiled)
+    }
+
+    fn bytecode(&mut self, compiled: &CompiledSource) -> Result<ByteCode, EngineError> {
+        let cached = compiled.bytecode.borrow().clone();
+        match cached {
+            Some((id, bytecode)) if id == self.id => Ok(bytecode),
+            _ => {
+                self.compiler.reset();
+                let bytecode = self.compiler.compile_program(&compiled.program).map_err(EngineError::Compile)?;
+                // Checked once here rather than on every run, the VMs are made without checking it again
+                bytecode.validate().map_err(EngineError::Compile)?;
+                *compiled.bytecode.borrow_mut() = Some((self.id, bytecode.clone()));
+                Ok(bytecode)
+            }
+        }
+    }
+
+    fn run_compiled(&mut self, compiled: &CompiledSource) -> Result<Object, EngineError> {
+        match self.backend {
+            Backend::Interpreter => self.interpreter.evaluate_program(&compiled.program).map_err(EngineError::Eval),
+            Backend::Vm => {
+                let bytecode = self.bytecode(compiled)?;
+                let vm = VM::new_with_globals(bytecode, std::mem::take(&mut self.globals));
+                vm.set_step_limit(self.step_limit);
+                vm.set_logger(self.logger.clone());
+                let result = vm.run();
+                let value = vm.last_popped();
+                self.globals = vm.into_globals();
+                result.map_err(EngineError::Runtime)?;
+
+                Ok(value)
+            }
+        }
+    }
+
+    fn run_isolated(&mut self, compiled: &CompiledSource) -> Result<Object, EngineError> {
+        match self.backend {
+            Backend::Interpreter => self.interpreter.run_isolated(&compiled.program).map_err(EngineError::Eval),
+            Backend::Vm => {
+                if compiled.vm.borrow().as_ref().is_none_or(|(id, _)| *id != self.id) {
+                    // Compiled on its own, what it binds stays out of the engine's globals
+                    let bytecode = self.compiler.compile_isolated(&compiled.program).map_err(EngineError::Compile)?;
+                    bytecode.validate().map_err(EngineError::Compile)?;
+                    let vm = VM::new(bytecode);
+                    *compiled.vm.borrow_mut() = Some((self.id, vm));
+                }
+                let vm = compiled.vm.borrow();
+                let (_, vm) = vm.as_ref().expect("the VM was just loaded");
+                vm.reset();
+                vm.set_globals(&self.globals);
+                vm.set_step_limit(self.step_limit);
+                vm.set_logger(self.logger.clone());
+                vm.run().map_err(EngineError::Runtime)?;
+
+                Ok(vm.last_popped())
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Script {
+    compiled: Rc<CompiledSource>,
+    outputs: Vec<String>,
+}
+
+impl Script {
+    pub fn with_outputs(mut self, names: &[&str]) -> Self {
+        self.outputs = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn source(&self) -> &str {
+        &self.compiled.source
+    }
+
+    pub fn run(&self, engine: &mut Engine) -> Result<Object, EngineError> {
+        engine.run_compiled(&self.compiled)
+    }
+
+    /// Runs the script against fresh state, see [`Engine::eval_isolated`].
+    pub fn run_isolated(&self, engine: &mut Engine) -> Result<Object, EngineError> {
+        engine.run_isolated(&self.compiled)
+    }
+
+    pub fn run_with_bindings(&self, engine: &mut Engine, bindings: &Bindings) -> Result<Object, EngineError> {
+        engine.bind(bindings)?;
+        self.run(engine)
+    }
+
+    /// Seeds `vars` as globals, runs the script and reads back every variable named in `with_outputs`.
+    pub fn run_with(&self, engine: &mut Engine, vars: Bindings) -> Result<RunOutput, EngineError> {
+        let value = self.run_with_bindings(engine, &vars)?;
+
+        let mut outputs = Bindings::new();
+        for name in &self.outputs {
+            if let Some(val) = engine.get_global(name) {
+                outputs.insert(name.to_string(), val);
+            }
+        }
+
+        Ok(RunOutput { value, outputs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_both_backends() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            engine.eval("let x = 5;").unwrap();
+            assert!(matches!(engine.eval("x * 2 + 1"), Ok(Object::Integer(11))), "{backend:?}");
+            assert!(matches!(engine.eval("if (x > 2) { true } else { false }"), Ok(Object::Boolean(true))), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        assert_eq!(run(r#"println("hi"); let x = 2; println(x * 3); [x]"#), "hi\n6\n[2]\n");
+        assert_eq!(run("if (false) { 1 }"), "");
+        assert_eq!(run("println(missing)"), "error: Unknown variable: missing at line 1, column 9\n");
+
+        let output = SharedBuffer::new();
+        let mut engine = Engine::new(Backend::Interpreter);
+        engine.set_output(output.clone());
+        engine.eval(r#"println("captured")"#).unwrap();
+        assert_eq!(output.take(), "captured\n");
+    }
+
+    #[test]
+    fn test_step_limit() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            engine.set_step_limit(Some(8));
+            let src = "[1, 2, 3, 4, 5, 6, 7, 8, 9][8] * 5";
+            match engine.eval(src) {
+                Err(EngineError::Eval(err)) => assert!(err.is_budget_exceeded(), "{err:?}"),
+                Err(EngineError::Runtime(err)) => assert!(err.is_budget_exceeded(), "{err:?}"),
+                other => panic!("{backend:?}: expected budget error, got {other:?}"),
+            }
+
+            engine.set_step_limit(None);
+            assert!(matches!(engine.eval(src), Ok(Object::Integer(45))), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_eval_cached_reuses_program() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            engine.eval("let base = 40;").unwrap();
+
+            for _ in 0..3 {
+                assert!(matches!(engine.eval_cached("base + 2"), Ok(Object::Integer(42))), "{backend:?}");
+            }
+            assert_eq!(engine.cached_sources(), 1);
+
+            engine.eval_cached("base - 2").unwrap();
+            assert_eq!(engine.cached_sources(), 2);
+        }
+    }
+
+    #[test]
+    fn test_script_run_with_bindings() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            let script = engine.compile("if (score > limit) { score - limit } else { 0 }").unwrap();
+
+            for (score, expected) in [(10, 3), (5, 0), (100, 93)] {
+                let bindings = Bindings::from([
+                    ("score".to_string(), Object::Integer(score)),
+                    ("limit".to_string(), Object::Integer(7)),
+                ]);
+                let result = script.run_with_bindings(&mut engine, &bindings).unwrap();
+                assert!(matches!(result, Object::Integer(val) if val == expected), "{backend:?}: {result:?}");
+            }
+            assert_eq!(script.source(), "if (score > limit) { score - limit } else { 0 }");
+        }
+    }
+
+    #[test]
+    fn test_script_run_with_outputs() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            let script = engine
+                .compile("let total = price * qty; let discounted = total > 100; total")
+                .unwrap()
+                .with_outputs(&["total", "discounted", "missing"]);
+
+            let vars = Bindings::from([
+                ("price".to_string(), Object::Integer(30)),
+                ("qty".to_string(), Object::Integer(4)),
+            ]);
+            let output = script.run_with(&mut engine, vars).unwrap();
+
+            assert!(matches!(output.outputs.get("total"), Some(Object::Integer(120))), "{backend:?}: {output:?}");
+            assert!(matches!(output.outputs.get("discounted"), Some(Object::Boolean(true))), "{backend:?}: {output:?}");
+            assert!(!output.outputs.contains_key("missing"));
+        }
+    }
+
+    #[test]
+    fn test_get_global() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            engine.eval("let a = 1; let b = a > 0; let c = a + 2;").unwrap();
+
+            assert!(matches!(engine.get_global("a"), Some(Object::Integer(1))), "{backend:?}");
+            assert!(matches!(engine.get_global("b"), Some(Object::Boolean(true))), "{backend:?}");
+            assert!(matches!(engine.get_global("c"), Some(Object::Integer(3))), "{backend:?}");
+            assert!(engine.get_global("d").is_none(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_redefine() {
+        let mut engine = Engine::new(Backend::Interpreter);
+        engine.eval("let scale = fn(x) { x * 2 }; let area = fn(w, h) { scale(w) * h }; let unit = 3;").unwrap();
+        assert!(matches!(engine.eval("area(2, 5)"), Ok(Object::Integer(20))));
+
+        engine.redefine("scale", "fn(x) { x * 10 }").unwrap();
+        assert!(matches!(engine.eval("area(2, 5)"), Ok(Object::Integer(100))));
+        assert!(matches!(engine.get_global("unit"), Some(Object::Integer(3))));
+
+        assert!(engine.redefine("missing", "fn(x) { x }").is_err());
+        assert!(engine.redefine("scale", "5").is_err());
+        assert!(engine.redefine("scale", "fn(x) { x }; fn(y) { y }").is_err());
+    }
+
+    #[test]
+    fn test_eval_isolated() {
+        for backend in [Backend::Interpreter, Backend::Vm] {
+            let mut engine = Engine::new(backend);
+            engine.bind(&Bindings::from([("base".to_string(), Object::Integer(40))])).unwrap();
+            let src = "const step = 2; let total = base + step; total";
+            for _ in 0..3 {
+                assert!(matches!(engine.eval_isolated(src), Ok(Object::Integer(42))), "{backend:?}");
+            }
+            assert_eq!(engine.cached_sources(), 1);
+            assert!(engine.get_global("total").is_none(), "{backend:?}");
+            assert!(engine.eval("total").is_err(), "{backend:?}");
+            assert!(matches!(engine.eval("let step = 5; step"), Ok(Object::Integer(5))), "{backend:?}");
+
+            let script = engine.compile("base * 2").unwrap();
+            engine.bind(&Bindings::from([("base".to_string(), Object::Integer(21))])).unwrap();
+            assert!(matches!(script.run_isolated(&mut engine), Ok(Object::Integer(42))), "{backend:?}");
+            assert!(engine.eval_isolated("missing").is_err(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_script_in_another_engine() {
+        let mut first = Engine::new(Backend::Vm);
+        first.eval("let p = 1; let q = 2;").unwrap();
+        let script = first.compile("q").unwrap();
+        assert!(matches!(script.run(&mut first), Ok(Object::Integer(2))));
+
+        // `q` has another slot in this engine, the script is compiled again for it
+        let mut second = Engine::new(Backend::Vm);
+        second.eval("let q = 99;").unwrap();
+        assert!(matches!(script.run(&mut second), Ok(Object::Integer(99))));
+        assert!(matches!(script.run_isolated(&mut second), Ok(Object::Integer(99))));
+        assert!(matches!(script.run(&mut first), Ok(Object::Integer(2))));
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let mut engine = Engine::new(Backend::Interpreter);
+        engine.set_cache_capacity(1);
+        engine.eval_cached("1").unwrap();
+        engine.eval_cached("2").unwrap();
+        assert_eq!(engine.cached_sources(), 1);
+
+        engine.set_cache_capacity(0);
+        assert_eq!(engine.cached_sources(), 0);
+    }
+}
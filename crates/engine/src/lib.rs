@@ -0,0 +1,6 @@
+pub mod backends;
+pub mod engine;
+pub mod report;
+
+pub use engine::*;
+pub use report::*;
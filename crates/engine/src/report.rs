@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use parser::{lexer::{token::Span, Lexer}, LimitError, ParseError, Parser};
+
+use crate::EngineError;
+
+const ERROR: &str = "\x1b[1;31m";
+const GUTTER: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The source an error came from, `name` is what the report calls it: a path, `stdin`, a playground tab.
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(name: &'a str, text: &'a str) -> Self {
+        Self { name, text }
+    }
+}
+
+/// Renders `err` as a report: an error code and the message, then the source line it's about with a caret under the
+/// span, then a note when there's more to say, like the call stack of a runtime error.
+pub fn render_diagnostic(source: &Source, err: &EngineError) -> String {
+    render(source, err, false)
+}
+
+/// Like [`render_diagnostic`], colored with ANSI escapes for terminals.
+pub fn render_diagnostic_colored(source: &Source, err: &EngineError) -> String {
+    render(source, err, true)
+}
+
+fn render(source: &Source, err: &EngineError, color: bool) -> String {
+    let paint = |style: &str, text: &str| if color { format!("{style}{text}{RESET}") } else { text.to_string() };
+    let (code, message, mut notes) = describe(err);
+    let (headline, details) = message.split_once('\n').unwrap_or((&message, ""));
+    notes.extend(details.lines().map(str::trim).filter(|line| !line.is_empty()).map(str::to_string));
+    let span = match err {
+        // Parse errors don't carry where the parser stopped, parsing again finds it
+        EngineError::Parse(_) => {
+            let mut parser = Parser::new(Lexer::new_borrowed(source.text));
+            parser.parse_program().err().and_then(|_| parser.span())
+        },
+        _ => last_span(headline).or_else(|| last_span(details)),
+    };
+    let headline = match span {
+        Some(span) => headline.replacen(&format!(" at {span}"), "", 1),
+        None => headline.to_string(),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{}{}", paint(ERROR, &format!("error[{code}]")), paint(BOLD, &format!(": {headline}")));
+    let line = span.and_then(|span| source.text.lines().nth(span.line.checked_sub(1)? as usize).map(|line| (span, line)));
+    let gutter = " ".repeat(line.map_or(0, |(span, _)| span.line.to_string().len()));
+    match (span, line) {
+        (Some(span), Some((_, text))) => {
+            let _ = writeln!(out, "{gutter}{} {}:{}:{}", paint(GUTTER, "-->"), source.name, span.line, span.col);
+            let _ = writeln!(out, "{gutter} {}", paint(GUTTER, "|"));
+            let _ = writeln!(out, "{} {text}", paint(GUTTER, &format!("{} |", span.line)));
+            let (padding, width) = underline(text, span.col);
+            let _ = writeln!(out, "{gutter} {} {padding}{}", paint(GUTTER, "|"), paint(ERROR, &"^".repeat(width)));
+        },
+        (Some(span), None) => {
+            let _ = writeln!(out, "{} {}:{}:{}", paint(GUTTER, "-->"), source.name, span.line, span.col);
+        },
+        (None, _) => {
+            let _ = writeln!(out, "{} {}", paint(GUTTER, "-->"), source.name);
+        },
+    }
+    for note in notes {
+        let _ = writeln!(out, "{gutter} {} {note}", paint(GUTTER, "= note:"));
+    }
+    out
+}
+
+/// The error code, message and notes of `err`.
+fn describe(err: &EngineError) -> (&'static str, String, Vec<String>) {
+    let note = |text: &str| vec![text.to_string()];
+    match err {
+        EngineError::Parse(ParseError::Syntax(msg)) => ("E0001", msg.clone(), note("nothing ran, programs only run once they parse")),
+        EngineError::Parse(ParseError::LimitExceeded(limit)) => {
+            let msg = match limit {
+                LimitError::SourceBytes { limit, actual } => format!("The source is {actual} bytes, more than the limit of {limit}"),
+                LimitError::Tokens { limit } => format!("The source has more than {limit} tokens"),
+                LimitError::AstNodes { limit } => format!("The program has more than {limit} AST nodes"),
+                LimitError::Depth { limit } => format!("Expressions are nested more than {limit} deep"),
+            };
+            ("E0002", msg, note("the parser limits bound the work done on a single source"))
+        },
+        EngineError::Compile(err) => ("E0200", err.0.clone(), Vec::new()),
+        EngineError::Eval(err) => runtime_error(&err.0, err.is_budget_exceeded(), err.is_interrupted()),
+        EngineError::Runtime(err) => runtime_error(&err.0, err.is_budget_exceeded(), err.is_interrupted()),
+    }
+}
+
+/// Both backends fail at runtime with the same messages, so their errors get the same codes.
+fn runtime_error(msg: &str, budget_exceeded: bool, interrupted: bool) -> (&'static str, String, Vec<String>) {
+    if msg.starts_with("Unknown variable") {
+        ("E0101", msg.to_string(), vec!["names have to be bound by a `let`, a fn param or the host".to_string()])
+    } else if budget_exceeded {
+        ("E0102", msg.to_string(), vec!["runs are capped at a step limit, raise it to let longer runs finish".to_string()])
+    } else if interrupted {
+        ("E0103", msg.to_string(), Vec::new())
+    } else {
+        ("E0100", msg.to_string(), Vec::new())
+    }
+}
+
+/// The last `line L, column C` mentioned in `text`, errors say where they happened at the end of their message.
+fn last_span(text: &str) -> Option<Span> {
+    text.rmatch_indices("line ").find_map(|(start, _)| {
+        let (line, rest) = text[start + "line ".len()..].split_once(", column ")?;
+        let col = rest.split(|c: char| !c.is_ascii_digit()).next()?;
+        Some(Span { line: line.parse().ok()?, col: col.parse().ok()? })
+    })
+}
+
+/// What goes before the caret to line it up under `col` (tabs kept so it lines up whatever their width), and how
+/// many carets: the length of the word at `col`, at least one.
+fn underline(text: &str, col: u32) -> (String, usize) {
+    let before = text.chars().take(col.saturating_sub(1) as usize).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let word = text.chars().skip(col.saturating_sub(1) as usize).take_while(|c| c.is_alphanumeric() || *c == '_').count();
+    (before, word.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use interpreter::EvalError;
+
+    use crate::{Backend, Engine};
+
+    use super::*;
+
+    fn report(src: &str, backend: Backend) -> String {
+        let err = Engine::new(backend).eval(src).unwrap_err();
+        render_diagnostic(&Source::new("main.mk", src), &err)
+    }
+
+    #[test]
+    fn test_render_diagnostic() {
+        assert_eq!(report("let x = 1;\nprintln(xs)", Backend::Interpreter), [
+            "error[E0101]: Unknown variable: xs, did you mean `x`?",
+            " --> main.mk:2:9",
+            "  |",
+            "2 | println(xs)",
+            "  |         ^^",
+            "  = note: names have to be bound by a `let`, a fn param or the host",
+            "",
+        ].join("\n"));
+
+        assert_eq!(report("let x = (1 + ;", Backend::Interpreter), [
+            "error[E0001]: Unable to parse token in prefix position: Token { typ: Semicolon, literal: \";\", span: Some(Span { line: 1, col: 14 }) }",
+            " --> main.mk:1:14",
+            "  |",
+            "1 | let x = (1 + ;",
+            "  |              ^",
+            "  = note: nothing ran, programs only run once they parse",
+            "",
+        ].join("\n"));
+
+        // Errors raised in a fn point at the call, with the call stack as notes
+        let rendered = report("let f = fn(x) {\n\tx / 0\n};\nf(1)", Backend::Interpreter);
+        assert!(rendered.starts_with("error[E0100]: division by zero\n --> main.mk:4:1\n"), "{rendered}");
+        assert!(rendered.ends_with("  = note: call stack (most recent call last):\n  = note: at f (line 4, column 1)\n"), "{rendered}");
+        let rendered = report("let f = fn(x) {\n\tx + y\n}", Backend::Interpreter);
+        assert!(rendered.contains("2 | \tx + y\n  | \t    ^\n"), "{rendered}");
+
+        assert_eq!(report("true > [1][0]", Backend::Vm).lines().nth(3), Some("1 | true > [1][0]"));
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let err = EngineError::Eval(EvalError("Something went wrong".to_string()));
+        assert_eq!(render_diagnostic(&Source::new("main.mk", ""), &err), "error[E0100]: Something went wrong\n--> main.mk\n");
+        let colored = render_diagnostic_colored(&Source::new("main.mk", ""), &err);
+        assert!(colored.starts_with("\x1b[1;31merror[E0100]\x1b[0m"), "{colored:?}");
+    }
+}
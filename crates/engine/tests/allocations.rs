@@ -0,0 +1,45 @@
+//! Counts the heap allocations of evaluating closures, which share the body of the fn expression they're made from
+//! rather than copying it.
+
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell};
+
+use engine::{Backend, Engine};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The allocations of making `closures` closures whose body is `statements` statements long.
+fn closure_allocations(statements: usize, closures: usize) -> usize {
+    let body = vec!["let y = x * 2 + 1;"; statements].join(" ");
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.eval(&format!("let make = fn() {{ fn(x) {{ {body} y }} }};")).unwrap();
+    let script = engine.compile(&format!("[{}]", vec!["make()"; closures].join(", "))).unwrap();
+    script.run(&mut engine).unwrap();
+
+    let before = ALLOCATIONS.with(Cell::get);
+    script.run(&mut engine).unwrap();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn test_closures_share_their_body() {
+    // However long the body, making a closure costs the same
+    assert_eq!(closure_allocations(1, 20), closure_allocations(200, 20));
+}
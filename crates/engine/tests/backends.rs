@@ -0,0 +1,76 @@
+//! Runs the same programs through the interpreter and the compiler + VM, which must agree on what they evaluate to.
+//! Only covers what the VM compiles: no functions, calls or hashes yet.
+
+use engine::backends::run_backends;
+
+const PROGRAMS: &[&str] = &[
+    // arithmetic and comparisons
+    "1 + 2 * 3 - 4 / 2",
+    "-(1 - 3) * -2",
+    "0x1F & 0b1010 | 1 << 4 ^ -16 >> 2",
+    "let n = [2][0]; [2 * 3 ** n, n ** 3 ** n, -n ** n, n ** 0]",
+    "[1 < 2, 2 > 1, 1 == 1, true != false, \"abc\" < \"abd\"]",
+    "[!0, !5, !true, !!false]",
+    "\"mon\" + \"key\"",
+    // failures, whatever the message
+    "1 / 0",
+    "9223372036854775807 + 1",
+    "1 < true",
+    "let n = [2][0]; n ** -1",
+    "let n = [2][0]; n ** 63",
+    "\"a\" - \"b\"",
+    // bindings
+    "let x = 5;",
+    "let a = 1; let a = a + 1; a",
+    "let (q, r) = (17 / 5, 17 - 17 / 5 * 5); [q, r]",
+    "let (a, b) = (1, 2, 3);",
+    // conditionals, including branches with nothing or a `let` last
+    "const n = 4; const s = \"x\"; const (a, b) = (n, [s]); [n * 2, s + s, a, b]",
+    "let t = [true][0]; if (t) { 10 } else { 20 }",
+    "let f = [false][0]; if (f) { 10 }",
+    "let t = [true][0]; if (t) { }",
+    "let f = [false][0]; if (f) { 1 } else { }",
+    "let t = [true][0]; if (t) { let y = 2; }",
+    "let t = [true][0]; if (t) { let (a, b) = (1, 2); }",
+    "let t = [true][0]; if (t) { if (t) { 1; 3 } }",
+    // blocks scope their lets
+    "let x = 1; let t = [true][0]; let y = if (t) { let x = x + 1; x }; [x, y]",
+    "const c = 1; let t = [true][0]; let y = if (t) { const c = 2; c }; [c, y]",
+    "if (1) { 1 } else { 2 }",
+    // arrays, tuples, strings and indexing
+    "[1, [2, 3], (4, \"five\")]",
+    "[[1, 2, 3][0], [1, 2, 3][-1], [1, 2, 3][3]]",
+    "[1, 2, 3, 4][1:3]",
+    "\"héllo\"[1:3] + \"héllo\"[-1]",
+    "[1, 2] == [1, 2]",
+    "let n = [4][0]; [0..n, n..0, 1..n + 1 == [1, 2, 3, 4]]",
+    "0..9223372036854775807",
+    // null
+    "let n = [null][0]; [n ?? 1, 0 ?? n, n ?? n, n == null, null != 0]",
+    "let t = [true][0]; [t && 1, !t && 1 / 0, t || 1 / 0, !t || [0][0], [] || null]",
+    "let a = [1, [2, 3]]; [a == [1, [2, 3]], a != [1, [2]], (1, a) == (1, [1, [2, 3]])]",
+];
+
+#[test]
+fn test_backends_agree() {
+    for src in PROGRAMS {
+        let results = run_backends(src);
+        assert!(results.agree(), "{src}\n{results}");
+    }
+}
+
+#[test]
+fn test_disagreement_is_reported() {
+    // Hashes aren't compiled yet
+    let results = run_backends("{\"a\": 1}[\"a\"]");
+    assert!(!results.agree());
+    assert!(results.to_string().ends_with("backends differ"), "{results}");
+
+    // Failing isn't enough to agree: the VM rejects these when compiling, the interpreter only fails once it gets to
+    // the unknown name or the import
+    for src in ["let t = [true][0]; if (t) { let y = 2; }; y", "let a = 1; import \"nonexist\"; a"] {
+        let results = run_backends(src);
+        assert!(results.interpreter.is_err() && results.vm.is_err(), "{src}\n{results}");
+        assert!(!results.agree(), "{src}\n{results}");
+    }
+}
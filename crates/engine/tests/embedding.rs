@@ -0,0 +1,179 @@
+//! End-to-end checks of the workflows an embedding host goes through, using only the public `engine` API.
+
+use std::rc::Rc;
+
+use engine::{run, Backend, Bindings, Engine, EngineError, Level, LogBuffer, Logger, Object, Record, SharedBuffer};
+
+fn bindings(vars: &[(&str, Object)]) -> Bindings {
+    vars.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+}
+
+#[test]
+fn test_bindings_in_outputs_out() {
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let mut engine = Engine::new(backend);
+        let script = engine.compile("let total = price * qty; let big = total > 100;").unwrap().with_outputs(&["total", "big", "missing"]);
+
+        for (price, qty, total) in [(30, 2, 60), (30, 4, 120)] {
+            let vars = bindings(&[("price", Object::Integer(price)), ("qty", Object::Integer(qty))]);
+            let output = script.run_with(&mut engine, vars).unwrap();
+            assert_eq!(output.outputs["total"], Object::Integer(total), "{backend:?}");
+            assert_eq!(output.outputs["big"], Object::Boolean(total > 100), "{backend:?}");
+            assert!(!output.outputs.contains_key("missing"), "{backend:?}");
+        }
+        assert_eq!(engine.get_global("total"), Some(Object::Integer(120)), "{backend:?}");
+    }
+}
+
+#[test]
+fn test_limits_stop_runaway_scripts() {
+    let cases = [
+        (Backend::Interpreter, "let spin = fn(n) { spin(n + 1) }; spin(0)"),
+        (Backend::Vm, "[1, 1, 1, 1, 1, 1, 1, 1]"),
+    ];
+    for (backend, src) in cases {
+        let mut engine = Engine::new(backend);
+        engine.set_step_limit(Some(5));
+        let err = engine.eval(src).unwrap_err();
+        assert!(err.to_string().starts_with("Execution budget exceeded"), "{backend:?}: {err}");
+
+        // The engine stays usable and the limit can be lifted
+        engine.set_step_limit(None);
+        assert_eq!(engine.eval("2 * 3").unwrap(), Object::Integer(6), "{backend:?}");
+    }
+}
+
+#[test]
+fn test_max_call_depth() {
+    // Lowered to fit the small stack of a test thread, deep recursion fails instead of overflowing it
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_max_call_depth(20);
+    let err = engine.eval("let spin = fn(n) { spin(n + 1) }; spin(0)").unwrap_err();
+    assert!(err.to_string().starts_with("Maximum call depth of 20 exceeded"), "{err}");
+}
+
+#[test]
+fn test_output_and_diagnostics() {
+    let output = SharedBuffer::new();
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_output(output.clone());
+    engine.deprecate_builtin("first", "use arr[0] instead").unwrap();
+
+    let value = engine.eval("let xs = [4, 5]; println(first(xs)); len(xs)").unwrap();
+    assert_eq!(value, Object::Integer(2));
+    assert_eq!(output.take(), "4\n");
+    assert!(engine.take_diagnostics().iter().any(|diagnostic| diagnostic.message.contains("`first`")));
+    assert!(engine.take_diagnostics().is_empty());
+}
+
+#[test]
+fn test_logger_receives_events() {
+    let record = |level: Level, target: &'static str, message: &str| Record { level, target, message: message.to_string() };
+
+    let log = LogBuffer::new();
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_logger(Some(Rc::new(log.clone())));
+    engine.eval("let double = fn(x) { x * 2 }; double(len([1]))").unwrap();
+    assert_eq!(log.take(), [
+        record(Level::Debug, "interpreter", "calling builtin len with 1 args"),
+        record(Level::Debug, "interpreter", "calling function `double` with 1 args"),
+        record(Level::Trace, "interpreter", "creating the scope of function `double` with locals [x]"),
+    ]);
+
+    let mut engine = Engine::new(Backend::Vm);
+    engine.set_logger(Some(Rc::new(log.clone())));
+    engine.eval("[1][0] + 2").unwrap();
+    let records = log.take();
+    assert_eq!(records[0], record(Level::Trace, "vm", "executing 0000 Constant"));
+    assert!(records.contains(&record(Level::Trace, "vm", "Integer(1) + Integer(2) = Integer(3)")), "{records:?}");
+
+    // Events below the level a logger wants aren't even formatted
+    struct DebugOnly(LogBuffer);
+    impl Logger for DebugOnly {
+        fn enabled(&self, level: Level) -> bool {
+            level >= Level::Debug
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.log(record);
+        }
+    }
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.set_logger(Some(Rc::new(DebugOnly(log.clone()))));
+    engine.eval("let f = fn() { 1 }; f()").unwrap();
+    assert_eq!(log.take(), [record(Level::Debug, "interpreter", "calling function `f` with 0 args")]);
+}
+
+#[test]
+fn test_errors_surface_through_the_engine() {
+    let mut engine = Engine::new(Backend::Interpreter);
+    assert!(matches!(engine.eval("let = 1;"), Err(EngineError::Parse(_))));
+    assert_eq!(engine.eval("missing + 1").unwrap_err().to_string(), "Unknown variable: missing at line 1, column 1");
+
+    let mut engine = Engine::new(Backend::Vm);
+    assert!(matches!(engine.eval("fn(x) { x }"), Err(EngineError::Compile(_))));
+    assert!(matches!(engine.eval("true > false"), Err(EngineError::Runtime(_))));
+}
+
+#[test]
+fn test_register_builtin() {
+    let mut engine = Engine::new(Backend::Interpreter);
+    let rates = Bindings::from([("EUR".to_string(), Object::Integer(2))]);
+    engine.register_builtin("rate", move |args| match args.as_slice() {
+        [Object::String(currency)] => Ok(rates.get(currency).cloned().unwrap_or(Object::Null)),
+        _ => Ok(Object::Null),
+    });
+    assert_eq!(engine.eval(r#"10 * rate("EUR")"#).unwrap(), Object::Integer(20));
+    assert_eq!(engine.eval(r#"rate("GBP")"#).unwrap(), Object::Null);
+}
+
+#[test]
+fn test_register_builtin_with_caller() {
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.register_builtin_with_caller("twice", |args, caller| match args.as_slice() {
+        [f, x] => {
+            let once = caller.call_function(f, vec![x.clone()])?;
+            caller.call_function(f, vec![once])
+        },
+        _ => Ok(Object::Null),
+    });
+    assert_eq!(engine.eval("twice(fn(x) { x * 3 }, 2)").unwrap(), Object::Integer(18));
+    assert!(engine.eval("twice(fn() { 1 }, 2)").is_err());
+}
+
+#[test]
+fn test_hot_reload() {
+    let mut engine = Engine::new(Backend::Interpreter);
+    engine.eval("let rate = fn(x) { x * 2 }; let apply = fn(x) { rate(x) + 1 };").unwrap();
+    assert_eq!(engine.eval("apply(10)").unwrap(), Object::Integer(21));
+
+    engine.redefine("rate", "fn(x) { x * 3 }").unwrap();
+    assert_eq!(engine.eval("apply(10)").unwrap(), Object::Integer(31));
+    assert!(engine.redefine("nope", "fn() { 1 }").is_err());
+}
+
+#[test]
+fn test_run_string_in_string_out() {
+    assert_eq!(run(r#"let greet = fn(who) { "hi " + who }; println(greet("you")); 1 + 1"#), "hi you\n2\n");
+}
+
+#[test]
+fn test_eval_json() {
+    use serde_json::json;
+
+    for backend in [Backend::Interpreter, Backend::Vm] {
+        let mut engine = Engine::new(backend);
+        let scope = json!({"price": 30, "qty": 4, "tags": ["sale"], "limits": {"max": 100}});
+        assert_eq!(engine.eval_json(&scope, r#"[price * qty > limits["max"], tags[0]]"#).unwrap(), json!([true, "sale"]), "{backend:?}");
+        let total: i64 = engine.eval_json_as(&json!({"qty": 5}), "price * qty").unwrap();
+        assert_eq!(total, 150, "{backend:?}");
+
+        let err = engine.eval_json_as::<String>(&scope, "qty").unwrap_err();
+        assert!(err.to_string().starts_with("Unable to read the value as alloc::string::String"), "{backend:?}: {err}");
+        assert!(engine.eval_json(&json!({"ratio": 0.5}), "1").is_err(), "{backend:?}");
+    }
+
+    let mut engine = Engine::new(Backend::Interpreter);
+    let err = engine.eval_json(&json!({}), "fn(x) { x }").unwrap_err();
+    assert_eq!(err.to_string(), "Cannot convert fn to JSON");
+}
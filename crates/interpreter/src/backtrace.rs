@@ -0,0 +1,107 @@
+use std::fmt;
+
+use parser::{ast::Expression, lexer::token::Span};
+
+/// Header of the call stack rendered into errors, also used to tell errors that already carry one.
+pub(crate) const CALL_STACK_HEADER: &str = "call stack (most recent call last):";
+
+/// A function being called, named after the expression it was called through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub call_site: Option<Span>,
+}
+
+impl Frame {
+    pub fn new(function: &Expression) -> Self {
+        let name = match function {
+            Expression::Identifier { value, .. } => value.clone(),
+            Expression::Function { .. } => "fn".to_string(),
+            _ => "<anonymous>".to_string(),
+        };
+        Self { name, call_site: function.span() }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.call_site {
+            Some(span) => write!(f, "{} ({span})", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Longest run of frames that is checked for repetition, enough for a handful of mutually recursive functions.
+const MAX_CYCLE_LEN: usize = 8;
+/// A cycle has to repeat at least this many times before it gets collapsed.
+const MIN_CYCLE_REPS: usize = 3;
+
+/// Renders call frames (outermost first), one per line, collapsing repeated cycles of frames into a single
+/// `... N more frames of f -> g -> f ...` line.
+pub fn collapse_frames(frames: &[Frame]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < frames.len() {
+        let (len, reps) = longest_cycle_at(frames, i);
+        if reps < MIN_CYCLE_REPS {
+            lines.push(format!("at {}", frames[i]));
+            i += 1;
+            continue;
+        }
+
+        let cycle = &frames[i..i + len];
+        lines.extend(cycle.iter().map(|frame| format!("at {frame}")));
+        let path = cycle.iter().chain(std::iter::once(&cycle[0])).map(|frame| frame.name.as_str()).collect::<Vec<&str>>().join(" -> ");
+        lines.push(format!("... {} more frames of {path} ...", (reps - 1) * len));
+        i += reps * len;
+    }
+    lines
+}
+
+/// The (cycle length, repetitions) starting at `start` that covers the most frames.
+fn longest_cycle_at(frames: &[Frame], start: usize) -> (usize, usize) {
+    let mut best = (1, 1);
+    for len in 1..=MAX_CYCLE_LEN.min(frames.len() - start) {
+        let cycle = &frames[start..start + len];
+        let reps = frames[start..]
+            .chunks_exact(len)
+            .take_while(|chunk| *chunk == cycle)
+            .count();
+        if reps > 1 && reps * len > best.0 * best.1 {
+            best = (len, reps);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(names: &[&str]) -> Vec<Frame> {
+        names.iter().map(|name| Frame { name: name.to_string(), call_site: None }).collect()
+    }
+
+    #[test]
+    fn test_collapse_frames() {
+        assert_eq!(collapse_frames(&frames(&["main", "f", "g"])), vec!["at main", "at f", "at g"]);
+
+        let mut mutual = frames(&["main"]);
+        for _ in 0..500 {
+            mutual.extend(frames(&["f", "g"]));
+        }
+        mutual.extend(frames(&["f"]));
+        assert_eq!(collapse_frames(&mutual), vec![
+            "at main",
+            "at f",
+            "at g",
+            "... 998 more frames of f -> g -> f ...",
+            "at f",
+        ]);
+
+        // Two repetitions aren't worth collapsing
+        assert_eq!(collapse_frames(&frames(&["f", "f"])), vec!["at f", "at f"]);
+        assert_eq!(collapse_frames(&frames(&["f", "f", "f"])), vec!["at f", "... 2 more frames of f -> f ..."]);
+    }
+}
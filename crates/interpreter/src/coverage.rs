@@ -0,0 +1,113 @@
+use std::{collections::{BTreeMap, BTreeSet}, convert::Infallible, fmt, path::{Path, PathBuf}};
+
+use parser::{ast::{walk_statement, Statement, Visitor}, Program};
+
+/// The lines of each file that statements started on as they ran, see [`crate::Interpreter::set_coverage`]. Files
+/// are keyed by their canonical path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    lines: BTreeMap<PathBuf, BTreeSet<u32>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, file: &Path, line: u32) {
+        match self.lines.get_mut(file) {
+            Some(lines) => { lines.insert(line); },
+            None => { self.lines.insert(file.to_path_buf(), BTreeSet::from([line])); },
+        }
+    }
+
+    /// Adds the lines `other` saw run, e.g. to sum up the runs of a test suite.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (file, lines) in &other.lines {
+            self.lines.entry(file.clone()).or_default().extend(lines);
+        }
+    }
+
+    /// The lines of `file` that ran, none if nothing of it did.
+    pub fn executed(&self, file: &Path) -> BTreeSet<u32> {
+        self.lines.get(file).cloned().unwrap_or_default()
+    }
+
+    /// How much of `program`, parsed from `file`, ran. `file` is reported as given but looked up canonicalized.
+    pub fn file_coverage(&self, file: &Path, program: &Program) -> FileCoverage {
+        let executed = file.canonicalize().map(|file| self.executed(&file)).unwrap_or_default();
+        let lines = statement_lines(program);
+        let mut uncovered: Vec<(u32, u32)> = Vec::new();
+        let mut previous_covered = true;
+        for line in &lines {
+            let covered = executed.contains(line);
+            match uncovered.last_mut() {
+                // Runs of uncovered statements are one range, whatever the lines between them hold
+                Some((_, end)) if !covered && !previous_covered => *end = *line,
+                _ if !covered => uncovered.push((*line, *line)),
+                _ => {},
+            }
+            previous_covered = covered;
+        }
+        FileCoverage {
+            file: file.to_path_buf(),
+            lines: lines.len(),
+            covered: lines.intersection(&executed).count(),
+            uncovered,
+        }
+    }
+}
+
+/// The lines statements of `program` start on, those of fn bodies and blocks included: what can be covered.
+pub fn statement_lines(program: &Program) -> BTreeSet<u32> {
+    struct Lines(BTreeSet<u32>);
+
+    impl Visitor for Lines {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            // Blocks don't run as statements of their own, their statements do
+            if let (false, Some(span)) = (matches!(statement, Statement::Block { .. }), statement.span()) {
+                self.0.insert(span.line);
+            }
+            walk_statement(self, statement)
+        }
+    }
+
+    let mut lines = Lines(BTreeSet::new());
+    let Ok(()) = lines.visit_program(program);
+    lines.0
+}
+
+/// How much of a file ran: of the lines statements start on, how many did and the ranges of those that didn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCoverage {
+    pub file: PathBuf,
+    pub lines: usize,
+    pub covered: usize,
+    pub uncovered: Vec<(u32, u32)>, // first and last line of each run of uncovered statements
+}
+
+impl FileCoverage {
+    /// The share of lines covered, a file without statements is fully covered.
+    pub fn percent(&self) -> f64 {
+        match self.lines {
+            0 => 100.0,
+            lines => self.covered as f64 * 100.0 / lines as f64,
+        }
+    }
+}
+
+impl fmt::Display for FileCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:.1}% ({}/{} lines)", self.file.display(), self.percent(), self.covered, self.lines)?;
+        if !self.uncovered.is_empty() {
+            let ranges = self.uncovered.iter().map(|(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{start}-{end}"),
+            });
+            write!(f, ", uncovered: {}", ranges.collect::<Vec<String>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::{HashMap, HashSet}, convert::Infallible, fmt};
+
+use parser::{ast::{self, walk_expression, walk_statement, Expression, Statement, Visitor}, Program};
+
+use crate::interpreter::order_arguements;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, message: message.into() }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, message: message.into() }
+    }
+
+    pub(crate) fn deprecated(name: &str, note: &str) -> Self {
+        Self::warning(format!("`{name}` is deprecated: {note}"))
+    }
+
+    pub(crate) fn deprecated_call(name: &str, note: &str) -> Self {
+        Self::warning(format!("called deprecated builtin `{name}`: {note}"))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}", self.message)
+    }
+}
+
+/// Finds references to deprecated builtins that the program doesn't rebind itself.
+pub(crate) struct DeprecationCheck<'a> {
+    deprecations: &'a HashMap<String, String>,
+    bound: HashSet<String>,
+    used: Vec<String>,
+}
+
+impl<'a> DeprecationCheck<'a> {
+    pub(crate) fn run(deprecations: &'a HashMap<String, String>, program: &Program) -> Vec<Diagnostic> {
+        if deprecations.is_empty() {
+            return Vec::new();
+        }
+
+        let mut check = Self { deprecations, bound: HashSet::new(), used: Vec::new() };
+        let Ok(()) = check.visit_program(program);
+
+        let mut reported = HashSet::new();
+        check.used
+            .iter()
+            .filter(|name| !check.bound.contains(*name) && reported.insert(*name))
+            .map(|name| Diagnostic::deprecated(name, &check.deprecations[name]))
+            .collect()
+    }
+}
+
+impl Visitor for DeprecationCheck<'_> {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        if let Statement::Let { name, value, .. } = statement {
+            let names = match name {
+                Expression::Tuple { elements, .. } => elements.iter().collect(),
+                name => vec![name],
+            };
+            for name in names {
+                if let Expression::Identifier { value, .. } = name {
+                    self.bound.insert(value.clone());
+                }
+            }
+            return self.visit_expression(value);
+        }
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        match expression {
+            ast::Expression::Identifier { value, .. } => {
+                if self.deprecations.contains_key(value) {
+                    self.used.push(value.clone());
+                }
+                Ok(())
+            },
+            ast::Expression::Function { params, body, .. } => {
+                for param in params {
+                    if let Some(name) = param.param_name() {
+                        self.bound.insert(name.to_string());
+                    }
+                }
+                for default in params.iter().filter_map(Expression::param_default) {
+                    self.visit_expression(default)?;
+                }
+                self.visit_statement(body)
+            },
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+/// The parameters of a fn literal, as far as checking calls to it goes.
+struct Signature {
+    parameters: Vec<String>, // without the rest param
+    required: usize,         // how many have no default
+    rest: bool,
+}
+
+impl Signature {
+    fn new(params: &[Expression]) -> Self {
+        let (rest, params): (Vec<&Expression>, Vec<&Expression>) = params.iter().partition(|param| param.is_rest_param());
+        Self {
+            parameters: params.iter().filter_map(|param| param.param_name().map(str::to_string)).collect(),
+            required: params.iter().filter(|param| param.param_default().is_none()).count(),
+            rest: !rest.is_empty(),
+        }
+    }
+
+    fn check(&self, arguements: &[Expression]) -> Result<(), String> {
+        let slots = order_arguements(&self.parameters, arguements)?;
+        if !self.rest && slots.len() > self.parameters.len() {
+            return Err(format!("expected at most {} args, got: {}", self.parameters.len(), arguements.len()));
+        }
+        match slots.iter().zip(&self.parameters).take(self.required).find(|(slot, _)| slot.is_none()) {
+            Some((_, parameter)) => Err(format!("missing arguement `{parameter}`")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Checks named arguements against the parameters of functions bound with `let name = fn(...)`, so mistakes are
+/// reported before the call runs.
+pub(crate) struct NamedArgCheck {
+    signatures: HashMap<String, Signature>,
+    collecting: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl NamedArgCheck {
+    pub(crate) fn run(program: &Program) -> Vec<Diagnostic> {
+        let mut check = Self { signatures: HashMap::new(), collecting: true, diagnostics: Vec::new() };
+        let Ok(()) = check.visit_program(program);
+        check.collecting = false;
+        let Ok(()) = check.visit_program(program);
+        check.diagnostics
+    }
+}
+
+impl Visitor for NamedArgCheck {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        if let (true, Statement::Let { name: Expression::Identifier { value: name, .. }, value, .. }) = (self.collecting, statement) {
+            match value {
+                Expression::Function { params, .. } => {
+                    self.signatures.insert(name.clone(), Signature::new(params));
+                },
+                // Rebinding to something that isn't a fn literal makes the signature unknown
+                _ => { self.signatures.remove(name); },
+            }
+        }
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        if let (false, Expression::Call { function, arguements, .. }) = (self.collecting, expression) {
+            let has_named = arguements.iter().any(|arguement| matches!(arguement, Expression::NamedArg { .. }));
+            if let (true, Expression::Identifier { value: name, .. }) = (has_named, function.as_ref()) {
+                if let Some(Err(msg)) = self.signatures.get(name).map(|signature| signature.check(arguements)) {
+                    self.diagnostics.push(Diagnostic::error(format!("In call to `{name}`: {msg}")));
+                }
+            }
+        }
+        walk_expression(self, expression)
+    }
+}
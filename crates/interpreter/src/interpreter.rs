@@ -1,151 +1,187 @@
-use std::{cell::RefCell, collections::HashMap, hash::{DefaultHasher, Hash, Hasher}, rc::{Rc, Weak}};
+use std::{cell::{Cell, RefCell}, cmp::Ordering, collections::HashMap, fs, io, path::{Path, PathBuf}, rc::Rc, sync::{atomic::{self, AtomicBool}, Arc}, time::{Duration, Instant}};
 
-use parser::{ast::{self, Expression, Statement}, Program};
+use parser::{analysis::{find_const_redeclarations, find_returns_outside_functions, find_undefined}, intern::{Interner, Symbol}, optimize, ast::{self, Expression, Statement}, lexer::{token::Span, Lexer}, Parser, Program};
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub struct EvalError(String);
-
-#[derive(Debug, Clone)]
-pub enum Object {
-    Integer(isize),
-    Boolean(bool),
-    String(String),
-    Array(Vec<Self>),
-    KVPair(Box<Self>, Box<Self>),
-    HashMap(HashMap<HashKey, Self>),
-    Return(Box<Self>),
-    Function {
-        parameters: Vec<String>, // Identifiers
-        body: ast::Statement,    // Block statement
-        fn_env: Weak<RefCell<Environment>>,
-    },
-    Null,
-
-    BuiltIn(fn(Vec<Object>) -> Result<Object, EvalError>)
-}
+use crate::{backtrace::{collapse_frames, Frame, CALL_STACK_HEADER}, coverage::Coverage, diagnostics::{DeprecationCheck, Diagnostic, NamedArgCheck}};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct HashKey {
-    pub typ: String,
-    pub value: usize,
-}
+/// Every Monkey call nests several Rust frames: about 45KiB of stack per call in a debug build and 8KiB in a release
+/// one. Reaching this depth takes some 64MiB in debug and more than the 8MiB of a main thread in release, so hosts
+/// should evaluate on a thread with a larger stack (`mk_run` uses 256MiB) or lower the depth to fit theirs.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
 
-impl HashKey {
-    pub fn get_hash_key(object: &Object) -> Result<Self, EvalError> {
-        match object {
-            Object::Integer(value) => Ok(Self { typ: "int".to_string(), value: *value as usize}),
-            Object::Boolean(value) => Ok(Self { typ: "bool".to_string(), value: if *value {1} else {0}}),
-            Object::String(value) => {
-                let mut hasher = DefaultHasher::new();
-                value.hash(&mut hasher);
-                Ok(Self { typ: "str".to_string(), value: hasher.finish() as usize})
-            },
-            _ => Err(EvalError(format!("Cannot hash object: {object:?}"))),
-        }
-    }
+pub use object::{bindings_from_json, from_json, to_json, BuiltinFn, Caller, Env, Environment, EvalError, HashKey, InputSource, Level, LogBuffer, Logger, Object, OutputSink, Record, SharedBuffer, Stdin, StderrLogger, Stdout, StringInput};
+use object::{log_event, normalize_index, parse_json, range, sorted_entries, stringify_json, NULL};
+
+/// Opt-in host access for builtins; everything is disabled by default so scripts stay sandboxed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub fs: bool,
+    pub env: bool, // environment variables, through `env`
+    pub stdin: bool, // the interpreter's input, through `read_line` and `read_all`
 }
 
-impl Object {
-    pub fn construct_fn(parameters: &Vec<ast::Expression>, body: &ast::Statement, env: &Env) -> Result<Object, EvalError> {
-        let mut param_names: Vec<String> = Vec::new();
-        if matches!(body, ast::Statement::Block { .. }) {
-            for param in parameters {
-                if let ast::Expression::Identifier { value, .. } = param {
-                    param_names.push(value.to_string());
-                } else {
-                    return Err(EvalError(format!("Invalid fn parameters: {parameters:?}, all parameters must be Identifiers, got: {param:?}")));
-                }
-            }
-            Ok(Self::Function { parameters: param_names, body: body.clone(), fn_env: Rc::downgrade(&env) })
-        } else {
-            return Err(EvalError(format!("Invalid fn body: {body:?}, must be Block statemnt")))
-        }
+impl Capabilities {
+    pub fn all() -> Self {
+        Self { fs: true, env: true, stdin: true }
     }
+}
 
-    pub fn unwrap_return(self) -> Self {
-        if let Self::Return(return_val) = self {
-            return return_val.unwrap_return()
-        }
-        self
-    }
+/// How indexing treats missing elements: `Lenient` (the default) yields `null` for out-of-range array indexes and
+/// missing hash keys, `Strict` reports both as errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexMode {
+    Strict,
+    #[default]
+    Lenient,
+}
 
-    // pub fn unwrap_kv_pair(self) -> Self {
-    //     if let Self::KVPair(, )
-    // }
+/// How an unknown variable is treated: `Error` (the default) aborts evaluation, `NullWithWarning` evaluates it to
+/// `null` and records a warning diagnostic, for migrating scripts that relied on that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownVariableMode {
+    #[default]
+    Error,
+    NullWithWarning,
 }
 
-pub type Env = Rc<RefCell<Environment>>;
+/// Everything observable about one run, see [`Interpreter::evaluate_program_outcome`].
 #[derive(Debug)]
-pub struct Environment {
-    vars: HashMap<String, Object>,
-    outer: Option<Env>
+pub struct EvalOutcome {
+    pub value: Result<Object, EvalError>,
+    pub stdout: String, // what the program printed
+    pub steps: usize, // expressions evaluated, the unit of `set_step_limit`
+    pub duration: Duration,
 }
 
-impl Environment {
-    pub fn new(outer: Option<Env>) -> Self {
-        Self {
-            vars: HashMap::new(),
-            outer,
+/// Lines call arguements up with `parameters`: positional arguements fill parameters in order, then each
+/// `name: value` fills the parameter called `name`. Every parameter must end up with exactly one arguement.
+pub(crate) fn order_arguements<'a>(parameters: &[String], arguements: &'a [Expression]) -> Result<Vec<Option<&'a Expression>>, String> {
+    let mut slots: Vec<Option<&Expression>> = vec![None; parameters.len()];
+    let mut positional = 0;
+    for arguement in arguements {
+        match arguement {
+            Expression::NamedArg { name, value, .. } => {
+                let idx = parameters
+                    .iter()
+                    .position(|parameter| parameter == name)
+                    .ok_or_else(|| format!("unknown named arguement `{name}`, expected one of: {}", parameters.join(", ")))?;
+                if slots[idx].replace(value).is_some() {
+                    return Err(format!("arguement `{name}` given more than once"));
+                }
+            },
+            // Positional arguements past the parameters are kept, so the arity error can count them
+            arguement if positional >= parameters.len() => slots.push(Some(arguement)),
+            arguement => {
+                slots[positional] = Some(arguement);
+                positional += 1;
+            },
         }
     }
+    Ok(slots)
+}
 
-    pub fn get(&self, name: &str) -> Option<Object> {
-        if let Some(obj) = self.vars.get(name) {
-            return Some(obj.clone());
-        }
-
-        if let Some(outer_env) = &self.outer {
-            return outer_env.borrow().get(name);
-        }
+/// Stands in for `with(hash, fn)`, which needs the interpreter to call `fn` and so is intercepted in
+/// `eval_call_expression` before a builtin would be called.
+fn with_builtin(_: Vec<Object>) -> Result<Object, EvalError> {
+    Err(EvalError("Built-in `with` can only be called by the interpreter".to_string()))
+}
 
-        None
+fn array_arg<'a>(builtin: &str, arg: &'a Object) -> Result<&'a Vec<Object>, EvalError> {
+    match arg {
+        Object::Array(arr) => Ok(arr),
+        arg => Err(EvalError(format!("Can't call built-in fn `{builtin}` on type: {arg:?}"))),
     }
+}
 
-    pub fn set(&mut self, name: &str, val: Object) {
-        self.vars.insert(name.to_string(), val);
+/// Checks and returns the arguement of `println`.
+fn println_arg(args: Vec<Object>) -> Result<Object, EvalError> {
+    match args.as_slice() {
+        [val @ (Object::String(_) | Object::Integer(_) | Object::Boolean(_))] => Ok(val.clone()),
+        [val] => Err(EvalError(format!("Can't call built-in fn `println` on type: {val:?}"))),
+        args => Err(EvalError(format!("Error in built-in println, expected 1 argument, got: {}", args.len()))),
     }
 }
 
+
+/// What coverage is recorded with while a program runs.
+#[derive(Default)]
+struct CoverageRun {
+    coverage: Coverage,
+    files: Vec<Option<PathBuf>>, // the file of the code running, innermost last, `None` for code that isn't a file's
+    fn_files: HashMap<*const Statement, (Rc<Statement>, PathBuf)>, // keyed by body, kept so the address isn't reused
+}
+
 pub struct Interpreter {
     envs: RefCell<Vec<Env>>,
+    index_mode: Cell<IndexMode>,
+    unknown_variable_mode: Cell<UnknownVariableMode>,
+    module_dir: RefCell<PathBuf>,
+    import_roots: RefCell<Vec<PathBuf>>, // where imports not found next to the importer are looked for, in order
+    module_stack: RefCell<Vec<PathBuf>>, // canonical paths of the modules currently being evaluated
+    modules: RefCell<HashMap<PathBuf, Env>>,
+    deprecations: RefCell<HashMap<String, String>>, // builtin name -> note
+    runtime_deprecation_warnings: Cell<bool>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    call_stack: RefCell<Vec<Frame>>, // the functions currently being called, outermost first
+    max_call_depth: Cell<usize>,
+    step_limit: Cell<Option<usize>>,
+    steps: Cell<usize>, // expressions evaluated by the current run
+    interrupt: RefCell<Option<Arc<AtomicBool>>>,
+    running: Cell<bool>,
+    coverage: RefCell<Option<CoverageRun>>,
+    optimize: Cell<bool>,
+    output: Rc<RefCell<Box<dyn OutputSink>>>, // shared with `println`
+    script_args: Rc<RefCell<Vec<String>>>, // shared with `args`
+    input: Rc<RefCell<Box<dyn InputSource>>>, // shared with `read_line` and `read_all`
+    logger: RefCell<Option<Rc<dyn Logger>>>,
+    with_builtin: BuiltinFn,
+    interner: Interner, // shared by the parses of the modules this imports
 }
 
 impl Interpreter {
-    pub fn new(mut global_env: Environment) -> Self {
-        fn check_num_args(args: &Vec<Object>, num_args: usize) -> Result<(), EvalError> {
-            if args.len() != num_args {  Err(EvalError(format!("Error in built-in len, expected 1 arguement, got: {}", args.len()))) } else { Ok(()) }
+    pub fn new(global_env: Environment) -> Self {
+        Self::new_with_capabilities(global_env, Capabilities::default())
+    }
+
+    pub fn new_with_capabilities(mut global_env: Environment, capabilities: Capabilities) -> Self {
+        fn check_num_args(builtin: &str, args: &[Object], num_args: usize) -> Result<(), EvalError> {
+            match args.len() == num_args {
+                true => Ok(()),
+                false if num_args == 1 => Err(EvalError(format!("Error in built-in {builtin}, expected 1 argument, got: {}", args.len()))),
+                false => Err(EvalError(format!("Error in built-in {builtin}, expected {num_args} arguments, got: {}", args.len()))),
+            }
         }
-        global_env.set("len", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        global_env.set("len", Object::builtin(|args| {
+            check_num_args("len", &args, 1)?;
             match &args[0] {
-                Object::String(str) => Ok(Object::Integer(str.len() as isize)),
+                // In chars, like indexes and slices count
+                Object::String(str) => Ok(Object::Integer(str.chars().count() as isize)),
                 Object::Array(arr) => Ok(Object::Integer(arr.len() as isize)),
                 _ => Err(EvalError(format!("Can't call built-in fn `len` on type: {:?}", args[0])))
             }
         }));
 
-        global_env.set("first", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        global_env.set("first", Object::builtin(|args| {
+            check_num_args("first", &args, 1)?;
             match &args[0] {
-                Object::Array(arr) => Ok( if arr.len() > 0 { arr[0].clone() } else { Object::Null }),
+                Object::Array(arr) => Ok( if !arr.is_empty() { arr[0].clone() } else { Object::Null }),
                 _ => Err(EvalError(format!("Can't call built-in fn `first` on type: {:?}", args[0])))
             }
         }));
 
-        global_env.set("last", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        global_env.set("last", Object::builtin(|args| {
+            check_num_args("last", &args, 1)?;
             match &args[0] {
-                Object::Array(arr) => Ok( if arr.len() > 0 { arr[arr.len() - 1].clone() } else { Object::Null }),
+                Object::Array(arr) => Ok( if !arr.is_empty() { arr[arr.len() - 1].clone() } else { Object::Null }),
                 _ => Err(EvalError(format!("Can't call built-in fn `last` on type: {:?}", args[0])))
             }
         }));
 
-        global_env.set("rest", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        global_env.set("rest", Object::builtin(|args| {
+            check_num_args("rest", &args, 1)?;
             match &args[0] {
                 Object::Array(arr) => 
-                    Ok( if arr.len() > 0 { 
+                    Ok( if !arr.is_empty() { 
                         let mut arr = arr.clone(); 
                         arr.remove(0); 
                         Object::Array(arr) 
@@ -154,10 +190,11 @@ impl Interpreter {
             }
         }));
 
-        global_env.set("push", Object::BuiltIn(|args| {
-            check_num_args(&args, 2)?;
+        global_env.set("push", Object::builtin_seeing_frozen(|args| {
+            check_num_args("push", &args, 2)?;
             match (&args[0], &args[1]) {
-                (Object::Array(arr), val @ _) => {
+                (Object::Frozen(_), _) => Err(EvalError(format!("Can't push to a frozen {}, `clone` it first", args[0].type_name()))),
+                (Object::Array(arr), val) => {
                     let mut arr = arr.clone();
                     arr.push(val.clone());
                     Ok(Object::Array(arr))
@@ -166,58 +203,729 @@ impl Interpreter {
             }
         }));
 
-        global_env.set("print", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        global_env.set("map", Object::builtin_with_caller(|args, caller| {
+            check_num_args("map", &args, 2)?;
+            let arr = array_arg("map", &args[0])?;
+            arr.iter().map(|elem| caller.call_function(&args[1], vec![elem.clone()])).collect::<Result<_, _>>().map(Object::Array)
+        }));
+
+        global_env.set("filter", Object::builtin_with_caller(|args, caller| {
+            check_num_args("filter", &args, 2)?;
+            let mut kept = Vec::new();
+            for elem in array_arg("filter", &args[0])? {
+                if caller.call_function(&args[1], vec![elem.clone()])?.is_truthy() {
+                    kept.push(elem.clone());
+                }
+            }
+            Ok(Object::Array(kept))
+        }));
+
+        global_env.set("reduce", Object::builtin_with_caller(|args, caller| {
+            check_num_args("reduce", &args, 3)?;
+            array_arg("reduce", &args[0])?
+                .iter()
+                .try_fold(args[1].clone(), |acc, elem| caller.call_function(&args[2], vec![acc, elem.clone()]))
+        }));
+
+        // `sort(arr)` orders like `cmp`, `sort(arr, f)` by `f(a, b)` returning an int below, at or above 0
+        global_env.set("sort", Object::builtin_with_caller(|args, caller| {
+            let (arr, compare) = match args.as_slice() {
+                [arr] => (array_arg("sort", arr)?, None),
+                [arr, compare] => (array_arg("sort", arr)?, Some(compare)),
+                args => return Err(EvalError(format!("Error in built-in sort, expected 1 or 2 arguments, got: {}", args.len()))),
+            };
+            let mut sorted = arr.clone();
+            let mut error = None;
+            sorted.sort_by(|a, b| {
+                let ordering = match compare {
+                    None => a.total_cmp(b),
+                    Some(compare) => match caller.call_function(compare, vec![a.clone(), b.clone()]) {
+                        Ok(Object::Integer(ordering)) => Ok(ordering.cmp(&0)),
+                        Ok(other) => Err(EvalError(format!("Error in built-in sort, comparator must return an int, got: {other}"))),
+                        Err(err) => Err(err),
+                    },
+                };
+                ordering.unwrap_or_else(|err| {
+                    error.get_or_insert(err);
+                    Ordering::Equal
+                })
+            });
+            match error {
+                Some(err) => Err(err),
+                None => Ok(Object::Array(sorted)),
+            }
+        }));
+
+        global_env.set("reverse", Object::builtin(|args| {
+            check_num_args("reverse", &args, 1)?;
+            Ok(Object::Array(array_arg("reverse", &args[0])?.iter().rev().cloned().collect()))
+        }));
+
+        // Same bounds as `x[start:end]`, `end` may be left out
+        global_env.set("slice", Object::builtin(|args| match args.as_slice() {
+            [target, start] => target.slice(start, &Object::Null),
+            [target, start, end] => target.slice(start, end),
+            args => Err(EvalError(format!("Error in built-in slice, expected 2 or 3 arguments, got: {}", args.len()))),
+        }));
+
+        // `range(end)` counts from 0 like `0..end`, `range(start, end, step)` steps down for a negative `step`
+        global_env.set("range", Object::builtin(|args| {
+            let ints = args.iter().map(|arg| match arg {
+                Object::Integer(int) => Ok(*int),
+                arg => Err(EvalError(format!("Can't call built-in fn `range` on type: {arg:?}"))),
+            }).collect::<Result<Vec<isize>, EvalError>>()?;
+            match ints.as_slice() {
+                [end] => range(0, *end, 1),
+                [start, end] => range(*start, *end, 1),
+                [start, end, step] => range(*start, *end, *step),
+                ints => Err(EvalError(format!("Error in built-in range, expected 1 to 3 arguments, got: {}", ints.len()))),
+            }
+        }));
+
+        global_env.set("concat", Object::builtin(|args| {
+            let mut joined = Vec::new();
+            for arg in &args {
+                joined.extend(array_arg("concat", arg)?.iter().cloned());
+            }
+            Ok(Object::Array(joined))
+        }));
+
+        global_env.set("contains", Object::builtin(|args| {
+            check_num_args("contains", &args, 2)?;
+            Ok(Object::Boolean(array_arg("contains", &args[0])?.contains(&args[1])))
+        }));
+
+        global_env.set("cmp", Object::builtin(|args| {
+            check_num_args("cmp", &args, 2)?;
+            Ok(Object::Integer(args[0].total_cmp(&args[1])? as isize))
+        }));
+
+        global_env.set("clone", Object::builtin(|args| {
+            check_num_args("clone", &args, 1)?;
+            // Arrays, tuples and hashes own their elements, so this copies the whole value. Builtins are passed
+            // frozen values thawed, so the copy isn't frozen
+            Ok(args[0].clone())
+        }));
+
+        global_env.set("freeze", Object::builtin(|mut args| {
+            check_num_args("freeze", &args, 1)?;
+            Ok(args.remove(0).freeze())
+        }));
+
+        global_env.set("is_frozen", Object::builtin_seeing_frozen(|args| {
+            check_num_args("is_frozen", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::Frozen(_))))
+        }));
+
+        global_env.set("print", Object::builtin(|args| {
+            check_num_args("print", &args, 1)?;
             match &args[0] {
                 Object::String(str) => Ok(Object::String(str.to_string())),
                 _ => Err(EvalError(format!("Can't call built-in fn `print` on type: {:?}", args[0])))
             }
         }));
 
-        global_env.set("println", Object::BuiltIn(|args| {
-            check_num_args(&args, 1)?;
+        let output: Rc<RefCell<Box<dyn OutputSink>>> = Rc::new(RefCell::new(Box::new(Stdout)));
+        let sink = Rc::clone(&output);
+        global_env.set("println", Object::builtin(move |args| {
+            let val = println_arg(args)?;
+            sink.borrow_mut().write_str(&format!("{val}\n"));
+            Ok(val)
+        }));
+
+        let script_args: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+        let shared_args = Rc::clone(&script_args);
+        global_env.set("args", Object::builtin(move |args| {
+            check_num_args("args", &args, 0)?;
+            Ok(Object::Array(shared_args.borrow().iter().cloned().map(Object::String).collect()))
+        }));
+
+        global_env.set("int", Object::builtin(|args| {
+            check_num_args("int", &args, 1)?;
+            match &args[0] {
+                Object::Integer(val) => Ok(Object::Integer(*val)),
+                Object::Boolean(val) => Ok(Object::Integer(if *val { 1 } else { 0 })),
+                Object::String(val) => Ok(val.trim().parse::<isize>().map(Object::Integer).unwrap_or(Object::Null)),
+                _ => Err(EvalError(format!("Can't call built-in fn `int` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("to_string", Object::builtin(|args| {
+            check_num_args("to_string", &args, 1)?;
+            match &args[0] {
+                Object::String(val) => Ok(Object::String(val.to_string())),
+                Object::Integer(val) => Ok(Object::String(val.to_string())),
+                Object::Boolean(val) => Ok(Object::String(val.to_string())),
+                Object::Null => Ok(Object::String("null".to_string())),
+                Object::Error(message) => Ok(Object::String(message.to_string())),
+                _ => Err(EvalError(format!("Can't call built-in fn `to_string` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("chars", Object::builtin(|args| {
+            check_num_args("chars", &args, 1)?;
             match &args[0] {
-                Object::String(val) => println!("{}", val),
-                Object::Integer(val) => println!("{}", val),
-                Object::Boolean(val) => println!("{}", val),
-                _ => return Err(EvalError(format!("Can't call built-in fn `println` on type: {:?}", args[0])))
+                Object::String(val) => Ok(Object::Array(val.chars().map(|c| Object::String(c.to_string())).collect())),
+                _ => Err(EvalError(format!("Can't call built-in fn `chars` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("bind", Object::builtin(|mut args| {
+            if args.is_empty() {
+                return Err(EvalError("Error in built-in bind, expected a function to bind arguements to".to_string()));
+            }
+            let function = args.remove(0);
+            function.bind(args)
+        }));
+
+        let with = BuiltinFn::new(with_builtin);
+        global_env.set("with", Object::BuiltIn(with.clone()));
+
+        // `assert(cond)` or `assert(cond, msg)`, failing with where it was called from
+        global_env.set("assert", Object::builtin_with_caller(|args, caller| {
+            let (cond, message) = match args.as_slice() {
+                [cond] => (cond, None),
+                [cond, Object::String(message)] => (cond, Some(message)),
+                _ => return Err(EvalError(format!("Error in built-in assert, expected a condition and an optional message, got: {args:?}"))),
             };
-            Ok(args[0].clone())
+            if cond.is_truthy() {
+                return Ok(Object::Null);
+            }
+            let at = caller.call_site().map(|span| format!(" at {span}")).unwrap_or_default();
+            Err(EvalError(match message {
+                Some(message) => format!("Assertion failed{at}: {message}"),
+                None => format!("Assertion failed{at}"),
+            }))
+        }));
+
+        global_env.set("error", Object::builtin(|args| {
+            check_num_args("error", &args, 1)?;
+            match &args[0] {
+                Object::String(message) => Ok(Object::Error(message.to_string())),
+                _ => Err(EvalError(format!("Can't call built-in fn `error` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("is_error", Object::builtin(|args| {
+            check_num_args("is_error", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::Error(_))))
         }));
 
+        // `rescue(f)` calls `f()`, turning a runtime error into an error value instead of aborting the program. Running
+        // out of steps or being interrupted still aborts, so scripts can't get around their budget or Ctrl+C.
+        global_env.set("rescue", Object::builtin_with_caller(|args, caller| {
+            check_num_args("rescue", &args, 1)?;
+            if !matches!(args[0], Object::Function { .. } | Object::BuiltIn(_) | Object::Bound { .. }) {
+                return Err(EvalError(format!("Can't call built-in fn `rescue` on type: {:?}", args[0])));
+            }
+            match caller.call_function(&args[0], Vec::new()) {
+                Err(err) if !err.is_budget_exceeded() && !err.is_interrupted() => {
+                    let message = err.0.split(CALL_STACK_HEADER).next().unwrap_or_default();
+                    Ok(Object::Error(message.trim_end_matches([',', ' ', '\n']).to_string()))
+                },
+                result => result,
+            }
+        }));
+
+        global_env.set("is_int", Object::builtin(|args| {
+            check_num_args("is_int", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::Integer(_))))
+        }));
+
+        global_env.set("is_string", Object::builtin(|args| {
+            check_num_args("is_string", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::String(_))))
+        }));
+
+        global_env.set("is_bool", Object::builtin(|args| {
+            check_num_args("is_bool", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::Boolean(_))))
+        }));
+
+        global_env.set("is_array", Object::builtin(|args| {
+            check_num_args("is_array", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::Array(_))))
+        }));
+
+        global_env.set("is_hash", Object::builtin(|args| {
+            check_num_args("is_hash", &args, 1)?;
+            Ok(Object::Boolean(matches!(args[0], Object::HashMap(_))))
+        }));
+
+        // Both list entries in key order, the order hashes print in
+        global_env.set("keys", Object::builtin(|args| {
+            check_num_args("keys", &args, 1)?;
+            match &args[0] {
+                Object::HashMap(hash_map) => Ok(Object::Array(sorted_entries(hash_map).into_iter().map(|(key, _)| key.clone()).collect())),
+                _ => Err(EvalError(format!("Can't call built-in fn `keys` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("values", Object::builtin(|args| {
+            check_num_args("values", &args, 1)?;
+            match &args[0] {
+                Object::HashMap(hash_map) => Ok(Object::Array(sorted_entries(hash_map).into_iter().map(|(_, val)| val.clone()).collect())),
+                _ => Err(EvalError(format!("Can't call built-in fn `values` on type: {:?}", args[0])))
+            }
+        }));
+
+        // Malformed JSON is an error value the program can check for, not a failure
+        global_env.set("json_parse", Object::builtin(|args| {
+            check_num_args("json_parse", &args, 1)?;
+            match &args[0] {
+                Object::String(text) => Ok(parse_json(text).unwrap_or_else(|err| Object::Error(err.0))),
+                _ => Err(EvalError(format!("Can't call built-in fn `json_parse` on type: {:?}", args[0])))
+            }
+        }));
+
+        global_env.set("json_stringify", Object::builtin(|args| {
+            check_num_args("json_stringify", &args, 1)?;
+            stringify_json(&args[0]).map(Object::String)
+        }));
+
+        if capabilities.fs {
+            global_env.set("read_file", Object::builtin(|args| {
+                check_num_args("read_file", &args, 1)?;
+                match &args[0] {
+                    Object::String(path) => fs::read_to_string(path)
+                        .map(Object::String)
+                        .map_err(|err| EvalError(format!("Error in built-in fn `read_file`, unable to read {path}: {err}"))),
+                    _ => Err(EvalError(format!("Can't call built-in fn `read_file` on type: {:?}", args[0])))
+                }
+            }));
+
+            global_env.set("write_file", Object::builtin(|args| {
+                check_num_args("write_file", &args, 2)?;
+                match (&args[0], &args[1]) {
+                    (Object::String(path), Object::String(contents)) => fs::write(path, contents)
+                        .map(|_| Object::Boolean(true))
+                        .map_err(|err| EvalError(format!("Error in built-in fn `write_file`, unable to write {path}: {err}"))),
+                    _ => Err(EvalError(format!("Can't call built-in fn `write_file` on types: {:?}, {:?}", args[0], args[1])))
+                }
+            }));
+        }
+
+        if capabilities.env {
+            // Null for variables that aren't set, or aren't valid unicode
+            global_env.set("env", Object::builtin(|args| {
+                check_num_args("env", &args, 1)?;
+                match &args[0] {
+                    Object::String(name) => Ok(std::env::var(name).map_or(Object::Null, Object::String)),
+                    _ => Err(EvalError(format!("Can't call built-in fn `env` on type: {:?}", args[0])))
+                }
+            }));
+        }
+
+        let input: Rc<RefCell<Box<dyn InputSource>>> = Rc::new(RefCell::new(Box::new(Stdin)));
+        if capabilities.stdin {
+            // Null once the input is used up
+            let source = Rc::clone(&input);
+            global_env.set("read_line", Object::builtin(move |args| {
+                check_num_args("read_line", &args, 0)?;
+                match source.borrow_mut().read_line() {
+                    Ok(line) => Ok(line.map_or(Object::Null, Object::String)),
+                    Err(err) => Err(EvalError(format!("Error in built-in fn `read_line`, unable to read input: {err}"))),
+                }
+            }));
+
+            let source = Rc::clone(&input);
+            global_env.set("read_all", Object::builtin(move |args| {
+                check_num_args("read_all", &args, 0)?;
+                source.borrow_mut().read_all()
+                    .map(Object::String)
+                    .map_err(|err| EvalError(format!("Error in built-in fn `read_all`, unable to read input: {err}")))
+            }));
+        }
+
         Self {
             envs: RefCell::new(vec![Rc::new(RefCell::new(global_env))]),
+            index_mode: Cell::new(IndexMode::default()),
+            unknown_variable_mode: Cell::new(UnknownVariableMode::default()),
+            module_dir: RefCell::new(PathBuf::from(".")),
+            import_roots: RefCell::new(Vec::new()),
+            module_stack: RefCell::new(Vec::new()),
+            modules: RefCell::new(HashMap::new()),
+            deprecations: RefCell::new(HashMap::new()),
+            runtime_deprecation_warnings: Cell::new(false),
+            diagnostics: RefCell::new(Vec::new()),
+            call_stack: RefCell::new(Vec::new()),
+            max_call_depth: Cell::new(DEFAULT_MAX_CALL_DEPTH),
+            step_limit: Cell::new(None),
+            steps: Cell::new(0),
+            interrupt: RefCell::new(None),
+            running: Cell::new(false),
+            coverage: RefCell::new(None),
+            optimize: Cell::new(false),
+            output,
+            script_args,
+            input,
+            logger: RefCell::new(None),
+            with_builtin: with,
+            interner: Interner::new(),
         }
     }
 
     pub fn evaluate_program(&self, program: &Program) -> Result<Object, EvalError> {
         let first_env = Rc::clone(&self.envs.borrow()[0]);
-        self.eval_statements(&program.statements, false, &first_env)
+        self.evaluate_in(program, &first_env)
+    }
+
+    /// Evaluates `program` in a fresh scope enclosed by the global one, like a module: it sees the builtins and the
+    /// host's globals, but what it binds is dropped after the run. The same parsed program can be run again and
+    /// again from the same state, e.g. once per request by a server. The scopes its fns close over are dropped
+    /// along with it, so a fn it returns can't be called once it's done.
+    pub fn run_isolated(&self, program: &Program) -> Result<Object, EvalError> {
+        let env = Rc::new(RefCell::new(Environment::new(Some(self.global_env()))));
+        let kept = self.envs.borrow().len();
+        let result = self.evaluate_in(program, &env);
+        self.envs.borrow_mut().truncate(kept);
+        result
+    }
+
+    fn evaluate_in(&self, program: &Program, env: &Env) -> Result<Object, EvalError> {
+        self.check_program(program)?;
+        // Imported modules are evaluated as part of the importing run and share its step budget
+        let outermost = !self.running.replace(true);
+        if outermost {
+            self.steps.set(0);
+        }
+
+        self.report_analysis(program);
+        let optimized;
+        let program = if self.optimize.get() {
+            let mut program = program.clone();
+            optimize::optimize(&mut program);
+            optimized = program;
+            &optimized
+        } else {
+            program
+        };
+        let result = self.eval_statements(&program.statements, env);
+
+        if outermost {
+            self.running.set(false);
+        }
+        result
+    }
+
+    /// Marks a builtin as deprecated, scripts using it get a warning with `note` (e.g. "use x instead").
+    pub fn deprecate_builtin(&self, name: &str, note: &str) -> Result<(), EvalError> {
+        match self.global_env().borrow().get(name) {
+            Some(Object::BuiltIn(_)) => {},
+            _ => return Err(EvalError(format!("Cannot deprecate `{name}`, it is not a builtin"))),
+        }
+        self.deprecations.borrow_mut().insert(name.to_string(), note.to_string());
+        Ok(())
+    }
+
+    /// Also warn each time a deprecated builtin is actually called, not just when a program referencing it is analyzed.
+    pub fn set_runtime_deprecation_warnings(&self, enabled: bool) {
+        self.runtime_deprecation_warnings.set(enabled);
+    }
+
+    /// Analysis-time diagnostics for `program`, without evaluating it.
+    pub fn analyze(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut diagnostics = DeprecationCheck::run(&self.deprecations.borrow(), program);
+        diagnostics.extend(NamedArgCheck::run(program));
+        diagnostics
+    }
+
+    /// Evaluates `program` and drains the diagnostics it produced, whether or not evaluation succeeded.
+    pub fn evaluate_program_with_diagnostics(&self, program: &Program) -> (Result<Object, EvalError>, Vec<Diagnostic>) {
+        let result = self.evaluate_program(program);
+        (result, self.take_diagnostics())
+    }
+
+    /// Evaluates `program` capturing what it prints rather than passing it on to the output sink.
+    pub fn evaluate_program_outcome(&self, program: &Program) -> EvalOutcome {
+        let stdout = SharedBuffer::new();
+        let output = self.output.replace(Box::new(stdout.clone()));
+        let start = Instant::now();
+
+        let value = self.evaluate_program(program);
+
+        let duration = start.elapsed();
+        *self.output.borrow_mut() = output;
+        EvalOutcome { value, stdout: stdout.take(), steps: self.steps.get(), duration }
+    }
+
+    /// Drains the diagnostics collected since the last call.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    /// Fails before anything runs if `program` returns outside a function, redeclares a const or uses variables
+    /// that are defined nowhere, naming all of them.
+    fn check_program(&self, program: &Program) -> Result<(), EvalError> {
+        let mut errors = find_returns_outside_functions(program).iter().map(ToString::to_string).collect::<Vec<String>>();
+        errors.extend(find_const_redeclarations(program).iter().map(ToString::to_string));
+        // With warnings on, each unknown variable is reported as it's evaluated instead
+        if self.unknown_variable_mode.get() == UnknownVariableMode::Error {
+            let known = self.global_env().borrow().vars().map(|(name, _)| name.to_string()).collect::<Vec<String>>();
+            errors.extend(find_undefined(program, &known).iter().map(ToString::to_string));
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(EvalError(errors.join("\n")))
+    }
+
+    fn report_analysis(&self, program: &Program) {
+        let diagnostics = self.analyze(program);
+        self.diagnostics.borrow_mut().extend(diagnostics);
+    }
+
+    fn report_deprecated_call(&self, name: &str) {
+        if !self.runtime_deprecation_warnings.get() {
+            return;
+        }
+        if let Some(note) = self.deprecations.borrow().get(name) {
+            self.report_once(Diagnostic::deprecated_call(name, note));
+        }
+    }
+
+    /// Runtime diagnostics can fire on every loop iteration/recursive call, only the first one is kept.
+    fn report_once(&self, diagnostic: Diagnostic) {
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Sends what scripts print to `output` instead of stdout.
+    pub fn set_output(&self, output: impl OutputSink + 'static) {
+        *self.output.borrow_mut() = Box::new(output);
+    }
+
+    /// Has `read_line` and `read_all` read from `input` instead of stdin.
+    pub fn set_input(&self, input: impl InputSource + 'static) {
+        *self.input.borrow_mut() = Box::new(input);
+    }
+
+    /// Receives events about what runs: modules loaded (info), functions called (debug) and the scopes made for them
+    /// (trace). `None` (the default) logs nothing.
+    pub fn set_logger(&self, logger: Option<Rc<dyn Logger>>) {
+        *self.logger.borrow_mut() = logger;
+    }
+
+    fn log(&self, level: Level, message: impl FnOnce() -> String) {
+        log_event(self.logger.borrow().as_ref(), level, "interpreter", message);
+    }
+
+    /// The arguements scripts get from `args()`, none by default.
+    pub fn set_args(&self, args: Vec<String>) {
+        *self.script_args.borrow_mut() = args;
+    }
+
+    /// Caps the number of expressions a single run may evaluate, `None` (the default) is unlimited. Runs that go
+    /// over abort with [`EvalError::budget_exceeded`].
+    pub fn set_step_limit(&self, limit: Option<usize>) {
+        self.step_limit.set(limit);
+    }
+
+    /// A flag another thread can raise, a signal handler say, to stop the run before its next expression with
+    /// [`EvalError::interrupted`]. The interpreter lowers it again when it stops.
+    pub fn set_interrupt(&self, interrupt: Option<Arc<AtomicBool>>) {
+        *self.interrupt.borrow_mut() = interrupt;
+    }
+
+    fn take_step(&self) -> Result<(), EvalError> {
+        if self.interrupt.borrow().as_ref().is_some_and(|interrupt| interrupt.swap(false, atomic::Ordering::Relaxed)) {
+            return Err(EvalError::interrupted());
+        }
+        let steps = self.steps.get() + 1;
+        match self.step_limit.get() {
+            Some(limit) if steps > limit => Err(EvalError::budget_exceeded(limit)),
+            _ => {
+                self.steps.set(steps);
+                Ok(())
+            },
+        }
+    }
+
+    /// How deep calls can nest before evaluation fails. Deep calls need a deep native stack, see
+    /// [`DEFAULT_MAX_CALL_DEPTH`] for how much.
+    pub fn set_max_call_depth(&self, depth: usize) {
+        self.max_call_depth.set(depth);
+    }
+
+    /// Records the lines of each file that statements run from, for [`Interpreter::coverage`]. Turning it on again
+    /// starts afresh. Only fns defined while it's on have their lines recorded.
+    pub fn set_coverage(&self, on: bool) {
+        *self.coverage.borrow_mut() = on.then(CoverageRun::default);
+    }
+
+    /// The lines that ran since coverage was turned on, `None` when it's off.
+    pub fn coverage(&self) -> Option<Coverage> {
+        self.coverage.borrow().as_ref().map(|run| run.coverage.clone())
+    }
+
+    /// Runs `evaluate` as code of `file`, which statements run are recorded against when coverage is on.
+    fn in_file<T>(&self, file: Option<PathBuf>, evaluate: impl FnOnce() -> T) -> T {
+        if let Some(run) = self.coverage.borrow_mut().as_mut() {
+            run.files.push(file);
+        }
+        let result = evaluate();
+        if let Some(run) = self.coverage.borrow_mut().as_mut() {
+            run.files.pop();
+        }
+        result
+    }
+
+    /// Simplify programs with `parser::optimize` before evaluating them, off by default as it changes the steps counted.
+    pub fn set_optimize(&self, optimize: bool) {
+        self.optimize.set(optimize);
+    }
+
+    /// What the names of the programs this runs should be parsed with, so they share them with its imports.
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn set_index_mode(&self, mode: IndexMode) {
+        self.index_mode.set(mode);
+    }
+
+    pub fn set_unknown_variable_mode(&self, mode: UnknownVariableMode) {
+        self.unknown_variable_mode.set(mode);
+    }
+
+    pub fn global_env(&self) -> Env {
+        Rc::clone(&self.envs.borrow()[0])
+    }
+
+    /// Binds `name` in the global scope, visible to every later run and to imported modules.
+    pub fn define_global(&self, name: &str, value: Object) {
+        self.global_env().borrow_mut().set(name, value);
+    }
+
+    /// Exposes `f` to scripts as the builtin `name`, replacing any global of that name.
+    pub fn register_builtin(&self, name: &str, f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) {
+        self.define_global(name, Object::builtin(f));
+    }
+
+    /// Like `register_builtin`, for host functions that call the Monkey functions they're passed through the `Caller`.
+    pub fn register_builtin_with_caller(&self, name: &str, f: impl Fn(Vec<Object>, &dyn Caller) -> Result<Object, EvalError> + 'static) {
+        self.define_global(name, Object::builtin_with_caller(f));
+    }
+
+    /// Directory that `import` paths are resolved against when not inside another module.
+    pub fn set_module_dir(&self, dir: impl Into<PathBuf>) {
+        *self.module_dir.borrow_mut() = dir.into();
+    }
+
+    /// Directories an `import` path is looked for in, in order, when it isn't found relative to the importer.
+    pub fn set_import_roots(&self, roots: Vec<PathBuf>) {
+        *self.import_roots.borrow_mut() = roots;
+    }
+
+    pub fn evaluate_file(&self, path: &Path) -> Result<Object, EvalError> {
+        let path = fs::canonicalize(path).map_err(|err| EvalError(format!("Unable to open {}: {err}", path.display())))?;
+        let program = self.load_module(&path)?;
+        self.evaluate_parsed_file(&path, &program)
+    }
+
+    /// Like `evaluate_file`, for a `program` the caller parsed from `path` already: imports resolve from its
+    /// directory and importing it back is a cycle.
+    pub fn evaluate_parsed_file(&self, path: &Path, program: &Program) -> Result<Object, EvalError> {
+        let path = fs::canonicalize(path).map_err(|err| EvalError(format!("Unable to open {}: {err}", path.display())))?;
+        self.module_stack.borrow_mut().push(path.clone());
+        let result = self.in_file(Some(path), || self.evaluate_program(program));
+        self.module_stack.borrow_mut().pop();
+
+        result
+    }
+
+    fn load_module(&self, path: &Path) -> Result<Program, EvalError> {
+        let file = fs::File::open(path).map_err(|err| EvalError(format!("Unable to read module {}: {err}", path.display())))?;
+        let mut parser = Parser::new(Lexer::from_reader(io::BufReader::new(file)));
+        parser.set_interner(self.interner.clone());
+        parser
+            .parse_program()
+            .map_err(|err| EvalError(format!("Unable to parse module {}: {err:?}", path.display())))
     }
     
-    fn eval_statements(&self, statements: &Vec<Statement>, is_block: bool, env: &Env) -> Result<Object, EvalError> {
-    
+    /// Stops at a `return`, leaving the value wrapped so enclosing blocks stop too until the function call unwraps it.
+    fn eval_statements(&self, statements: &[Statement], env: &Env) -> Result<Object, EvalError> {
+        // Hoisting: fn declarations can be called from anywhere in their block, e.g. by one declared before them. They
+        // are bound once, before the rest, so a later declaration of the same name wins wherever it's called from
         let mut result = Object::Null;
-        for statement in statements {
+        for declaration in statements.iter().filter(|statement| statement.is_fn_declaration()) {
+            result = self.eval_statement(declaration, env)?;
+        }
+
+        for statement in statements.iter().filter(|statement| !statement.is_fn_declaration()) {
             result = self.eval_statement(statement, env)?;
             if let Object::Return(_) = result {
-                if is_block {
-                    return Ok(result) // if in a block statement, we don't want to unwrap the return value
-                }
-                return Ok(result.unwrap_return());
+                return Ok(result);
             }
         }
-    
         Ok(result)
     }
     
     fn eval_statement(&self, statement: &Statement, env: &Env) -> Result<Object, EvalError> {
+        if let (Some(run), Some(span)) = (self.coverage.borrow_mut().as_mut(), statement.span()) {
+            if let Some(Some(file)) = run.files.last() {
+                run.coverage.record(file, span.line);
+            }
+        }
         match statement {
-            Statement::ExpressionStatement { expression, .. } => self.eval_expression(&expression, env),
-            Statement::Block { statements, .. } => self.eval_statements(statements, true, env),
-            Statement::Return { return_value, .. } => self.eval_return_statement(&return_value, env),
-            Statement::Let { name, value, .. } => self.eval_let_statement(name, value, env),
+            Statement::ExpressionStatement { expression, .. } => self.eval_expression(expression, env),
+            Statement::Block { statements, .. } => self.eval_statements(statements, env),
+            Statement::Return { return_value, .. } => self.eval_return_statement(return_value, env),
+            Statement::Let { name, value, .. } => self.eval_let_statement(name, value, statement.is_const(), env),
+            Statement::Import { path, .. } => self.eval_import_statement(path, env),
+        }
+    }
+
+    fn eval_import_statement(&self, path: &str, env: &Env) -> Result<Object, EvalError> {
+        let base_dir = match self.module_stack.borrow().last() {
+            Some(importer) => importer.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => self.module_dir.borrow().clone(),
+        };
+        let resolved = std::iter::once(&base_dir)
+            .chain(self.import_roots.borrow().iter())
+            .map(|dir| dir.join(path))
+            .find(|resolved| resolved.exists())
+            .unwrap_or_else(|| base_dir.join(path));
+        let module_path = fs::canonicalize(&resolved).map_err(|err| EvalError(format!("Unable to import \"{path}\" ({}): {err}", resolved.display())))?;
+
+        if let Some(start) = self.module_stack.borrow().iter().position(|loading| *loading == module_path) {
+            let cycle = self.module_stack.borrow()[start..]
+                .iter()
+                .chain(std::iter::once(&module_path))
+                .map(|module| module.display().to_string())
+                .collect::<Vec<String>>()
+                .join(" -> ");
+            return Err(EvalError(format!("Import cycle detected: {cycle}")));
         }
+
+        let cached = self.modules.borrow().get(&module_path).cloned();
+        let module_env = match cached {
+            Some(module_env) => module_env,
+            None => {
+                self.log(Level::Info, || format!("loading module {}", module_path.display()));
+                let program = self.load_module(&module_path)?;
+                self.check_program(&program)?;
+                self.report_analysis(&program);
+                let global_env = Rc::clone(&self.envs.borrow()[0]);
+                let module_env = Rc::new(RefCell::new(Environment::new(Some(global_env))));
+
+                self.module_stack.borrow_mut().push(module_path.clone());
+                let result = self.in_file(Some(module_path.clone()), || self.eval_statements(&program.statements, &module_env));
+                self.module_stack.borrow_mut().pop();
+                result?;
+
+                self.modules.borrow_mut().insert(module_path, Rc::clone(&module_env));
+                module_env
+            }
+        };
+
+        for (name, val) in module_env.borrow().vars() {
+            env.borrow_mut().set(name, val.clone());
+        }
+
+        Ok(Object::Null)
     }
     
     fn eval_return_statement(&self, return_value: &ast::Expression, env: &Env) -> Result<Object, EvalError> {
@@ -225,20 +933,44 @@ impl Interpreter {
         Ok(Object::Return(Box::new(return_value)))
     }
     
-    fn eval_let_statement(&self, name: &ast::Expression, value: &ast::Expression, env: &Env) -> Result<Object, EvalError> {
+    fn eval_let_statement(&self, name: &ast::Expression, value: &ast::Expression, constant: bool, env: &Env) -> Result<Object, EvalError> {
+        let bind = |name: &str, val: Object| {
+            let mut env = env.borrow_mut();
+            if env.is_const(name) {
+                return Err(EvalError(format!("Cannot redeclare const {name}")));
+            }
+            match constant {
+                true => env.set_const(name, val),
+                false => env.set(name, val),
+            }
+            Ok(())
+        };
         let val = self.eval_expression(value, env)?;
-        if let ast::Expression::Identifier { value, .. } = name {
-            env.borrow_mut().set(value, val.clone());
-            Ok(val)
-        } else {
-            Err(EvalError(format!("Invalid let statement, expected identifier, got: {name:?}")))
+        match (name, val.thawed()) {
+            (ast::Expression::Identifier { value, .. }, _) => bind(value, val.clone())?,
+            (ast::Expression::Tuple { elements: names, .. }, Object::Tuple(vals)) if names.len() == vals.len() => {
+                for (name, val) in names.iter().zip(vals) {
+                    if let ast::Expression::Identifier { value, .. } = name {
+                        bind(value, val.clone())?;
+                    } else {
+                        return Err(EvalError(format!("Invalid let statement, expected identifier, got: {name:?}")));
+                    }
+                }
+            },
+            (ast::Expression::Tuple { elements: names, .. }, _) => {
+                return Err(EvalError(format!("Cannot destructure {val} into {}, expected a tuple of {} values", name.dbg(), names.len())));
+            },
+            _ => return Err(EvalError(format!("Invalid let statement, expected identifier, got: {name:?}"))),
         }
+        Ok(val)
     }
     
     fn eval_expression(&self, expression: &ast::Expression, env: &Env) -> Result<Object, EvalError> {
+        self.take_step()?;
         match expression {
             ast::Expression::Integer { value, .. } => Ok(Object::Integer(*value)),
-            ast::Expression::Boolean { value, .. } => Ok(Object::Boolean(*value)),
+            ast::Expression::Boolean { value, .. } => Ok(Object::from(*value)),
+            ast::Expression::Null { .. } => Ok(NULL),
             ast::Expression::String { value, .. } => Ok(Object::String(value.to_string())),
             ast::Expression::Array { elements, .. } => {
                 let eval_elms = elements
@@ -246,6 +978,12 @@ impl Interpreter {
                     .map(|exp| self.eval_expression(exp, env)).collect::<Result<Vec<Object>, EvalError>>()?;
                Ok(Object::Array(eval_elms))
             },
+            ast::Expression::Tuple { elements, .. } => {
+                let vals = elements
+                    .iter()
+                    .map(|exp| self.eval_expression(exp, env)).collect::<Result<Vec<Object>, EvalError>>()?;
+                Ok(Object::Tuple(vals))
+            },
             ast::Expression::KVPair { key, value } => {
                 let key = self.eval_expression(key, env)?;
                 match key {
@@ -257,7 +995,7 @@ impl Interpreter {
                 let mut hash_map = HashMap::new();
                 for kv_pair in kv_pairs {
                     if let ref kv_pair @ Object::KVPair(ref key, ..) = self.eval_expression(kv_pair, env)? {
-                        hash_map.insert(HashKey::get_hash_key(&key)?, kv_pair.clone());
+                        hash_map.insert(HashKey::get_hash_key(key)?, kv_pair.clone());
                     } else {
                         return Err(EvalError(format!("Invalid hash map, all entries must be a kv pair, got: {kv_pair:?}")));
                     }
@@ -267,37 +1005,37 @@ impl Interpreter {
             },
             ast::Expression::Index { name, i, .. } => {
                 let i = self.eval_expression(i, env)?;
-                match self.eval_expression(name, env)? {
-                    Object::Array(arr) => {
-                        if let Object::Integer(index) = i {
-                            let index = index as usize;
-                            if index >= arr.len() {
-                                return Err(EvalError(format!("Array index out of bounds: i: {}, {}.len(): {}", index, name.as_ref().dbg(),  arr.len())))
-                            } else {
-                                return Ok(arr[index].clone())
-                            }
-                        } else {
-                            return Err(EvalError(format!("Invalid array index expression, expected int, got: {i:?}")))
-                        }
-                    },
-                    Object::HashMap(hash_map) => {
-                        let hash_key = HashKey::get_hash_key(&i)?;
-                        if let Some(kv_pair) = hash_map.get(&hash_key) {
-                            if let Object::KVPair(_, value) = kv_pair {
-                                Ok(*value.clone())
-                            } else {
-                                Ok(Object::Null)
-                            }
-                        } else {
-                            Ok(Object::Null)
-                        }
-                    }
-                    _ => Err(EvalError(format!("Invalid array index expression: ({:?})[{:?}]", name, i )))
-                }
-            }
+                let left = self.eval_expression(name, env)?;
+                self.eval_index_expression(name, left, i)
+            },
+            ast::Expression::Slice { name, start, end, .. } => {
+                let left = self.eval_expression(name, env)?;
+                let start = match start {
+                    Some(start) => self.eval_expression(start, env)?,
+                    None => Object::Null,
+                };
+                let end = match end {
+                    Some(end) => self.eval_expression(end, env)?,
+                    None => Object::Null,
+                };
+                left.slice(&start, &end)
+            },
             ast::Expression::Prefix { operator, right, .. } => {
                 let right = self.eval_expression(right, env)?;
-                self.eval_prefix_expression(operator, right)
+                right.prefix(operator)
+            },
+            // Only evaluates the fallback when it's needed
+            ast::Expression::Infix { left, operator, right, .. } if operator == "??" => match self.eval_expression(left, env)?.unwrap_return() {
+                Object::Null => self.eval_expression(right, env),
+                left => Ok(left),
+            },
+            // Only evaluates the right side when the left one doesn't decide the result
+            ast::Expression::Infix { left, operator, right, .. } if operator == "&&" || operator == "||" => {
+                let left = self.eval_expression(left, env)?.unwrap_return().is_truthy();
+                if left == (operator == "||") {
+                    return Ok(Object::from(left));
+                }
+                Ok(Object::from(self.eval_expression(right, env)?.unwrap_return().is_truthy()))
             },
             ast::Expression::Infix { left, operator, right, .. } => {
                 let left = self.eval_expression(left, env)?;
@@ -308,96 +1046,91 @@ impl Interpreter {
                 let condition = self.eval_expression(condition, env)?;
                 self.eval_if_expression(condition, consequence, alternative, env)
             },
-            ast::Expression::Identifier { value, .. } => env.borrow().get(value).ok_or(EvalError(format!("Unknown variable: {value}"))),
-            ast::Expression::Function { params, body, .. } => {
-                let cur_env = Rc::clone(&env);
+            ast::Expression::Identifier { value, symbol, local, .. } => self.eval_identifier(value, symbol, *local, env),
+            ast::Expression::Function { params, body, locals, .. } => {
+                if let Some(run) = self.coverage.borrow_mut().as_mut() {
+                    if let (Some(Some(file)), Statement::Block { statements, .. }) = (run.files.last(), body.as_ref()) {
+                        run.fn_files.insert(statements.as_ptr(), (Rc::clone(body), file.clone()));
+                    }
+                }
+                let cur_env = Rc::clone(env);
                 self.envs.borrow_mut().push(cur_env);
-                Object::construct_fn(params, body, env)
+                Object::construct_fn(params, body, locals, env)
             },
             ast::Expression::Call { function, arguements, .. } => self.eval_call_expression(function, arguements, env),
+            ast::Expression::NamedArg { .. } => Err(EvalError(format!("Named arguement {} outside of a call", expression.dbg()))),
             // _ => Err(EvalError("".to_string()))
         }
     }
     
-    fn eval_prefix_expression(&self, operator: &str, right: Object) -> Result<Object, EvalError> {
-        match operator {
-            "!" => {
-                match right {
-                    Object::Integer(val) => Ok(Object::Boolean(val == 0)),
-                    Object::Boolean(val) => Ok(Object::Boolean(!val)),
-                    Object::Null => Ok(Object::Boolean(true)),
-                    _ => Err(EvalError(format!("Invalid arg {right:?} for prefix operator {operator}")))
+    fn eval_identifier(&self, name: &str, symbol: &Symbol, local: Option<(usize, usize)>, env: &Env) -> Result<Object, EvalError> {
+        if let Some(val) = env.borrow().lookup(symbol, local) {
+            return Ok(val);
+        }
+
+        match self.unknown_variable_mode.get() {
+            UnknownVariableMode::Error => Err(EvalError(format!("Unknown variable: {name}"))),
+            UnknownVariableMode::NullWithWarning => {
+                self.report_once(Diagnostic::warning(format!("Unknown variable: {name}, evaluated as null")));
+                Ok(Object::Null)
+            },
+        }
+    }
+
+    fn eval_index_expression(&self, name: &ast::Expression, left: Object, i: Object) -> Result<Object, EvalError> {
+        let strict = self.index_mode.get() == IndexMode::Strict;
+        match left.thaw() {
+            Object::Array(arr) => {
+                if let Object::Integer(index) = i {
+                    match normalize_index(index, arr.len()).and_then(|index| arr.get(index)) {
+                        Some(val) => Ok(val.clone()),
+                        None if strict => Err(EvalError(format!("Array index out of bounds: i: {}, {}.len(): {} (strict index mode, the default lenient mode returns null)", index, name.dbg(), arr.len()))),
+                        None => Ok(Object::Null),
+                    }
+                } else {
+                    Err(EvalError(format!("Invalid array index expression, expected int, got: {i:?}")))
                 }
             },
-            "-" => {
-                match right {
-                    Object::Integer(val) => Ok(Object::Integer(-val)),
-                    _ => Err(EvalError(format!("Invalid arg {right:?} for prefix operator {operator}")))
+            Object::String(string) => {
+                if let Object::Integer(index) = i {
+                    let len = string.chars().count();
+                    match normalize_index(index, len).and_then(|index| string.chars().nth(index)) {
+                        Some(c) => Ok(Object::String(c.to_string())),
+                        None if strict => Err(EvalError(format!("String index out of bounds: i: {}, {}.len(): {} (strict index mode, the default lenient mode returns null)", index, name.dbg(), len))),
+                        None => Ok(Object::Null),
+                    }
+                } else {
+                    Err(EvalError(format!("Invalid string index expression, expected int, got: {i:?}")))
                 }
             },
-            _ => Err(EvalError(format!("Cannot eval prefix expression: {operator}{right:?}"))),
+            Object::HashMap(hash_map) => {
+                let hash_key = HashKey::get_hash_key(&i)?;
+                match hash_map.get(&hash_key) {
+                    Some(Object::KVPair(_, value)) => Ok(*value.clone()),
+                    _ if strict => Err(EvalError(format!("Missing hash key: {:?} in {} (strict index mode, the default lenient mode returns null)", i, name.dbg()))),
+                    _ => Ok(Object::Null),
+                }
+            }
+            _ => Err(EvalError(format!("Invalid array index expression: ({:?})[{:?}]", name, i )))
         }
     }
-    
-    fn eval_infix_expression(&self, left: Object, operator: &str, right: Object) -> Result<Object, EvalError> {
-        let left = left.unwrap_return();
-        let right: Object = right.unwrap_return();
-
-        match (&left, &right) {
-            (Object::Integer(left_val), Object::Integer(right_val)) => {
-                Ok(match operator {
-                    "+" => Object::Integer(left_val + right_val),
-                    "-" => Object::Integer(left_val - right_val),
-                    "*" => Object::Integer(left_val * right_val),
-                    "/" => Object::Integer(left_val / right_val),
-                    ">" => Object::Boolean(left_val > right_val),
-                    "<" => Object::Boolean(left_val < right_val),
-                    "==" => Object::Boolean(left_val == right_val),
-                    "!=" => Object::Boolean(left_val != right_val),
-                    _ => return Err(EvalError(format!("Invalid operator in infix position: {left:?}{operator}{right:?}"))),
-                })
-            },
-            (Object::Boolean(left_val), Object::Boolean(right_val)) => {
-                Ok(match operator {
-                    ">" => Object::Boolean(left_val > right_val),
-                    "<" => Object::Boolean(left_val < right_val),
-                    "==" => Object::Boolean(left_val == right_val),
-                    "!=" => Object::Boolean(left_val != right_val),
-                    _ => return Err(EvalError(format!("Invalid operator in infix position: {left:?}{operator}{right:?}"))),
-                })
-            },
-            (Object::String(left_val), Object::String(right_val)) => {
-                Ok(match operator {
-                    "+" => Object::String(left_val.to_string() + right_val),
-                    "==" => Object::Boolean(left_val == right_val),
-                    "!=" => Object::Boolean(left_val != right_val),
-                    _ => return Err(EvalError(format!("Invalid operator in infix position: {left:?}{operator}{right:?}"))),
-                })
-            },
 
-            _ => Err(EvalError(format!("Type mismatch {left:?} {operator} {right:?}")))
-        }
+    fn eval_infix_expression(&self, left: Object, operator: &str, right: Object) -> Result<Object, EvalError> {
+        left.unwrap_return().infix(operator, &right.unwrap_return())
     }
     
-    fn eval_if_expression(&self, condition: Object, consequence: &Box<Statement>, alternative: &Option<Box<Statement>>, env: &Env) -> Result<Object, EvalError> {
-        let mut bool_condition = false;
-        if let Object::Integer(val) = condition {
-            bool_condition = val != 0;
-        }
-    
-        if let Object::Boolean(val) = condition {
-            bool_condition = val;
-        }
-    
-        if bool_condition {
-            match consequence.as_ref() {
-                Statement::Block { statements, .. } => self.eval_statements(&statements, true, env),
+    fn eval_if_expression(&self, condition: Object, consequence: &Statement, alternative: &Option<Box<Statement>>, env: &Env) -> Result<Object, EvalError> {
+        // Each block gets a scope of its own, so its lets don't outlive it
+        let block_env = || Rc::new(RefCell::new(Environment::new(Some(Rc::clone(env)))));
+        if condition.is_truthy() {
+            match consequence {
+                Statement::Block { statements, .. } => self.eval_statements(statements, &block_env()),
                 _ => Err(EvalError(format!("Consequence must be a block statement, got: {consequence:?}")))
             }
         } else {
             if let Some(alt) = alternative {
                 match alt.as_ref() {
-                    Statement::Block { statements, .. } => self.eval_statements(&statements, true, env),
+                    Statement::Block { statements, .. } => self.eval_statements(statements, &block_env()),
                     _ => Err(EvalError(format!("Alternative must be a block statement, got: {alt:?}")))
                 }
             }else {
@@ -406,36 +1139,1037 @@ impl Interpreter {
         }
     }
     
-    fn eval_call_expression(&self, function: &Box<Expression>, arguements: &Vec<Expression>, env: &Env) -> Result<Object, EvalError> {
-        let function_obj = &self.eval_expression(function, env)?.unwrap_return();
-    
-        if let Object::Function { parameters, body, fn_env } = function_obj {
-            if parameters.len() != arguements.len() {
-                return Err(EvalError(format!("Invalid call expression, expected {:?} args, got: {:?}, function obj: {:?}", parameters.len(), arguements.len(), function_obj)));
+    fn push_call_frame(&self, function: &Expression) -> Result<(), EvalError> {
+        let mut call_stack = self.call_stack.borrow_mut();
+        let max_call_depth = self.max_call_depth.get();
+        if call_stack.len() >= max_call_depth {
+            let backtrace = collapse_frames(&call_stack).join("\n  ");
+            return Err(EvalError(format!("Maximum call depth of {max_call_depth} exceeded, {CALL_STACK_HEADER}\n  {backtrace}")));
+        }
+
+        call_stack.push(Frame::new(function));
+        Ok(())
+    }
+
+    fn eval_fn_body(&self, statements: &[Statement], env: &Env, function: &Expression) -> Result<Object, EvalError> {
+        self.push_call_frame(function)?;
+        // The body's statements are the fn's file's, wherever it's called from
+        let file = self.coverage.borrow().as_ref().and_then(|run| Some(run.fn_files.get(&statements.as_ptr())?.1.clone()));
+        let result = self.in_file(file, || self.eval_statements(statements, env)).map_err(|err| self.with_backtrace(err));
+        self.call_stack.borrow_mut().pop();
+
+        Ok(result?.unwrap_return())
+    }
+
+    /// Appends the current call stack to an error raised inside a function, done once by the innermost call so
+    /// the stack still holds the whole chain.
+    fn with_backtrace(&self, err: EvalError) -> EvalError {
+        if err.0.contains(CALL_STACK_HEADER) {
+            return err;
+        }
+        let backtrace = collapse_frames(&self.call_stack.borrow()).join("\n  ");
+        EvalError(format!("{}\n{CALL_STACK_HEADER}\n  {backtrace}", err.0))
+    }
+
+    /// `with(hash, fn)` calls `fn` in a scope holding the hash's entries as variables, on top of the scope `fn`
+    /// closes over, so the bindings are visible to `fn` but gone once it returns.
+    fn eval_with(&self, args: Vec<Object>, function: &Expression) -> Result<Object, EvalError> {
+        let [bindings, body_fn]: [Object; 2] = args
+            .try_into()
+            .map_err(|args: Vec<Object>| EvalError(format!("Error in built-in with, expected 2 arguments, got: {}", args.len())))?;
+        let bindings = match bindings.thaw() {
+            Object::HashMap(bindings) => bindings,
+            bindings => return Err(EvalError(format!("Error in built-in with, expected a hash of bindings, got: {}", bindings.type_name()))),
+        };
+        let (body_fn, fn_args) = match body_fn {
+            Object::Bound { function, args } => (*function, args),
+            body_fn => (body_fn, Vec::new()),
+        };
+        let Object::Function { parameters, defaults, body, fn_env, .. } = &body_fn else {
+            return Err(EvalError(format!("Error in built-in with, expected a function, got: {}", body_fn.type_name())));
+        };
+        let Statement::Block { statements, .. } = body.as_ref() else {
+            return Err(EvalError(format!("Invalid call expression, function body: {body:?} must be Block statement")));
+        };
+        if parameters.len() - defaults.len() > fn_args.len() {
+            return Err(EvalError(format!("Error in built-in with, expected a function taking no arguements, got: {body_fn}")));
+        }
+
+        let fn_env = fn_env.upgrade().ok_or_else(|| EvalError(format!("Error in built-in with, environment of {body_fn} was dropped")))?;
+        let mut scope = Environment::new(Some(fn_env));
+        for pair in bindings.values() {
+            match pair {
+                Object::KVPair(key, val) => match key.as_ref() {
+                    Object::String(name) => scope.set(name, *val.clone()),
+                    key => return Err(EvalError(format!("Error in built-in with, binding names must be strings, got: {key}"))),
+                },
+                pair => return Err(EvalError(format!("Invalid hash entry: {pair:?}"))),
             }
-    
-            if let ast::Statement::Block { statements, .. } = body {
-                let new_env = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&fn_env.upgrade().expect(&format!("Unable to get fn_env!: function: {function:?}, function_obj: {function_obj:?}")))))));
-    
-                for i in 0..arguements.len() {
-                    new_env.borrow_mut().set(&parameters[i], self.eval_expression(&arguements[i], env)?)
+        }
+
+        let args = fn_args.into_iter().map(Some).collect();
+        let new_env = self.bind_parameters(&body_fn, Rc::new(RefCell::new(scope)), args, None)?;
+        self.eval_fn_body(statements, &new_env, function)
+    }
+
+    fn eval_call_expression(&self, function: &Expression, arguements: &[Expression], env: &Env) -> Result<Object, EvalError> {
+        let function_obj = &self.eval_expression(function, env)?.unwrap_return();
+        let (function_obj, args) = match function_obj {
+            Object::Bound { function, args } => (function.as_ref(), args.clone()),
+            function_obj => (function_obj, Vec::new()),
+        };
+
+        let arguements = match function_obj {
+            // Named arguements can only fill the parameters left open by `bind`, which may have bound into a rest param
+            Object::Function { parameters, .. } => order_arguements(parameters.get(args.len()..).unwrap_or_default(), arguements)
+                .map_err(|msg| EvalError(format!("Invalid call of {}: {msg}", fn_name(Some(function)))))?,
+            Object::BuiltIn(_) => {
+                if arguements.iter().any(|arguement| matches!(arguement, Expression::NamedArg { .. })) {
+                    return Err(EvalError(format!("Invalid call expression, builtin {} does not take named arguements", function.dbg())));
                 }
-    
-                return Ok(self.eval_statements(statements, true, &Rc::clone(&new_env))?.unwrap_return())
-            } else {
-                return Err(EvalError(format!("Invalid call expression, function body: {body:?} must be Block statement")))
+                if let Expression::Identifier { value, .. } = function {
+                    self.report_deprecated_call(value);
+                }
+                arguements.iter().map(Some).collect()
+            },
+            _ => return Err(EvalError(format!("Invalid call expression, expression: {function:?} must evalate to function, got: {function_obj:?}"))),
+        };
+        let mut args: Vec<Option<Object>> = args.into_iter().map(Some).collect();
+        for arguement in arguements {
+            args.push(arguement.map(|arguement| self.eval_expression(arguement, env)).transpose()?);
+        }
+        self.call(function_obj, args, Some(function), function)
+    }
+
+    /// Calls `function_obj` with arguements that are already evaluated, `call_site` is what the call stack shows.
+    fn apply_function(&self, function_obj: &Object, args: Vec<Object>, call_site: &Expression) -> Result<Object, EvalError> {
+        // Called back by a builtin, so `call_site` names the builtin and not the function
+        self.call(function_obj, args.into_iter().map(Some).collect(), None, call_site)
+    }
+
+    /// Like `apply_function`, but parameters given no arguement (`None`) get their default value. Arity errors name
+    /// the function after `callee`, the expression called, or just call it a function without one.
+    fn call(&self, function_obj: &Object, args: Vec<Option<Object>>, callee: Option<&Expression>, call_site: &Expression) -> Result<Object, EvalError> {
+        match function_obj {
+            Object::Bound { function, args: bound } => {
+                self.call(function, bound.iter().cloned().map(Some).chain(args).collect(), callee, call_site)
+            },
+            Object::Function { body, fn_env, .. } => {
+                let Statement::Block { statements, .. } = body.as_ref() else {
+                    return Err(EvalError(format!("Invalid call expression, function body: {body:?} must be Block statement")));
+                };
+                let fn_env = fn_env
+                    .upgrade()
+                    .ok_or_else(|| EvalError(format!("Cannot call {}, the scope it closes over was dropped", fn_name(callee))))?;
+                self.log(Level::Debug, || format!("calling {} with {} args", fn_name(callee), args.iter().flatten().count()));
+                let new_env = self.bind_parameters(function_obj, fn_env, args, callee)?;
+                self.eval_fn_body(statements, &new_env, call_site)
+            },
+            Object::BuiltIn(f) => {
+                // Builtins don't take named arguements, so every one is given
+                let args: Vec<Object> = args.into_iter().flatten().collect();
+                self.log(Level::Debug, || format!("calling builtin {} with {} args", call_site.dbg(), args.len()));
+                if *f == self.with_builtin {
+                    self.eval_with(args, call_site)
+                } else {
+                    f.call_with(args, &CallSite { interpreter: self, call_site })
+                }
+            },
+            _ => Err(EvalError(format!("Invalid call, expected a function, got: {}", function_obj.type_name()))),
+        }
+    }
+
+    /// Makes the scope a call of `function_obj` runs in, on top of `outer`. Defaults are evaluated in that scope in
+    /// order, so they can refer to the parameters before them.
+    fn bind_parameters(&self, function_obj: &Object, outer: Env, args: Vec<Option<Object>>, callee: Option<&Expression>) -> Result<Env, EvalError> {
+        let Object::Function { parameters, defaults, rest, locals, .. } = function_obj else {
+            return Err(EvalError(format!("Invalid call, expected a function, got: {}", function_obj.type_name())));
+        };
+        let required = parameters.len() - defaults.len();
+        let missing = args.len() < required || args.iter().take(required).any(Option::is_none);
+        if missing || (rest.is_none() && args.len() > parameters.len()) {
+            let expected = match (rest, defaults.is_empty()) {
+                (Some(_), _) => format!("at least {required} args"),
+                (None, true) => format!("{} args", parameters.len()),
+                (None, false) => format!("{required} to {} args", parameters.len()),
+            };
+            let got = args.iter().flatten().count();
+            let signature = function_obj.signature().unwrap_or_default();
+            return Err(EvalError(format!("{} expects {expected} ({signature}), got {got}", fn_name(callee))));
+        }
+
+        self.log(Level::Trace, || {
+            let locals = locals.iter().map(|local| local.as_str()).collect::<Vec<&str>>().join(", ");
+            format!("creating the scope of {} with locals [{locals}]", fn_name(callee))
+        });
+        let new_env = Rc::new(RefCell::new(Environment::new_with_locals(Rc::clone(locals), Some(outer))));
+        let mut args = args.into_iter();
+        // The params take the first slots of the locals, see `analysis::fn_locals`
+        for i in 0..parameters.len() {
+            let arg = match args.next().flatten() {
+                Some(arg) => arg,
+                None => self.eval_expression(&defaults[i - required], &new_env)?,
+            };
+            new_env.borrow_mut().set_local(i, arg);
+        }
+        if rest.is_some() {
+            new_env.borrow_mut().set_local(parameters.len(), Object::Array(args.flatten().collect()));
+        }
+        Ok(new_env)
+    }
+}
+
+/// How errors about a call name the function called.
+fn fn_name(callee: Option<&Expression>) -> String {
+    match callee {
+        Some(Expression::Identifier { value, .. }) => format!("function `{value}`"),
+        _ => "function".to_string(),
+    }
+}
+
+/// Lets builtins call back into the interpreter, the functions they call show up under the builtin's call site.
+struct CallSite<'a> {
+    interpreter: &'a Interpreter,
+    call_site: &'a Expression,
+}
+
+impl Caller for CallSite<'_> {
+    fn call_function(&self, function: &Object, args: Vec<Object>) -> Result<Object, EvalError> {
+        self.interpreter.apply_function(function, args, self.call_site)
+    }
+
+    fn call_site(&self) -> Option<Span> {
+        self.call_site.span()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    fn eval(src: &str) -> Result<Object, EvalError> {
+        eval_with(src, Capabilities::default())
+    }
+
+    fn eval_with(src: &str, capabilities: Capabilities) -> Result<Object, EvalError> {
+        let mut parser = Parser::new(Lexer::new(src.to_string()));
+        let program = parser.parse_program().unwrap();
+        Interpreter::new_with_capabilities(Environment::new(None), capabilities).evaluate_program(&program)
+    }
+
+    #[test]
+    fn test_conversion_builtins() {
+        assert!(matches!(eval(r#"int("42")"#), Ok(Object::Integer(42))));
+        assert!(matches!(eval(r#"int(" -7 ")"#), Ok(Object::Integer(-7))));
+        assert!(matches!(eval("int(true)"), Ok(Object::Integer(1))));
+        assert!(matches!(eval(r#"int("forty two")"#), Ok(Object::Null)));
+        assert!(eval("int([1])").is_err());
+
+        assert!(matches!(eval("to_string(42)"), Ok(Object::String(s)) if s == "42"));
+        assert!(matches!(eval("to_string(false)"), Ok(Object::String(s)) if s == "false"));
+        assert!(eval("to_string([1])").is_err());
+
+        let chars = |chars: &[&str]| Object::Array(chars.iter().map(|c| Object::String(c.to_string())).collect());
+        assert_eq!(eval(r#"chars("héllo")"#).unwrap(), chars(&["h", "é", "l", "l", "o"]));
+        assert_eq!(eval(r#"chars("")"#).unwrap(), chars(&[]));
+        assert!(eval("chars(1)").is_err());
+        assert_eq!(eval(r#"let s = "héllo"; [len(s), s[1], s[len(s) - 1] == chars(s)[4]]"#).unwrap(), Object::Array(vec![
+            Object::Integer(5),
+            Object::String("é".to_string()),
+            Object::Boolean(true),
+        ]));
+    }
+
+    #[test]
+    fn test_type_predicates() {
+        assert!(matches!(eval("is_int(1)"), Ok(Object::Boolean(true))));
+        assert!(matches!(eval(r#"is_int("1")"#), Ok(Object::Boolean(false))));
+        assert!(matches!(eval(r#"is_string("1")"#), Ok(Object::Boolean(true))));
+        assert!(matches!(eval("is_bool(true)"), Ok(Object::Boolean(true))));
+        assert!(matches!(eval("is_array([1, 2])"), Ok(Object::Boolean(true))));
+        assert!(matches!(eval(r#"is_hash({"a": 1})"#), Ok(Object::Boolean(true))));
+        assert!(matches!(eval("is_hash([])"), Ok(Object::Boolean(false))));
+    }
+
+    #[test]
+    fn test_optimize() {
+        let program = Parser::new(Lexer::new("let f = fn(x) { let n = 6; if (n > 5) { x * n } else { 0 } }; f(7)".to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        let plain = interpreter.evaluate_program_outcome(&program);
+        interpreter.set_optimize(true);
+        let optimized = interpreter.evaluate_program_outcome(&program);
+        assert_eq!(optimized.value.unwrap(), plain.value.unwrap());
+        assert!(optimized.steps < plain.steps, "{} >= {}", optimized.steps, plain.steps);
+
+        // Splicing in the `if` drops a scope, so `x` is the inner fn's own local again, not the outer one's
+        let program = Parser::new(Lexer::new("let g = fn(x) { let f = fn(x) { if (true) { x } }; f(2) }; g(1)".to_string())).parse_program().unwrap();
+        assert_eq!(interpreter.evaluate_program(&program).unwrap(), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_evaluate_program_outcome() {
+        let interpreter = Interpreter::new(Environment::new(None));
+        let output = SharedBuffer::new();
+        interpreter.set_output(output.clone());
+
+        let program = Parser::new(Lexer::new(r#"println("a"); println(1 + 1); 3"#.to_string())).parse_program().unwrap();
+        let outcome = interpreter.evaluate_program_outcome(&program);
+        assert_eq!(outcome.value.unwrap(), Object::Integer(3));
+        assert_eq!(outcome.stdout, "a\n2\n");
+        assert_eq!(outcome.steps, 9);
+
+        let program = Parser::new(Lexer::new(r#"println("before"); len(1)"#.to_string())).parse_program().unwrap();
+        let outcome = interpreter.evaluate_program_outcome(&program);
+        assert!(outcome.value.is_err());
+        assert_eq!(outcome.stdout, "before\n");
+
+        // The sink set by the host is back in place and saw none of the captured output
+        interpreter.evaluate_program(&Parser::new(Lexer::new(r#"println("after")"#.to_string())).parse_program().unwrap()).unwrap();
+        assert_eq!(output.take(), "after\n");
+    }
+
+    #[test]
+    fn test_cmp() {
+        assert_eq!(eval("cmp(1, 2)").unwrap(), Object::Integer(-1));
+        assert_eq!(eval(r#"cmp("b", "a")"#).unwrap(), Object::Integer(1));
+        assert_eq!(eval("cmp(true, true)").unwrap(), Object::Integer(0));
+        assert_eq!(eval(r#"cmp(10, "1")"#).unwrap(), Object::Integer(-1));
+        assert!(eval("cmp([1], [1])").is_err());
+        assert!(eval("cmp(1)").is_err());
+    }
+
+    #[test]
+    fn test_clone_and_freeze() {
+        let src = r#"let a = [1, [2, 3], {"k": "v"}]; let b = clone(a);"#;
+        assert_eq!(eval(&format!("{src} b")).unwrap(), eval(&format!("{src} a")).unwrap());
+        assert_eq!(eval(&format!("{src} push(b, 4); a")).unwrap(), eval(&format!("{src} a")).unwrap());
+        assert_eq!(eval("freeze((1, \"a\"))").unwrap(), Object::Tuple(vec![Object::Integer(1), Object::String("a".to_string())]));
+        assert!(eval("clone()").is_err());
+        assert!(eval("freeze(1, 2)").is_err());
+
+        // Frozen values read like the values they wrap, but can't be pushed to until cloned
+        let src = r#"let a = freeze([1, 2]);"#;
+        assert_eq!(eval(&format!("{src} [is_frozen(a), is_frozen(freeze(a)), is_frozen(clone(a)), is_frozen([1, 2])]")).unwrap(), eval("[true, true, false, false]").unwrap());
+        assert_eq!(eval(&format!("{src} [a[0], len(a), a == [1, 2], a[1:], is_array(a)]")).unwrap(), eval("[1, 2, true, [2], true]").unwrap());
+        assert_eq!(eval(&format!("{src} push(a, 3)")).unwrap_err().0, "Can't push to a frozen array, `clone` it first");
+        assert_eq!(eval(&format!("{src} push(clone(a), 3)")).unwrap(), eval("[1, 2, 3]").unwrap());
+        assert_eq!(eval(r#"let (x, y) = freeze((1, "b")); y"#).unwrap(), Object::String("b".to_string()));
+        assert_eq!(eval(r#"freeze({"k": "v"})["k"]"#).unwrap(), Object::String("v".to_string()));
+        assert_eq!(eval("[is_frozen(freeze(1)), freeze(1)]").unwrap(), eval("[false, 1]").unwrap());
+    }
+
+    #[test]
+    fn test_file_builtins_gated() {
+        let path = std::env::temp_dir().join(format!("mk_fs_test_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let src = format!(r#"write_file("{path}", "hello"); read_file("{path}")"#);
+
+        assert!(eval(&src).is_err());
+        assert!(matches!(eval_with(&src, Capabilities::all()), Ok(Object::String(s)) if s == "hello"));
+        assert!(eval_with(r#"read_file("/definitely/not/here.mk")"#, Capabilities::all()).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_script_args() {
+        let interpreter = Interpreter::new(Environment::new(None));
+        let program = Parser::new(Lexer::new("args()".to_string())).parse_program().unwrap();
+        assert_eq!(interpreter.evaluate_program(&program).unwrap(), Object::Array(Vec::new()));
+        interpreter.set_args(vec!["a".to_string(), "b c".to_string()]);
+        assert_eq!(interpreter.evaluate_program(&program).unwrap().to_string(), r#"["a", "b c"]"#);
+        assert!(eval("args(1)").is_err());
+    }
+
+    #[test]
+    fn test_env_builtin_gated() {
+        let src = r#"[len(env("PATH")) > 0, env("MK_SURELY_UNSET_VAR")]"#;
+        assert!(eval(src).is_err());
+        assert_eq!(eval_with(src, Capabilities::all()).unwrap().to_string(), "[true, null]");
+        assert!(eval_with("env(1)", Capabilities::all()).is_err());
+    }
+
+    #[test]
+    fn test_input_builtins_gated() {
+        let src = "let name = read_line(); let rest = read_all(); [name, rest, read_line()]";
+        assert!(eval(src).is_err());
+
+        let interpreter = Interpreter::new_with_capabilities(Environment::new(None), Capabilities::all());
+        interpreter.set_input(StringInput::new("Ada\nline 2\nline 3\n"));
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        assert_eq!(interpreter.evaluate_program(&program).unwrap().to_string(), r#"["Ada", "line 2\nline 3\n", null]"#);
+        assert!(eval_with("read_line(1)", Capabilities::all()).is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join(format!("mk_import_test_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/math.mk"), "let square = fn(x) { x * x }; let ten = 10;").unwrap();
+        std::fs::write(dir.join("main.mk"), r#"import "lib/math.mk"; square(ten) + 1"#).unwrap();
+        std::fs::write(dir.join("a.mk"), r#"import "b.mk"; let a = 1;"#).unwrap();
+        std::fs::write(dir.join("b.mk"), r#"import "a.mk"; let b = 2;"#).unwrap();
+        std::fs::write(dir.join("shared.mk"), "let base = 3;").unwrap();
+
+        let interpreter = Interpreter::new(Environment::new(None));
+        assert!(matches!(interpreter.evaluate_file(&dir.join("main.mk")), Ok(Object::Integer(101))));
+
+        let interpreter = Interpreter::new(Environment::new(None));
+        match interpreter.evaluate_file(&dir.join("a.mk")) {
+            Err(EvalError(msg)) => assert!(msg.starts_with("Import cycle detected"), "{msg}"),
+            other => panic!("Expected import cycle error, got: {other:?}"),
+        }
+
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_module_dir(&dir);
+        let mut parser = Parser::new(Lexer::new(r#"import "missing.mk";"#.to_string()));
+        assert!(interpreter.evaluate_program(&parser.parse_program().unwrap()).is_err());
+
+        // Paths not found next to the importer are looked for in the import roots
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_module_dir(dir.join("lib"));
+        interpreter.set_import_roots(vec![dir.join("missing"), dir.clone()]);
+        let mut parser = Parser::new(Lexer::new(r#"import "math.mk"; import "shared.mk"; square(base)"#.to_string()));
+        assert!(matches!(interpreter.evaluate_program(&parser.parse_program().unwrap()), Ok(Object::Integer(9))));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_index_modes() {
+        let src = r#"let arr = [1, 2, 3]; let hash = {"a": 1};"#;
+        // (expression, strict result, lenient result); Ok(None) is null, Err(()) any EvalError
+        type Expected = Result<Option<isize>, ()>;
+        let cases: [(&str, Expected, Expected); 6] = [
+            ("arr[1]", Ok(Some(2)), Ok(Some(2))),
+            ("arr[3]", Err(()), Ok(None)),
+            ("arr[-1]", Ok(Some(3)), Ok(Some(3))),
+            ("arr[-4]", Err(()), Ok(None)),
+            (r#"hash["a"]"#, Ok(Some(1)), Ok(Some(1))),
+            (r#"hash["b"]"#, Err(()), Ok(None)),
+        ];
+
+        for (expr, strict, lenient) in cases {
+            for (mode, expected) in [(IndexMode::Strict, strict), (IndexMode::Lenient, lenient)] {
+                let mut parser = Parser::new(Lexer::new(format!("{src} {expr}")));
+                let interpreter = Interpreter::new(Environment::new(None));
+                interpreter.set_index_mode(mode);
+                let got = match interpreter.evaluate_program(&parser.parse_program().unwrap()) {
+                    Ok(Object::Integer(val)) => Ok(Some(val)),
+                    Ok(Object::Null) => Ok(None),
+                    Ok(other) => panic!("{expr}: unexpected {other:?}"),
+                    Err(_) => Err(()),
+                };
+                assert_eq!(got, expected, "{expr} in {mode:?} mode");
             }
         }
+    }
+
+    #[test]
+    fn test_negative_and_slice_indexing() {
+        let cases = [
+            ("[1, 2, 3][-1]", Object::Integer(3)),
+            ("[1, 2, 3, 4][1:3]", Object::Array(vec![Object::Integer(2), Object::Integer(3)])),
+            ("[1, 2, 3][:-1]", Object::Array(vec![Object::Integer(1), Object::Integer(2)])),
+            ("[1, 2, 3][-1:]", Object::Array(vec![Object::Integer(3)])),
+            ("[1, 2, 3][2:1]", Object::Array(vec![])),
+            ("[1, 2, 3][:10]", Object::Array(vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)])),
+            (r#""hello"[1]"#, Object::String("e".to_string())),
+            (r#""hello"[-1]"#, Object::String("o".to_string())),
+            (r#""hello"[1:3]"#, Object::String("el".to_string())),
+            (r#""hello"[:]"#, Object::String("hello".to_string())),
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(format!("{:?}", eval(src).unwrap()), format!("{expected:?}"), "{src}");
+        }
+
+        assert!(eval(r#"[1, 2, 3]["a":]"#).is_err());
+        assert!(eval("5[1:2]").is_err());
+    }
+
+    #[test]
+    fn test_deprecated_builtins() {
+        let parse = |src: &str| Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.deprecate_builtin("first", "use arr[0] instead").unwrap();
+        assert!(interpreter.deprecate_builtin("nope", "").is_err());
+
+        let program = parse("let arr = [1, 2]; first(arr) + first(arr)");
+        assert_eq!(interpreter.analyze(&program), vec![Diagnostic::warning("`first` is deprecated: use arr[0] instead")]);
+        assert!(interpreter.take_diagnostics().is_empty());
+
+        assert!(matches!(interpreter.evaluate_program(&program), Ok(Object::Integer(2))));
+        assert_eq!(interpreter.take_diagnostics().len(), 1);
+
+        // Programs that rebind the name aren't using the builtin
+        assert!(interpreter.analyze(&parse("let first = fn(x) { x }; first(1)")).is_empty());
+        assert!(interpreter.analyze(&parse("let f = fn(first) { first }; f(1)")).is_empty());
+
+        // Runtime warnings only fire when the call actually happens, once per builtin
+        interpreter.set_runtime_deprecation_warnings(true);
+        interpreter.evaluate_program(&parse("if (false) { first([1]) }")).unwrap();
+        assert_eq!(interpreter.take_diagnostics().len(), 1);
+        interpreter.evaluate_program(&parse("first([1]); first([2])")).unwrap();
+        assert_eq!(interpreter.take_diagnostics(), vec![
+            Diagnostic::warning("`first` is deprecated: use arr[0] instead"),
+            Diagnostic::warning("called deprecated builtin `first`: use arr[0] instead"),
+        ]);
+    }
+
+    #[test]
+    fn test_max_call_depth_backtrace() {
+        let src = "
+            let f = fn(n) { g(n + 1) };
+            let g = fn(n) { f(n + 1) };
+            let start = fn() { f(0) };
+            start()
+        ";
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_max_call_depth(20);
+
+        match interpreter.evaluate_program(&program) {
+            Err(EvalError(msg)) => assert_eq!(msg, [
+                "Maximum call depth of 20 exceeded, call stack (most recent call last):",
+                "  at start (line 5, column 13)",
+                "  at f (line 4, column 32)",
+                "  at g (line 2, column 29)",
+                "  at f (line 3, column 29)",
+                "  ... 16 more frames of g -> f -> g ...",
+            ].join("\n")),
+            other => panic!("Expected call depth error, got: {other:?}"),
+        }
+
+        // The stack unwinds with the error, so the interpreter is usable afterwards
+        let program = Parser::new(Lexer::new("f(0)".to_string())).parse_program().unwrap();
+        assert!(interpreter.evaluate_program(&program).is_err());
+        assert!(interpreter.call_stack.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_array_builtins() {
+        let src = "
+            let xs = [3, 1, 2];
+            let doubled = map(xs, fn(x) { x * 2 });
+            let odd = filter(xs, fn(x) { x - (x / 2) * 2 == 1 });
+            let total = reduce(xs, 0, fn(acc, x) { acc + x });
+            let desc = sort(xs, fn(a, b) { b - a });
+            [doubled, odd, total, sort(xs), desc, reverse(xs), slice(xs, 1), slice(xs, 0, -1), concat(xs, [4], []), contains(xs, 2)]
+        ";
+        assert_eq!(eval(src).unwrap().to_string(), "[[6, 2, 4], [3, 1], 6, [1, 2, 3], [3, 2, 1], [2, 1, 3], [1, 2], [3, 1], [3, 1, 2, 4], true]");
+        assert_eq!(eval(r#"map(["a", "b"], bind(fn(p, s) { p + s }, "x"))"#).unwrap().to_string(), r#"["xa", "xb"]"#);
+        assert_eq!(eval(r#"slice("hello", 1, 3)"#).unwrap(), Object::String("el".to_string()));
+
+        assert!(matches!(eval("map([1], fn(a, b) { a })"), Err(EvalError(msg)) if msg.starts_with("function expects 2 args (a, b), got 1")));
+        assert!(matches!(eval("sort([1, 2], fn(a, b) { true })"), Err(EvalError(msg)) if msg.contains("comparator must return an int")));
+        // Errors inside the callback show the builtin on the call stack
+        let err = eval("map([0], fn(x) { len(x) })").unwrap_err().0;
+        assert!(err.ends_with("at map (line 1, column 1)"), "{err}");
+        assert!(eval("filter(1, fn(x) { x })").is_err());
+
+        let src = "[range(3), range(1, 4), range(6, 0, -2), reduce(1..5, 0, fn(acc, x) { acc + x }), filter(0..6, fn(x) { x & 1 == 1 })]";
+        assert_eq!(eval(src).unwrap().to_string(), "[[0, 1, 2], [1, 2, 3], [6, 4, 2], 10, [1, 3, 5]]");
+        assert!(matches!(eval("range(1, 2, 0)"), Err(EvalError(msg)) if msg.starts_with("range step can't be 0")));
+        assert!(eval("range(\"a\")").is_err());
+    }
+
+    #[test]
+    fn test_builtin_arity() {
+        let cases = [
+            ("map([1])", "Error in built-in map, expected 2 arguments, got: 1"),
+            ("filter([1], fn(x) { x }, 2)", "Error in built-in filter, expected 2 arguments, got: 3"),
+            ("reduce([1], fn(acc, x) { acc })", "Error in built-in reduce, expected 3 arguments, got: 2"),
+            ("sort()", "Error in built-in sort, expected 1 or 2 arguments, got: 0"),
+            ("reverse([1], [2])", "Error in built-in reverse, expected 1 argument, got: 2"),
+            ("slice([1])", "Error in built-in slice, expected 2 or 3 arguments, got: 1"),
+            ("contains([1])", "Error in built-in contains, expected 2 arguments, got: 1"),
+            ("int()", "Error in built-in int, expected 1 argument, got: 0"),
+            ("to_string(1, 2)", "Error in built-in to_string, expected 1 argument, got: 2"),
+            ("is_int()", "Error in built-in is_int, expected 1 argument, got: 0"),
+        ];
+        for (src, expected) in cases {
+            let err = eval(src).unwrap_err().0;
+            assert!(err.starts_with(expected), "{src}: {err}");
+        }
+        // `concat` takes any number of arrays, what it rejects is named the same way
+        let err = eval("concat([1], 2)").unwrap_err().0;
+        assert!(err.starts_with("Can't call built-in fn `concat` on type"), "{err}");
+    }
+
+    #[test]
+    fn test_bitwise() {
+        // A 16 bit FNV-1a style hash over byte values
+        let src = "
+            let hash = fn(bytes) { reduce(bytes, 0x811C, fn(h, b) { ((h ^ b) * 0x193) & 0xFFFF }) };
+            [hash([104, 105]), hash([]), 0b1010 | 0b0101, 1 << 3 >> 1]
+        ";
+        assert_eq!(eval(src).unwrap().to_string(), "[60591, 33052, 15, 4]");
+    }
+
+    #[test]
+    fn test_power() {
+        let src = "let pow = fn(n) { 2 ** n }; [2 * 3 ** 2, 2 ** 3 ** 2, pow(10), (-2) ** 3]";
+        assert_eq!(eval(src).unwrap().to_string(), "[18, 512, 1024, -8]");
+        assert!(eval("let f = fn(n) { n ** -1 }; f(2)").unwrap_err().0.starts_with("negative exponent: 2 ** -1\n"));
+    }
+
+    #[test]
+    fn test_null_coalescing() {
+        let src = r#"
+            let config = {"name": "monkey"};
+            let get = fn(key) { config[key] ?? "unset" };
+            [get("name"), get("port"), null ?? null, false ?? 1, 1 ?? 1 / 0, config["x"] == null]
+        "#;
+        assert_eq!(eval(src).unwrap().to_string(), r#"["monkey", "unset", null, false, 1, true]"#);
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        let interpreter = Interpreter::new(Environment::new(None));
+        let src = r#"
+            let loud = fn(x) { println(x); x };
+            [false && 1 / 0, true || 1 / 0, true && loud(0), false || loud(2), [] && true, null || false, 1 && 2]
+        "#;
+        let outcome = interpreter.evaluate_program_outcome(&Parser::new(Lexer::new(src.to_string())).parse_program().unwrap());
+        assert_eq!(outcome.value.unwrap().to_string(), "[false, true, false, true, false, false, true]");
+        assert_eq!(outcome.stdout, "0\n2\n");
+        assert!(eval("true && 1 / 0").is_err());
+    }
+
+    #[test]
+    fn test_run_isolated() {
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.define_global("base", Object::Integer(10));
+        let program = Parser::new(Lexer::new("const step = 1; let x = base + step; x".to_string())).parse_program().unwrap();
+        // Consts can't be bound twice in a scope, but each run gets a scope of its own
+        for _ in 0..2 {
+            assert_eq!(interpreter.run_isolated(&program).unwrap(), Object::Integer(11));
+        }
+        assert_eq!(interpreter.global_env().borrow().get("x"), None);
+        interpreter.evaluate_program(&program).unwrap();
+        assert!(interpreter.evaluate_program(&program).is_err());
+
+        // Runs don't keep the scopes of the fns they made
+        let program = Parser::new(Lexer::new("let add = fn(x) { fn(y) { x + y } }; add(1)".to_string())).parse_program().unwrap();
+        let kept = interpreter.envs.borrow().len();
+        for _ in 0..3 {
+            interpreter.run_isolated(&program).unwrap();
+        }
+        assert_eq!(interpreter.envs.borrow().len(), kept);
+        interpreter.define_global("inc", interpreter.run_isolated(&program).unwrap());
+        let call = Parser::new(Lexer::new("inc(2)".to_string())).parse_program().unwrap();
+        assert!(interpreter.evaluate_program(&call).unwrap_err().0.starts_with("Cannot call function `inc`, the scope it closes over was dropped"));
+    }
+
+    #[test]
+    fn test_json_builtins() {
+        // Monkey strings have no escapes, so JSON with strings in it comes from json_stringify
+        let src = r#"
+            let text = json_stringify({"name": "monkey", "ports": [80, 443], 1: (true, null)});
+            let config = json_parse(text);
+            [text, config["ports"][1], config["1"], json_parse("[1, -2, {}]")]
+        "#;
+        assert_eq!(eval(src).unwrap().to_string(), r#"["{\"1\":[true,null],\"name\":\"monkey\",\"ports\":[80,443]}", 443, [true, null], [1, -2, {}]]"#);
+        assert_eq!(eval(r#"is_error(json_parse("{")) && is_error(json_parse("1.5"))"#).unwrap(), Object::Boolean(true));
+        assert_eq!(eval("json_stringify(len)").unwrap_err().0, "Cannot convert builtin to JSON");
+        assert!(eval("json_parse(1)").is_err());
+    }
+
+    #[test]
+    fn test_const_bindings() {
+        assert_eq!(eval("const limit = 3; let f = fn(x) { const limit = x; limit * 2 }; [limit, f(5)]").unwrap().to_string(), "[3, 10]");
+        assert_eq!(eval("const limit = 3; let limit = 4;").unwrap_err().0, "Cannot redeclare const limit at line 1, column 22");
+
+        // Bindings made by earlier programs are only checked when they run
+        let interpreter = Interpreter::new(Environment::new(None));
+        let run = |src: &str| interpreter.evaluate_program(&Parser::new(Lexer::new(src.to_string())).parse_program().unwrap());
+        run("let x = 1; const (y, z) = (2, 3);").unwrap();
+        run("const x = 10;").unwrap();
+        assert_eq!(run("let x = 11;").unwrap_err().0, "Cannot redeclare const x");
+        assert_eq!(run("fn z() {}").unwrap_err().0, "Cannot redeclare const z");
+        assert_eq!(run("x + y + z").unwrap(), Object::Integer(15));
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let src = "let x = 1; const c = 10; let y = if (x > 0) { let x = x + 1; const c = 3; x + c } else { 0 }; [x, y, c]";
+        assert_eq!(eval(src).unwrap().to_string(), "[1, 5, 10]");
+        assert_eq!(eval("if (true) { let t = 1; }; t").unwrap_err().0, "Unknown variable: t at line 1, column 27");
+        assert_eq!(eval("if (false) { 0 } else { let t = 1; t }").unwrap(), Object::Integer(1));
+        // Lets in a fn's blocks don't leak into the fn's scope either
+        assert!(eval("let f = fn(n) { if (n) { let m = 1; }; m }; f(true)").is_err());
+    }
+
+    #[test]
+    fn test_structural_equality() {
+        let src = r#"
+            let a = [1, [2, "x"]];
+            let h = {"k": [1], 2: true};
+            [a == [1, [2, "x"]], a != [1, [2]], h == {2: true, "k": [1]}, h == {"k": [2], 2: true}, {} == {}, null == null]
+        "#;
+        assert_eq!(eval(src).unwrap().to_string(), "[true, true, true, false, true, true]");
+        assert!(eval("[1] < [2]").is_err());
+        assert!(eval("{} > {}").is_err());
+    }
+
+    #[test]
+    fn test_error_values() {
+        let src = r#"
+            let parse = fn(s) { let n = int(s); if (is_int(n)) { n } else { error("not a number: " + s) } };
+            let size = fn(x) { rescue(fn() { len(x) }) };
+            [parse("12"), parse("x"), is_error(parse("x")), size([1]), size(1), to_string(error("boom"))]
+        "#;
+        assert_eq!(eval(src).unwrap().to_string(), r#"[12, error: not a number: x, true, 1, error: Can't call built-in fn `len` on type: Integer(1), "boom"]"#);
+        assert_eq!(eval("rescue(fn() { 1 / 0 })").unwrap(), Object::Error("division by zero".to_string()));
+        assert!(eval("rescue(1)").is_err());
+
+        let mut parser = Parser::new(Lexer::new("rescue(fn() { let f = fn() { f() }; f() })".to_string()));
+        let program = parser.parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_step_limit(Some(100));
+        assert!(interpreter.evaluate_program(&program).unwrap_err().is_budget_exceeded());
+    }
+
+    #[test]
+    fn test_keys_and_values() {
+        let src = r#"let h = {"b": 2, "a": 1, 3: "three", true: [4]};"#;
+        let cases = [
+            ("keys(h)", r#"[true, 3, "a", "b"]"#),
+            ("values(h)", r#"[[4], "three", 1, 2]"#),
+            ("h", r#"{true: [4], 3: "three", "a": 1, "b": 2}"#),
+            ("keys({})", "[]"),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(eval(&format!("{src} {expr}")).unwrap().to_string(), expected, "{expr}");
+        }
+        assert!(eval("keys([1])").is_err());
+    }
 
-        if let Object::BuiltIn(f) = function_obj {
-            let mut args = Vec::new();
-            for i in 0..arguements.len() {
-                args.push(self.eval_expression(&arguements[i], env)?)
+    #[test]
+    fn test_host_functions() {
+        let interpreter = Interpreter::new(Environment::new(None));
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        interpreter.register_builtin("lookup", move |args| {
+            counter.set(counter.get() + 1);
+            match args.as_slice() {
+                [Object::String(key)] if key == "answer" => Ok(Object::Integer(42)),
+                [key] => Err(EvalError(format!("No such key: {key}"))),
+                args => Err(EvalError(format!("lookup takes 1 arguement, got: {}", args.len()))),
             }
-            return f(args)
-        } 
-    
-        Err(EvalError(format!("Invalid call expression, expression: {function:?} must evalate to function, got: {function_obj:?}")))
+        });
+        interpreter.define_global("base", Object::Integer(100));
+
+        let run = |src: &str| interpreter.evaluate_program(&Parser::new(Lexer::new(src.to_string())).parse_program().unwrap());
+        assert_eq!(run(r#"base + lookup("answer")"#).unwrap(), Object::Integer(142));
+        assert_eq!(run(r#"let get = bind(lookup, "answer"); get() + ("answer" |> lookup)"#).unwrap(), Object::Integer(84));
+        assert!(matches!(run(r#"lookup("question")"#), Err(EvalError(msg)) if msg == "No such key: question"));
+        assert_eq!(calls.get(), 4);
+
+        assert_eq!(run("lookup").unwrap().type_name(), "builtin");
+        assert!(interpreter.deprecate_builtin("lookup", "use fetch instead").is_ok());
+    }
+
+    #[test]
+    fn test_error_backtrace() {
+        let src = "
+            let inner = fn() { len(1) };
+            let outer = fn() { [1] |> fn(xs) { inner() } };
+            outer()
+        ";
+        match eval(src) {
+            Err(EvalError(msg)) => assert_eq!(msg, [
+                "Can't call built-in fn `len` on type: Integer(1)",
+                "call stack (most recent call last):",
+                "  at outer (line 4, column 13)",
+                "  at fn (line 3, column 39)",
+                "  at inner (line 3, column 48)",
+            ].join("\n")),
+            other => panic!("Expected len error, got: {other:?}"),
+        }
+
+        assert!(matches!(eval("len(1)"), Err(EvalError(msg)) if msg == "Can't call built-in fn `len` on type: Integer(1)"));
+        assert!(matches!(eval("let xs = [1]; fn() { len(x) }"), Err(EvalError(msg)) if msg == "Unknown variable: x at line 1, column 26, did you mean `xs`?"));
+    }
+
+    #[test]
+    fn test_default_max_call_depth() {
+        // The stack DEFAULT_MAX_CALL_DEPTH says the default depth takes in a debug build
+        let result = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let program = Parser::new(Lexer::new("let f = fn(n) { f(n + 1) }; f(0)".to_string())).parse_program().unwrap();
+            let interpreter = Interpreter::new(Environment::new(None));
+            match interpreter.evaluate_program(&program) {
+                Err(EvalError(msg)) => msg,
+                other => panic!("Expected call depth error, got: {other:?}"),
+            }
+        }).unwrap().join().unwrap();
+
+        assert!(result.starts_with("Maximum call depth of 1000 exceeded"), "{result}");
+        assert!(result.ends_with("  at f (line 1, column 17)\n  ... 998 more frames of f -> f ..."), "{result}");
+    }
+
+    #[test]
+    fn test_unknown_variable_modes() {
+        let program = Parser::new(Lexer::new("let a = if (missing) { 1 } else { 2 }; [a, missing, other]".to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+
+        let (result, diagnostics) = interpreter.evaluate_program_with_diagnostics(&program);
+        assert!(matches!(result, Err(EvalError(msg)) if msg == "Unknown variable: missing at line 1, column 13\nUnknown variable: other at line 1, column 53"));
+        assert!(diagnostics.is_empty());
+
+        interpreter.set_unknown_variable_mode(UnknownVariableMode::NullWithWarning);
+        let (result, diagnostics) = interpreter.evaluate_program_with_diagnostics(&program);
+        assert_eq!(format!("{:?}", result.unwrap()), format!("{:?}", Object::Array(vec![Object::Integer(2), Object::Null, Object::Null])));
+        assert_eq!(diagnostics, vec![
+            Diagnostic::warning("Unknown variable: missing, evaluated as null"),
+            Diagnostic::warning("Unknown variable: other, evaluated as null"),
+        ]);
+    }
+
+    #[test]
+    fn test_return_outside_function() {
+        assert!(matches!(eval("1; return 2;"), Err(EvalError(msg)) if msg == "Return outside of a function at line 1, column 4"));
+        let src = "let f = fn(x) { if (x) { if (true) { return 1; } } 2 }; [f(true), f(false)]";
+        assert_eq!(eval(src).unwrap().to_string(), "[1, 2]");
+
+        // Rejected whatever the unknown variable mode, nothing has run when it fails
+        let program = Parser::new(Lexer::new("println(1); if (true) { return 2; }".to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_unknown_variable_mode(UnknownVariableMode::NullWithWarning);
+        let outcome = interpreter.evaluate_program_outcome(&program);
+        assert!(outcome.value.is_err());
+        assert_eq!(outcome.stdout, "");
+    }
+
+    #[test]
+    fn test_fn_declarations_are_hoisted() {
+        let src = "
+            let parity = [is_even(10), is_odd(7), is_even(3)];
+            fn is_even(n) { if (n == 0) { true } else { is_odd(n - 1) } }
+            fn is_odd(n) { if (n == 0) { false } else { is_even(n - 1) } }
+            parity
+        ";
+        assert_eq!(eval(src).unwrap().to_string(), "[true, true, false]");
+
+        // Within a fn body too, and a declaration is still a binding like any other
+        let src = "fn outer() { let x = inner(); fn inner() { 41 } x + 1 }; [outer(), inner]";
+        assert!(matches!(eval(src), Err(EvalError(msg)) if msg.starts_with("Unknown variable: inner")));
+        assert_eq!(eval("fn outer() { let x = inner(); fn inner() { 41 } x + 1 }; outer()").unwrap(), Object::Integer(42));
+        assert_eq!(eval("fn f() { 1 }; let f = 2; f").unwrap(), Object::Integer(2));
+
+        // Declarations are bound once, so the last one of a name wins wherever it's called from
+        assert_eq!(eval("let a = f(); fn f() { 1 } fn f() { 2 } a").unwrap(), Object::Integer(2));
+        assert_eq!(eval("fn f() { 1 } let a = f(); fn f() { 2 } [a, f()]").unwrap().to_string(), "[2, 2]");
+    }
+
+    #[test]
+    fn test_tuples() {
+        let src = "
+            let divmod = fn(a, b) { (a / b, a - (a / b) * b) };
+            let (q, r) = divmod(17, 5);
+            [q, r, divmod(9, 3)]
+        ";
+        assert_eq!(eval(src).unwrap(), Object::Array(vec![
+            Object::Integer(3),
+            Object::Integer(2),
+            Object::Tuple(vec![Object::Integer(3), Object::Integer(0)]),
+        ]));
+
+        assert!(eval("let (a, b) = (1, 2, 3);").is_err());
+        assert!(eval("let (a, b) = [1, 2];").is_err());
+    }
+
+    #[test]
+    fn test_named_args() {
+        let src = "let area = fn(width, height, scale) { width * height * scale };";
+        assert_eq!(eval(&format!("{src} area(2, scale: 10, height: 3)")).unwrap(), Object::Integer(60));
+        assert_eq!(eval(&format!("{src} area(height: 3, width: 2, scale: 1)")).unwrap(), Object::Integer(6));
+
+        for (call, msg, runtime_msg) in [
+            ("area(2, 3, scale: 1, width: 5)", "arguement `width` given more than once", "Invalid call of function `area`: arguement `width` given more than once"),
+            ("area(2, 3, depth: 1)", "unknown named arguement `depth`, expected one of: width, height, scale", "unknown named arguement `depth`"),
+            ("area(2, scale: 1)", "missing arguement `height`", "function `area` expects 3 args (width, height, scale), got 2"),
+        ] {
+            let program = Parser::new(Lexer::new(format!("{src} {call}"))).parse_program().unwrap();
+            let interpreter = Interpreter::new(Environment::new(None));
+            assert_eq!(interpreter.analyze(&program), vec![Diagnostic::error(format!("In call to `area`: {msg}"))]);
+            match interpreter.evaluate_program(&program) {
+                Err(EvalError(err)) => assert!(err.contains(runtime_msg), "{err}"),
+                other => panic!("{call}: expected error, got {other:?}"),
+            }
+        }
+
+        assert!(eval("len(x: [1])").is_err());
+    }
+
+    #[test]
+    fn test_default_params() {
+        let src = "let add = fn(x, y = 10) { x + y };";
+        assert_eq!(eval(&format!("{src} add(1)")).unwrap(), Object::Integer(11));
+        assert_eq!(eval(&format!("{src} add(1, 2)")).unwrap(), Object::Integer(3));
+        assert_eq!(eval(&format!("{src} add(y: 5, x: 1)")).unwrap(), Object::Integer(6));
+        assert_eq!(eval(&format!("{src} bind(add, 2)()")).unwrap(), Object::Integer(12));
+        assert_eq!(eval(&format!("{src} map([1, 2], add)")).unwrap(), Object::Array(vec![Object::Integer(11), Object::Integer(12)]));
+        assert_eq!(eval(&format!("{src} add")).unwrap().to_string(), "fn(x, y = 10) {\n\t(x + y)\n }");
+
+        // Defaults are evaluated at each call in the fn's scope, after the params before them are bound
+        assert_eq!(eval("let n = 1; let f = fn(a, b = a * 2, c = n) { [a, b, c] }; let n = 5; f(3)").unwrap(), Object::Array(vec![
+            Object::Integer(3),
+            Object::Integer(6),
+            Object::Integer(5),
+        ]));
+        assert_eq!(eval("let f = fn(a, b = 2, c = 3) { a + b + c }; f(1, c: 0)").unwrap(), Object::Integer(3));
+        assert_eq!(eval(r#"with({"v": 4}, fn(x = v) { x })"#).unwrap(), Object::Integer(4));
+
+        for (call, msg) in [
+            ("add()", "function `add` expects 1 to 2 args (x, y = 10), got 0"),
+            ("add(1, 2, 3)", "function `add` expects 1 to 2 args (x, y = 10), got 3"),
+            ("add(y: 1)", "function `add` expects 1 to 2 args (x, y = 10), got 1"),
+            ("let sub = fn(a, b) { a - b }; sub(1)", "function `sub` expects 2 args (a, b), got 1"),
+            ("fn(a) { a }()", "function expects 1 args (a), got 0"),
+        ] {
+            match eval(&format!("{src} {call}")) {
+                Err(EvalError(err)) => assert!(err.starts_with(msg), "{call}: {err}"),
+                other => panic!("{call}: expected error, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rest_params() {
+        let src = "let sum = fn(first, ...rest) { reduce(rest, first, fn(acc, x) { acc + x }) };";
+        assert_eq!(eval(&format!("{src} sum(1)")).unwrap(), Object::Integer(1));
+        assert_eq!(eval(&format!("{src} sum(1, 2, 3)")).unwrap(), Object::Integer(6));
+        assert_eq!(eval(&format!("{src} bind(sum, 1, 2, 3)(4)")).unwrap(), Object::Integer(10));
+        assert_eq!(eval(&format!("{src} sum")).unwrap().to_string().lines().next(), Some("fn(first, ...rest) {"));
+        assert_eq!(eval("let f = fn(a, b = 2, ...rest) { [a, b, rest] }; f(1)").unwrap(), Object::Array(vec![
+            Object::Integer(1),
+            Object::Integer(2),
+            Object::Array(Vec::new()),
+        ]));
+        assert_eq!(eval("let f = fn(a, ...rest) { rest }; f(a: 1)").unwrap(), Object::Array(Vec::new()));
+
+        match eval(&format!("{src} sum()")) {
+            Err(EvalError(err)) => assert!(err.starts_with("function `sum` expects at least 1 args (first, ...rest), got 0"), "{err}"),
+            other => panic!("expected error, got {other:?}"),
+        }
+        assert!(eval(&format!("{src} sum(1, rest: [2])")).is_err());
+
+        let program = Parser::new(Lexer::new(format!("{src} sum(2, 3, 4, first: 1)"))).parse_program().unwrap();
+        let diagnostics = Interpreter::new(Environment::new(None)).analyze(&program);
+        assert_eq!(diagnostics, vec![Diagnostic::error("In call to `sum`: arguement `first` given more than once".to_string())]);
+    }
+
+    #[test]
+    fn test_fn_locals() {
+        // A local isn't bound until its `let` runs, so until then the name is the outer one
+        assert_eq!(eval("let x = 1; let f = fn() { let y = x; let x = 2; [y, x] }; [f(), x]").unwrap().to_string(), "[[1, 2], 1]");
+        assert_eq!(eval("let f = fn(x, x) { x }; f(1, 2)").unwrap(), Object::Integer(2));
+        assert_eq!(eval("let f = fn(a, b = a + 1) { let a = a * 10; [a, b] }; f(1)").unwrap().to_string(), "[10, 2]");
+        assert_eq!(eval("let adder = fn(n) { fn(x) { x + n } }; let add_two = adder(2); adder(5); add_two(1)").unwrap(), Object::Integer(3));
+        assert_eq!(eval(r#"let f = fn() { with({"v": 1}, fn() { v }) }; f()"#).unwrap(), Object::Integer(1));
+        // The scope `with` adds sits between a fn and the locals it closes over, and can shadow them
+        assert_eq!(eval(r#"let f = fn(x) { with({"x": 2}, fn() { x }) }; f(1)"#).unwrap(), Object::Integer(2));
+        assert_eq!(eval("let f = fn(x) { fn() { if (x) { let y = x + 1; fn() { [x, y] } } } }; f(1)()()").unwrap().to_string(), "[1, 2]");
+    }
+
+    #[test]
+    fn test_closures_share_their_body() {
+        let Object::Array(closures) = eval("let make = fn(n) { fn() { n } }; [make(1), make(2)]").unwrap() else {
+            panic!("expected an array");
+        };
+        match closures.as_slice() {
+            [Object::Function { body: first, .. }, Object::Function { body: second, .. }] => assert!(Rc::ptr_eq(first, second)),
+            closures => panic!("expected 2 fns, got {closures:?}"),
+        }
+    }
+
+    #[test]
+    fn test_bind() {
+        let src = "let sum = fn(a, b, c) { a + b + c }; let plus_one = bind(sum, 1);";
+        assert_eq!(eval(&format!("{src} plus_one(2, 3)")).unwrap(), Object::Integer(6));
+        assert_eq!(eval(&format!("{src} bind(plus_one, 10)(100)")).unwrap(), Object::Integer(111));
+        assert_eq!(eval(&format!("{src} plus_one(c: 3, b: 2)")).unwrap(), Object::Integer(6));
+        assert_eq!(eval(r#"let greet = bind(push, ["hi"]); greet("there")"#).unwrap(), Object::Array(vec![
+            Object::String("hi".to_string()),
+            Object::String("there".to_string()),
+        ]));
+        // Bound args are evaluated once, when bound
+        assert_eq!(eval("let x = 1; let f = bind(fn(a) { a }, x); let x = 2; f()").unwrap(), Object::Integer(1));
+
+        assert!(eval(&format!("{src} bind(sum, 1, 2, 3, 4)")).is_err());
+        assert!(eval(&format!("{src} plus_one(a: 1, b: 2, c: 3)")).is_err());
+        assert!(eval("bind(5, 1)").is_err());
+    }
+
+    #[test]
+    fn test_with() {
+        assert_eq!(eval(r#"let x = 1; with({"x": 10, "y": 2}, fn() { x * y })"#).unwrap(), Object::Integer(20));
+        // Bindings are scoped to the call
+        assert!(eval(r#"with({"y": 2}, fn() { y }); y"#).is_err());
+        assert_eq!(eval(r#"let x = 1; with({"y": 2}, fn() { x + y })"#).unwrap(), Object::Integer(3));
+        // Closures created inside keep seeing the bindings
+        assert_eq!(eval(r#"let get = with({"v": 7}, fn() { fn() { v } }); get()"#).unwrap(), Object::Integer(7));
+        assert_eq!(eval(r#"let f = fn(a) { a + b }; let w = with; w({"b": 1}, bind(f, 2))"#).unwrap(), Object::Integer(3));
+
+        assert!(eval(r#"with({1: 2}, fn() { 1 })"#).is_err());
+        assert!(eval(r#"with({"a": 1}, fn(x) { x })"#).is_err());
+        assert!(eval(r#"with([1], fn() { 1 })"#).is_err());
+        assert!(eval("with()").is_err());
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let parse = |src: &str| Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        interpreter.set_step_limit(Some(500));
+
+        let fib = parse("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };");
+        interpreter.evaluate_program(&fib).unwrap();
+        let err = interpreter.evaluate_program(&parse("fib(20)")).unwrap_err();
+        assert!(err.is_budget_exceeded(), "{err:?}");
+        assert!(interpreter.call_stack.borrow().is_empty());
+
+        // Every run gets the full budget again
+        assert_eq!(interpreter.evaluate_program(&parse("fib(5)")).unwrap(), Object::Integer(5));
+        interpreter.set_step_limit(None);
+        assert_eq!(interpreter.evaluate_program(&parse("fib(12)")).unwrap(), Object::Integer(144));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let parse = |src: &str| Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let interpreter = Interpreter::new(Environment::new(None));
+        let interrupt = Arc::new(AtomicBool::new(false));
+        interpreter.set_interrupt(Some(Arc::clone(&interrupt)));
+        interpreter.evaluate_program(&parse("let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };")).unwrap();
+
+        // Raised from another thread mid-run, and `rescue` doesn't catch it
+        let raiser = {
+            let interrupt = Arc::clone(&interrupt);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                interrupt.store(true, atomic::Ordering::Relaxed);
+            })
+        };
+        let err = interpreter.evaluate_program(&parse("rescue(fn() { fib(40) })")).unwrap_err();
+        raiser.join().unwrap();
+        assert!(err.is_interrupted(), "{err:?}");
+        assert!(!interrupt.load(atomic::Ordering::Relaxed));
+        assert!(interpreter.call_stack.borrow().is_empty());
+
+        // The session goes on
+        assert_eq!(interpreter.evaluate_program(&parse("fib(5)")).unwrap(), Object::Integer(5));
+    }
+
+    #[test]
+    fn test_pipe() {
+        let src = "let double = fn(x) { x * 2 }; let sub = fn(a, b) { a - b };";
+        assert_eq!(eval(&format!("{src} 3 |> double |> double")).unwrap(), Object::Integer(12));
+        assert_eq!(eval(&format!("{src} 1 + 2 |> bind(sub, 10) |> to_string")).unwrap(), Object::String("7".to_string()));
+        assert!(eval(&format!("{src} 1 |> 2")).is_err());
     }
-    
 }
@@ -1,3 +1,10 @@
 pub mod interpreter;
+pub mod diagnostics;
+pub mod backtrace;
+pub mod lint;
+pub mod coverage;
 
 pub use interpreter::*;
+pub use diagnostics::*;
+pub use lint::*;
+pub use coverage::*;
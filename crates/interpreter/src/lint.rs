@@ -0,0 +1,154 @@
+use std::{collections::HashSet, convert::Infallible};
+
+use parser::{analysis::scope_bindings, ast::{walk_expression, walk_statement, Expression, Statement, Visitor}, lexer::token::Span, Program};
+
+use crate::diagnostics::Diagnostic;
+
+/// Optional lints for `program`, all warnings: bindings shadowing an outer one, local `let`s that are never read and
+/// statements after a `return`. Nothing reported here stops the program from running.
+pub fn analyze(program: &Program) -> Vec<Diagnostic> {
+    let mut lint = Lint { scopes: Vec::new(), diagnostics: Vec::new() };
+    lint.check_reachable(&program.statements);
+    lint.enter_scope(&program.statements, &[]);
+    for statement in &program.statements {
+        let Ok(()) = lint.visit_statement(statement);
+    }
+    // Globals are visible to the host and later runs, so only bindings in functions count as unused
+    lint.scopes.pop();
+    lint.diagnostics
+}
+
+struct Binding {
+    name: String,
+    span: Option<Span>,
+    is_param: bool,
+    used: bool,
+}
+
+struct Lint {
+    scopes: Vec<Vec<Binding>>, // one per function body or block, innermost last
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Lint {
+    fn enter_scope(&mut self, statements: &[Statement], params: &[Expression]) {
+        let mut shadowing = HashSet::new();
+        let scope = scope_bindings(statements, params)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, span))| Binding { name, span, is_param: i < params.len(), used: false })
+            .collect::<Vec<Binding>>();
+        for binding in &scope {
+            let shadows = self.scopes.iter().flatten().any(|outer| outer.name == binding.name);
+            if shadows && shadowing.insert(&binding.name) {
+                self.diagnostics.push(Diagnostic::warning(format!("`{}`{} shadows an outer binding", binding.name, at(binding.span))));
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    fn leave_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        let mut reported = HashSet::new();
+        for binding in &scope {
+            let used = scope.iter().any(|other| other.name == binding.name && other.used);
+            if !binding.is_param && !used && reported.insert(&binding.name) {
+                self.diagnostics.push(Diagnostic::warning(format!("`{}`{} is never used", binding.name, at(binding.span))));
+            }
+        }
+    }
+
+    fn check_reachable(&mut self, statements: &[Statement]) {
+        if let Some(i) = statements.iter().position(|statement| matches!(statement, Statement::Return { .. })) {
+            if let Some(unreachable) = statements.get(i + 1) {
+                self.diagnostics.push(Diagnostic::warning(format!("unreachable statement{}", at(unreachable.span()))));
+            }
+        }
+    }
+}
+
+impl Visitor for Lint {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        match statement {
+            // Binding a name isn't a use of it
+            Statement::Let { value, .. } => self.visit_expression(value),
+            Statement::Block { statements, .. } => {
+                self.check_reachable(statements);
+                self.enter_scope(statements, &[]);
+                let result = walk_statement(self, statement);
+                self.leave_scope();
+                result
+            },
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Identifier { value, .. } => {
+                let scope = self.scopes.iter_mut().rev().find(|scope| scope.iter().any(|binding| binding.name == *value));
+                if let Some(binding) = scope.and_then(|scope| scope.iter_mut().find(|binding| binding.name == *value)) {
+                    binding.used = true;
+                }
+                Ok(())
+            },
+            Expression::Function { params, body, .. } => {
+                let statements = match body.as_ref() {
+                    Statement::Block { statements, .. } => statements.as_slice(),
+                    body => std::slice::from_ref(body),
+                };
+                self.enter_scope(statements, params);
+                for default in params.iter().filter_map(Expression::param_default) {
+                    self.visit_expression(default)?;
+                }
+                // The body is the fn's scope, not a block of its own
+                self.check_reachable(statements);
+                let result = statements.iter().try_for_each(|statement| self.visit_statement(statement));
+                self.leave_scope();
+                result
+            },
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+fn at(span: Option<Span>) -> String {
+    span.map(|span| format!(" at {span}")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    fn lint(src: &str) -> Vec<String> {
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        analyze(&program).into_iter().map(|diagnostic| diagnostic.to_string()).collect()
+    }
+
+    #[test]
+    fn test_analyze() {
+        let src = "
+let total = 0;
+let add = fn(total, n) {
+    let unused = 1;
+    let (q, r) = (n, n);
+    return total + q;
+    r
+};
+let f = fn() { if (true) { return 1; 2 } else { 3 } };
+";
+        assert_eq!(lint(src), [
+            "warning: `total` at line 3, column 14 shadows an outer binding",
+            "warning: unreachable statement at line 7, column 5",
+            "warning: `unused` at line 4, column 9 is never used",
+            "warning: unreachable statement at line 9, column 38",
+        ]);
+
+        // Rebinding in the same scope isn't shadowing, and recursion or a later read is a use
+        assert!(lint("let x = 1; let x = x + 1; let f = fn(n) { let m = n; let g = fn() { f(m) }; g() };").is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use compiler::{vm::{Step, VM}, Arg};
+
+/// Drives a VM one command at a time for the REPL's `:debug`, each command answering with the lines to print.
+pub struct Debugger {
+    vm: VM,
+}
+
+impl Debugger {
+    pub const HELP: [&'static str; 7] = [
+        "s             execute one instruction",
+        "c             continue to the next breakpoint or the end",
+        "b <offset>    break before the instruction at offset",
+        "d <offset>    delete the breakpoint at offset",
+        "l             list the instructions, > marks the next one and * breakpoints",
+        "p             print the stack",
+        "q             stop debugging",
+    ];
+
+    pub fn new(vm: VM) -> Self {
+        Self { vm }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.vm.finished()
+    }
+
+    pub fn into_vm(self) -> VM {
+        self.vm
+    }
+
+    pub fn command(&mut self, input: &str) -> Vec<String> {
+        let (command, arg) = input.split_once(char::is_whitespace).map_or((input, ""), |(command, arg)| (command, arg.trim()));
+        let offset = || arg.parse::<usize>().map_err(|_| format!("Usage: {command} <offset>"));
+
+        let result = match (command, arg) {
+            ("s", "") => self.vm.step().map_err(|err| err.0).map(|step| match step {
+                Some(step) => vec![show_step(&step)],
+                None => vec!["finished".to_string()],
+            }),
+            ("c", "") => self.vm.run_to_breakpoint().map_err(|err| err.0).map(|stop| match stop {
+                Some(offset) => vec![format!("breakpoint at {offset:04}")],
+                None => vec![format!("finished, last popped: {}", self.vm.last_popped())],
+            }),
+            ("b", _) => offset().and_then(|offset| {
+                self.vm.set_breakpoint(offset).map_err(|err| err.0)?;
+                Ok(vec![format!("breakpoint set at {offset:04}")])
+            }),
+            ("d", _) => offset().map(|offset| match self.vm.clear_breakpoint(offset) {
+                true => vec![format!("breakpoint deleted at {offset:04}")],
+                false => vec![format!("no breakpoint at {offset:04}")],
+            }),
+            ("l", "") => Ok(self.listing()),
+            ("p", "") => Ok(vec![show_stack(&self.vm.stack())]),
+            _ => Err(format!("Unknown debugger command {input}, expected one of s, c, b, d, l, p or q")),
+        };
+        result.unwrap_or_else(|err| vec![err])
+    }
+
+    fn listing(&self) -> Vec<String> {
+        let breakpoints = self.vm.breakpoints();
+        self.vm
+            .instructions()
+            .into_iter()
+            .map(|(offset, opcode, args)| {
+                let next = if offset == self.vm.ip() { '>' } else { ' ' };
+                let breakpoint = if breakpoints.contains(&offset) { '*' } else { ' ' };
+                format!("{next}{breakpoint} {offset:04} {opcode:?}{}", show_args(&args))
+            })
+            .collect()
+    }
+}
+
+fn show_args(args: &[Arg]) -> String {
+    args.iter()
+        .map(|arg| match arg {
+            Arg::U8(val) => format!(" {val}"),
+            Arg::U16(val) => format!(" {val}"),
+            Arg::U32(val) => format!(" {val}"),
+        })
+        .collect()
+}
+
+fn show_stack(stack: &[compiler::Object]) -> String {
+    format!("stack: [{}]", stack.iter().map(|val| val.to_string()).collect::<Vec<String>>().join(", "))
+}
+
+fn show_step(step: &Step) -> String {
+    let mut line = format!("{:04} {:?}{}", step.offset, step.opcode, show_args(&step.args));
+    if let Some(span) = step.span {
+        line += &format!(" ({span})");
+    }
+    line + "  " + &show_stack(&step.stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use compiler::Compiler;
+    use parser::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    #[test]
+    fn test_debugger() {
+        let program = Parser::new(Lexer::new_borrowed("let x = [1][0];\nx + 2")).parse_program().unwrap();
+        let mut debugger = Debugger::new(VM::new(Compiler::new().compile_program(&program).unwrap()));
+
+        assert_eq!(debugger.command("s"), ["0000 Constant 0 (line 1, column 10)  stack: [1]"]);
+        assert_eq!(debugger.command("b 13"), ["breakpoint set at 0013"]);
+        assert_eq!(debugger.command("b 2"), ["No instruction starts at offset 2"]);
+        assert_eq!(debugger.command("b x"), ["Usage: b <offset>"]);
+        assert_eq!(debugger.command("c"), ["breakpoint at 0013"]);
+        assert_eq!(debugger.command("p"), ["stack: []"]);
+        assert_eq!(debugger.command("l")[4..7], [
+            "   0010 SetGlobal 0",
+            ">* 0013 GetGlobal 0",
+            "   0016 Constant 2",
+        ]);
+        assert_eq!(debugger.command("d 13"), ["breakpoint deleted at 0013"]);
+        assert_eq!(debugger.command("d 13"), ["no breakpoint at 0013"]);
+        assert_eq!(debugger.command("c"), ["finished, last popped: 3"]);
+        assert!(debugger.finished());
+        assert_eq!(debugger.command("s"), ["finished"]);
+        assert!(debugger.command("jump").join("").starts_with("Unknown debugger command jump"));
+    }
+}
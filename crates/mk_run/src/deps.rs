@@ -0,0 +1,211 @@
+use std::{collections::HashMap, convert::Infallible, fs, io, path::Path};
+
+use parser::{ast::{walk_statement, Statement, Visitor}, lexer::Lexer, Parser};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Emit {
+    Dot,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub name: String, // path relative to the entry's directory where possible
+    pub imports: Vec<usize>,
+    pub missing: bool,
+}
+
+/// Modules reachable from an entry script through `import`, resolved the same way the interpreter does.
+#[derive(Debug)]
+pub struct DepGraph {
+    pub modules: Vec<Module>,
+}
+
+struct ImportCollector(Vec<String>);
+
+impl Visitor for ImportCollector {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        if let Statement::Import { path, .. } = statement {
+            self.0.push(path.clone());
+        }
+        walk_statement(self, statement)
+    }
+}
+
+fn parse_imports(path: &Path) -> io::Result<Vec<String>> {
+    let file = fs::File::open(path)?;
+    let program = Parser::new(Lexer::from_reader(io::BufReader::new(file)))
+        .parse_program()
+        .map_err(|err| io::Error::other(format!("Unable to parse module {}: {err:?}", path.display())))?;
+
+    let mut collector = ImportCollector(Vec::new());
+    let Ok(()) = collector.visit_program(&program);
+    Ok(collector.0)
+}
+
+impl DepGraph {
+    pub fn build(entry: &Path) -> io::Result<Self> {
+        let entry = fs::canonicalize(entry)?;
+        let root = entry.parent().map(Path::to_path_buf).unwrap_or_default();
+        let name = |path: &Path| path.strip_prefix(&root).unwrap_or(path).display().to_string();
+
+        let mut paths = vec![entry.clone()];
+        let mut modules = vec![Module { name: name(&entry), imports: Vec::new(), missing: false }];
+        let mut ids = HashMap::from([(entry, 0)]);
+
+        let mut next = 0;
+        while next < paths.len() {
+            if !modules[next].missing {
+                let base_dir = paths[next].parent().map(Path::to_path_buf).unwrap_or_default();
+                for import in parse_imports(&paths[next])? {
+                    let resolved = base_dir.join(&import);
+                    // Missing modules stay in the graph so the broken import is visible
+                    let (path, missing) = match fs::canonicalize(&resolved) {
+                        Ok(path) => (path, false),
+                        Err(_) => (resolved, true),
+                    };
+                    let id = *ids.entry(path.clone()).or_insert_with(|| {
+                        modules.push(Module { name: name(&path), imports: Vec::new(), missing });
+                        paths.push(path);
+                        modules.len() - 1
+                    });
+                    if !modules[next].imports.contains(&id) {
+                        modules[next].imports.push(id);
+                    }
+                }
+            }
+            next += 1;
+        }
+
+        Ok(Self { modules })
+    }
+
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut seen = vec![false; self.modules.len()];
+        let mut stack = vec![from];
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if !std::mem::replace(&mut seen[id], true) {
+                stack.extend(&self.modules[id].imports);
+            }
+        }
+        false
+    }
+
+    /// An import is part of a cycle when the imported module leads back to the importer.
+    pub fn in_cycle(&self, from: usize, to: usize) -> bool {
+        self.reaches(to, from)
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        self.modules
+            .iter()
+            .enumerate()
+            .flat_map(move |(from, module)| module.imports.iter().map(move |&to| (from, to, self.in_cycle(from, to))))
+    }
+
+    fn module_in_cycle(&self, id: usize) -> bool {
+        self.modules[id].imports.iter().any(|&to| self.in_cycle(id, to))
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph deps {\n");
+        for (id, module) in self.modules.iter().enumerate() {
+            let mut attrs = vec![format!("label=\"{}\"", escape(&module.name))];
+            if module.missing {
+                attrs.push("style=dashed".to_string());
+            }
+            if self.module_in_cycle(id) {
+                attrs.push("color=red".to_string());
+            }
+            out.push_str(&format!("    m{id} [{}];\n", attrs.join(", ")));
+        }
+        for (from, to, cycle) in self.edges() {
+            let attrs = if cycle { " [color=red]" } else { "" };
+            out.push_str(&format!("    m{from} -> m{to}{attrs};\n"));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let modules = self.modules
+            .iter()
+            .enumerate()
+            .map(|(id, module)| format!(
+                "{{\"name\": \"{}\", \"missing\": {}, \"in_cycle\": {}}}",
+                escape(&module.name), module.missing, self.module_in_cycle(id),
+            ))
+            .collect::<Vec<String>>();
+        let edges = self.edges()
+            .map(|(from, to, cycle)| format!(
+                "{{\"from\": \"{}\", \"to\": \"{}\", \"cycle\": {cycle}}}",
+                escape(&self.modules[from].name), escape(&self.modules[to].name),
+            ))
+            .collect::<Vec<String>>();
+        format!("{{\"modules\": [{}], \"edges\": [{}]}}\n", modules.join(", "), edges.join(", "))
+    }
+
+    pub fn emit(&self, emit: Emit) -> String {
+        match emit {
+            Emit::Dot => self.to_dot(),
+            Emit::Json => self.to_json(),
+        }
+    }
+}
+
+/// Escapes a string for a double quoted DOT id or JSON string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dep_graph() {
+        let dir = std::env::temp_dir().join(format!("mk_deps_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.mk"), r#"import "lib/a.mk"; import "lib/util.mk"; import "gone.mk";"#).unwrap();
+        fs::write(dir.join("lib/a.mk"), r#"import "b.mk"; let a = 1;"#).unwrap();
+        fs::write(dir.join("lib/b.mk"), r#"import "a.mk"; import "util.mk"; let b = 2;"#).unwrap();
+        fs::write(dir.join("lib/util.mk"), "let util = 3;").unwrap();
+
+        let graph = DepGraph::build(&dir.join("main.mk")).unwrap();
+        let names = graph.modules.iter().map(|module| module.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["main.mk", "lib/a.mk", "lib/util.mk", "gone.mk", "lib/b.mk"]);
+        assert!(graph.modules[3].missing);
+
+        let cycles = graph.edges().filter(|(_, _, cycle)| *cycle).map(|(from, to, _)| (from, to)).collect::<Vec<_>>();
+        assert_eq!(cycles, [(1, 4), (4, 1)]);
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("m1 -> m4 [color=red];"), "{dot}");
+        assert!(dot.contains("m0 -> m2;"), "{dot}");
+        assert!(dot.contains("m3 [label=\"gone.mk\", style=dashed];"), "{dot}");
+
+        let json = graph.to_json();
+        assert!(json.contains(r#"{"from": "lib/b.mk", "to": "lib/a.mk", "cycle": true}"#), "{json}");
+        assert!(json.contains(r#"{"name": "lib/util.mk", "missing": false, "in_cycle": false}"#), "{json}");
+
+        fs::write(dir.join("bad.mk"), "let = ;").unwrap();
+        assert!(DepGraph::build(&dir.join("bad.mk")).is_err());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, LazyLock};
+
+/// Exit status of a run stopped by Ctrl+C, as shells report a process killed by SIGINT.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPT: LazyLock<Arc<AtomicBool>> = LazyLock::new(Arc::default);
+
+/// Raised by Ctrl+C, for the interpreter and the VM to stop at their next step. They lower it when they stop.
+pub fn flag() -> Arc<AtomicBool> {
+    Arc::clone(&INTERRUPT)
+}
+
+/// Drops a Ctrl+C nothing was running to stop, so it doesn't stop the next run.
+pub fn clear() {
+    INTERRUPT.store(false, Ordering::Relaxed);
+}
+
+/// Ends a run stopped by Ctrl+C with `message`, the error it stopped with.
+pub fn exit(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(EXIT_INTERRUPTED)
+}
+
+/// Catches Ctrl+C to raise the flag instead of killing the process. A second Ctrl+C before anything stopped for
+/// the first still exits, for runs stuck where the flag isn't checked, like a builtin waiting on stdin.
+pub fn install() {
+    let handled = ctrlc::set_handler(|| {
+        if INTERRUPT.swap(true, Ordering::Relaxed) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+    if let Err(err) = handled {
+        eprintln!("Unable to catch Ctrl+C, it will kill the process: {err}");
+    }
+}
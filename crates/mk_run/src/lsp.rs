@@ -0,0 +1,325 @@
+use std::{collections::HashMap, convert::Infallible, io::{self, BufRead, Write}};
+
+use interpreter::{Environment, Interpreter};
+use parser::{ast::{walk_expression, walk_statement, Expression, Statement, Visitor}, lexer::{token::{Span, Token}, Lexer}, ParseError, Parser, Program};
+use serde_json::{json, Value};
+
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const SEVERITY_ERROR: u32 = 1;
+
+/// Hovering evaluates constant bindings, this keeps that cheap whatever the expression.
+const HOVER_STEP_LIMIT: usize = 1_000;
+
+/// Speaks the Language Server Protocol over `input` and `output` until the client sends `exit`.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(len) = header.strip_prefix("Content-Length:") {
+            content_length = len.trim().parse::<usize>().ok();
+        }
+    }
+
+    let len = content_length.ok_or_else(|| io::Error::other("Message without a Content-Length header"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::other)
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>, // uri -> text
+}
+
+impl Server {
+    /// The responses and notifications to send back for `message`.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": { "textDocumentSync": 1, "documentSymbolProvider": true, "hoverProvider": true },
+                "serverInfo": { "name": "mk" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => return self.update(uri, params["textDocument"]["text"].as_str()),
+            "textDocument/didChange" => {
+                // Only full syncs are advertised, so the last change holds the whole text
+                let text = params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str());
+                return self.update(uri, text);
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            },
+            "textDocument/documentSymbol" => match self.documents.get(&uri).map(|text| parse(text)) {
+                Some(Ok(program)) => Value::Array(document_symbols(&program)),
+                _ => Value::Null,
+            },
+            "textDocument/hover" => {
+                let line = params["position"]["line"].as_u64().unwrap_or_default();
+                let character = params["position"]["character"].as_u64().unwrap_or_default();
+                match self.documents.get(&uri).map(|text| parse(text)) {
+                    Some(Ok(program)) => hover(&program, line as u32 + 1, character as u32 + 1).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                }
+            },
+            // Notifications (no id) the server doesn't act on, e.g. `initialized`
+            _ if message.get("id").is_none() => return Vec::new(),
+            method => return vec![json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": { "code": -32601, "message": format!("Unsupported method: {method}") },
+            })],
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+
+    fn update(&mut self, uri: String, text: Option<&str>) -> Vec<Value> {
+        let Some(text) = text else {
+            return Vec::new();
+        };
+        let diagnostics = match parse(text) {
+            Ok(_) => Vec::new(),
+            Err((message, span)) => vec![json!({ "range": range(span, 1), "severity": SEVERITY_ERROR, "source": "mk", "message": message })],
+        };
+        self.documents.insert(uri.clone(), text.to_string());
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn parse(text: &str) -> Result<Program, (String, Option<Span>)> {
+    let mut parser = Parser::new(Lexer::new(text.to_string()));
+    parser.parse_program().map_err(|err| {
+        let message = match err {
+            ParseError::Syntax(msg) => msg,
+            ParseError::LimitExceeded(limit) => format!("Parser limit exceeded: {limit:?}"),
+        };
+        (message, parser.span())
+    })
+}
+
+/// An LSP range of `len` chars from `span`, spans count from 1 while LSP positions count from 0.
+fn range(span: Option<Span>, len: usize) -> Value {
+    let (line, character) = span.map_or((0, 0), |span| (span.line - 1, span.col - 1));
+    json!({
+        "start": { "line": line, "character": character },
+        "end": { "line": line, "character": character as usize + len },
+    })
+}
+
+/// A symbol per `let` name and unbound fn literal, the bindings inside a fn nested under it.
+fn document_symbols(program: &Program) -> Vec<Value> {
+    struct Symbols(Vec<Vec<Value>>); // the symbols of each fn being walked, innermost last
+
+    impl Symbols {
+        fn push(&mut self, name: &str, kind: u32, span: Option<Span>, children: Vec<Value>) {
+            let range = range(span, name.chars().count());
+            if let Some(symbols) = self.0.last_mut() {
+                symbols.push(json!({ "name": name, "kind": kind, "range": range, "selectionRange": range, "children": children }));
+            }
+        }
+
+        fn children(&mut self, function: &Expression) -> Vec<Value> {
+            self.0.push(Vec::new());
+            let Ok(()) = walk_expression(self, function);
+            self.0.pop().unwrap_or_default()
+        }
+    }
+
+    impl Visitor for Symbols {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            match statement {
+                Statement::Let { name: Expression::Identifier { value: name, token, .. }, value: function @ Expression::Function { .. }, .. } => {
+                    let children = self.children(function);
+                    self.push(name, SYMBOL_FUNCTION, token.span, children);
+                    Ok(())
+                },
+                Statement::Let { name, value, .. } => {
+                    let names = match name {
+                        Expression::Tuple { elements, .. } => elements.iter().collect(),
+                        name => vec![name],
+                    };
+                    for name in names {
+                        if let Expression::Identifier { value, token, .. } = name {
+                            self.push(value, SYMBOL_VARIABLE, token.span, Vec::new());
+                        }
+                    }
+                    self.visit_expression(value)
+                },
+                _ => walk_statement(self, statement),
+            }
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            match expression {
+                Expression::Function { token, .. } => {
+                    let children = self.children(expression);
+                    self.push("fn", SYMBOL_FUNCTION, token.span, children);
+                    Ok(())
+                },
+                _ => walk_expression(self, expression),
+            }
+        }
+    }
+
+    let mut symbols = Symbols(vec![Vec::new()]);
+    let Ok(()) = symbols.visit_program(program);
+    symbols.0.pop().unwrap_or_default()
+}
+
+/// Describes the variable at `line`, `col` (counting from 1): a fn's params, or the value of a constant binding.
+fn hover(program: &Program, line: u32, col: u32) -> Option<Value> {
+    struct Lookup {
+        line: u32,
+        col: u32,
+        hovered: Option<(String, Span)>,
+        bindings: Vec<(String, Span, Expression)>,
+    }
+
+    impl Visitor for Lookup {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            if let Statement::Let { name: Expression::Identifier { value: name, token, .. }, value, .. } = statement {
+                if let Some(span) = token.span {
+                    self.bindings.push((name.clone(), span, value.clone()));
+                }
+            }
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            if let Expression::Identifier { value, token, .. } = expression {
+                let on_name = |span: &Span| span.line == self.line && (span.col..span.col + value.chars().count() as u32).contains(&self.col);
+                if let Some(span) = token.span.filter(on_name) {
+                    self.hovered = Some((value.clone(), span));
+                }
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    let mut lookup = Lookup { line, col, hovered: None, bindings: Vec::new() };
+    let Ok(()) = lookup.visit_program(program);
+    let (name, span) = lookup.hovered?;
+
+    // The closest binding before the hovered name, or a later one for uses in fns defined first
+    let candidates = lookup.bindings.iter().filter(|(binding, ..)| *binding == name).collect::<Vec<_>>();
+    let before = candidates.iter().rev().find(|(_, binding, _)| (binding.line, binding.col) <= (span.line, span.col));
+    let (_, _, value) = before.or(candidates.first())?;
+
+    let description = match value {
+        Expression::Function { params, .. } => format!("{name}: fn({})", params.iter().map(Expression::dbg_param).collect::<Vec<String>>().join(", ")),
+        value if is_constant(value) => {
+            let interpreter = Interpreter::new(Environment::new(None));
+            interpreter.set_step_limit(Some(HOVER_STEP_LIMIT));
+            let statement = Statement::construct_expression_statement(Token::new_identifier(&name), value.clone());
+            let value = interpreter.evaluate_program(&Program { statements: vec![statement] }).ok()?;
+            format!("{name} = {value}")
+        },
+        _ => return None,
+    };
+    Some(json!({ "contents": { "kind": "plaintext", "value": description }, "range": range(Some(span), name.chars().count()) }))
+}
+
+/// Whether `expression` is built from literals only, so evaluating it needs no bindings and can't run away.
+fn is_constant(expression: &Expression) -> bool {
+    struct Constant(bool);
+
+    impl Visitor for Constant {
+        type Error = Infallible;
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            if matches!(expression, Expression::Identifier { .. } | Expression::Call { .. } | Expression::Function { .. }) {
+                self.0 = false;
+                return Ok(());
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    let mut constant = Constant(true);
+    let Ok(()) = constant.visit_expression(expression);
+    constant.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        server.handle(&json!({ "method": "textDocument/didOpen", "params": { "textDocument": { "uri": "file:///a.mk", "text": text } } }))
+    }
+
+    fn request(server: &mut Server, method: &str, params: Value) -> Value {
+        server.handle(&json!({ "id": 1, "method": method, "params": params })).remove(0)["result"].clone()
+    }
+
+    #[test]
+    fn test_lsp() {
+        let mut server = Server::default();
+        assert_eq!(request(&mut server, "initialize", json!({}))["capabilities"]["hoverProvider"], true);
+
+        let published = open(&mut server, "let x = 1;\nlet = 2;");
+        let diagnostic = &published[0]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["range"]["start"], json!({ "line": 1, "character": 0 }), "{diagnostic}");
+
+        let src = "let rate = 3 * 4;\nlet scale = fn(x) { let y = x * rate; y };\nscale(rate)";
+        assert_eq!(open(&mut server, src)[0]["params"]["diagnostics"], json!([]));
+
+        let document = json!({ "textDocument": { "uri": "file:///a.mk" } });
+        let symbols = request(&mut server, "textDocument/documentSymbol", document.clone());
+        assert_eq!(symbols.as_array().unwrap().iter().map(|symbol| symbol["name"].clone()).collect::<Vec<Value>>(), ["rate", "scale"]);
+        assert_eq!(symbols[1]["kind"], SYMBOL_FUNCTION);
+        assert_eq!(symbols[1]["children"][0]["name"], "y");
+
+        let hover = |server: &mut Server, line: u32, character: u32| {
+            let params = json!({ "textDocument": document["textDocument"], "position": { "line": line, "character": character } });
+            request(server, "textDocument/hover", params)["contents"]["value"].clone()
+        };
+        assert_eq!(hover(&mut server, 2, 8), "rate = 12");
+        assert_eq!(hover(&mut server, 2, 1), "scale: fn(x)");
+        assert_eq!(hover(&mut server, 1, 20), Value::Null); // `y` isn't constant
+
+        assert!(server.handle(&json!({ "method": "initialized", "params": {} })).is_empty());
+        assert_eq!(server.handle(&json!({ "id": 2, "method": "workspace/symbol" }))[0]["error"]["code"], -32601);
+    }
+}
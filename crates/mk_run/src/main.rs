@@ -1,16 +1,28 @@
-use clap::Parser;
-use compiler::vm::VM;
-use compiler::Compiler;
-use interpreter::{Environment, Interpreter};
+use clap::{Parser, Subcommand};
+use compiler::{report::SizeReport, vm::VM, Compiler};
+use interpreter::{Capabilities, Environment, EvalError, Interpreter, Level, Logger, Object, StderrLogger};
 use parser::lexer::Lexer;
+use deps::{DepGraph, Emit};
+use engine::{render_diagnostic, render_diagnostic_colored, EngineError, Source};
+use manifest::{Manifest, MANIFEST_FILE};
+use repl::start_repl;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read};
 
-use parser;
 use parser::Parser as MkParser;
 
+mod debugger;
+mod deps;
+mod interrupt;
+mod lsp;
+mod manifest;
+mod repl;
+mod test_runner;
+mod tree;
+
 #[derive(Parser)]
 struct Args {
     /// The file name to read (located in /programs directory)
@@ -28,37 +40,344 @@ struct Args {
 
     #[arg(long, action = clap::ArgAction::SetTrue)]
     replc: bool,
+
+    /// Enable the read_file/write_file builtins
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    allow_fs: bool,
+
+    /// Enable the env builtin, to read environment variables
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    allow_env: bool,
+
+    /// Enable the read_line/read_all builtins, to read stdin
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    allow_stdin: bool,
+
+    /// Log what the interpreter and the VM do to stderr, from this level up: trace, debug, info, warn or error
+    #[arg(long)]
+    log_level: Option<Level>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ParseFormat {
+    /// Each statement as source, then the full AST dump
+    Raw,
+    /// An indented tree of the statements and expressions with their positions, colored on a terminal
+    Tree,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum RunBackend {
+    Interpreter,
+    Vm,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a script and print the value of its last expression, `-` reads the script from stdin. Without a script,
+    /// runs the entry point of the project's mk.toml in the working directory
+    Run {
+        file: Option<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t = RunBackend::Interpreter)]
+        backend: RunBackend,
+
+        /// Count the instructions the VM executes and print the most executed ones to stderr, VM backend only
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        profile: bool,
+
+        /// Arguements for the script, after `--`, which it gets from `args()`. Interpreter backend only
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Evaluate the code given on the command line and print its value
+    Eval {
+        code: String,
+    },
+    /// Parse a script without running it and print its AST
+    Parse {
+        file: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = ParseFormat::Raw)]
+        format: ParseFormat,
+    },
+    /// Print the import graph of a script, with cycles highlighted
+    Deps {
+        entry: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = Emit::Dot)]
+        emit: Emit,
+    },
+    /// Compile a script to bytecode without running it
+    Build {
+        file: PathBuf,
+
+        /// List where the bytecode's bytes go
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        report_size: bool,
+    },
+    /// Lint a script without running it: shadowed bindings, unused lets and unreachable statements
+    Check {
+        file: PathBuf,
+    },
+    /// Run a Language Server Protocol server over stdio, for editor diagnostics, symbols and hovers
+    Lsp,
+    /// Run the `test_*` functions of every script under a directory
+    Test {
+        dir: PathBuf,
+
+        /// Print how much of each script the tests ran: the share of lines with statements and those that didn't run
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        coverage: bool,
+    },
+    /// Run a script with both the interpreter and the VM, failing if their results differ
+    DiffBackends {
+        file: PathBuf,
+    },
+}
+
+/// Deep Monkey recursion nests a lot of Rust frames, so evaluation gets more stack than the main thread's default.
+const EVAL_STACK_SIZE: usize = 256 << 20;
+
 fn main() -> Result<(), std::io::Error> {
-    
     let args = Args::parse();
+    std::thread::Builder::new()
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(move || run(args))?
+        .join()
+        .expect("evaluation thread panicked")
+}
+
+fn run(args: Args) -> Result<(), std::io::Error> {
+    let capabilities = Capabilities { fs: args.allow_fs, env: args.allow_env, stdin: args.allow_stdin };
+    let logger = args.log_level.map(|level| Rc::new(StderrLogger { level }) as Rc<dyn Logger>);
 
-    if args.repl {
-        start_repl(false, false);
+    if let Some(command) = args.command {
+        run_command(command, capabilities, logger)?;
+    } else if args.repl {
+        interrupt::install();
+        start_repl(false, false, capabilities, logger);
     }else if args.reple || args.replc {
-        start_repl(args.reple, args.replc);
+        interrupt::install();
+        start_repl(args.reple, args.replc, capabilities, logger);
     } else {
         if let Some(file_name) = args.file {
             let parsed = parse_file(&file_name)?;
             print_program(parsed);
         } else  if let Some(file_name) = args.filee {
-            let parsed = parse_file(&file_name)?;
+            let file_path = Path::new("programs").join(file_name);
             let env = Environment::new(None);
-            let interpreter = Interpreter::new(env);
-            println!("{:?}", interpreter.evaluate_program(&parsed).unwrap());
+            let interpreter = Interpreter::new_with_capabilities(env, capabilities);
+            println!("{:?}", interpreter.evaluate_file(&file_path).unwrap());
+            for diagnostic in interpreter.take_diagnostics() {
+                eprintln!("{diagnostic}");
+            }
         }
     }
 
     Ok(())
 }
 
+fn run_command(command: Command, capabilities: Capabilities, logger: Option<Rc<dyn Logger>>) -> Result<(), std::io::Error> {
+    match command {
+        Command::Run { file, backend: RunBackend::Interpreter, profile, args } => {
+            if profile {
+                return Err(io::Error::other("--profile needs --backend vm, the interpreter doesn't execute instructions"));
+            }
+            let (file, capabilities, import_roots) = project(file, capabilities)?;
+            let (text, name) = (read_script(&file)?, script_name(&file));
+            let source = Source::new(&name, &text);
+            let program = parse_or_fail(&source);
+            interpret(&source, capabilities, logger, args, |interpreter| {
+                interpreter.set_import_roots(import_roots);
+                // Imports from a script on stdin resolve against the working directory
+                match is_stdin(&file) {
+                    true => interpreter.evaluate_program(&program),
+                    false => interpreter.evaluate_parsed_file(&file, &program),
+                }
+            });
+        },
+        Command::Eval { code } => {
+            let source = Source::new("<eval>", &code);
+            let program = parse_or_fail(&source);
+            interpret(&source, capabilities, logger, Vec::new(), |interpreter| interpreter.evaluate_program(&program));
+        },
+        Command::Run { file, backend: RunBackend::Vm, profile, args } => {
+            if !args.is_empty() {
+                return Err(io::Error::other("Script arguements need --backend interpreter, the VM has no builtins to read them"));
+            }
+            let (file, ..) = project(file, capabilities)?;
+            let (text, name) = (read_script(&file)?, script_name(&file));
+            let source = Source::new(&name, &text);
+            let program = parse_or_fail(&source);
+            let bytecode = Compiler::new().compile_program(&program).unwrap_or_else(|err| fail(&source, EngineError::Compile(err)));
+            interrupt::install();
+            let vm = VM::load(bytecode).unwrap_or_else(|err| fail(&source, EngineError::Runtime(err)));
+            vm.set_profiling(profile);
+            vm.set_interrupt(Some(interrupt::flag()));
+            vm.set_logger(logger);
+            let result = vm.run();
+            if let Some(profile) = vm.profile() {
+                eprint!("{}", profile.report(Some(&text)));
+            }
+            match result {
+                Err(err) if err.is_interrupted() => interrupt::exit(&err.0),
+                Err(err) => fail(&source, EngineError::Runtime(err)),
+                Ok(()) => println!("{}", vm.last_popped()),
+            }
+        },
+        Command::Parse { file, format } => {
+            let program = parse_script(&file)?;
+            match format {
+                ParseFormat::Raw => print_program(program),
+                ParseFormat::Tree => print!("{}", tree::render(&program, io::stdout().is_terminal())),
+            }
+        },
+        Command::Deps { entry, emit } => print!("{}", DepGraph::build(&entry)?.emit(emit)),
+        Command::Build { file, report_size } => {
+            let program = parse_script(&file)?;
+            let compile = |optimize: bool| {
+                let mut compiler = Compiler::new();
+                compiler.set_optimize(optimize);
+                compiler.compile_program(&program).map_err(|err| io::Error::other(format!("Unable to compile {}: {err:?}", file.display())))
+            };
+
+            let bytecode = compile(true)?;
+            if report_size {
+                let mut report = SizeReport::new(&bytecode).map_err(|err| io::Error::other(format!("{err:?}")))?;
+                report.unoptimized_bytes = Some(compile(false)?.bytes.len());
+                print!("{report}");
+            } else {
+                println!("{}: {} bytes, {} constants", file.display(), bytecode.bytes.len(), bytecode.constants.len());
+            }
+        },
+        Command::Lsp => lsp::serve(io::stdin().lock(), io::stdout().lock())?,
+        Command::Check { file } => {
+            for diagnostic in interpreter::analyze(&parse_script(&file)?) {
+                println!("{}: {diagnostic}", file.display());
+            }
+        },
+        Command::Test { dir, coverage } => {
+            let report = test_runner::run_tests(&dir, coverage)?;
+            print!("{report}");
+            if report.failed() > 0 {
+                std::process::exit(1);
+            }
+        },
+        Command::DiffBackends { file } => {
+            let results = engine::backends::run_backends(&read_script(&file)?);
+            println!("{results}");
+            if !results.agree() {
+                std::process::exit(1);
+            }
+        },
+    }
+    Ok(())
+}
+
+/// The script `run` runs, with the capabilities and import roots it gets: `file` with those of the command line,
+/// or without one the entry point of the project in the working directory, with those of its manifest as well.
+fn project(file: Option<PathBuf>, capabilities: Capabilities) -> io::Result<(PathBuf, Capabilities, Vec<PathBuf>)> {
+    if let Some(file) = file {
+        return Ok((file, capabilities, Vec::new()));
+    }
+    let manifest = Manifest::find(Path::new("."))?;
+    if !manifest.entry.is_file() {
+        return Err(io::Error::other(format!("The entry point {} of {MANIFEST_FILE} isn't a file", manifest.entry.display())));
+    }
+    let capabilities = Capabilities {
+        fs: capabilities.fs || manifest.capabilities.fs,
+        env: capabilities.env || manifest.capabilities.env,
+        stdin: capabilities.stdin || manifest.capabilities.stdin,
+    };
+    Ok((manifest.entry, capabilities, manifest.import_roots))
+}
+
+/// Runs `evaluate` with an interpreter set up for the command line, then prints the value it evaluates to or reports
+/// the error on `source`.
+fn interpret(
+    source: &Source,
+    capabilities: Capabilities,
+    logger: Option<Rc<dyn Logger>>,
+    args: Vec<String>,
+    evaluate: impl FnOnce(&Interpreter) -> Result<Object, EvalError>,
+) {
+    interrupt::install();
+    let interpreter = Interpreter::new_with_capabilities(Environment::new(None), capabilities);
+    interpreter.set_interrupt(Some(interrupt::flag()));
+    interpreter.set_args(args);
+    interpreter.set_logger(logger);
+    let result = evaluate(&interpreter);
+    for diagnostic in interpreter.take_diagnostics() {
+        eprintln!("{diagnostic}");
+    }
+    match result {
+        Err(err) if err.is_interrupted() => interrupt::exit(&err.0),
+        Err(err) => fail(source, EngineError::Eval(err)),
+        Ok(value) => println!("{value}"),
+    }
+}
+
+fn parse_or_fail(source: &Source) -> parser::Program {
+    MkParser::new(Lexer::new_borrowed(source.text)).parse_program().unwrap_or_else(|err| fail(source, EngineError::Parse(err)))
+}
+
+/// Reports `err` on `source` to stderr, colored on a terminal, and exits: how `run` and `eval` fail.
+fn fail(source: &Source, err: EngineError) -> ! {
+    let report = if io::stderr().is_terminal() { render_diagnostic_colored(source, &err) } else { render_diagnostic(source, &err) };
+    eprint!("{report}");
+    std::process::exit(1)
+}
+
+/// The path standing for stdin, e.g. in `echo '1 + 2' | mk_run run -`.
+const STDIN_PATH: &str = "-";
+
+fn is_stdin(file: &Path) -> bool {
+    file == Path::new(STDIN_PATH)
+}
+
+fn script_name(file: &Path) -> String {
+    if is_stdin(file) { "stdin".to_string() } else { file.display().to_string() }
+}
+
+fn read_stdin() -> Result<String, std::io::Error> {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    Ok(source)
+}
+
+fn read_script(file: &Path) -> Result<String, std::io::Error> {
+    if is_stdin(file) {
+        read_stdin()
+    } else {
+        fs::read_to_string(file)
+    }
+}
+
+fn parse_script(file: &Path) -> Result<parser::Program, std::io::Error> {
+    if is_stdin(file) {
+        return parse_source(Lexer::new(read_stdin()?), &script_name(file));
+    }
+    let lexer = Lexer::from_reader(io::BufReader::new(fs::File::open(file)?));
+    parse_source(lexer, &script_name(file))
+}
+
+fn parse_source(lexer: Lexer, name: &str) -> Result<parser::Program, std::io::Error> {
+    MkParser::new(lexer)
+        .parse_program()
+        .map_err(|err| io::Error::other(format!("Unable to parse {name}: {err:?}")))
+}
+
 fn parse_file(file_name: &str) -> Result<parser::Program, std::io::Error> {
+    if file_name == STDIN_PATH {
+        return parse_script(Path::new(STDIN_PATH));
+    }
     let file_path = Path::new("programs").join(file_name);
     println!("{}", file_path.to_str().unwrap());
-    let program = fs::read_to_string(file_path)?;
-
-    let lexer = Lexer::new(program.to_string());
+    let lexer = Lexer::from_reader(io::BufReader::new(fs::File::open(file_path)?));
 
     let mut parser = MkParser::new(lexer);
 
@@ -73,82 +392,3 @@ fn print_program(program: parser::Program) {
 
     println!("{program:#?}");
 }
-
-fn start_repl(eval: bool, compile: bool) {
-    let monkey_face = r#"
-    .--.  .-"     "-.  .--.
-    / .. \/  .-. .-.  \/ .. \
-   | |  '|  /   Y   \  |'  | |      .-"-.            .-"-.            .-"-.   
-   | \   \  \ 0 | 0 /  /   / |    _/_-.-_\_        _/.-.-.\_        _/.-.-.\_ 
-    \ '- ,\.-"""""""-./, -' /    / __} {__ \      /|( o o )|\      ( ( o o ) )
-     ''-' /_   ^ ^   _\ '-''    / //  "  \\ \    | //  "  \\ |      |/  "  \| 
-         |  \._   _./  |       / / \'---'/ \ \  / / \'---'/ \ \      \'/^\'/  
-         \   \ '~' /   /       \ \_/`"""`\_/ /  \ \_/`"""`\_/ /      /`\ /`\  
-          '._ '-=-' _.'         \           /    \           /      /  /|\  \ 
-             '-----'
-"#;
-
-    println!("{monkey_face}");
-    let env = Environment::new(None);
-    let interpreter = Interpreter::new(env);
-
-    loop {
-        print!("->");
-
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read line");
-
-        match input.trim() {
-            "E" => break,
-            _ => {
-                let lexer = Lexer::new(input.to_string());
-
-                // loop {
-                //     let token = lexer.next_token();
-                //     println!("{:?}", token);
-                //     if token.typ == TokenType::Eof { break }
-                // }
-
-                let mut parser = parser::Parser::new(lexer);
-                let mut compiler = Compiler::new();
-        
-                match parser.parse_program() {
-                    Ok(program) => {
-                        for statement in &program.statements {
-                            println!("{}", statement.dbg());
-                        }
-
-                        if eval {
-                            println!("******* EVAL *******");
-                            println!("{:?}", interpreter.evaluate_program(&program));
-                            println!("********************");
-                        }
-
-                        if compile {
-                            println!("******* COMPILE *******");
-                            let bytecode = match compiler.compile_program(&program) {
-                                Ok(bytecode) => bytecode,
-                                Err(e) => {
-                                    println!("{e:?}");
-                                    println!("********************");
-                                    continue;
-                                }
-                            };
-                            println!("{:?}", bytecode);
-                            compiler.decompile().unwrap();
-                            let vm = VM::new(bytecode);
-                            if let Err(e) = vm.run() {
-                                println!("{e:?}");
-                            }
-                            println!("********************");
-                        }
-            
-                        // println!("{program:#?}")
-                    },
-                    Err(err) => println!("{err:?}")
-                }
-            }
-        }
-    }
-}
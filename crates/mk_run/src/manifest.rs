@@ -0,0 +1,85 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use interpreter::Capabilities;
+use serde::Deserialize;
+
+/// The manifest `mk run` looks for in the working directory when it isn't given a script.
+pub const MANIFEST_FILE: &str = "mk.toml";
+
+/// A project's `mk.toml`: the script to run, where its imports are looked for and the capabilities it gets, e.g.
+///
+/// ```toml
+/// entry = "src/main.mk"
+/// import_roots = ["lib"]
+/// capabilities = ["fs", "env"]
+/// ```
+///
+/// Paths are relative to the manifest's directory.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub entry: PathBuf,
+    pub import_roots: Vec<PathBuf>,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    entry: PathBuf,
+    #[serde(default)]
+    import_roots: Vec<PathBuf>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the project in `dir`.
+    pub fn find(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let text = fs::read_to_string(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("No script given and no {MANIFEST_FILE} to run in {}: {err}", dir.display())))?;
+        Self::parse(&text, dir).map_err(|err| io::Error::other(format!("Invalid {}: {err}", path.display())))
+    }
+
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let file: ManifestFile = toml::from_str(text).map_err(|err| err.message().to_string())?;
+        let mut capabilities = Capabilities::default();
+        for capability in &file.capabilities {
+            match capability.as_str() {
+                "fs" => capabilities.fs = true,
+                "env" => capabilities.env = true,
+                "stdin" => capabilities.stdin = true,
+                _ => return Err(format!("Unknown capability {capability}, expected one of fs, env or stdin")),
+            }
+        }
+        Ok(Self {
+            entry: dir.join(file.entry),
+            import_roots: file.import_roots.iter().map(|root| dir.join(root)).collect(),
+            capabilities,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let dir = Path::new("project");
+        let manifest = Manifest::parse("entry = \"src/main.mk\"\nimport_roots = [\"lib\", \"vendor\"]\ncapabilities = [\"fs\", \"stdin\"]", dir).unwrap();
+        assert_eq!(manifest, Manifest {
+            entry: dir.join("src/main.mk"),
+            import_roots: vec![dir.join("lib"), dir.join("vendor")],
+            capabilities: Capabilities { fs: true, env: false, stdin: true },
+        });
+
+        // Only the entry is required
+        let manifest = Manifest::parse("entry = \"main.mk\"", dir).unwrap();
+        assert_eq!((manifest.import_roots, manifest.capabilities), (Vec::new(), Capabilities::default()));
+
+        assert_eq!(Manifest::parse("entry = \"main.mk\"\ncapabilities = [\"net\"]", dir).unwrap_err(), "Unknown capability net, expected one of fs, env or stdin");
+        assert_eq!(Manifest::parse("import_roots = []", dir).unwrap_err(), "missing field `entry`");
+        assert!(Manifest::find(Path::new("no/such/dir")).unwrap_err().to_string().starts_with("No script given and no mk.toml to run in no/such/dir"));
+    }
+}
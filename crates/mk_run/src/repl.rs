@@ -0,0 +1,533 @@
+use std::{collections::HashMap, fs, io::{self, Write}, path::Path, rc::Rc};
+
+use compiler::{vm::VM, Compiler};
+use interpreter::{Capabilities, Environment, EvalError, Interpreter, Logger, Object};
+use parser::{ast::Statement, lexer::Lexer, Parser, Program};
+
+use crate::{debugger::Debugger, interrupt};
+
+const MONKEY_FACE: &str = r#"
+    .--.  .-"     "-.  .--.
+    / .. \/  .-. .-.  \/ .. \
+   | |  '|  /   Y   \  |'  | |      .-"-.            .-"-.            .-"-.
+   | \   \  \ 0 | 0 /  /   / |    _/_-.-_\_        _/.-.-.\_        _/.-.-.\_
+    \ '- ,\.-"""""""-./, -' /    / __} {__ \      /|( o o )|\      ( ( o o ) )
+     ''-' /_   ^ ^   _\ '-''    / //  "  \\ \    | //  "  \\ |      |/  "  \|
+         |  \._   _./  |       / / \'---'/ \ \  / / \'---'/ \ \      \'/^\'/
+         \   \ '~' /   /       \ \_/`"""`\_/ /  \ \_/`"""`\_/ /      /`\ /`\
+          '._ '-=-' _.'         \           /    \           /      /  /|\  \
+             '-----'
+"#;
+
+const VARS_PAGE_SIZE: usize = 20;
+const VARS_VALUE_WIDTH: usize = 40;
+
+/// Options of `:vars`, bindings are listed oldest generation first unless `sorted` by name.
+#[derive(Debug, PartialEq, Eq)]
+struct VarsQuery {
+    sorted: bool,
+    typ: Option<String>,
+    page: usize, // 1-based
+}
+
+impl VarsQuery {
+    fn parse(args: &str) -> Result<Self, String> {
+        let mut query = Self { sorted: false, typ: None, page: 1 };
+        for arg in args.split_whitespace() {
+            match arg.split_once('=') {
+                None if arg == "--sorted" => query.sorted = true,
+                Some(("--type", typ)) => query.typ = Some(typ.to_string()),
+                Some(("--page", page)) => {
+                    query.page = page.parse().ok().filter(|page| *page > 0).ok_or_else(|| format!("Invalid page: {page}"))?;
+                },
+                _ => return Err(format!("Unknown :vars option {arg}, expected --sorted, --type=<type> or --page=<n>")),
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Colon-prefixed meta commands understood by the REPL alongside Monkey input.
+#[derive(Debug, PartialEq, Eq)]
+enum ReplCommand {
+    Help,
+    Env,
+    Vars(VarsQuery),
+    Bytecode,
+    Reset,
+    Load(String),
+    Save(String),
+    Restore(String),
+    Debug(String),
+}
+
+impl ReplCommand {
+    /// `None` when `input` is Monkey source rather than a command.
+    fn parse(input: &str) -> Option<Result<Self, String>> {
+        let input = input.strip_prefix(':')?;
+        let (command, arg) = input.split_once(char::is_whitespace).map_or((input, ""), |(command, arg)| (command, arg.trim()));
+
+        Some(match (command, arg) {
+            ("help", "") => Ok(Self::Help),
+            ("env", "") => Ok(Self::Env),
+            ("vars", args) => VarsQuery::parse(args).map(Self::Vars),
+            ("bytecode", "") => Ok(Self::Bytecode),
+            ("reset", "") => Ok(Self::Reset),
+            ("load", "") => Err("Usage: :load <path>".to_string()),
+            ("load", path) => Ok(Self::Load(path.to_string())),
+            ("save", "") => Err("Usage: :save <path>".to_string()),
+            ("save", path) => Ok(Self::Save(path.to_string())),
+            ("restore", "") => Err("Usage: :restore <path>".to_string()),
+            ("restore", path) => Ok(Self::Restore(path.to_string())),
+            ("debug", "") => Err("Usage: :debug <input>".to_string()),
+            ("debug", input) => Ok(Self::Debug(input.to_string())),
+            ("help" | "env" | "bytecode" | "reset", _) => Err(format!(":{command} takes no arguements")),
+            _ => Err(format!("Unknown command :{command}, try :help")),
+        })
+    }
+}
+
+/// State kept across REPL inputs. In compile mode the compiler and the VM's globals persist, so later inputs
+/// can use the globals earlier ones defined.
+struct Session {
+    eval: bool,
+    compile: bool,
+    capabilities: Capabilities,
+    interpreter: Interpreter,
+    compiler: Compiler,
+    vm_globals: Vec<Object>,
+    vm_constants: Vec<Object>, // pool the compiled lines share, each line only brings its new constants
+    last_program: Option<Program>,
+    inputs: usize,
+    results: usize, // how many inputs ended with an expression, the last is bound to `_` and the `n`th to `_n`
+    generations: HashMap<String, (usize, Object)>, // binding -> input that last changed it, and its value then
+    logger: Option<Rc<dyn Logger>>, // for the interpreter and every VM the session runs
+}
+
+impl Session {
+    fn new(eval: bool, compile: bool, capabilities: Capabilities) -> Self {
+        let interpreter = Interpreter::new_with_capabilities(Environment::new(None), capabilities);
+        interpreter.set_module_dir("programs");
+        interpreter.set_interrupt(Some(interrupt::flag()));
+
+        Self {
+            eval,
+            compile,
+            capabilities,
+            interpreter,
+            compiler: Compiler::new(),
+            vm_globals: Vec::new(),
+            vm_constants: Vec::new(),
+            last_program: None,
+            inputs: 0,
+            results: 0,
+            generations: HashMap::new(),
+            logger: None,
+        }
+    }
+
+    fn set_logger(&mut self, logger: Option<Rc<dyn Logger>>) {
+        self.interpreter.set_logger(logger.clone());
+        self.logger = logger;
+    }
+
+    fn builtins(&self) -> Vec<String> {
+        let mut builtins: Vec<String> = self.interpreter.global_env().borrow().vars()
+            .filter(|(_, val)| matches!(val, Object::BuiltIn(_)))
+            .map(|(name, _)| name.to_string())
+            .collect();
+        builtins.sort();
+        builtins
+    }
+
+    /// The session's bindings, from the VM when only compiling and from the interpreter otherwise.
+    fn bindings(&self) -> Vec<(String, Object)> {
+        if self.compile && !self.eval {
+            return self.compiler.globals()
+                .into_iter()
+                .map(|(name, idx)| (name, self.vm_globals.get(idx as usize).cloned().unwrap_or(Object::Null)))
+                .collect();
+        }
+
+        let mut bindings: Vec<(String, Object)> = self.interpreter.global_env().borrow().vars()
+            .filter(|(_, val)| !matches!(val, Object::BuiltIn(_)))
+            .map(|(name, val)| (name.to_string(), val.clone()))
+            .collect();
+        bindings.sort_by(|(x, _), (y, _)| x.cmp(y));
+        bindings
+    }
+
+    /// Counts an input and stamps the bindings it added or changed with its number.
+    fn next_generation(&mut self) {
+        self.inputs += 1;
+        let bindings = self.bindings();
+        self.generations.retain(|name, _| bindings.iter().any(|(bound, _)| bound == name));
+        for (name, val) in bindings {
+            match self.generations.get(&name) {
+                Some((_, old)) if *old == val => {},
+                _ => { self.generations.insert(name, (self.inputs, val)); },
+            }
+        }
+    }
+
+    fn vars(&self, query: &VarsQuery) -> Vec<String> {
+        let mut rows: Vec<(usize, &String, &Object)> = self.generations
+            .iter()
+            .filter(|(_, (_, val))| query.typ.as_deref().is_none_or(|typ| val.type_name() == typ))
+            .map(|(name, (generation, val))| (*generation, name, val))
+            .collect();
+        if query.sorted {
+            rows.sort_by_key(|(_, name, _)| *name);
+        } else {
+            rows.sort_by_key(|(generation, name, _)| (*generation, *name));
+        }
+
+        if rows.is_empty() {
+            return vec!["(no bindings)".to_string()];
+        }
+        let pages = rows.len().div_ceil(VARS_PAGE_SIZE);
+        if query.page > pages {
+            return vec![format!("No page {}, there are {pages}", query.page)];
+        }
+
+        let name_width = rows.iter().map(|(_, name, _)| name.chars().count()).max().unwrap_or(0).max("name".len());
+        let mut lines = vec![format!("{:>4}  {:<name_width$}  {:<7}  value", "gen", "name", "type")];
+        for (generation, name, val) in rows.iter().skip((query.page - 1) * VARS_PAGE_SIZE).take(VARS_PAGE_SIZE) {
+            let mut value = val.to_string().split_whitespace().collect::<Vec<&str>>().join(" ");
+            if value.chars().count() > VARS_VALUE_WIDTH {
+                value = value.chars().take(VARS_VALUE_WIDTH - 3).collect::<String>() + "...";
+            }
+            lines.push(format!("{generation:>4}  {name:<name_width$}  {:<7}  {value}", val.type_name()));
+        }
+        if pages > 1 {
+            lines.push(format!("page {}/{pages}, --page=<n> for others", query.page));
+        }
+        lines
+    }
+
+    fn eval_input(&mut self, input: &str) {
+        let mut parser = Parser::new(Lexer::new_borrowed(input));
+        parser.set_interner(self.interpreter.interner().clone());
+        let program = match parser.parse_program() {
+            Ok(program) => program,
+            Err(err) => return println!("{err:?}"),
+        };
+
+        for statement in &program.statements {
+            println!("{}", statement.dbg());
+        }
+
+        // Only inputs that end with an expression have a result worth keeping, not those that end with a `let`
+        let has_result = matches!(program.statements.last(), Some(Statement::ExpressionStatement { .. }));
+        let (mut evaluated, mut ran) = (None, None);
+
+        if self.eval {
+            println!("******* EVAL *******");
+            let result = self.interpreter.evaluate_program(&program);
+            println!("{:?}", result);
+            evaluated = result.ok().filter(|_| has_result);
+            for diagnostic in self.interpreter.take_diagnostics() {
+                eprintln!("{diagnostic}");
+            }
+            println!("********************");
+        }
+
+        if self.compile {
+            println!("******* COMPILE *******");
+            match self.compiler.compile_delta(&program) {
+                Ok(bytecode) => {
+                    println!("{:?}", bytecode);
+                    self.compiler.decompile().unwrap();
+                    let pool = std::mem::take(&mut self.vm_constants);
+                    match VM::new_with_pool(bytecode, std::mem::take(&mut self.vm_globals), pool) {
+                        Ok(vm) => {
+                            vm.set_interrupt(Some(interrupt::flag()));
+                            vm.set_logger(self.logger.clone());
+                            match vm.run() {
+                                Ok(()) => ran = Some(vm.last_popped()).filter(|_| has_result),
+                                Err(e) => println!("{e:?}"),
+                            }
+                            (self.vm_globals, self.vm_constants) = vm.into_state();
+                        },
+                        Err(e) => println!("{e:?}"),
+                    }
+                },
+                Err(e) => println!("{e:?}"),
+            }
+            println!("********************");
+        }
+
+        if evaluated.is_some() || ran.is_some() {
+            self.bind_result(evaluated, ran);
+        }
+        self.last_program = Some(program);
+        self.next_generation();
+    }
+
+    /// Binds the result of the input just run to `_`, and to `_1`, `_2`... in the order results came, for later
+    /// inputs to use: by the interpreter what it `evaluated`, in the VM's globals what it `ran` to.
+    fn bind_result(&mut self, evaluated: Option<Object>, ran: Option<Object>) {
+        self.results += 1;
+        let names = ["_".to_string(), format!("_{}", self.results)];
+        if let Some(val) = evaluated {
+            let env = self.interpreter.global_env();
+            names.iter().for_each(|name| env.borrow_mut().set(name, val.clone()));
+        }
+        if let Some(val) = ran {
+            for name in &names {
+                let idx = self.compiler.define_global(name) as usize;
+                if self.vm_globals.len() <= idx {
+                    self.vm_globals.resize(idx + 1, Object::Null);
+                }
+                self.vm_globals[idx] = val.clone();
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: ReplCommand) {
+        match command {
+            ReplCommand::Help => {
+                println!(":help          show this message");
+                println!(":env           list the bindings defined in this session");
+                println!(":vars [--sorted] [--type=<type>] [--page=<n>]");
+                println!("               browse the bindings with their type and the input that last changed them");
+                println!(":bytecode      disassemble the last input");
+                println!(":reset         forget all bindings and start a fresh session");
+                println!(":load <path>   evaluate a file into this session");
+                println!(":save <path>   write the interpreter's bindings to a snapshot file");
+                println!(":restore <path> bind the variables of a snapshot file in this session");
+                println!(":debug <input> step through the bytecode of an input in the VM");
+                println!("E              exit");
+                println!("_, _<n>        the result of the last input, and of the nth input that had one");
+                println!("builtins: {}", self.builtins().join(", "));
+            },
+            ReplCommand::Env => {
+                let bindings = self.bindings();
+                if bindings.is_empty() {
+                    println!("(no bindings)");
+                }
+                for (name, val) in bindings {
+                    println!("{name} = {val}");
+                }
+            },
+            ReplCommand::Vars(query) => {
+                for line in self.vars(&query) {
+                    println!("{line}");
+                }
+            },
+            ReplCommand::Bytecode => {
+                if self.compile && self.last_program.is_some() {
+                    // The persistent compiler still holds the last input, and can resolve the globals it uses
+                    if let Err(e) = self.compiler.decompile() {
+                        println!("{e:?}");
+                    }
+                    return;
+                }
+                let Some(program) = &self.last_program else {
+                    println!("No input to disassemble yet");
+                    return;
+                };
+                let mut compiler = Compiler::new();
+                if let Err(e) = compiler.compile_program(program).and_then(|_| compiler.decompile()) {
+                    println!("{e:?}");
+                }
+            },
+            ReplCommand::Reset => {
+                let logger = self.logger.take();
+                *self = Self::new(self.eval, self.compile, self.capabilities);
+                self.set_logger(logger);
+                println!("Session reset");
+            },
+            ReplCommand::Load(path) => {
+                println!("{:?}", self.interpreter.evaluate_file(Path::new(&path)));
+                for diagnostic in self.interpreter.take_diagnostics() {
+                    eprintln!("{diagnostic}");
+                }
+                self.next_generation();
+            },
+            ReplCommand::Save(path) => {
+                let saved = self.interpreter.global_env().borrow().to_snapshot()
+                    .and_then(|snapshot| fs::write(&path, snapshot).map_err(|err| EvalError(format!("Unable to write {path}: {err}"))));
+                match saved {
+                    Ok(()) => println!("Saved to {path}"),
+                    Err(err) => println!("{}", err.0),
+                }
+            },
+            ReplCommand::Restore(path) => {
+                let restored = fs::read_to_string(&path)
+                    .map_err(|err| EvalError(format!("Unable to read {path}: {err}")))
+                    .and_then(|snapshot| Environment::restore_snapshot(&self.interpreter.global_env(), &snapshot));
+                match restored {
+                    Ok(()) => println!("Restored {path}"),
+                    Err(err) => println!("{}", err.0),
+                }
+                self.next_generation();
+            },
+            ReplCommand::Debug(input) => self.debug(&input),
+        }
+    }
+
+    /// Runs `input` in a VM under the debugger, reading its commands from stdin until it finishes or is quit. In
+    /// compile mode it runs against the session's globals and what it binds is kept.
+    fn debug(&mut self, input: &str) {
+        let program = match Parser::new(Lexer::new_borrowed(input)).parse_program() {
+            Ok(program) => program,
+            Err(err) => return println!("{err:?}"),
+        };
+        let vm = if self.compile {
+            match self.compiler.compile_delta(&program) {
+                Ok(bytecode) => VM::new_with_pool(bytecode, std::mem::take(&mut self.vm_globals), std::mem::take(&mut self.vm_constants)),
+                Err(e) => return println!("{e:?}"),
+            }
+        } else {
+            Compiler::new().compile_program(&program).map_err(|e| compiler::RuntimeError(e.0)).and_then(VM::load)
+        };
+        let mut debugger = match vm {
+            Ok(vm) => {
+                vm.set_interrupt(Some(interrupt::flag()));
+                vm.set_logger(self.logger.clone());
+                Debugger::new(vm)
+            },
+            Err(e) => return println!("{e:?}"),
+        };
+
+        println!("Debugging, commands:");
+        for line in Debugger::HELP {
+            println!("  {line}");
+        }
+        while !debugger.finished() {
+            print!("(debug) ");
+            io::stdout().flush().unwrap();
+            let mut command = String::new();
+            if io::stdin().read_line(&mut command).unwrap_or(0) == 0 || command.trim() == "q" {
+                break;
+            }
+            for line in debugger.command(command.trim()) {
+                println!("{line}");
+            }
+        }
+
+        if self.compile {
+            (self.vm_globals, self.vm_constants) = debugger.into_vm().into_state();
+            self.last_program = Some(program);
+            self.next_generation();
+        }
+    }
+}
+
+pub fn start_repl(eval: bool, compile: bool, capabilities: Capabilities, logger: Option<Rc<dyn Logger>>) {
+    println!("{MONKEY_FACE}");
+    println!("Type :help for commands, E to exit");
+    let mut session = Session::new(eval, compile, capabilities);
+    session.set_logger(logger);
+
+    loop {
+        print!("->");
+
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).expect("Failed to read line");
+        // Ctrl+C at the prompt had nothing to stop, a second one there exits
+        interrupt::clear();
+
+        match input.trim() {
+            "E" => break,
+            command if command.starts_with(':') => match ReplCommand::parse(command) {
+                Some(Ok(command)) => session.run_command(command),
+                Some(Err(msg)) => println!("{msg}"),
+                None => unreachable!("inputs starting with `:` are always commands"),
+            },
+            _ => session.eval_input(&input),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repl_command() {
+        assert_eq!(ReplCommand::parse(":help"), Some(Ok(ReplCommand::Help)));
+        assert_eq!(ReplCommand::parse(":load  lib/math.mk"), Some(Ok(ReplCommand::Load("lib/math.mk".to_string()))));
+        assert_eq!(ReplCommand::parse(":load"), Some(Err("Usage: :load <path>".to_string())));
+        assert_eq!(ReplCommand::parse(":env x"), Some(Err(":env takes no arguements".to_string())));
+        assert_eq!(ReplCommand::parse(":debug  1 + 2"), Some(Ok(ReplCommand::Debug("1 + 2".to_string()))));
+        assert_eq!(ReplCommand::parse(":nope"), Some(Err("Unknown command :nope, try :help".to_string())));
+        assert_eq!(ReplCommand::parse("let x = 1;"), None);
+        assert_eq!(
+            ReplCommand::parse(":vars --type=fn --sorted --page=2"),
+            Some(Ok(ReplCommand::Vars(VarsQuery { sorted: true, typ: Some("fn".to_string()), page: 2 }))),
+        );
+        assert!(matches!(ReplCommand::parse(":vars --page=0"), Some(Err(_))));
+        assert!(matches!(ReplCommand::parse(":vars --reverse"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_result_bindings() {
+        for (eval, compile) in [(true, false), (false, true), (true, true)] {
+            let mut session = Session::new(eval, compile, Capabilities::default());
+            session.eval_input("[1, 2, 3]");
+            session.eval_input("let x = 10;");
+            session.eval_input("_[0] + x");
+            session.eval_input("_1[2] * _ + _2");
+            let bindings: HashMap<String, Object> = session.bindings().into_iter().collect();
+            assert_eq!(bindings["_"], Object::Integer(44), "eval: {eval}, compile: {compile}");
+            assert_eq!(bindings["_1"].to_string(), "[1, 2, 3]");
+            assert_eq!(bindings["_2"], Object::Integer(11));
+            assert!(!bindings.contains_key("_4"));
+        }
+    }
+
+    #[test]
+    fn test_vars() {
+        let query = |args: &str| VarsQuery::parse(args).unwrap();
+
+        for (eval, compile) in [(true, false), (false, true)] {
+            let mut session = Session::new(eval, compile, Capabilities::default());
+            session.eval_input("let b = [1, 2];");
+            session.eval_input(r#"let a = "a"; let c = 3;"#);
+            session.eval_input("let b = true;");
+            assert_eq!(session.vars(&query("")), vec![
+                " gen  name  type     value",
+                "   2  a     str      a",
+                "   2  c     int      3",
+                "   3  b     bool     true",
+            ], "eval: {eval}");
+            assert_eq!(session.vars(&query("--sorted --type=int")), vec![" gen  name  type     value", "   2  c     int      3"]);
+        }
+
+        let mut session = Session::new(true, false, Capabilities::default());
+        let long_name = "abcdefghijklmnopqrstuvwxyz";
+        session.eval_input(&format!(r#"let f = fn(x) {{ x }}; let s = "{long_name}{long_name}";"#));
+        assert_eq!(session.vars(&query("--type=fn")), vec![" gen  name  type     value", "   1  f     fn       fn(x) { x }"]);
+        assert_eq!(session.vars(&query("--type=str"))[1], format!("   1  s     str      {}...", &(long_name.to_string() + long_name)[..37]));
+
+        let src: String = ["p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z"].iter().flat_map(|x| [x.to_string(), x.repeat(2)])
+            .map(|name| format!("let {name} = 1;"))
+            .collect();
+        session.eval_input(&src);
+        let page_two = session.vars(&query("--page=2 --type=int"));
+        assert_eq!(page_two.len(), 4);
+        assert_eq!(page_two.last().unwrap(), "page 2/2, --page=<n> for others");
+        assert_eq!(session.vars(&query("--page=3")), vec!["No page 3, there are 2"]);
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let path = std::env::temp_dir().join(format!("mk_repl_snapshot_{}.json", std::process::id()));
+        let path = path.display().to_string();
+
+        let mut session = Session::new(true, false, Capabilities::default());
+        session.eval_input(r#"let scale = 3; let times = fn(x, y) { x * y }; let triple = bind(times, scale); let tags = {"a": [1, "b"]};"#);
+        session.run_command(ReplCommand::Save(path.clone()));
+
+        let mut restored = Session::new(true, false, Capabilities::default());
+        restored.run_command(ReplCommand::Restore(path.clone()));
+        let shown = |session: &Session| session.bindings().iter().map(|(name, val)| format!("{name} = {val}")).collect::<Vec<String>>();
+        assert_eq!(shown(&restored), shown(&session));
+        assert!(matches!(restored.interpreter.evaluate_program(&Parser::new(Lexer::new_borrowed("triple(5) + tags[\"a\"][0]")).parse_program().unwrap()), Ok(Object::Integer(16))));
+        assert_eq!(restored.vars(&VarsQuery::parse("--type=int").unwrap()), vec![" gen  name   type     value", "   1  scale  int      3"]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use interpreter::{Coverage, Environment, FileCoverage, Interpreter, Object, SharedBuffer};
+use parser::{ast::{Expression, Statement}, lexer::token::{Span, Token}, Program};
+
+/// A `test_*` function and how running it went.
+pub struct TestResult {
+    pub file: PathBuf,
+    pub name: String,
+    pub span: Option<Span>, // where the test is defined
+    pub failure: Option<String>,
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            None => write!(f, "ok   {}::{}", self.file.display(), self.name),
+            Some(failure) => {
+                write!(f, "FAIL {}::{}", self.file.display(), self.name)?;
+                if let Some(span) = self.span {
+                    write!(f, " ({span})")?;
+                }
+                for line in failure.lines() {
+                    write!(f, "\n    {line}")?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+    pub coverage: Option<Vec<FileCoverage>>, // of every script, when asked for
+}
+
+impl TestReport {
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|result| result.failure.is_some()).count()
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{result}")?;
+        }
+        let failed = self.failed();
+        writeln!(f, "\n{} passed, {failed} failed", self.results.len() - failed)?;
+        if let Some(coverage) = &self.coverage {
+            writeln!(f, "\ncoverage:")?;
+            for file in coverage {
+                writeln!(f, "{file}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every top-level `let test_* = fn() { .. }` of the `.mk` files under `dir`, in path order. Each test gets a
+/// fresh interpreter that runs its file first, then calls it: it fails if that errors or it returns an error value.
+/// With `coverage`, also reports how much of each script the tests ran between them.
+pub fn run_tests(dir: &Path, coverage: bool) -> io::Result<TestReport> {
+    let mut files = Vec::new();
+    find_scripts(dir, &mut files)?;
+    files.sort();
+
+    let mut results = Vec::new();
+    let mut covered = coverage.then(Coverage::new);
+    let mut programs = Vec::new();
+    for file in files {
+        let program = crate::parse_script(&file)?;
+        for (name, span) in test_functions(&program) {
+            let failure = run_test(&file, &name, span, covered.as_mut()).err();
+            results.push(TestResult { file: file.clone(), name, span, failure });
+        }
+        programs.push((file, program));
+    }
+    let coverage = covered.map(|covered| programs.iter().map(|(file, program)| covered.file_coverage(file, program)).collect());
+    Ok(TestReport { results, coverage })
+}
+
+fn find_scripts(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scripts(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "mk") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn test_functions(program: &Program) -> Vec<(String, Option<Span>)> {
+    program
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Let { name: Expression::Identifier { value, token, .. }, value: Expression::Function { .. }, .. }
+            if value.starts_with("test_") => Some((value.clone(), token.span)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs the test `name` of `file`, adding the lines it ran to `coverage` if given, whether it passes or not.
+fn run_test(file: &Path, name: &str, span: Option<Span>, coverage: Option<&mut Coverage>) -> Result<(), String> {
+    let interpreter = Interpreter::new(Environment::new(None));
+    interpreter.set_output(SharedBuffer::new()); // only the results are printed
+    interpreter.set_coverage(coverage.is_some());
+    let result = call_test(&interpreter, file, name, span);
+    if let (Some(coverage), Some(covered)) = (coverage, interpreter.coverage()) {
+        coverage.merge(&covered);
+    }
+    result
+}
+
+fn call_test(interpreter: &Interpreter, file: &Path, name: &str, span: Option<Span>) -> Result<(), String> {
+    interpreter.evaluate_file(file).map_err(|err| err.0)?;
+
+    // Called from where it's defined, so the test shows up at its definition in call stacks
+    let mut function = Token::new_identifier(name);
+    function.span = span;
+    let identifier = Expression::identifier(function.clone());
+    let call = Expression::Call { token: Token::new_l_paren(), function: Box::new(identifier), arguements: Vec::new() };
+    let program = Program { statements: vec![Statement::construct_expression_statement(function, call)] };
+    match interpreter.evaluate_program(&program) {
+        Ok(Object::Error(message)) => Err(format!("returned error: {message}")),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_tests() {
+        let dir = std::env::temp_dir().join(format!("mk_test_runner_{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("math.mk"), "
+let double = fn(x) { x * 2 };
+let test_double = fn() { assert(double(2) == 4, \"2 doubles to 4\") };
+let test_wrong = fn() {
+    assert(double(2) == 5, \"2 doubles to 5\")
+};
+let helper = fn() { 1 };
+").unwrap();
+        fs::write(dir.join("nested/errors.mk"), "let test_error_value = fn() { error(\"nope\") };\nlet test_crash = fn() { len(1) };").unwrap();
+        fs::write(dir.join("nested/no_tests.mk"), "let x = 1;").unwrap();
+        fs::write(dir.join("notes.txt"), "let test_ignored = fn() { 1 };").unwrap();
+
+        let report = run_tests(&dir, false).unwrap();
+        let lines = report.to_string().replace(&dir.display().to_string(), "");
+        assert_eq!(lines, [
+            "ok   /math.mk::test_double",
+            "FAIL /math.mk::test_wrong (line 4, column 5)",
+            "    Assertion failed at line 5, column 5: 2 doubles to 5",
+            "    call stack (most recent call last):",
+            "      at test_wrong (line 4, column 5)",
+            "FAIL /nested/errors.mk::test_error_value (line 1, column 5)",
+            "    returned error: nope",
+            "FAIL /nested/errors.mk::test_crash (line 2, column 5)",
+            "    Can't call built-in fn `len` on type: Integer(1)",
+            "    call stack (most recent call last):",
+            "      at test_crash (line 2, column 5)",
+            "",
+            "1 passed, 3 failed",
+            "",
+        ].join("\n"));
+        assert_eq!(report.failed(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_coverage() {
+        let dir = std::env::temp_dir().join(format!("mk_test_coverage_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.mk"), "
+let sign = fn(x) {
+    if (x < 0) {
+        let negative = -1;
+        negative
+    } else {
+        1
+    }
+};
+let unused = fn() {
+    0
+};
+").unwrap();
+        fs::write(dir.join("lib_test.mk"), "import \"lib.mk\";\nlet test_sign = fn() { assert(sign(2) == 1, \"positive\") };").unwrap();
+
+        let report = run_tests(&dir, true).unwrap();
+        let coverage = report.coverage.as_ref().unwrap();
+        // The lib's fn runs from the test's file, its lines are still the lib's
+        assert_eq!(coverage[0].to_string().replace(&dir.display().to_string(), ""), "/lib.mk: 57.1% (4/7 lines), uncovered: 4-5, 11");
+        assert_eq!(coverage[1].to_string().replace(&dir.display().to_string(), ""), "/lib_test.mk: 100.0% (2/2 lines)");
+        assert!(report.to_string().contains("1 passed, 0 failed\n\ncoverage:\n"), "{report}");
+        assert!(run_tests(&dir, false).unwrap().coverage.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use parser::{ast::{Expression, Statement}, lexer::token::Span, Program};
+
+const KIND: &str = "\x1b[1;36m";
+const VALUE: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// A node of the printed tree: its kind, what it holds, and its children under the field they're in.
+struct Node {
+    kind: &'static str,
+    detail: Option<String>,
+    span: Option<Span>,
+    children: Vec<(String, Node)>,
+}
+
+impl Node {
+    fn new(kind: &'static str, detail: Option<String>, span: Option<Span>) -> Self {
+        Self { kind, detail, span, children: Vec::new() }
+    }
+
+    fn child(mut self, label: &str, node: Node) -> Self {
+        self.children.push((label.to_string(), node));
+        self
+    }
+
+    fn children(mut self, label: &str, nodes: impl IntoIterator<Item = Node>) -> Self {
+        for (i, node) in nodes.into_iter().enumerate() {
+            self.children.push((format!("{label}[{i}]"), node));
+        }
+        self
+    }
+
+    fn render(&self, label: Option<&str>, depth: usize, color: bool, out: &mut String) {
+        let paint = |style: &str, text: &str| if color { format!("{style}{text}{RESET}") } else { text.to_string() };
+
+        out.push_str(&"  ".repeat(depth));
+        if let Some(label) = label {
+            out.push_str(&paint(DIM, &format!("{label}: ")));
+        }
+        out.push_str(&paint(KIND, self.kind));
+        if let Some(detail) = &self.detail {
+            out.push(' ');
+            out.push_str(&paint(VALUE, detail));
+        }
+        if let Some(span) = self.span {
+            out.push(' ');
+            out.push_str(&paint(DIM, &format!("@{}:{}", span.line, span.col)));
+        }
+        out.push('\n');
+
+        for (label, child) in &self.children {
+            child.render(Some(label), depth + 1, color, out);
+        }
+    }
+}
+
+/// Prints `program` as an indented tree of its statements and expressions, each with the line and column it
+/// starts at (or of its operator, for infix expressions). `color` adds ANSI colors for terminals.
+pub fn render(program: &Program, color: bool) -> String {
+    let mut out = String::new();
+    for statement in &program.statements {
+        statement_node(statement).render(None, 0, color, &mut out);
+    }
+    out
+}
+
+fn statement_node(statement: &Statement) -> Node {
+    let span = statement.span();
+    match statement {
+        Statement::ExpressionStatement { expression, .. } => Node::new("ExpressionStatement", None, span).child("expression", expression_node(expression)),
+        Statement::Let { name, value, .. } => {
+            let kind = if statement.is_fn_declaration() { "FnDeclaration" } else { "Let" };
+            Node::new(kind, None, span).child("name", expression_node(name)).child("value", expression_node(value))
+        },
+        Statement::Return { return_value, .. } => Node::new("Return", None, span).child("value", expression_node(return_value)),
+        Statement::Block { statements, .. } => Node::new("Block", None, span).children("statements", statements.iter().map(statement_node)),
+        Statement::Import { path, .. } => Node::new("Import", Some(format!("{path:?}")), span),
+    }
+}
+
+fn expression_node(expression: &Expression) -> Node {
+    let span = expression.span();
+    let elements = |elements: &[Expression]| elements.iter().map(expression_node).collect::<Vec<Node>>();
+    match expression {
+        Expression::Identifier { value, .. } => Node::new("Identifier", Some(value.clone()), span),
+        Expression::Integer { value, .. } => Node::new("Integer", Some(value.to_string()), span),
+        Expression::Boolean { value, .. } => Node::new("Boolean", Some(value.to_string()), span),
+        Expression::Null { .. } => Node::new("Null", None, span),
+        Expression::String { value, .. } => Node::new("String", Some(format!("{value:?}")), span),
+        Expression::Array { elements: items, .. } => Node::new("Array", None, span).children("elements", elements(items)),
+        Expression::Tuple { elements: items, .. } => Node::new("Tuple", None, span).children("elements", elements(items)),
+        Expression::KVPair { key, value } => {
+            Node::new("KVPair", None, span).child("key", expression_node(key)).child("value", expression_node(value))
+        },
+        Expression::Hash { kv_pairs } => Node::new("Hash", None, span).children("pairs", elements(kv_pairs)),
+        Expression::Index { name, i, .. } => Node::new("Index", None, span).child("name", expression_node(name)).child("index", expression_node(i)),
+        Expression::Slice { name, start, end, .. } => {
+            let mut node = Node::new("Slice", None, span).child("name", expression_node(name));
+            if let Some(start) = start {
+                node = node.child("start", expression_node(start));
+            }
+            if let Some(end) = end {
+                node = node.child("end", expression_node(end));
+            }
+            node
+        },
+        Expression::Prefix { operator, right, .. } => Node::new("Prefix", Some(operator.clone()), span).child("right", expression_node(right)),
+        Expression::Infix { left, operator, right, .. } => {
+            Node::new("Infix", Some(operator.clone()), span).child("left", expression_node(left)).child("right", expression_node(right))
+        },
+        Expression::If { condition, consequence, alternative, .. } => {
+            let node = Node::new("If", None, span)
+                .child("condition", expression_node(condition))
+                .child("consequence", statement_node(consequence));
+            match alternative {
+                Some(alternative) => node.child("alternative", statement_node(alternative)),
+                None => node,
+            }
+        },
+        Expression::Function { params, body, .. } => {
+            Node::new("Function", None, span).children("params", elements(params)).child("body", statement_node(body))
+        },
+        Expression::Call { function, arguements, .. } => {
+            Node::new("Call", None, span).child("function", expression_node(function)).children("args", elements(arguements))
+        },
+        Expression::NamedArg { name, value, .. } => Node::new("NamedArg", Some(name.clone()), span).child("value", expression_node(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let program = Parser::new(Lexer::new_borrowed("let f = fn(x) { -x };\nf(1 + 2)[0:]")).parse_program().unwrap();
+        assert_eq!(render(&program, false), [
+            "Let @1:1",
+            "  name: Identifier f @1:5",
+            "  value: Function @1:9",
+            "    params[0]: Identifier x @1:12",
+            "    body: Block @1:15",
+            "      statements[0]: ExpressionStatement @1:17",
+            "        expression: Prefix - @1:17",
+            "          right: Identifier x @1:18",
+            "ExpressionStatement @2:1",
+            "  expression: Slice @2:9",
+            "    name: Call @2:2",
+            "      function: Identifier f @2:1",
+            "      args[0]: Infix + @2:5",
+            "        left: Integer 1 @2:3",
+            "        right: Integer 2 @2:7",
+            "    start: Integer 0 @2:10",
+            "",
+        ].join("\n"));
+
+        let colored = render(&Parser::new(Lexer::new_borrowed("\"s\"")).parse_program().unwrap(), true);
+        assert_eq!(colored, "\x1b[1;36mExpressionStatement\x1b[0m \x1b[2m@1:1\x1b[0m\n  \x1b[2mexpression: \x1b[0m\x1b[1;36mString\x1b[0m \x1b[32m\"s\"\x1b[0m \x1b[2m@1:1\x1b[0m\n");
+    }
+}
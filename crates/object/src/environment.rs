@@ -0,0 +1,328 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
+
+use parser::{ast::{Expression, Statement}, intern::Symbol, lexer::Lexer, Parser};
+use serde_json::{json, Map, Value};
+
+use crate::{sorted_entries, EvalError, HashKey, Object};
+
+pub type Env = Rc<RefCell<Environment>>;
+
+const SNAPSHOT_VERSION: u64 = 1;
+
+/// A scope of variables. The scope of a fn call keeps the fn's locals, resolved when it was parsed, in slots; the
+/// map holds globals and any name bound at runtime that isn't a local, by an import in the fn say.
+#[derive(Debug)]
+pub struct Environment {
+    vars: HashMap<Symbol, Object>,
+    locals: Rc<[Symbol]>,
+    slots: Vec<Option<Object>>, // a local's value once it's bound, until then lookups go on to the outer scope
+    consts: HashSet<Symbol>, // names bound by a `const` in this scope, which can't be bound again
+    outer: Option<Env>
+}
+
+impl Environment {
+    pub fn new(outer: Option<Env>) -> Self {
+        Self::new_with_locals(Rc::new([]), outer)
+    }
+
+    pub fn new_with_locals(locals: Rc<[Symbol]>, outer: Option<Env>) -> Self {
+        Self {
+            vars: HashMap::new(),
+            slots: vec![None; locals.len()],
+            locals,
+            consts: HashSet::new(),
+            outer,
+        }
+    }
+
+    /// A param repeated in a fn's params has a slot for each, the last one given wins.
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.as_str() == name)
+    }
+
+    /// Looks `name` up in this scope then the outer ones.
+    pub fn get(&self, name: &str) -> Option<Object> {
+        let val = match self.slot(name) {
+            Some(slot) => self.slots[slot].as_ref(),
+            None => self.vars.get(name),
+        };
+        if let Some(obj) = val {
+            return Some(obj.clone());
+        }
+
+        if let Some(outer_env) = &self.outer {
+            return outer_env.borrow().get(name);
+        }
+
+        None
+    }
+
+    /// Like [`Environment::get`], for a parsed identifier. A local resolved to `slot` of the scope `depth` out is read
+    /// from there, unless that scope turns out not to have it there, as when `with` put a scope in between.
+    pub fn lookup(&self, name: &Symbol, local: Option<(usize, usize)>) -> Option<Object> {
+        match local.and_then(|(depth, slot)| self.local(name, depth, slot)) {
+            Some(val) => val,
+            None => self.get(name.as_str()),
+        }
+    }
+
+    fn local(&self, name: &Symbol, depth: usize, slot: usize) -> Option<Option<Object>> {
+        if depth > 0 {
+            return self.outer.as_ref()?.borrow().local(name, depth - 1, slot);
+        }
+        if self.locals.get(slot) != Some(name) {
+            return None;
+        }
+        Some(match &self.slots[slot] {
+            Some(val) => Some(val.clone()),
+            // Until a local is bound lookups go on to the outer scope
+            None => self.outer.as_ref().and_then(|outer| outer.borrow().get(name.as_str())),
+        })
+    }
+
+    pub fn set(&mut self, name: &str, val: Object) {
+        match (self.slot(name), self.vars.get_mut(name)) {
+            (Some(slot), _) => self.slots[slot] = Some(val),
+            (None, Some(var)) => *var = val,
+            (None, None) => { self.vars.insert(Symbol::from(name), val); },
+        }
+    }
+
+    /// Binds `name` like [`Environment::set`], for good: the interpreter refuses to bind it again in this scope.
+    pub fn set_const(&mut self, name: &str, val: Object) {
+        self.set(name, val);
+        if !self.consts.contains(name) {
+            self.consts.insert(Symbol::from(name));
+        }
+    }
+
+    /// Whether `name` was bound by a `const` in this scope, those of outer scopes can be shadowed.
+    pub fn is_const(&self, name: &str) -> bool {
+        self.consts.contains(name)
+    }
+
+    /// Binds the local in `slot` of the locals this scope was made with.
+    pub fn set_local(&mut self, slot: usize, val: Object) {
+        self.slots[slot] = Some(val);
+    }
+
+    /// This scope's bindings, in no particular order.
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &Object)> {
+        let locals = self.locals.iter().zip(&self.slots).filter_map(|(name, val)| Some((name, val.as_ref()?)));
+        self.vars.iter().chain(locals).map(|(name, val)| (name.as_str(), val))
+    }
+
+    /// Serializes this scope's bindings to JSON, to be restored with [`Environment::restore_snapshot`]. Builtins are
+    /// left out since every interpreter defines its own, and fns are stored as source, so only fns defined in this
+    /// scope can be saved: a closure over a fn's locals is an error.
+    pub fn to_snapshot(&self) -> Result<String, EvalError> {
+        let mut vars = Map::new();
+        let mut bindings = self.vars().collect::<Vec<(&str, &Object)>>();
+        bindings.sort_by_key(|(name, _)| *name);
+        for (name, val) in bindings {
+            match val {
+                Object::BuiltIn(_) => {},
+                val => {
+                    let val = self.snapshot_value(val).map_err(|err| EvalError(format!("Cannot snapshot `{name}`: {}", err.0)))?;
+                    vars.insert(name.to_string(), val);
+                },
+            }
+        }
+        Ok(json!({ "version": SNAPSHOT_VERSION, "vars": vars }).to_string())
+    }
+
+    fn snapshot_value(&self, val: &Object) -> Result<Value, EvalError> {
+        let values = |vals: &[Object]| vals.iter().map(|val| self.snapshot_value(val)).collect::<Result<Vec<Value>, EvalError>>();
+        Ok(match val {
+            Object::Integer(val) => json!({ "type": "int", "value": val }),
+            Object::Boolean(val) => json!({ "type": "bool", "value": val }),
+            Object::String(val) => json!({ "type": "str", "value": val }),
+            Object::Error(val) => json!({ "type": "error", "value": val }),
+            Object::Null => json!({ "type": "null" }),
+            Object::Array(vals) => json!({ "type": "array", "value": values(vals)? }),
+            Object::Tuple(vals) => json!({ "type": "tuple", "value": values(vals)? }),
+            Object::HashMap(hash_map) => {
+                let entries = sorted_entries(hash_map)
+                    .into_iter()
+                    .map(|(key, val)| Ok(json!([self.snapshot_value(key)?, self.snapshot_value(val)?])))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                json!({ "type": "hash", "value": entries })
+            },
+            Object::Frozen(val) => json!({ "type": "frozen", "value": self.snapshot_value(val)? }),
+            Object::Function { body, fn_env, .. } => {
+                if !fn_env.upgrade().is_some_and(|fn_env| std::ptr::eq(fn_env.as_ptr(), self)) {
+                    return Err(EvalError(format!("{val} closes over the locals of another fn")));
+                }
+                json!({ "type": "fn", "params": val.signature(), "body": body.to_source() })
+            },
+            Object::Bound { function, args } => {
+                json!({ "type": "bound", "function": self.snapshot_value(function)?, "args": values(args)? })
+            },
+            Object::BuiltIn(_) | Object::KVPair(..) | Object::Return(_) => {
+                return Err(EvalError(format!("{} values can't be saved", val.type_name())));
+            },
+        })
+    }
+
+    /// Binds the variables saved by [`Environment::to_snapshot`] in `env`, over any it already has. Restored fns
+    /// close over `env`, and as they're re-parsed from their printed source, errors in them point into that.
+    pub fn restore_snapshot(env: &Env, snapshot: &str) -> Result<(), EvalError> {
+        let snapshot: Value = serde_json::from_str(snapshot).map_err(|err| EvalError(format!("Invalid snapshot: {err}")))?;
+        if snapshot["version"] != json!(SNAPSHOT_VERSION) {
+            return Err(EvalError(format!("Unsupported snapshot version: {}", snapshot["version"])));
+        }
+        let Some(vars) = snapshot["vars"].as_object() else {
+            return Err(EvalError("Invalid snapshot: missing vars".to_string()));
+        };
+
+        let mut restored = Vec::with_capacity(vars.len());
+        for (name, val) in vars {
+            let val = restore_value(env, val).map_err(|err| EvalError(format!("Cannot restore `{name}`: {}", err.0)))?;
+            restored.push((name, val));
+        }
+        // Nothing is bound unless everything could be restored
+        for (name, val) in restored {
+            env.borrow_mut().set(name, val);
+        }
+        Ok(())
+    }
+
+    /// A new scope holding the variables saved in `snapshot`.
+    pub fn from_snapshot(snapshot: &str, outer: Option<Env>) -> Result<Env, EvalError> {
+        let env = Rc::new(RefCell::new(Self::new(outer)));
+        Self::restore_snapshot(&env, snapshot)?;
+        Ok(env)
+    }
+}
+
+fn restore_value(env: &Env, val: &Value) -> Result<Object, EvalError> {
+    let invalid = || EvalError(format!("Invalid snapshot value: {val}"));
+    let values = |vals: &Value| {
+        vals.as_array().ok_or_else(invalid)?.iter().map(|val| restore_value(env, val)).collect::<Result<Vec<Object>, EvalError>>()
+    };
+    let value = &val["value"];
+
+    Ok(match val["type"].as_str().ok_or_else(invalid)? {
+        "int" => Object::Integer(value.as_i64().and_then(|val| isize::try_from(val).ok()).ok_or_else(invalid)?),
+        "bool" => Object::Boolean(value.as_bool().ok_or_else(invalid)?),
+        "str" => Object::String(value.as_str().ok_or_else(invalid)?.to_string()),
+        "error" => Object::Error(value.as_str().ok_or_else(invalid)?.to_string()),
+        "null" => Object::Null,
+        "array" => Object::Array(values(value)?),
+        "tuple" => Object::Tuple(values(value)?),
+        "hash" => {
+            let mut hash_map = HashMap::new();
+            for entry in value.as_array().ok_or_else(invalid)? {
+                let [key, val] = values(entry)?.try_into().map_err(|_| invalid())?;
+                hash_map.insert(HashKey::get_hash_key(&key)?, Object::KVPair(Box::new(key), Box::new(val)));
+            }
+            Object::HashMap(hash_map)
+        },
+        "frozen" => restore_value(env, value)?.freeze(),
+        "fn" => {
+            let params = val["params"].as_str().ok_or_else(invalid)?;
+            let src = format!("fn({params}) {}", val["body"].as_str().ok_or_else(invalid)?);
+            let mut program = Parser::new(Lexer::new_borrowed(&src))
+                .parse_program()
+                .map_err(|err| EvalError(format!("Invalid fn source {src}: {err:?}")))?;
+            match program.statements.pop() {
+                Some(Statement::ExpressionStatement { expression: Expression::Function { params, body, locals, .. }, .. }) if program.statements.is_empty() => {
+                    Object::construct_fn(&params, &body, &locals, env)?
+                },
+                _ => return Err(EvalError(format!("Invalid fn source: {src}"))),
+            }
+        },
+        "bound" => {
+            let function = restore_value(env, &val["function"])?;
+            function.bind(values(&val["args"])?)?
+        },
+        _ => return Err(invalid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locals() {
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
+        globals.borrow_mut().set("x", Object::Integer(1));
+        let mut local = Environment::new_with_locals(Rc::from([Symbol::from("x"), Symbol::from("y")]), Some(Rc::clone(&globals)));
+
+        // Until a local is bound lookups go on to the outer scope
+        assert_eq!(local.get("x"), Some(Object::Integer(1)));
+        local.set("x", Object::Integer(2));
+        local.set("imported", Object::Integer(3));
+        assert_eq!(local.get("x"), Some(Object::Integer(2)));
+        assert_eq!(local.get("imported"), Some(Object::Integer(3)));
+        assert_eq!(local.get("y"), None);
+        assert_eq!(globals.borrow().get("x"), Some(Object::Integer(1)));
+
+        let mut vars = local.vars().collect::<Vec<(&str, &Object)>>();
+        vars.sort_by_key(|(name, _)| *name);
+        assert_eq!(vars, [("imported", &Object::Integer(3)), ("x", &Object::Integer(2))]);
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let env = Rc::new(RefCell::new(Environment::new(None)));
+        let add = Object::construct_fn(
+            &vec![Expression::construct_identifier_expression("a"), Expression::construct_identifier_expression("b")],
+            &Rc::new(Statement::construct_block_statement(vec![Statement::construct_expression_statement(
+                parser::lexer::token::Token::new_plus(),
+                Expression::try_infix_expression("+", Expression::construct_identifier_expression("a"), Expression::construct_identifier_expression("b")).unwrap(),
+            )])),
+            &Rc::from([Symbol::from("a"), Symbol::from("b")]),
+            &env,
+        ).unwrap();
+        let mut hash_map = HashMap::new();
+        for (key, val) in [(Object::String("k".to_string()), Object::Boolean(true)), (Object::Integer(-1), Object::Null)] {
+            hash_map.insert(HashKey::get_hash_key(&key).unwrap(), Object::KVPair(Box::new(key), Box::new(val)));
+        }
+        {
+            let mut env = env.borrow_mut();
+            env.set("n", Object::Integer(isize::MIN));
+            env.set("s", Object::String("a \"quoted\" str".to_string()));
+            env.set("xs", Object::Array(vec![Object::Tuple(vec![Object::Boolean(false)]), Object::Error("oops".to_string())]).freeze());
+            env.set("h", Object::HashMap(hash_map));
+            env.set("inc", add.clone().bind(vec![Object::Integer(1)]).unwrap());
+            env.set("add", add);
+            env.set("len", Object::builtin(|_| Ok(Object::Null)));
+        }
+
+        let snapshot = env.borrow().to_snapshot().unwrap();
+        let restored = Environment::from_snapshot(&snapshot, None).unwrap();
+        let restored_ref = restored.borrow();
+        for name in ["n", "s", "xs", "h"] {
+            assert_eq!(restored_ref.get(name), env.borrow().get(name), "{name}");
+        }
+        assert!(matches!(restored_ref.get("xs"), Some(Object::Frozen(_))));
+        assert!(restored_ref.get("len").is_none());
+        match restored_ref.get("inc") {
+            Some(Object::Bound { function, args }) => {
+                assert_eq!(args, vec![Object::Integer(1)]);
+                assert!(matches!(*function, Object::Function { ref parameters, ref fn_env, .. } if parameters == &["a", "b"] && std::ptr::eq(fn_env.as_ptr(), Rc::as_ptr(&restored))));
+            },
+            other => panic!("expected a bound fn, got {other:?}"),
+        }
+        assert_eq!(restored_ref.to_snapshot().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let env = Rc::new(RefCell::new(Environment::new(None)));
+        let local = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(&env)))));
+        let closure = Object::construct_fn(&vec![], &Rc::new(Statement::construct_block_statement(vec![])), &Rc::from([]), &local).unwrap();
+        env.borrow_mut().set("f", closure);
+        assert!(env.borrow().to_snapshot().unwrap_err().0.starts_with("Cannot snapshot `f`: fn() "));
+
+        let env = Rc::new(RefCell::new(Environment::new(None)));
+        env.borrow_mut().set("kept", Object::Integer(1));
+        let snapshot = r#"{"version": 1, "vars": {"a": {"type": "int", "value": 2}, "b": {"type": "fn", "params": "x", "body": "{ x +"}}}"#;
+        assert!(Environment::restore_snapshot(&env, snapshot).unwrap_err().0.starts_with("Cannot restore `b`: Invalid fn source"));
+        assert!(env.borrow().get("a").is_none());
+        assert!(Environment::restore_snapshot(&env, r#"{"version": 2, "vars": {}}"#).is_err());
+        assert!(Environment::restore_snapshot(&env, "not json").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, BufRead, Read};
+
+/// Where `read_line` and `read_all` read from, so embedders and tests can feed programs input instead of stdin.
+pub trait InputSource {
+    /// The next line without its line ending, `None` once the input is used up.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+
+    /// Everything left of the input.
+    fn read_all(&mut self) -> io::Result<String>;
+}
+
+/// Reads the process's stdin, the default for the CLI. It's only locked while reading, so a REPL can share it.
+pub struct Stdin;
+
+impl InputSource for Stdin {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        read_line(&mut io::stdin().lock())
+    }
+
+    fn read_all(&mut self) -> io::Result<String> {
+        let mut text = String::new();
+        io::stdin().lock().read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+/// Input given up front, e.g. canned answers to a program's prompts.
+#[derive(Debug, Clone, Default)]
+pub struct StringInput(io::Cursor<String>);
+
+impl StringInput {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self(io::Cursor::new(text.into()))
+    }
+}
+
+impl InputSource for StringInput {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        read_line(&mut self.0)
+    }
+
+    fn read_all(&mut self) -> io::Result<String> {
+        let mut text = String::new();
+        self.0.read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_input() {
+        let mut input = StringInput::new("first\r\n\nlast");
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("first"));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some(""));
+        assert_eq!(input.read_all().unwrap(), "last");
+        assert_eq!(input.read_line().unwrap(), None);
+        assert_eq!(input.read_all().unwrap(), "");
+    }
+}
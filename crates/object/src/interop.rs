@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use crate::{sorted_entries, EvalError, HashKey, Object};
+
+/// Converts host JSON to a Monkey value: objects become hashes with string keys, numbers ints. Numbers that aren't
+/// ints of the platform's width have no Monkey value.
+pub fn from_json(value: &Value) -> Result<Object, EvalError> {
+    Ok(match value {
+        Value::Null => Object::Null,
+        Value::Bool(val) => Object::Boolean(*val),
+        Value::Number(val) => Object::Integer(
+            val.as_i64()
+                .and_then(|val| isize::try_from(val).ok())
+                .ok_or_else(|| EvalError(format!("Cannot convert {val} to an int, Monkey only has ints")))?,
+        ),
+        Value::String(val) => Object::String(val.clone()),
+        Value::Array(vals) => Object::Array(vals.iter().map(from_json).collect::<Result<Vec<Object>, EvalError>>()?),
+        Value::Object(fields) => {
+            let mut hash_map = HashMap::new();
+            for (key, val) in fields {
+                let key = Object::String(key.clone());
+                hash_map.insert(HashKey::get_hash_key(&key)?, Object::KVPair(Box::new(key), Box::new(from_json(val)?)));
+            }
+            Object::HashMap(hash_map)
+        },
+    })
+}
+
+/// Converts a Monkey value to JSON for the host: arrays and tuples become arrays, hashes objects. JSON keys are
+/// strings, so int and bool keys are written as strings. Fns and errors have no JSON form.
+pub fn to_json(obj: &Object) -> Result<Value, EvalError> {
+    let values = |vals: &[Object]| vals.iter().map(to_json).collect::<Result<Vec<Value>, EvalError>>();
+    Ok(match obj {
+        Object::Null => Value::Null,
+        Object::Boolean(val) => Value::Bool(*val),
+        Object::Integer(val) => Value::from(*val as i64),
+        Object::String(val) => Value::String(val.clone()),
+        Object::Array(vals) | Object::Tuple(vals) => Value::Array(values(vals)?),
+        Object::HashMap(hash_map) => {
+            let mut fields = Map::new();
+            for (key, val) in sorted_entries(hash_map) {
+                let key = match key {
+                    Object::String(key) => key.clone(),
+                    key => key.to_string(),
+                };
+                fields.insert(key, to_json(val)?);
+            }
+            Value::Object(fields)
+        },
+        Object::Return(val) | Object::Frozen(val) => to_json(val)?,
+        obj => return Err(EvalError(format!("Cannot convert {} to JSON", obj.type_name()))),
+    })
+}
+
+/// Parses JSON text into a Monkey value, see [`from_json`].
+pub fn parse_json(text: &str) -> Result<Object, EvalError> {
+    let value: Value = serde_json::from_str(text).map_err(|err| EvalError(format!("Invalid JSON: {err}")))?;
+    from_json(&value)
+}
+
+/// Writes a Monkey value as compact JSON text, see [`to_json`].
+pub fn stringify_json(obj: &Object) -> Result<String, EvalError> {
+    Ok(to_json(obj)?.to_string())
+}
+
+/// The fields of `scope`, a JSON object, as bindings of a global scope.
+pub fn bindings_from_json(scope: &Value) -> Result<HashMap<String, Object>, EvalError> {
+    let Value::Object(fields) = scope else {
+        return Err(EvalError(format!("Expected a JSON object of globals, got: {scope}")));
+    };
+    fields.iter().map(|(name, val)| Ok((name.clone(), from_json(val)?))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = json!({"name": "monkey", "age": 3, "tags": ["a", null, true], "nested": {"empty": {}}});
+        let obj = from_json(&value).unwrap();
+        assert_eq!(obj.to_string(), r#"{"age": 3, "name": "monkey", "nested": {"empty": {}}, "tags": ["a", null, true]}"#);
+        assert_eq!(to_json(&obj).unwrap(), value);
+
+        assert_eq!(from_json(&json!(1.5)).unwrap_err().0, "Cannot convert 1.5 to an int, Monkey only has ints");
+        let tuple = Object::Tuple(vec![Object::Integer(1), Object::Error("boom".to_string())]);
+        assert_eq!(to_json(&tuple).unwrap_err().0, "Cannot convert error to JSON");
+
+        let parsed = parse_json(r#"{"a": [1, "two", false]}"#).unwrap();
+        assert_eq!(stringify_json(&parsed).unwrap(), r#"{"a":[1,"two",false]}"#);
+        assert_eq!(parse_json("{1: 2}").unwrap_err().0, "Invalid JSON: key must be a string at line 1 column 2");
+
+        let bindings = bindings_from_json(&json!({"x": 1})).unwrap();
+        assert_eq!(bindings["x"], Object::Integer(1));
+        assert!(bindings_from_json(&json!([1])).is_err());
+    }
+}
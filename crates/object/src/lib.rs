@@ -0,0 +1,13 @@
+pub mod object;
+pub mod environment;
+pub mod input;
+pub mod interop;
+pub mod log;
+pub mod output;
+
+pub use object::*;
+pub use environment::*;
+pub use input::*;
+pub use interop::*;
+pub use log::*;
+pub use output::*;
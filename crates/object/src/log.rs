@@ -0,0 +1,109 @@
+use std::{cell::RefCell, fmt, rc::Rc, str::FromStr};
+
+/// How much a logged event matters, most verbose first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Trace => "trace",
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, String> {
+        match level {
+            "trace" => Ok(Self::Trace),
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(format!("Unknown log level {level}, expected one of trace, debug, info, warn or error")),
+        }
+    }
+}
+
+/// An event logged by the interpreter or the VM, `target` says which.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub level: Level,
+    pub target: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} {}] {}", self.level, self.target, self.message)
+    }
+}
+
+/// Receives what the interpreter and the VM are doing (instructions executed, functions called, scopes created), so
+/// embedders can follow a run. Loggers are shared, between an interpreter and the VMs of an engine say.
+pub trait Logger {
+    /// Whether events at `level` are wanted, they aren't even formatted otherwise.
+    fn enabled(&self, level: Level) -> bool {
+        let _ = level;
+        true
+    }
+
+    fn log(&self, record: &Record);
+}
+
+/// Writes the events at `level` and above to stderr, what the CLI logs with.
+#[derive(Debug, Clone, Copy)]
+pub struct StderrLogger {
+    pub level: Level,
+}
+
+impl Logger for StderrLogger {
+    fn enabled(&self, level: Level) -> bool {
+        level >= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!("{record}");
+    }
+}
+
+/// Keeps every event it's given in memory. Clones share the same records, keep one to read what the other was given.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Rc<RefCell<Vec<Record>>>);
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the records logged so far and empties the buffer.
+    pub fn take(&self) -> Vec<Record> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Logger for LogBuffer {
+    fn log(&self, record: &Record) {
+        self.0.borrow_mut().push(record.clone());
+    }
+}
+
+/// Logs the message `message` builds to `logger`, only building it when there's a logger that wants `level`.
+pub fn log_event(logger: Option<&Rc<dyn Logger>>, level: Level, target: &'static str, message: impl FnOnce() -> String) {
+    if let Some(logger) = logger.filter(|logger| logger.enabled(level)) {
+        logger.log(&Record { level, target, message: message() });
+    }
+}
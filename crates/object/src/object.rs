@@ -0,0 +1,720 @@
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, fmt, hash::{DefaultHasher, Hash, Hasher}, ops::{Add, Div, Mul, Sub}, rc::{Rc, Weak}};
+
+use parser::{ast, intern::Symbol, lexer::token::Span};
+
+use crate::{Env, Environment};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct EvalError(pub String);
+
+const BUDGET_EXCEEDED: &str = "Execution budget exceeded";
+const INTERRUPTED: &str = "Interrupted by user";
+
+impl EvalError {
+    /// The error both backends abort with once a run uses up its step limit.
+    pub fn budget_exceeded(limit: usize) -> Self {
+        Self(format!("{BUDGET_EXCEEDED}: ran more than {limit} steps"))
+    }
+
+    pub fn is_budget_exceeded(&self) -> bool {
+        self.0.starts_with(BUDGET_EXCEEDED)
+    }
+
+    /// The error both backends abort with once their interrupt flag is raised, by Ctrl+C say.
+    pub fn interrupted() -> Self {
+        Self(INTERRUPTED.to_string())
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.starts_with(INTERRUPTED)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Object {
+    Integer(isize),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Self>),
+    Tuple(Vec<Self>),
+    KVPair(Box<Self>, Box<Self>),
+    HashMap(HashMap<HashKey, Self>),
+    Return(Box<Self>),
+    Function {
+        parameters: Vec<String>, // Identifiers
+        defaults: Vec<ast::Expression>, // default values of the last parameters, evaluated when a call leaves them out
+        rest: Option<String>, // bound to an Array of the arguements past `parameters`
+        body: Rc<ast::Statement>, // Block statement, shared with the fn expression
+        locals: Rc<[Symbol]>, // slots of the scope each call runs in
+        fn_env: Weak<RefCell<Environment>>,
+    },
+    Null,
+
+    BuiltIn(BuiltinFn),
+    /// A function with its leading arguements already fixed by `bind`.
+    Bound {
+        function: Box<Self>, // Function or BuiltIn, never another Bound
+        args: Vec<Self>,
+    },
+    /// A failure caught by `rescue` or made by `error`, handled like any other value.
+    Error(String),
+    /// An array, tuple or hash marked by `freeze`. It reads like the value it wraps, but `push` rejects it.
+    Frozen(Box<Self>),
+}
+
+/// The values `true`, `false` and `null` evaluate to. Unlike the book's Go objects these are plain enum values that
+/// don't allocate, so sharing them is sharing a name: evaluating and cloning them is as cheap as copying a `bool`.
+pub const TRUE: Object = Object::Boolean(true);
+pub const FALSE: Object = Object::Boolean(false);
+pub const NULL: Object = Object::Null;
+
+impl From<bool> for Object {
+    fn from(val: bool) -> Self {
+        if val { TRUE } else { FALSE }
+    }
+}
+
+/// The (key, value) entries of a hash ordered by key with [`Object::total_cmp`], so hashes print and iterate the
+/// same way every run.
+pub fn sorted_entries(hash_map: &HashMap<HashKey, Object>) -> Vec<(&Object, &Object)> {
+    let mut entries = hash_map
+        .values()
+        .filter_map(|pair| match pair {
+            Object::KVPair(key, val) => Some((key.as_ref(), val.as_ref())),
+            _ => None,
+        })
+        .collect::<Vec<(&Object, &Object)>>();
+    // Hash keys are always bools, ints or strings, which total_cmp orders
+    entries.sort_by(|(x, _), (y, _)| x.total_cmp(y).unwrap_or(Ordering::Equal));
+    entries
+}
+
+/// Lets builtins like `map` call the Monkey functions they're passed, implemented by whatever evaluates them.
+pub trait Caller {
+    fn call_function(&self, function: &Object, args: Vec<Object>) -> Result<Object, EvalError>;
+
+    /// Where the builtin was called from, if known.
+    fn call_site(&self) -> Option<Span> {
+        None
+    }
+}
+
+/// Any evaluator handle of the form `|function, args| ...` can call functions for builtins.
+impl<F: Fn(&Object, Vec<Object>) -> Result<Object, EvalError>> Caller for F {
+    fn call_function(&self, function: &Object, args: Vec<Object>) -> Result<Object, EvalError> {
+        self(function, args)
+    }
+}
+
+/// The `Caller` of builtins called from outside an interpreter, which has nothing to call functions with.
+struct NoCaller;
+
+impl Caller for NoCaller {
+    fn call_function(&self, function: &Object, _: Vec<Object>) -> Result<Object, EvalError> {
+        Err(EvalError(format!("Cannot call {function} outside of an interpreter")))
+    }
+}
+
+type BuiltinBody = dyn Fn(Vec<Object>, &dyn Caller) -> Result<Object, EvalError>;
+
+/// The body of a builtin function. Reference counted rather than a fn pointer so builtins can capture state, e.g.
+/// a host database handle or the output sink.
+#[derive(Clone)]
+pub struct BuiltinFn(Rc<BuiltinBody>);
+
+fn thaw_args(args: Vec<Object>) -> Vec<Object> {
+    args.into_iter().map(Object::thaw).collect()
+}
+
+impl BuiltinFn {
+    /// A builtin that's passed frozen values as the values they wrap, as it doesn't change them.
+    pub fn new(f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) -> Self {
+        Self(Rc::new(move |args, _: &dyn Caller| f(thaw_args(args))))
+    }
+
+    /// A builtin that takes functions as arguements and calls them through `caller`.
+    pub fn with_caller(f: impl Fn(Vec<Object>, &dyn Caller) -> Result<Object, EvalError> + 'static) -> Self {
+        Self(Rc::new(move |args, caller: &dyn Caller| f(thaw_args(args), caller)))
+    }
+
+    /// A builtin that's passed frozen values as they are, for those like `push` that have to tell them apart.
+    pub fn seeing_frozen(f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) -> Self {
+        Self(Rc::new(move |args, _: &dyn Caller| f(args)))
+    }
+
+    pub fn call(&self, args: Vec<Object>) -> Result<Object, EvalError> {
+        self.call_with(args, &NoCaller)
+    }
+
+    pub fn call_with(&self, args: Vec<Object>, caller: &dyn Caller) -> Result<Object, EvalError> {
+        (self.0)(args, caller)
+    }
+}
+
+impl fmt::Debug for BuiltinFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BuiltinFn({:p})", Rc::as_ptr(&self.0))
+    }
+}
+
+// Builtins are only equal to themselves (or a clone)
+impl PartialEq for BuiltinFn {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Rc::as_ptr(&self.0), Rc::as_ptr(&other.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HashKey {
+    pub typ: String,
+    pub value: usize,
+}
+
+impl HashKey {
+    pub fn get_hash_key(object: &Object) -> Result<Self, EvalError> {
+        match object {
+            Object::Integer(value) => Ok(Self { typ: "int".to_string(), value: *value as usize}),
+            Object::Boolean(value) => Ok(Self { typ: "bool".to_string(), value: if *value {1} else {0}}),
+            Object::String(value) => {
+                let mut hasher = DefaultHasher::new();
+                value.hash(&mut hasher);
+                Ok(Self { typ: "str".to_string(), value: hasher.finish() as usize})
+            },
+            _ => Err(EvalError(format!("Cannot hash object: {object:?}"))),
+        }
+    }
+}
+
+/// Maps a possibly negative index (counting back from the end) onto `0..len`.
+pub fn normalize_index(index: isize, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as isize } else { index };
+    usize::try_from(index).ok().filter(|index| *index < len)
+}
+
+/// `left << right` or `left >> right`, the shift being sign preserving. Shifting by a negative amount or by the
+/// width of an int or more is an error rather than wrapping the amount.
+fn shift(left: isize, operator: &str, right: isize) -> Result<isize, EvalError> {
+    let amount = u32::try_from(right).ok().filter(|amount| *amount < isize::BITS);
+    match (amount, operator) {
+        (Some(amount), "<<") => Ok(left << amount),
+        (Some(amount), _) => Ok(left >> amount),
+        (None, _) => Err(EvalError(format!("shift amount out of range: {left} {operator} {right}"))),
+    }
+}
+
+/// `left ** right`, ints have no fractions for a negative exponent to give.
+fn power(left: isize, right: isize) -> Result<isize, EvalError> {
+    if right < 0 {
+        return Err(EvalError(format!("negative exponent: {left} ** {right}")));
+    }
+    u32::try_from(right).ok().and_then(|right| left.checked_pow(right)).ok_or_else(|| EvalError(format!("integer overflow: {left} ** {right}")))
+}
+
+/// Most ints a range holds, so a range up to a huge bound fails instead of using up memory.
+pub const MAX_RANGE_LEN: usize = 1 << 24;
+
+/// The ints from `start` up to but not including `end`, `step` apart, counting down for a negative `step`: what
+/// `start..end` (with a step of 1) and the `range` builtin evaluate to.
+pub fn range(start: isize, end: isize, step: isize) -> Result<Object, EvalError> {
+    if step == 0 {
+        return Err(EvalError("range step can't be 0".to_string()));
+    }
+    let (distance, step) = (end as i128 - start as i128, step as i128);
+    let len = if distance.signum() == step.signum() { (distance.abs() + step.abs() - 1) / step.abs() } else { 0 };
+    if len > MAX_RANGE_LEN as i128 {
+        return Err(EvalError(format!("range from {start} to {end} by {step} holds {len} ints, more than the {MAX_RANGE_LEN} a range can")));
+    }
+    Ok(Object::Array((0..len).map(|i| Object::Integer((start as i128 + i * step) as isize)).collect()))
+}
+
+/// Resolves optional, possibly negative slice bounds to a clamped `start..end` range.
+fn slice_bounds(start: Option<isize>, end: Option<isize>, len: usize) -> (usize, usize) {
+    let clamp = |bound: isize| {
+        let bound = if bound < 0 { bound + len as isize } else { bound };
+        bound.clamp(0, len as isize) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
+impl Object {
+    pub fn construct_fn(parameters: &Vec<ast::Expression>, body: &Rc<ast::Statement>, locals: &Rc<[Symbol]>, env: &Env) -> Result<Object, EvalError> {
+        let mut param_names: Vec<String> = Vec::new();
+        if matches!(body.as_ref(), ast::Statement::Block { .. }) {
+            for param in parameters.iter().filter(|param| !param.is_rest_param()) {
+                if let Some(name) = param.param_name() {
+                    param_names.push(name.to_string());
+                } else {
+                    return Err(EvalError(format!("Invalid fn parameters: {parameters:?}, all parameters must be Identifiers, got: {param:?}")));
+                }
+            }
+            let defaults = parameters.iter().filter_map(|param| param.param_default().cloned()).collect();
+            let rest = parameters.iter().find(|param| param.is_rest_param()).and_then(|param| param.param_name()).map(str::to_string);
+            Ok(Self::Function { parameters: param_names, defaults, rest, body: Rc::clone(body), locals: Rc::clone(locals), fn_env: Rc::downgrade(env) })
+        } else {
+            Err(EvalError(format!("Invalid fn body: {body:?}, must be Block statemnt")))
+        }
+    }
+
+    /// The parameters of a function as written, e.g. `x, y = 10, ...rest`.
+    pub fn signature(&self) -> Option<String> {
+        let Self::Function { parameters, defaults, rest, .. } = self else {
+            return None;
+        };
+        let required = parameters.len() - defaults.len();
+        let params = parameters.iter().enumerate().map(|(i, parameter)| match i.checked_sub(required) {
+            Some(default) => format!("{parameter} = {}", defaults[default].to_source()),
+            None => parameter.clone(),
+        });
+        Some(params.chain(rest.iter().map(|rest| format!("...{rest}"))).collect::<Vec<String>>().join(", "))
+    }
+
+    /// Fixes the leading arguements of a function, binding an already bound function appends to its arguements.
+    pub fn bind(self, mut args: Vec<Object>) -> Result<Object, EvalError> {
+        match self {
+            Self::Bound { function, args: mut bound } => {
+                bound.append(&mut args);
+                function.bind(bound)
+            },
+            Self::Function { ref parameters, rest: None, .. } if args.len() > parameters.len() => {
+                Err(EvalError(format!("Cannot bind {} arguements to {self}, it takes {}", args.len(), parameters.len())))
+            },
+            Self::Function { .. } | Self::BuiltIn(_) => Ok(Self::Bound { function: Box::new(self), args }),
+            _ => Err(EvalError(format!("Cannot bind arguements to {}, expected a function", self.type_name()))),
+        }
+    }
+
+    pub fn builtin(f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) -> Self {
+        Self::BuiltIn(BuiltinFn::new(f))
+    }
+
+    pub fn builtin_with_caller(f: impl Fn(Vec<Object>, &dyn Caller) -> Result<Object, EvalError> + 'static) -> Self {
+        Self::BuiltIn(BuiltinFn::with_caller(f))
+    }
+
+    pub fn builtin_seeing_frozen(f: impl Fn(Vec<Object>) -> Result<Object, EvalError> + 'static) -> Self {
+        Self::BuiltIn(BuiltinFn::seeing_frozen(f))
+    }
+
+    /// Marks an array, tuple or hash as frozen, any other value is returned as is. Freezing is shallow, the
+    /// elements of a frozen array are only frozen if they were already.
+    pub fn freeze(self) -> Self {
+        match self {
+            Self::Array(_) | Self::Tuple(_) | Self::HashMap(_) => Self::Frozen(Box::new(self)),
+            _ => self,
+        }
+    }
+
+    /// The value a frozen value wraps, any other value as is.
+    pub fn thaw(self) -> Self {
+        match self {
+            Self::Frozen(val) => *val,
+            _ => self,
+        }
+    }
+
+    pub fn thawed(&self) -> &Self {
+        match self {
+            Self::Frozen(val) => val,
+            _ => self,
+        }
+    }
+
+    pub fn unwrap_return(self) -> Self {
+        if let Self::Return(return_val) = self {
+            return return_val.unwrap_return()
+        }
+        self
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "int",
+            Self::Boolean(_) => "bool",
+            Self::String(_) => "str",
+            Self::Array(_) => "array",
+            Self::Tuple(_) => "tuple",
+            Self::KVPair(..) => "pair",
+            Self::HashMap(_) => "hash",
+            Self::Return(val) => val.type_name(),
+            Self::Function { .. } => "fn",
+            Self::Null => "null",
+            Self::BuiltIn(_) => "builtin",
+            Self::Bound { function, .. } => function.type_name(),
+            Self::Error(_) => "error",
+            Self::Frozen(val) => val.type_name(),
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Boolean(val) => *val,
+            Self::Integer(val) => *val != 0,
+            _ => false,
+        }
+    }
+
+    pub fn prefix(&self, operator: &str) -> Result<Object, EvalError> {
+        match operator {
+            "!" => {
+                match self {
+                    Object::Integer(val) => Ok(Object::from(*val == 0)),
+                    Object::Boolean(val) => Ok(Object::from(!val)),
+                    Object::Null => Ok(TRUE),
+                    _ => Err(EvalError(format!("Invalid arg {self:?} for prefix operator {operator}")))
+                }
+            },
+            "-" => {
+                match self {
+                    Object::Integer(val) => val.checked_neg().map(Object::Integer).ok_or_else(|| EvalError(format!("integer overflow: -({val})"))),
+                    _ => Err(EvalError(format!("Invalid arg {self:?} for prefix operator {operator}")))
+                }
+            },
+            _ => Err(EvalError(format!("Cannot eval prefix expression: {operator}{self:?}"))),
+        }
+    }
+
+    /// `self[start:end]` for arrays and strings, a `Null` bound is left open.
+    pub fn slice(&self, start: &Object, end: &Object) -> Result<Object, EvalError> {
+        let bound = |obj: &Object| match obj {
+            Object::Null => Ok(None),
+            Object::Integer(val) => Ok(Some(*val)),
+            obj => Err(EvalError(format!("Invalid slice bound, expected int, got: {obj:?}"))),
+        };
+        let (start, end) = (bound(start)?, bound(end)?);
+
+        // Slices are copies, frozen or not
+        match self.thawed() {
+            Object::Array(arr) => {
+                let (start, end) = slice_bounds(start, end, arr.len());
+                Ok(Object::Array(arr[start..end].to_vec()))
+            },
+            Object::String(string) => {
+                let (start, end) = slice_bounds(start, end, string.chars().count());
+                Ok(Object::String(string.chars().skip(start).take(end - start).collect()))
+            },
+            _ => Err(EvalError(format!("Invalid slice expression, expected array or string, got: {self:?}")))
+        }
+    }
+
+    /// The total order behind `cmp`: bools before ints before strings, and within a type false < true, ints by
+    /// value and strings by code point. Any other type is incomparable.
+    pub fn total_cmp(&self, other: &Object) -> Result<Ordering, EvalError> {
+        fn rank(obj: &Object) -> Option<u8> {
+            match obj {
+                Object::Boolean(_) => Some(0),
+                Object::Integer(_) => Some(1),
+                Object::String(_) => Some(2),
+                _ => None,
+            }
+        }
+
+        match (self, other) {
+            (Object::Boolean(x), Object::Boolean(y)) => Ok(x.cmp(y)),
+            (Object::Integer(x), Object::Integer(y)) => Ok(x.cmp(y)),
+            (Object::String(x), Object::String(y)) => Ok(x.cmp(y)),
+            _ => match (rank(self), rank(other)) {
+                (Some(x), Some(y)) => Ok(x.cmp(&y)),
+                _ => Err(EvalError(format!("Cannot compare {} with {}, only ints, strings and bools are ordered", self.type_name(), other.type_name()))),
+            },
+        }
+    }
+
+    /// `<` and `>`, only ints and strings (compared by code point) have an ordering.
+    pub fn compare(&self, operator: &str, right: &Object) -> Result<Object, EvalError> {
+        let ordering = match (self, right) {
+            (Object::Integer(left_val), Object::Integer(right_val)) => left_val.cmp(right_val),
+            (Object::String(left_val), Object::String(right_val)) => left_val.cmp(right_val),
+            _ => return Err(EvalError(format!(
+                "Cannot compare {} {operator} {}, only two ints or two strings can be ordered", self.type_name(), right.type_name()
+            ))),
+        };
+
+        match operator {
+            "<" => Ok(Object::from(ordering.is_lt())),
+            ">" => Ok(Object::from(ordering.is_gt())),
+            _ => Err(EvalError(format!("Invalid comparison operator: {operator}"))),
+        }
+    }
+
+    /// Applies a binary operator, the single definition of infix semantics shared by the interpreter and the VM.
+    pub fn infix(&self, operator: &str, right: &Object) -> Result<Object, EvalError> {
+        let invalid = || EvalError(format!("Invalid operator in infix position: {self:?}{operator}{right:?}"));
+        if matches!(operator, "<" | ">") {
+            return self.compare(operator, right);
+        }
+
+        match (self.thawed(), right.thawed()) {
+            (Object::Integer(left_val), Object::Integer(right_val)) => {
+                let overflow = || EvalError(format!("integer overflow: {left_val} {operator} {right_val}"));
+                Ok(match operator {
+                    "+" => Object::Integer(left_val.checked_add(*right_val).ok_or_else(overflow)?),
+                    "-" => Object::Integer(left_val.checked_sub(*right_val).ok_or_else(overflow)?),
+                    "*" => Object::Integer(left_val.checked_mul(*right_val).ok_or_else(overflow)?),
+                    "/" if *right_val == 0 => return Err(EvalError("division by zero".to_string())),
+                    "/" => Object::Integer(left_val.checked_div(*right_val).ok_or_else(overflow)?),
+                    "**" => Object::Integer(power(*left_val, *right_val)?),
+                    "&" => Object::Integer(left_val & right_val),
+                    "|" => Object::Integer(left_val | right_val),
+                    "^" => Object::Integer(left_val ^ right_val),
+                    "<<" | ">>" => Object::Integer(shift(*left_val, operator, *right_val)?),
+                    ".." => range(*left_val, *right_val, 1)?,
+                    "==" => Object::from(left_val == right_val),
+                    "!=" => Object::from(left_val != right_val),
+                    _ => return Err(invalid()),
+                })
+            },
+            (Object::Boolean(left_val), Object::Boolean(right_val)) => {
+                Ok(match operator {
+                    "==" => Object::from(left_val == right_val),
+                    "!=" => Object::from(left_val != right_val),
+                    _ => return Err(invalid()),
+                })
+            },
+            // Collections are equal when their elements are, all the way down. They can't be ordered, `compare`
+            // rejects them
+            (Object::Array(_), Object::Array(_)) | (Object::Tuple(_), Object::Tuple(_)) | (Object::HashMap(_), Object::HashMap(_))
+                if matches!(operator, "==" | "!=") =>
+            {
+                Ok(Object::Boolean((self == right) == (operator == "==")))
+            },
+            // Anything can be checked against null
+            (Object::Null, _) | (_, Object::Null) if matches!(operator, "==" | "!=") => {
+                Ok(Object::Boolean((matches!(self, Object::Null) && matches!(right, Object::Null)) == (operator == "==")))
+            },
+            (Object::String(left_val), Object::String(right_val)) => {
+                Ok(match operator {
+                    "+" => Object::String(left_val.to_string() + right_val),
+                    "==" => Object::from(left_val == right_val),
+                    "!=" => Object::from(left_val != right_val),
+                    _ => return Err(invalid()),
+                })
+            },
+
+            _ => Err(EvalError(format!("Type mismatch {self:?} {operator} {right:?}")))
+        }
+    }
+}
+
+#[allow(unpredictable_function_pointer_comparisons)]
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Integer(x), Self::Integer(y)) => x == y,
+            (Self::Boolean(x), Self::Boolean(y)) => x == y,
+            (Self::String(x), Self::String(y)) => x == y,
+            (Self::Array(x), Self::Array(y)) => x == y,
+            (Self::Tuple(x), Self::Tuple(y)) => x == y,
+            (Self::KVPair(x_key, x_val), Self::KVPair(y_key, y_val)) => x_key == y_key && x_val == y_val,
+            (Self::HashMap(x), Self::HashMap(y)) => x == y,
+            (Self::Return(x), Self::Return(y)) => x == y,
+            // Functions are only equal to themselves: same definition closing over the same environment
+            (
+                Self::Function { parameters: x_params, defaults: x_defaults, rest: x_rest, body: x_body, fn_env: x_env, .. },
+                Self::Function { parameters: y_params, defaults: y_defaults, rest: y_rest, body: y_body, fn_env: y_env, .. },
+            ) => x_env.ptr_eq(y_env) && x_params == y_params && x_defaults == y_defaults && x_rest == y_rest && x_body == y_body,
+            (Self::Null, Self::Null) => true,
+            (Self::BuiltIn(x), Self::BuiltIn(y)) => x == y,
+            (Self::Bound { function: x_fn, args: x_args }, Self::Bound { function: y_fn, args: y_args }) => x_fn == y_fn && x_args == y_args,
+            (Self::Error(x), Self::Error(y)) => x == y,
+            // Freezing doesn't change what a value equals
+            (Self::Frozen(x), y) => x.as_ref() == y,
+            (x, Self::Frozen(y)) => x == y.as_ref(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Strings print raw at the top level but quoted inside collections, so `["a, b"]` stays unambiguous
+        fn nested(obj: &Object) -> String {
+            match obj {
+                Object::String(val) => format!("{val:?}"),
+                obj => obj.to_string(),
+            }
+        }
+
+        match self {
+            Self::Integer(val) => write!(f, "{val}"),
+            Self::Boolean(val) => write!(f, "{val}"),
+            Self::String(val) => write!(f, "{val}"),
+            Self::Array(vals) => write!(f, "[{}]", vals.iter().map(nested).collect::<Vec<String>>().join(", ")),
+            Self::Tuple(vals) => write!(f, "({})", vals.iter().map(nested).collect::<Vec<String>>().join(", ")),
+            Self::KVPair(key, val) => write!(f, "{}: {}", nested(key), nested(val)),
+            Self::HashMap(hash_map) => {
+                let entries = sorted_entries(hash_map).into_iter().map(|(key, val)| format!("{}: {}", nested(key), nested(val)));
+                write!(f, "{{{}}}", entries.collect::<Vec<String>>().join(", "))
+            },
+            Self::Return(val) => write!(f, "{val}"),
+            Self::Function { body, .. } => write!(f, "fn({}) {}", self.signature().unwrap_or_default(), body.dbg()),
+            Self::Null => write!(f, "null"),
+            Self::BuiltIn(_) => write!(f, "builtin function"),
+            Self::Bound { function, args } => {
+                write!(f, "bind({function}, {})", args.iter().map(nested).collect::<Vec<String>>().join(", "))
+            },
+            Self::Error(message) => write!(f, "error: {message}"),
+            Self::Frozen(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+impl Add for Object {
+    type Output = Result<Self, EvalError>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.infix("+", &rhs)
+    }
+}
+
+impl Sub for Object {
+    type Output = Result<Self, EvalError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.infix("-", &rhs)
+    }
+}
+
+impl Mul for Object {
+    type Output = Result<Self, EvalError>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.infix("*", &rhs)
+    }
+}
+
+impl Div for Object {
+    type Output = Result<Self, EvalError>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        self.infix("/", &rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infix() {
+        assert_eq!(Object::Integer(6).infix("/", &Object::Integer(4)).unwrap(), Object::Integer(1));
+        assert_eq!((Object::String("a".to_string()) + Object::String("b".to_string())).unwrap(), Object::String("ab".to_string()));
+        assert_eq!(Object::String("b".to_string()).infix(">", &Object::String("ab".to_string())).unwrap(), Object::Boolean(true));
+        assert_eq!(Object::Integer(-1).infix("<", &Object::Integer(0)).unwrap(), Object::Boolean(true));
+        let err = Object::Boolean(true).infix(">", &Object::Boolean(false)).unwrap_err();
+        assert_eq!(err.0, "Cannot compare bool > bool, only two ints or two strings can be ordered");
+        assert!(Object::Integer(1).infix("<", &Object::String("2".to_string())).is_err());
+        assert!(Object::Integer(1).infix("+", &Object::String("1".to_string())).is_err());
+        assert!(Object::String("a".to_string()).infix("-", &Object::String("b".to_string())).is_err());
+
+        let nested = Object::Array(vec![Object::Integer(1), Object::Array(vec![Object::Null])]);
+        assert_eq!(nested.infix("==", &nested.clone()).unwrap(), Object::Boolean(true));
+        assert_eq!(nested.infix("!=", &Object::Array(vec![Object::Integer(1)])).unwrap(), Object::Boolean(true));
+        assert_eq!(Object::Array(vec![Object::Integer(1)]).infix("==", &Object::Array(vec![Object::String("1".to_string())])).unwrap(), Object::Boolean(false));
+        assert_eq!(Object::Null.infix("==", &Object::Null).unwrap(), Object::Boolean(true));
+        assert!(nested.infix("==", &Object::Tuple(Vec::new())).is_err());
+        let err = nested.infix("<", &nested.clone()).unwrap_err();
+        assert_eq!(err.0, "Cannot compare array < array, only two ints or two strings can be ordered");
+
+        let err = Object::Integer(isize::MAX).infix("+", &Object::Integer(1)).unwrap_err();
+        assert_eq!(err.0, format!("integer overflow: {} + 1", isize::MAX));
+        assert!(Object::Integer(isize::MIN).infix("/", &Object::Integer(-1)).is_err());
+        assert!(Object::Integer(isize::MIN).prefix("-").is_err());
+        assert_eq!(Object::Integer(1).infix("/", &Object::Integer(0)).unwrap_err().0, "division by zero");
+
+        assert_eq!(Object::Integer(0b1100).infix("^", &Object::Integer(0b1010)).unwrap(), Object::Integer(0b0110));
+        assert_eq!(Object::Integer(-16).infix(">>", &Object::Integer(2)).unwrap(), Object::Integer(-4));
+        assert!(Object::Integer(1).infix("<<", &Object::Integer(64)).is_err());
+        assert_eq!(Object::Integer(-3).infix("**", &Object::Integer(3)).unwrap(), Object::Integer(-27));
+        assert_eq!(Object::Integer(5).infix("**", &Object::Integer(0)).unwrap(), Object::Integer(1));
+        assert_eq!(Object::Integer(2).infix("**", &Object::Integer(-1)).unwrap_err().0, "negative exponent: 2 ** -1");
+        assert_eq!(Object::Integer(2).infix("**", &Object::Integer(64)).unwrap_err().0, "integer overflow: 2 ** 64");
+        assert!(Object::Boolean(true).infix("&", &Object::Boolean(true)).is_err());
+
+        assert_eq!(Object::Integer(1).infix("..", &Object::Integer(4)).unwrap().to_string(), "[1, 2, 3]");
+        assert_eq!(Object::Integer(4).infix("..", &Object::Integer(1)).unwrap(), Object::Array(vec![]));
+        assert_eq!(range(10, -1, -5).unwrap().to_string(), "[10, 5, 0]");
+        assert_eq!(range(isize::MAX - 1, isize::MAX, 7).unwrap().to_string(), format!("[{}]", isize::MAX - 1));
+        assert!(range(isize::MIN, isize::MAX, 1).is_err());
+        assert!(range(0, 1, 0).is_err());
+
+        assert_eq!(Object::Null.infix("==", &Object::Null).unwrap(), Object::Boolean(true));
+        assert_eq!(Object::Integer(0).infix("!=", &Object::Null).unwrap(), Object::Boolean(true));
+        assert!(Object::Null.infix("<", &Object::Null).is_err());
+    }
+
+    #[test]
+    fn test_builtin_caller() {
+        let apply = BuiltinFn::with_caller(|args, caller| match args.as_slice() {
+            [f, x] => caller.call_function(f, vec![x.clone()]),
+            _ => Ok(Object::Null),
+        });
+        let args = vec![Object::String("inc".to_string()), Object::Integer(1)];
+        assert!(apply.call(args.clone()).is_err());
+        let caller = |_: &Object, args: Vec<Object>| args[0].clone() + Object::Integer(1);
+        assert_eq!(apply.call_with(args, &caller).unwrap(), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_total_cmp() {
+        let ordered = [
+            Object::Boolean(false),
+            Object::Boolean(true),
+            Object::Integer(-3),
+            Object::Integer(2),
+            Object::String("".to_string()),
+            Object::String("a".to_string()),
+        ];
+        for (i, x) in ordered.iter().enumerate() {
+            for (j, y) in ordered.iter().enumerate() {
+                assert_eq!(x.total_cmp(y).unwrap(), i.cmp(&j), "{x} vs {y}");
+            }
+        }
+        assert!(Object::Null.total_cmp(&Object::Integer(1)).is_err());
+        assert!(Object::Array(vec![]).total_cmp(&Object::Array(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let arr = Object::Array(vec![Object::Integer(1), Object::String("a, b".to_string()), Object::Null]);
+        assert_eq!(arr.to_string(), r#"[1, "a, b", null]"#);
+        assert_eq!(Object::String("raw".to_string()).to_string(), "raw");
+        assert_eq!(Object::Tuple(vec![Object::Integer(3), Object::String("r".to_string())]).to_string(), r#"(3, "r")"#);
+
+        let key = Object::String("k".to_string());
+        let hash_map = HashMap::from([(
+            HashKey::get_hash_key(&key).unwrap(),
+            Object::KVPair(Box::new(key), Box::new(Object::Boolean(true))),
+        )]);
+        assert_eq!(Object::HashMap(hash_map).to_string(), r#"{"k": true}"#);
+
+        let keys = ["b", "a", "c"].map(|key| Object::String(key.to_string())).into_iter().chain([Object::Integer(2), Object::Boolean(false)]);
+        let hash_map = keys
+            .map(|key| (HashKey::get_hash_key(&key).unwrap(), Object::KVPair(Box::new(key), Box::new(Object::Null))))
+            .collect::<HashMap<HashKey, Object>>();
+        assert_eq!(Object::HashMap(hash_map).to_string(), r#"{false: null, 2: null, "a": null, "b": null, "c": null}"#);
+    }
+
+    #[test]
+    fn test_builtin() {
+        let total = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&total);
+        let add = Object::builtin(move |args| {
+            for arg in &args {
+                if let Object::Integer(val) = arg {
+                    *counter.borrow_mut() += val;
+                }
+            }
+            Ok(Object::Integer(*counter.borrow()))
+        });
+
+        let Object::BuiltIn(f) = &add else { panic!("Expected a builtin, got: {add:?}") };
+        assert_eq!(f.call(vec![Object::Integer(2), Object::Integer(3)]).unwrap(), Object::Integer(5));
+        assert_eq!(f.call(vec![Object::Integer(1)]).unwrap(), Object::Integer(6));
+
+        assert_eq!(add.clone(), add);
+        assert_ne!(Object::builtin(|_| Ok(Object::Null)), add);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{cell::RefCell, io::{self, Write}, rc::Rc};
+
+/// Where program output goes, so embedders (e.g. a browser playground) can capture it instead of stdout.
+pub trait OutputSink {
+    fn write_str(&mut self, text: &str);
+}
+
+/// Writes straight to the process's stdout, the default for the CLI.
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn write_str(&mut self, text: &str) {
+        let mut stdout = io::stdout().lock();
+        // Output is best effort, same as `print!` minus the panic on a closed pipe
+        let _ = stdout.write_all(text.as_bytes()).and_then(|()| stdout.flush());
+    }
+}
+
+/// Collects output in memory. Clones share the same buffer, keep one to read what the other was given.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<String>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns everything written so far and empties the buffer.
+    pub fn take(&self) -> String {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl OutputSink for SharedBuffer {
+    fn write_str(&mut self, text: &str) {
+        self.0.borrow_mut().push_str(text);
+    }
+}
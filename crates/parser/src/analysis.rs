@@ -0,0 +1,522 @@
+use std::{collections::HashSet, convert::Infallible, fmt, rc::Rc};
+
+use crate::{ast::{walk_expression, walk_expression_mut, walk_statement, walk_statement_mut, Expression, Statement, Visitor, VisitorMut}, intern::{Interner, Symbol}, lexer::token::Span, Program};
+
+/// A variable that is neither bound in an enclosing scope nor known to the host, found before the program runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedVariable {
+    pub name: String,
+    pub span: Option<Span>,
+    pub suggestion: Option<String>, // the closest name in scope, if one is close enough to be a typo
+}
+
+impl fmt::Display for UndefinedVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown variable: {}", self.name)?;
+        if let Some(span) = self.span {
+            write!(f, " at {span}")?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{suggestion}`?")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports every variable of `program` that can't resolve, each name once. `known` are the globals the program
+/// runs with (builtins, host bindings, earlier REPL lines).
+///
+/// A name counts as bound anywhere in its scope, whatever the order: functions may refer to globals defined after
+/// them. Programs that `import` or use `with` are skipped, both bind names only known at runtime.
+pub fn find_undefined(program: &Program, known: &[String]) -> Vec<UndefinedVariable> {
+    let mut check = UndefinedCheck {
+        known,
+        scopes: vec![bound_names(&program.statements, &[])],
+        undefined: Vec::new(),
+        reported: HashSet::new(),
+        dynamic: false,
+    };
+    let Ok(()) = check.visit_program(program);
+    if check.dynamic {
+        return Vec::new();
+    }
+    check.undefined
+}
+
+/// A `return` that isn't inside any function, which would have nothing to return from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReturnOutsideFunction {
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for ReturnOutsideFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Return outside of a function")?;
+        if let Some(span) = self.span {
+            write!(f, " at {span}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports every `return` of `program` outside a function body, including those in top-level blocks.
+pub fn find_returns_outside_functions(program: &Program) -> Vec<ReturnOutsideFunction> {
+    struct Check(Vec<ReturnOutsideFunction>);
+
+    impl Visitor for Check {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            if let Statement::Return { .. } = statement {
+                self.0.push(ReturnOutsideFunction { span: statement.span() });
+            }
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            match expression {
+                Expression::Function { .. } => Ok(()),
+                _ => walk_expression(self, expression),
+            }
+        }
+    }
+
+    let mut check = Check(Vec::new());
+    let Ok(()) = check.visit_program(program);
+    check.0
+}
+
+/// A name bound again in the scope a `const` bound it in: a function body, or the top level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstRedeclaration {
+    pub name: String,
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for ConstRedeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot redeclare const {}", self.name)?;
+        if let Some(span) = self.span {
+            write!(f, " at {span}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports every `let`, `const` or fn declaration of `program` binding a name a `const` before it bound in the same
+/// scope. Nested functions and blocks have scopes of their own, where the consts around them can be shadowed.
+pub fn find_const_redeclarations(program: &Program) -> Vec<ConstRedeclaration> {
+    struct Check {
+        scopes: Vec<HashSet<String>>, // the consts bound so far in each scope, innermost last
+        redeclarations: Vec<ConstRedeclaration>,
+    }
+
+    impl Visitor for Check {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            if let Statement::Block { .. } = statement {
+                self.scopes.push(HashSet::new());
+                let result = walk_statement(self, statement);
+                self.scopes.pop();
+                return result;
+            }
+            if let Statement::Let { name, .. } = statement {
+                let names = match name {
+                    Expression::Tuple { elements, .. } => elements.as_slice(),
+                    name => std::slice::from_ref(name),
+                };
+                for name in names {
+                    let Expression::Identifier { value, token, .. } = name else { continue };
+                    let consts = self.scopes.last_mut().expect("the top level scope is never popped");
+                    if consts.contains(value) {
+                        self.redeclarations.push(ConstRedeclaration { name: value.clone(), span: token.span });
+                    } else if statement.is_const() {
+                        consts.insert(value.clone());
+                    }
+                }
+            }
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            match expression {
+                Expression::Function { .. } => {
+                    self.scopes.push(HashSet::new());
+                    let result = walk_expression(self, expression);
+                    self.scopes.pop();
+                    result
+                },
+                _ => walk_expression(self, expression),
+            }
+        }
+    }
+
+    let mut check = Check { scopes: vec![HashSet::new()], redeclarations: Vec::new() };
+    let Ok(()) = check.visit_program(program);
+    check.redeclarations
+}
+
+/// The names a function body (or the top level) binds: its params, then its `let`s in order. Nested blocks and
+/// functions have scopes of their own, so their `let`s aren't included.
+pub fn scope_bindings(statements: &[Statement], params: &[Expression]) -> Vec<(String, Option<Span>)> {
+    struct Collector(Vec<(String, Option<Span>)>);
+
+    impl Collector {
+        fn bind(&mut self, name: &Expression) {
+            match name {
+                Expression::Identifier { value, token, .. } | Expression::NamedArg { name: value, token, .. } => self.0.push((value.clone(), token.span)),
+                Expression::Prefix { right, .. } if name.is_rest_param() => self.bind(right),
+                _ => {},
+            }
+        }
+    }
+
+    impl Visitor for Collector {
+        type Error = Infallible;
+
+        fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+            match statement {
+                Statement::Let { name: Expression::Tuple { elements, .. }, .. } => elements.iter().for_each(|name| self.bind(name)),
+                Statement::Let { name, .. } => self.bind(name),
+                Statement::Block { .. } => return Ok(()),
+                _ => {},
+            }
+            walk_statement(self, statement)
+        }
+
+        fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+            match expression {
+                Expression::Function { .. } => Ok(()),
+                _ => walk_expression(self, expression),
+            }
+        }
+    }
+
+    let mut collector = Collector(Vec::new());
+    params.iter().for_each(|param| collector.bind(param));
+    for statement in statements {
+        let Ok(()) = collector.visit_statement(statement);
+    }
+    collector.0
+}
+
+/// The names a call of a fn with `params` and `body` binds in its own scope: the params in order, then each other
+/// name once in the order first bound. Resolved when the fn is parsed, so each call can keep them in slots rather
+/// than a map, and bind the `i`th param to slot `i`.
+pub fn fn_locals(params: &[Expression], body: &Statement, interner: &Interner) -> Rc<[Symbol]> {
+    let statements = match body {
+        Statement::Block { statements, .. } => statements.as_slice(),
+        body => std::slice::from_ref(body),
+    };
+    let mut locals: Vec<Symbol> = Vec::new();
+    for (i, (name, _)) in scope_bindings(statements, params).into_iter().enumerate() {
+        let name = interner.intern(&name);
+        if i < params.len() || !locals.contains(&name) {
+            locals.push(name);
+        }
+    }
+    locals.into()
+}
+
+/// A scope the interpreter makes while running, as far as finding locals goes: a fn call's, which keeps the fn's
+/// locals in slots, or an `if` branch's, holding the names its `let`s bind.
+enum Scope {
+    Fn(Rc<[Symbol]>),
+    Block(HashSet<String>),
+}
+
+struct Resolver {
+    scopes: Vec<(Scope, bool)>, // innermost last, with whether names only known at runtime are bound in it
+}
+
+impl Resolver {
+    fn resolve(&self, name: &Symbol) -> Option<(usize, usize)> {
+        for (depth, (scope, dynamic)) in self.scopes.iter().rev().enumerate() {
+            match scope {
+                Scope::Fn(locals) => if let Some(slot) = locals.iter().rposition(|local| local == name) {
+                    return Some((depth, slot));
+                },
+                Scope::Block(names) if names.contains(name.as_str()) => return None,
+                Scope::Block(_) => {},
+            }
+            if *dynamic {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn visit_scope(&mut self, scope: Scope, statements: &mut [Statement]) {
+        self.scopes.push((scope, false));
+        for statement in statements {
+            let Ok(()) = self.visit_statement_mut(statement);
+        }
+        self.scopes.pop();
+    }
+}
+
+impl VisitorMut for Resolver {
+    type Error = Infallible;
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) -> Result<(), Infallible> {
+        match statement {
+            Statement::Let { value, .. } => return self.visit_expression_mut(value),
+            // Both bind names in the scope they run in, an import whatever the module binds
+            Statement::Import { .. } | Statement::Block { .. } => if let Some((_, dynamic)) = self.scopes.last_mut() {
+                *dynamic = true;
+            },
+            _ => {},
+        }
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Identifier { symbol, local, .. } => *local = self.resolve(symbol),
+            Expression::Function { params, body, locals, .. } => {
+                // Defaults are evaluated in the scope of the call
+                self.scopes.push((Scope::Fn(Rc::clone(locals)), false));
+                for param in params.iter_mut() {
+                    if let Expression::NamedArg { value, .. } = param {
+                        self.visit_expression_mut(value)?;
+                    }
+                }
+                self.scopes.pop();
+                match Rc::make_mut(body) {
+                    Statement::Block { statements, .. } => self.visit_scope(Scope::Fn(Rc::clone(locals)), statements),
+                    body => self.visit_scope(Scope::Fn(Rc::clone(locals)), std::slice::from_mut(body)),
+                }
+            },
+            Expression::If { condition, consequence, alternative, .. } => {
+                self.visit_expression_mut(condition)?;
+                for branch in std::iter::once(consequence).chain(alternative) {
+                    match branch.as_mut() {
+                        Statement::Block { statements, .. } => {
+                            let names = scope_bindings(statements, &[]).into_iter().map(|(name, _)| name).collect();
+                            self.visit_scope(Scope::Block(names), statements);
+                        },
+                        branch => self.visit_statement_mut(branch)?,
+                    }
+                }
+            },
+            _ => return walk_expression_mut(self, expression),
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the identifiers naming a local of an enclosing fn to where the interpreter keeps it: how many scopes out
+/// from the one the identifier is evaluated in, and which of that scope's slots. Names a scope in between may bind,
+/// by a `let` in an `if` branch or an `import`, are left to be looked up by name, as are globals.
+pub fn resolve_locals(program: &mut Program) {
+    let Ok(()) = Resolver { scopes: Vec::new() }.visit_program_mut(program);
+}
+
+fn bound_names(statements: &[Statement], params: &[Expression]) -> HashSet<String> {
+    scope_bindings(statements, params).into_iter().map(|(name, _)| name).collect()
+}
+
+struct UndefinedCheck<'a> {
+    known: &'a [String],
+    scopes: Vec<HashSet<String>>, // innermost last
+    undefined: Vec<UndefinedVariable>,
+    reported: HashSet<String>,
+    dynamic: bool, // names may be bound at runtime, by an import or `with`
+}
+
+impl UndefinedCheck<'_> {
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name)) || self.known.iter().any(|known| known == name)
+    }
+
+    fn suggest(&self, name: &str) -> Option<String> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        self.scopes
+            .iter()
+            .flatten()
+            .chain(self.known)
+            .map(|candidate| (levenshtein(name, candidate), candidate))
+            // Replacing every char isn't a typo, e.g. `a` for `n`
+            .filter(|(distance, candidate)| *distance <= max_distance && *distance < name.len().max(candidate.len()))
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+}
+
+impl Visitor for UndefinedCheck<'_> {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        match statement {
+            // The names were collected with their scope
+            Statement::Let { value, .. } => self.visit_expression(value),
+            Statement::Import { .. } => {
+                self.dynamic = true;
+                Ok(())
+            },
+            Statement::Block { statements, .. } => {
+                self.scopes.push(bound_names(statements, &[]));
+                let result = statements.iter().try_for_each(|statement| self.visit_statement(statement));
+                self.scopes.pop();
+                result
+            },
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Identifier { value, token, .. } => {
+                self.dynamic |= value == "with";
+                if !self.is_bound(value) && self.reported.insert(value.clone()) {
+                    let suggestion = self.suggest(value);
+                    self.undefined.push(UndefinedVariable { name: value.clone(), span: token.span, suggestion });
+                }
+                Ok(())
+            },
+            Expression::Function { params, body, .. } => {
+                let statements = match body.as_ref() {
+                    Statement::Block { statements, .. } => statements.as_slice(),
+                    body => std::slice::from_ref(body),
+                };
+                self.scopes.push(bound_names(statements, params));
+                // Defaults are evaluated in the fn's scope
+                for default in params.iter().filter_map(Expression::param_default) {
+                    self.visit_expression(default)?;
+                }
+                let result = statements.iter().try_for_each(|statement| self.visit_statement(statement));
+                self.scopes.pop();
+                result
+            },
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+/// Edit distance counting single char insertions, deletions and substitutions.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, a_ch) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_ch) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_ch != *b_ch);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    fn undefined(src: &str, known: &[&str]) -> Vec<String> {
+        let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        let known = known.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        find_undefined(&program, &known).iter().map(UndefinedVariable::to_string).collect()
+    }
+
+    #[test]
+    fn test_find_undefined() {
+        assert_eq!(undefined("let xs = [1]; len(x) + len(xs) + y", &["len"]), [
+            "Unknown variable: x at line 1, column 19, did you mean `xs`?",
+            "Unknown variable: y at line 1, column 34",
+        ]);
+
+        // Later globals, params, block lets and tuple names are all bound, params and block lets don't leak out
+        let src = "
+            let f = fn(n) { if (n > 0) { let m = n; g(m) } else { m } };
+            let g = fn(k) { let (a, b) = (k, n); a + b };
+            f(1)
+        ";
+        assert_eq!(undefined(src, &[]), ["Unknown variable: m at line 2, column 67", "Unknown variable: n at line 3, column 46"]);
+
+        assert_eq!(undefined("missing; missing", &[]), ["Unknown variable: missing at line 1, column 1"]);
+        assert!(undefined(r#"let f = fn() { a }; with({"a": 1}, f)"#, &["with"]).is_empty());
+        assert!(undefined(r#"import "lib.mk"; helper()"#, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_returns_outside_functions() {
+        let find = |src: &str| {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            find_returns_outside_functions(&program).iter().map(ReturnOutsideFunction::to_string).collect::<Vec<String>>()
+        };
+        assert_eq!(find("return 5;\nif (true) { return 1; }"), [
+            "Return outside of a function at line 1, column 1",
+            "Return outside of a function at line 2, column 13",
+        ]);
+        assert!(find("let f = fn(x) { if (x) { return 1; } return fn() { return 2; }; };").is_empty());
+    }
+
+    #[test]
+    fn test_find_const_redeclarations() {
+        let find = |src: &str| {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            find_const_redeclarations(&program).iter().map(ConstRedeclaration::to_string).collect::<Vec<String>>()
+        };
+        assert_eq!(find("const max = 10;
+let max = 11;
+if (true) { const (max, min) = (1, 2); fn min() {} }"), [
+            "Cannot redeclare const max at line 2, column 5",
+            "Cannot redeclare const min at line 3, column 43",
+        ]);
+        // Lets can be rebound by a const, and fns and blocks have scopes of their own
+        assert!(find("let x = 1; const x = 2; let f = fn(x) { const x = 3; fn() { let x = 4; } }").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_locals() {
+        // Each identifier read, in order, with where it resolved to
+        fn resolved(src: &str) -> Vec<(String, Option<(usize, usize)>)> {
+            struct Reads(Vec<(String, Option<(usize, usize)>)>);
+            impl Visitor for Reads {
+                type Error = Infallible;
+                fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+                    match statement {
+                        Statement::Let { value, .. } => self.visit_expression(value),
+                        statement => walk_statement(self, statement),
+                    }
+                }
+                fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+                    match expression {
+                        Expression::Identifier { value, local, .. } => {
+                            self.0.push((value.clone(), *local));
+                            Ok(())
+                        },
+                        Expression::Function { body, .. } => self.visit_statement(body),
+                        expression => walk_expression(self, expression),
+                    }
+                }
+            }
+            let mut reads = Reads(Vec::new());
+            let Ok(()) = reads.visit_program(&Parser::new(Lexer::new(src.to_string())).parse_program().unwrap());
+            reads.0
+        }
+        let read = |name: &str, local| (name.to_string(), local);
+
+        assert_eq!(resolved("let g = 1; fn f(a, b) { let c = a; fn() { if (c) { b + g } } }"), [
+            read("a", Some((0, 0))),
+            read("c", Some((1, 2))),
+            read("b", Some((2, 1))),
+            read("g", None),
+        ]);
+        // The last of repeated params, and none for names an `if` branch or an import may bind
+        assert_eq!(resolved("fn(x, x) { x }"), [read("x", Some((0, 1)))]);
+        assert_eq!(resolved("fn(x) { if (true) { let x = 2; x } }"), [read("x", None)]);
+        assert_eq!(resolved(r#"fn(x) { fn() { import "m.mk"; x } }"#), [read("x", None)]);
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("same", "same"), 0);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::lexer::{token::{Span, TokenType}, Lexer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    Keyword,
+    Identifier,
+    Literal, // ints, strings, `true` and `false`
+    Operator,
+    Punctuation, // delimiters like `,` `;` `(` `{`
+    Comment,
+    Invalid,
+}
+
+impl TokenClass {
+    pub fn of(typ: TokenType) -> Option<Self> {
+        let class = match typ {
+            TokenType::Function | TokenType::Let | TokenType::Const | TokenType::If | TokenType::Else | TokenType::Return
+            | TokenType::Import => Self::Keyword,
+            TokenType::Identifier => Self::Identifier,
+            TokenType::Int | TokenType::String | TokenType::True | TokenType::False | TokenType::Null => Self::Literal,
+            TokenType::Assign | TokenType::Plus | TokenType::Dash | TokenType::FSlash | TokenType::Star | TokenType::StarStar | TokenType::LT
+            | TokenType::GT | TokenType::Exclam | TokenType::Pipe | TokenType::Ampersand | TokenType::Bar | TokenType::Caret
+            | TokenType::ShiftL | TokenType::ShiftR | TokenType::Eq | TokenType::NEq | TokenType::Coalesce | TokenType::And | TokenType::Or | TokenType::DotDot | TokenType::PlusAssign
+            | TokenType::DashAssign | TokenType::StarAssign | TokenType::FSlashAssign => Self::Operator,
+            TokenType::Comma | TokenType::Semicolon | TokenType::Colon | TokenType::LParen | TokenType::RParen
+            | TokenType::LBrace | TokenType::RBrace | TokenType::LBracket | TokenType::RBracket | TokenType::Ellipsis => Self::Punctuation,
+            TokenType::Comment => Self::Comment,
+            TokenType::Illegal => Self::Invalid,
+            TokenType::Eof => return None,
+        };
+        Some(class)
+    }
+}
+
+/// Classifies every token of `src` for syntax highlighting, in source order. A class runs from its span up to the
+/// next one, less the whitespace in between; tokens never end in whitespace.
+pub fn classify(src: &str) -> Vec<(Span, TokenClass)> {
+    let mut lexer = Lexer::new_borrowed(src).keep_comments();
+    let mut classes = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        match (token.span, TokenClass::of(token.typ)) {
+            (Some(span), Some(class)) => classes.push((span, class)),
+            _ => return classes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let src = "let add = fn(a) { a + 1 }; // sum\nadd(\"x\") @";
+        let classes = classify(src).into_iter().map(|(span, class)| (span.line, span.col, class)).collect::<Vec<_>>();
+        assert_eq!(classes, [
+            (1, 1, TokenClass::Keyword),
+            (1, 5, TokenClass::Identifier),
+            (1, 9, TokenClass::Operator),
+            (1, 11, TokenClass::Keyword),
+            (1, 13, TokenClass::Punctuation),
+            (1, 14, TokenClass::Identifier),
+            (1, 15, TokenClass::Punctuation),
+            (1, 17, TokenClass::Punctuation),
+            (1, 19, TokenClass::Identifier),
+            (1, 21, TokenClass::Operator),
+            (1, 23, TokenClass::Literal),
+            (1, 25, TokenClass::Punctuation),
+            (1, 26, TokenClass::Punctuation),
+            (1, 28, TokenClass::Comment),
+            (2, 1, TokenClass::Identifier),
+            (2, 4, TokenClass::Punctuation),
+            (2, 5, TokenClass::Literal),
+            (2, 8, TokenClass::Punctuation),
+            (2, 10, TokenClass::Invalid),
+        ]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{borrow::Borrow, cell::RefCell, collections::HashSet, fmt, hash::{Hash, Hasher}, rc::Rc};
+
+/// An identifier's name. The identifiers a parser reads share one allocation per name through its [`Interner`], so
+/// symbols of the same parse compare by pointer. Otherwise symbols compare and hash like their names, so maps keyed by
+/// them are looked up with a plain `&str`, without interning it.
+#[derive(Debug, Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A symbol of its own, sharing its name with no other, for names that don't come from a parse.
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self(Rc::from(name))
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The names parsed so far, for the symbols of later parses to share. Clones share the names, an engine hands one to
+/// every parse it makes, and they're freed along with the last clone and symbol.
+#[derive(Debug, Clone, Default)]
+pub struct Interner(Rc<RefCell<HashSet<Rc<str>>>>);
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&self, name: &str) -> Symbol {
+        let mut names = self.0.borrow_mut();
+        match names.get(name) {
+            Some(name) => Symbol(Rc::clone(name)),
+            None => {
+                let name: Rc<str> = Rc::from(name);
+                names.insert(Rc::clone(&name));
+                Symbol(name)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let interner = Interner::new();
+        let x = interner.intern("x");
+        assert!(Rc::ptr_eq(&interner.intern("x").0, &x.0));
+        assert!(Rc::ptr_eq(&interner.clone().intern("x").0, &x.0));
+        assert_ne!(interner.intern("y"), x);
+        assert_eq!(x.as_str(), "x");
+        assert_eq!(interner.intern("ü_").to_string(), "ü_");
+
+        // Symbols of other interners, or of none, are still equal by name
+        assert_eq!(Interner::new().intern("x"), x);
+        assert_eq!(Symbol::from("x"), x);
+        let vars = std::collections::HashMap::from([(x, 1)]);
+        assert_eq!(vars.get("x"), Some(&1));
+    }
+}
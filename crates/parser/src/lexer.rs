@@ -1,5 +1,7 @@
-use token::Token;
-use helper::{is_digit, is_letter, is_str_char};
+use std::{borrow::Cow, io::{self, BufRead}};
+
+use token::{Span, Token};
+use helper::{is_digit, is_letter, is_radix_digit, is_str_char};
 
 pub mod token;
 mod helper;
@@ -8,34 +10,117 @@ mod helper;
 #[derive(Debug)]
 pub struct LexerError;
 
-pub struct Lexer {
-    src: String,
-    chars: Vec<char>,
-    position: usize,
+/// Where the lexer pulls chars from, either a string it walks in place or a reader decoded a char at a time.
+enum Source<'a> {
+    Str { src: Cow<'a, str>, offset: usize },
+    Reader(Box<dyn BufRead + 'a>),
+}
+
+impl Source<'_> {
+    fn next_char(&mut self) -> io::Result<Option<char>> {
+        match self {
+            Self::Str { src, offset } => {
+                let c = src[*offset..].chars().next();
+                *offset += c.map_or(0, char::len_utf8);
+                Ok(c)
+            },
+            Self::Reader(reader) => read_utf8_char(reader.as_mut()),
+        }
+    }
+}
+
+fn read_utf8_char(reader: &mut dyn BufRead) -> io::Result<Option<char>> {
+    let mut bytes = [0u8; 4];
+    match reader.fill_buf()?.first() {
+        Some(byte) => bytes[0] = *byte,
+        None => return Ok(None),
+    }
+    reader.consume(1);
+
+    let width = match bytes[0] {
+        0x00..=0x7F => return Ok(Some(bytes[0] as char)),
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    reader.read_exact(&mut bytes[1..width])?;
+    std::str::from_utf8(&bytes[..width])
+        .map(|c| c.chars().next())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Streams tokens out of its source holding only the current and next char, so the source is never copied.
+pub struct Lexer<'a> {
+    source: Source<'a>,
+    src_len: Option<usize>,
+    bytes_read: usize,
+    error: Option<io::Error>,
     ch: char,
+    peek: char,
+    line: u32, // position of `ch`
+    col: u32,
+    keep_comments: bool,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     pub fn new(src: String) -> Self {
-        let chars: Vec<char> = src.chars().collect();
-        let first_char = if chars.len() > 0 {
-            chars[0]
-        }else {
-            '\0'
-        };
+        Self::from_source(Some(src.len()), Source::Str { src: Cow::Owned(src), offset: 0 })
+    }
 
-        Self {
-            src,
-            chars,
-            position: 0,
-            ch: first_char,
-        }
+    /// Lexes `src` in place without taking ownership.
+    pub fn new_borrowed(src: &'a str) -> Self {
+        Self::from_source(Some(src.len()), Source::Str { src: Cow::Borrowed(src), offset: 0 })
     }
 
-    pub fn next_token(&mut self) -> Token {
+    /// Lexes UTF-8 from `reader` as tokens are requested. A read error ends the input and is kept for [`Lexer::take_error`].
+    pub fn from_reader(reader: impl BufRead + 'a) -> Self {
+        Self::from_source(None, Source::Reader(Box::new(reader)))
+    }
 
-        self.eat_whitespace();
+    fn from_source(src_len: Option<usize>, source: Source<'a>) -> Self {
+        let mut lexer = Self { source, src_len, bytes_read: 0, error: None, ch: '\0', peek: '\0', line: 1, col: 0, keep_comments: false };
+        lexer.read_char();
+        lexer.read_char();
+        lexer.col = 1;
+        lexer
+    }
 
+    /// Length of the source in bytes when known up front, `None` for readers.
+    pub fn src_len(&self) -> Option<usize> {
+        self.src_len
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Returns `//` comments as `Comment` tokens rather than skipping them like whitespace, for tools like highlighters.
+    pub fn keep_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    pub fn next_token(&mut self) -> Token {
+        loop {
+            self.eat_whitespace();
+            let span = Span { line: self.line, col: self.col };
+            if self.ch == '/' && self.peek_char() == '/' {
+                let comment = Token::new_comment(&self.read_comment());
+                if self.keep_comments {
+                    return comment.with_span(span);
+                }
+                continue;
+            }
+            return self.read_token().with_span(span);
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         let c = self.ch;
 
         let token = match c {
@@ -47,6 +132,26 @@ impl Lexer {
                     Token::new_assign()
                 }
             },
+            '+' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new_plus_assign()
+            },
+            '-' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new_dash_assign()
+            },
+            '*' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new_star_assign()
+            },
+            '*' if self.peek_char() == '*' => {
+                self.read_char();
+                Token::new_star_star()
+            },
+            '/' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new_f_slash_assign()
+            },
             '+' => Token::new_plus(),
             ',' => Token::new_comma(),
             ';' => Token::new_semicolon(),
@@ -60,8 +165,44 @@ impl Lexer {
             '-' => Token::new_dash(),
             '/' => Token::new_f_slash(),
             '*' => Token::new_star(),
+            '<' if self.peek_char() == '<' => {
+                self.read_char();
+                Token::new_shift_l()
+            },
+            '>' if self.peek_char() == '>' => {
+                self.read_char();
+                Token::new_shift_r()
+            },
             '<' => Token::new_l_t(),
             '>' => Token::new_g_t(),
+            '|' if self.peek_char() == '>' => {
+                self.read_char();
+                Token::new_pipe()
+            },
+            '|' if self.peek_char() == '|' => {
+                self.read_char();
+                Token::new_or()
+            },
+            '|' => Token::new_bar(),
+            '?' if self.peek_char() == '?' => {
+                self.read_char();
+                Token::new_coalesce()
+            },
+            '.' if self.peek_char() == '.' => {
+                self.read_char();
+                if self.peek_char() == '.' {
+                    self.read_char();
+                    Token::new_ellipsis()
+                } else {
+                    Token::new_dot_dot()
+                }
+            },
+            '&' if self.peek_char() == '&' => {
+                self.read_char();
+                Token::new_and()
+            },
+            '&' => Token::new_ampersand(),
+            '^' => Token::new_caret(),
             '!' => {
                 if self.peek_char() == '=' {
                     self.read_char();
@@ -79,13 +220,16 @@ impl Lexer {
             c if is_letter(c) => {
                 return match self.read_identifier().as_str() {
                     "let" => Token::new_let(),
+                    "const" => Token::new_const(),
                     "fn" => Token::new_function(),
                     "if" => Token::new_if(),
                     "else" => Token::new_else(),
                     "true" => Token::new_true(),
                     "false" => Token::new_false(),
+                    "null" => Token::new_null(),
                     "return" => Token::new_return(),
-                    i @ _ => Token::new_identifier(i)
+                    "import" => Token::new_import(),
+                    i => Token::new_identifier(i)
                 }
             },
 
@@ -95,7 +239,7 @@ impl Lexer {
 
             '\0' => Token::new_eof(),
             
-            _ => Token::new_illegal(),
+            c => Token::new_illegal(c),
         };
 
         self.read_char();
@@ -104,40 +248,76 @@ impl Lexer {
     }
 
     fn read_char(&mut self) {
-        self.ch = self.peek_char();
-        self.position += 1;
+        if self.ch == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        self.ch = self.peek;
+        self.peek = match self.source.next_char() {
+            Ok(Some(c)) => {
+                self.bytes_read += c.len_utf8();
+                c
+            },
+            Ok(None) => '\0',
+            Err(err) => {
+                self.error = Some(err);
+                self.source = Source::Str { src: Cow::Borrowed(""), offset: 0 };
+                '\0'
+            },
+        };
     }
 
     fn peek_char(&self) -> char {
-        let new_pos = self.position + 1;
-        if new_pos >= self.chars.len() {
-            '\0'
-        }else {
-            self.chars[new_pos]
-        }
+        self.peek
     }
 
     fn read_match(&mut self, matcher: fn(char) -> bool) -> String {
-        let start = self.position;
+        let mut matched = String::new();
 
         loop {
+            if self.ch != '\0' {
+                matched.push(self.ch);
+            }
             self.read_char();
             if !matcher(self.ch) { break; }
         }
 
-        self.src[start..self.position].to_string()
+        matched
     }
 
+    /// Identifiers start with a letter or `_`, digits can follow: `x2`, `_1`.
     fn read_identifier(&mut self) -> String {
-        self.read_match(is_letter)
+        self.read_match(|c| is_letter(c) || is_digit(c))
     }
 
+    /// Reads a decimal, `0x` hex or `0b` binary int, the parser checks the digits fit the base.
     fn read_int(&mut self) -> String {
+        if self.ch == '0' && matches!(self.peek_char(), 'x' | 'b') {
+            return self.read_match(is_radix_digit);
+        }
         self.read_match(is_digit)
     }
 
+    /// Reads up to the closing quote, unlike `read_match` not taking the first char for granted so `""` is empty.
     fn read_string(&mut self) -> String {
-        self.read_match(is_str_char)
+        let mut string = String::new();
+        while is_str_char(self.ch) {
+            string.push(self.ch);
+            self.read_char();
+        }
+        string
+    }
+
+    /// Reads a comment up to the end of its line, leaving the newline.
+    fn read_comment(&mut self) -> String {
+        let mut comment = String::new();
+        while self.ch != '\n' && self.ch != '\0' {
+            comment.push(self.ch);
+            self.read_char();
+        }
+        comment
     }
 
     fn eat_whitespace(&mut self) {
@@ -205,8 +385,10 @@ mod tests {
 
             10 == 10;
             10 != 9;
+            x |> f | y & 0x1F ^ 0b10 << 1 >> 2 ...rest .. @
             "foobar"
             "foo bar";
+            "";
         "#.to_string();
 
         let expected = vec![
@@ -285,6 +467,28 @@ mod tests {
             Token::new_n_eq(),
             Token::new_int("9"),
             Token::new_semicolon(),
+            Token::new_identifier("x"),
+            Token::new_pipe(),
+            Token::new_identifier("f"),
+            Token::new_bar(),
+            Token::new_identifier("y"),
+            Token::new_ampersand(),
+            Token::new_int("0x1F"),
+            Token::new_caret(),
+            Token::new_int("0b10"),
+            Token::new_shift_l(),
+            Token::new_int("1"),
+            Token::new_shift_r(),
+            Token::new_int("2"),
+            Token::new_ellipsis(),
+            Token::new_identifier("rest"),
+            Token::new_dot_dot(),
+            Token::new_illegal('@'),
+            Token::new_string("foobar"),
+            Token::new_string("foo bar"),
+            Token::new_semicolon(),
+            Token::new_string(""),
+            Token::new_semicolon(),
             Token::new_eof(),
         ];
 
@@ -300,4 +504,83 @@ mod tests {
         }
 
     }
+
+    #[test]
+    fn test_sources_agree() {
+        let src = r#"let s = "héllo → wörld"; s[1:3] == "él";"#;
+        let tokens = |mut lexer: Lexer| {
+            let mut tokens = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                tokens.push(token.clone());
+                if token.typ == TokenType::Eof { break tokens; }
+            }
+        };
+
+        let expected = tokens(Lexer::new(src.to_string()));
+        assert!(expected.contains(&Token::new_string("héllo → wörld")));
+        assert_eq!(tokens(Lexer::new_borrowed(src)), expected);
+        // A one byte buffer splits every multi-byte char across refills
+        assert_eq!(tokens(Lexer::from_reader(io::BufReader::with_capacity(1, src.as_bytes()))), expected);
+    }
+
+    #[test]
+    fn test_multi_byte_chars() {
+        // Chars of 2 to 4 bytes, in a string and where an identifier would be
+        let src = "let s = \"ü€😀\"; ü s";
+        let mut lexer = Lexer::new_borrowed(src);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token.typ == TokenType::Eof { break; }
+            tokens.push((token.literal, token.span.map(|span| span.col)));
+        }
+        assert_eq!(tokens, [
+            ("let".to_string(), Some(1)),
+            ("s".to_string(), Some(5)),
+            ("=".to_string(), Some(7)),
+            ("ü€😀".to_string(), Some(9)),
+            (";".to_string(), Some(14)),
+            ("ü".to_string(), Some(16)),
+            ("s".to_string(), Some(18)),
+        ]);
+        assert_eq!(lexer.bytes_read(), src.len());
+        assert_eq!(lexer.src_len(), Some(src.len()));
+    }
+
+    #[test]
+    fn test_reader_invalid_utf8() {
+        let mut lexer = Lexer::from_reader(&b"let x = \"\xFF\";"[..]);
+        while lexer.next_token().typ != TokenType::Eof {}
+        assert_eq!(lexer.take_error().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_spans() {
+        let mut lexer = Lexer::new("let x = 5;\n  \"é\" != x".to_string());
+        let expected = [(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 7), (2, 10), (2, 11)];
+        for (line, col) in expected {
+            let token = lexer.next_token();
+            assert_eq!(token.span, Some(Span { line, col }), "{token:?}");
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = "// leading\nx / 2 // trailing\n";
+        let types = |mut lexer: Lexer| std::iter::from_fn(move || Some(lexer.next_token().typ).filter(|typ| *typ != TokenType::Eof)).collect::<Vec<_>>();
+        assert_eq!(types(Lexer::new_borrowed(src)), [TokenType::Identifier, TokenType::FSlash, TokenType::Int]);
+
+        let mut lexer = Lexer::new_borrowed(src).keep_comments();
+        assert_eq!(lexer.next_token(), Token::new_comment("// leading"));
+        assert_eq!(types(lexer), [TokenType::Identifier, TokenType::FSlash, TokenType::Int, TokenType::Comment]);
+    }
+
+    #[test]
+    fn test_identifiers_with_digits() {
+        let mut lexer = Lexer::new_borrowed("_1 x2y 3z");
+        for expected in [Token::new_identifier("_1"), Token::new_identifier("x2y"), Token::new_int("3"), Token::new_identifier("z")] {
+            assert_eq!(lexer.next_token(), expected);
+        }
+    }
 }
@@ -3,7 +3,11 @@ pub fn is_letter(c: char) -> bool {
 }
 
 pub fn is_digit(c: char) -> bool {
-    matches!(c, '0'..='9')
+    c.is_ascii_digit()
+}
+
+pub fn is_radix_digit(c: char) -> bool {
+    c.is_ascii_alphanumeric()
 }
 
 pub fn is_str_char(c: char) -> bool {
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
 pub enum TokenType {
     Illegal,
@@ -9,6 +11,11 @@ pub enum TokenType {
     // operators
     Assign,
     Plus,
+    // compound assignment, only lexed to reject it clearly
+    PlusAssign,
+    DashAssign,
+    StarAssign,
+    FSlashAssign,
     // delimiters
     Comma,
     Semicolon,
@@ -22,128 +29,234 @@ pub enum TokenType {
     Dash,
     FSlash,
     Star,
+    StarStar,
     LT,
     GT,
     Exclam,
+    Pipe,
+    Coalesce,
+    And,
+    Or,
+    DotDot,
+    Ellipsis,
+    // bitwise
+    Ampersand,
+    Bar,
+    Caret,
+    ShiftL,
+    ShiftR,
     //compare
     Eq,
     NEq,
     // keywords
     Function,
     Let,
+    Const,
     True,
     False,
+    Null,
     If,
     Else,
     Return,
+    Import,
+    // only returned by lexers that keep comments
+    Comment,
+}
+
+/// Where a token starts in the source, both 1-based with the column counted in chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.col)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Eq, Clone)]
 pub struct Token {
     pub typ: TokenType,
     pub literal: String,
+    pub span: Option<Span>, // set for tokens read by the lexer, `None` for constructed ones
+}
+
+// Where a token came from doesn't change what it is
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.typ == other.typ && self.literal == other.literal
+    }
 }
 
 impl Token {
-    pub fn new_illegal() -> Self {
-        Self { typ: TokenType::Illegal, literal: "illegal".to_string() }
+    pub fn with_span(self, span: Span) -> Self {
+        Self { span: Some(span), ..self }
+    }
+
+
+    /// A char no token starts with, kept as the literal so errors can show it.
+    pub fn new_illegal(c: char) -> Self {
+        Self { typ: TokenType::Illegal, literal: c.to_string(), span: None }
     }
     pub fn new_eof() -> Self {
-        Self { typ: TokenType::Eof, literal: "".to_string() }
+        Self { typ: TokenType::Eof, literal: "".to_string(), span: None }
+    }
+    pub fn new_comment(text: &str) -> Self {
+        Self { typ: TokenType::Comment, literal: text.to_string(), span: None }
     }
     // identifiers + literals
     pub fn new_identifier(identifier: &str) -> Self {
-        Self { typ: TokenType::Identifier, literal: identifier.to_string() }
+        Self { typ: TokenType::Identifier, literal: identifier.to_string(), span: None }
     }
     pub fn new_int(value: &str) -> Self {
-        Self { typ: TokenType::Int, literal: value.to_string() }
+        Self { typ: TokenType::Int, literal: value.to_string(), span: None }
     }
     pub fn new_int_i(value: isize) -> Self {
         Self::new_int(&value.to_string())
     }
     pub fn new_string(value: &str) -> Self {
-        Self { typ: TokenType::String, literal: value.to_string() }
+        Self { typ: TokenType::String, literal: value.to_string(), span: None }
     }
     // operators
     pub fn new_assign() -> Self {
-        Self { typ: TokenType::Assign, literal: "=".to_string() }
+        Self { typ: TokenType::Assign, literal: "=".to_string(), span: None }
     }
     pub fn new_plus() -> Self {
-        Self { typ: TokenType::Plus, literal: "+".to_string() }
+        Self { typ: TokenType::Plus, literal: "+".to_string(), span: None }
+    }
+    pub fn new_plus_assign() -> Self {
+        Self { typ: TokenType::PlusAssign, literal: "+=".to_string(), span: None }
+    }
+    pub fn new_dash_assign() -> Self {
+        Self { typ: TokenType::DashAssign, literal: "-=".to_string(), span: None }
+    }
+    pub fn new_star_assign() -> Self {
+        Self { typ: TokenType::StarAssign, literal: "*=".to_string(), span: None }
+    }
+    pub fn new_f_slash_assign() -> Self {
+        Self { typ: TokenType::FSlashAssign, literal: "/=".to_string(), span: None }
     }
     // delimiters
     pub fn new_comma() -> Self {
-        Self { typ: TokenType::Comma, literal: ",".to_string() }
+        Self { typ: TokenType::Comma, literal: ",".to_string(), span: None }
     }
     pub fn new_semicolon() -> Self {
-        Self { typ: TokenType::Semicolon, literal: ";".to_string() }
+        Self { typ: TokenType::Semicolon, literal: ";".to_string(), span: None }
     }
     pub fn new_colon() -> Self {
-        Self { typ: TokenType::Colon, literal: ":".to_string() }
+        Self { typ: TokenType::Colon, literal: ":".to_string(), span: None }
     }
     pub fn new_l_paren() -> Self {
-        Self { typ: TokenType::LParen, literal: "(".to_string() }
+        Self { typ: TokenType::LParen, literal: "(".to_string(), span: None }
     }
     pub fn new_r_paren() -> Self {
-        Self { typ: TokenType::RParen, literal: ")".to_string() }
+        Self { typ: TokenType::RParen, literal: ")".to_string(), span: None }
     }
     pub fn new_l_brace() -> Self {
-        Self { typ: TokenType::LBrace, literal: "{".to_string() }
+        Self { typ: TokenType::LBrace, literal: "{".to_string(), span: None }
     }
     pub fn new_r_brace() -> Self {
-        Self { typ: TokenType::RBrace, literal: "}".to_string() }
+        Self { typ: TokenType::RBrace, literal: "}".to_string(), span: None }
     }
     pub fn new_l_bracket() -> Self {
-        Self { typ: TokenType::LBracket, literal: "[".to_string() }
+        Self { typ: TokenType::LBracket, literal: "[".to_string(), span: None }
     }
     pub fn new_r_bracket() -> Self {
-        Self { typ: TokenType::RBracket, literal: "]".to_string() }
+        Self { typ: TokenType::RBracket, literal: "]".to_string(), span: None }
     }
     pub fn new_dash() -> Self {
-        Self { typ: TokenType::Dash, literal: "-".to_string() }
+        Self { typ: TokenType::Dash, literal: "-".to_string(), span: None }
     }
     pub fn new_f_slash() -> Self {
-        Self { typ: TokenType::FSlash, literal: "/".to_string() }
+        Self { typ: TokenType::FSlash, literal: "/".to_string(), span: None }
     }
     pub fn new_star() -> Self {
-        Self { typ: TokenType::Star, literal: "*".to_string() }
+        Self { typ: TokenType::Star, literal: "*".to_string(), span: None }
+    }
+    pub fn new_star_star() -> Self {
+        Self { typ: TokenType::StarStar, literal: "**".to_string(), span: None }
     }
     pub fn new_g_t() -> Self {
-        Self { typ: TokenType::GT, literal: ">".to_string() }
+        Self { typ: TokenType::GT, literal: ">".to_string(), span: None }
     }
     pub fn new_l_t() -> Self {
-        Self { typ: TokenType::LT, literal: "<".to_string() }
+        Self { typ: TokenType::LT, literal: "<".to_string(), span: None }
     }
     pub fn new_exclam() -> Self {
-        Self { typ: TokenType::Exclam, literal: "!".to_string() }
+        Self { typ: TokenType::Exclam, literal: "!".to_string(), span: None }
+    }
+    pub fn new_pipe() -> Self {
+        Self { typ: TokenType::Pipe, literal: "|>".to_string(), span: None }
+    }
+    pub fn new_coalesce() -> Self {
+        Self { typ: TokenType::Coalesce, literal: "??".to_string(), span: None }
+    }
+    pub fn new_and() -> Self {
+        Self { typ: TokenType::And, literal: "&&".to_string(), span: None }
+    }
+    pub fn new_or() -> Self {
+        Self { typ: TokenType::Or, literal: "||".to_string(), span: None }
+    }
+    pub fn new_dot_dot() -> Self {
+        Self { typ: TokenType::DotDot, literal: "..".to_string(), span: None }
+    }
+    pub fn new_ellipsis() -> Self {
+        Self { typ: TokenType::Ellipsis, literal: "...".to_string(), span: None }
+    }
+    // bitwise
+    pub fn new_ampersand() -> Self {
+        Self { typ: TokenType::Ampersand, literal: "&".to_string(), span: None }
+    }
+    pub fn new_bar() -> Self {
+        Self { typ: TokenType::Bar, literal: "|".to_string(), span: None }
+    }
+    pub fn new_caret() -> Self {
+        Self { typ: TokenType::Caret, literal: "^".to_string(), span: None }
+    }
+    pub fn new_shift_l() -> Self {
+        Self { typ: TokenType::ShiftL, literal: "<<".to_string(), span: None }
+    }
+    pub fn new_shift_r() -> Self {
+        Self { typ: TokenType::ShiftR, literal: ">>".to_string(), span: None }
     }
     //compare
     pub fn new_eq() -> Self {
-        Self { typ: TokenType::Eq, literal: "==".to_string() }
+        Self { typ: TokenType::Eq, literal: "==".to_string(), span: None }
     }
     pub fn new_n_eq() -> Self {
-        Self { typ: TokenType::NEq, literal: "!=".to_string() }
+        Self { typ: TokenType::NEq, literal: "!=".to_string(), span: None }
     }
     // keywords
     pub fn new_function() -> Self {
-        Self { typ: TokenType::Function, literal: "fn".to_string() }
+        Self { typ: TokenType::Function, literal: "fn".to_string(), span: None }
+    }
+    pub fn new_const() -> Self {
+        Self { typ: TokenType::Const, literal: "const".to_string(), span: None }
     }
     pub fn new_let() -> Self {
-        Self { typ: TokenType::Let, literal: "let".to_string() }
+        Self { typ: TokenType::Let, literal: "let".to_string(), span: None }
+    }
+    pub fn new_null() -> Self {
+        Self { typ: TokenType::Null, literal: "null".to_string(), span: None }
     }
     pub fn new_true() -> Self {
-        Self { typ: TokenType::True, literal: "true".to_string() }
+        Self { typ: TokenType::True, literal: "true".to_string(), span: None }
     }
     pub fn new_false() -> Self {
-        Self { typ: TokenType::False, literal: "false".to_string() }
+        Self { typ: TokenType::False, literal: "false".to_string(), span: None }
     }
     pub fn new_if() -> Self {
-        Self { typ: TokenType::If, literal: "if".to_string() }
+        Self { typ: TokenType::If, literal: "if".to_string(), span: None }
     }
     pub fn new_else() -> Self {
-        Self { typ: TokenType::Else, literal: "else".to_string() }
+        Self { typ: TokenType::Else, literal: "else".to_string(), span: None }
     }
     pub fn new_return() -> Self {
-        Self { typ: TokenType::Return, literal: "return".to_string() }
+        Self { typ: TokenType::Return, literal: "return".to_string(), span: None }
+    }
+    pub fn new_import() -> Self {
+        Self { typ: TokenType::Import, literal: "import".to_string(), span: None }
     }
 }
\ No newline at end of file
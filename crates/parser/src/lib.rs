@@ -1,4 +1,8 @@
+pub mod analysis;
+pub mod highlight;
+pub mod intern;
 pub mod lexer;
+pub mod optimize;
 pub mod parser;
 
 pub use parser::*;
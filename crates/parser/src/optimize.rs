@@ -0,0 +1,279 @@
+use std::{collections::HashMap, convert::Infallible, mem, rc::Rc};
+
+use crate::{analysis, ast::{walk_expression, walk_expression_mut, walk_statement, walk_statement_mut, Expression, Statement, Visitor, VisitorMut}, lexer::token::Span, Program};
+
+/// Simplifies `program` before it's compiled or interpreted, without changing what it evaluates to:
+/// - folds arithmetic, comparisons and logical operators on int and bool literals
+/// - propagates `let` bindings of literals whose name is bound nowhere else in the program
+/// - replaces `if`s on a constant condition by the branch taken, dropping the other one, and `??`s on a literal by
+///   the side taken
+///
+/// Only operations both backends agree on are folded, anything that would fail at runtime (overflow, dividing by zero,
+/// type mismatches) is left to fail there. Globals are only propagated into top-level code, since fns may be called
+/// after a later run rebinds them, and nothing is propagated in programs that `import` or use `with`.
+pub fn optimize(program: &mut Program) {
+    let mut bindings = BindingCount::default();
+    let Ok(()) = bindings.visit_program(program);
+    let mut optimizer = Optimizer { bindings: bindings.counts, propagate: !bindings.dynamic, constants: vec![(HashMap::new(), false)] };
+    let Ok(()) = optimizer.visit_program_mut(program);
+    // Splicing in blocks drops their scopes, so locals are that many scopes closer
+    analysis::resolve_locals(program);
+}
+
+/// How many times each name is bound by a `let` or a fn param, anywhere in the program.
+#[derive(Default)]
+struct BindingCount {
+    counts: HashMap<String, usize>,
+    dynamic: bool, // names may be bound at runtime, by an import or `with`
+}
+
+impl BindingCount {
+    fn bind(&mut self, name: &Expression) {
+        match name {
+            Expression::Identifier { value, .. } | Expression::NamedArg { name: value, .. } => *self.counts.entry(value.clone()).or_default() += 1,
+            Expression::Tuple { elements, .. } => elements.iter().for_each(|name| self.bind(name)),
+            Expression::Prefix { right, .. } if name.is_rest_param() => self.bind(right),
+            _ => {},
+        }
+    }
+}
+
+impl Visitor for BindingCount {
+    type Error = Infallible;
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Infallible> {
+        match statement {
+            Statement::Let { name, value, .. } => {
+                self.bind(name);
+                self.visit_expression(value)
+            },
+            Statement::Import { .. } => {
+                self.dynamic = true;
+                Ok(())
+            },
+            _ => walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Identifier { value, .. } => self.dynamic |= value == "with",
+            Expression::Function { params, .. } => params.iter().for_each(|param| self.bind(param)),
+            _ => {},
+        }
+        walk_expression(self, expression)
+    }
+}
+
+struct Optimizer {
+    bindings: HashMap<String, usize>,
+    propagate: bool,
+    constants: Vec<(HashMap<String, Expression>, bool)>, // literal bindings of the top level then each fn or block entered, and whether it's in a fn
+}
+
+impl Optimizer {
+    fn constant(&self, name: &str) -> Option<&Expression> {
+        let in_fn = self.constants.last().is_some_and(|(_, in_fn)| *in_fn);
+        self.constants.iter().rev().filter(|(_, scope_in_fn)| *scope_in_fn || !in_fn).find_map(|(constants, _)| constants.get(name))
+    }
+
+    fn scoped(&mut self, in_fn: bool, visit: impl FnOnce(&mut Self) -> Result<(), Infallible>) -> Result<(), Infallible> {
+        self.constants.push((HashMap::new(), in_fn));
+        let result = visit(self);
+        self.constants.pop();
+        result
+    }
+
+    /// Splices in the statements of `if`s on a constant condition, dropping the jumps. Blocks with `let`s keep their
+    /// scope so aren't spliced. An `if` with nothing to run evaluates to null, which only matters as the last statement.
+    fn flatten(statements: Vec<Statement>) -> Vec<Statement> {
+        let len = statements.len();
+        let mut flattened = Vec::with_capacity(len);
+        for (i, statement) in statements.into_iter().enumerate() {
+            match statement {
+                Statement::ExpressionStatement { expression: Expression::If { condition, consequence, alternative, .. }, .. }
+                if matches!(condition.as_ref(), Expression::Boolean { .. })
+                    && !taken(&condition, &consequence, &alternative).is_some_and(binds_names)
+                    && (taken(&condition, &consequence, &alternative).is_some() || i + 1 < len) => {
+                    if let Some(Statement::Block { statements, .. }) = taken(&condition, &consequence, &alternative) {
+                        flattened.extend(statements.clone());
+                    }
+                },
+                statement => flattened.push(statement),
+            }
+        }
+        flattened
+    }
+}
+
+/// Whether `block` has `let`s of its own, which splicing it in would leak.
+fn binds_names(block: &Statement) -> bool {
+    matches!(block, Statement::Block { statements, .. } if statements.iter().any(|statement| matches!(statement, Statement::Let { .. })))
+}
+
+/// The non-empty block an `if` on the literal `condition` runs.
+fn taken<'a>(condition: &Expression, consequence: &'a Statement, alternative: &'a Option<Box<Statement>>) -> Option<&'a Statement> {
+    let branch = match condition {
+        Expression::Boolean { value: true, .. } => Some(consequence),
+        Expression::Boolean { value: false, .. } => alternative.as_deref(),
+        _ => None,
+    };
+    branch.filter(|branch| matches!(branch, Statement::Block { statements, .. } if !statements.is_empty()))
+}
+
+impl VisitorMut for Optimizer {
+    type Error = Infallible;
+
+    fn visit_program_mut(&mut self, program: &mut Program) -> Result<(), Infallible> {
+        for statement in &mut program.statements {
+            self.visit_statement_mut(statement)?;
+        }
+        program.statements = Self::flatten(mem::take(&mut program.statements));
+        Ok(())
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) -> Result<(), Infallible> {
+        match statement {
+            // The name being bound mustn't be replaced by its value
+            Statement::Let { name, value, .. } => {
+                self.visit_expression_mut(value)?;
+                if let (Expression::Identifier { value: name, .. }, true) = (name, self.propagate && is_literal(value)) {
+                    if self.bindings.get(name) == Some(&1) {
+                        if let Some((constants, _)) = self.constants.last_mut() {
+                            constants.insert(name.clone(), value.clone());
+                        }
+                    }
+                }
+                Ok(())
+            },
+            Statement::Block { statements, .. } => {
+                let in_fn = self.constants.last().is_some_and(|(_, in_fn)| *in_fn);
+                self.scoped(in_fn, |optimizer| statements.iter_mut().try_for_each(|statement| optimizer.visit_statement_mut(statement)))?;
+                *statements = Self::flatten(mem::take(statements));
+                Ok(())
+            },
+            _ => walk_statement_mut(self, statement),
+        }
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) -> Result<(), Infallible> {
+        match expression {
+            Expression::Identifier { value, token, .. } => {
+                if let Some(constant) = self.constant(value) {
+                    *expression = with_span(constant.clone(), token.span);
+                }
+                return Ok(());
+            },
+            Expression::Function { body, .. } => return self.scoped(true, |optimizer| optimizer.visit_statement_mut(Rc::make_mut(body))),
+            _ => walk_expression_mut(self, expression)?,
+        }
+
+        let span = expression.span();
+        let folded = match expression {
+            Expression::Prefix { operator, right, .. } => match (operator.as_str(), right.as_ref()) {
+                ("-", Expression::Integer { value, .. }) => value.checked_neg().map(Expression::construct_integer_expression),
+                ("!", Expression::Boolean { value, .. }) => Some(Expression::construct_boolean_expression(!value)),
+                _ => None,
+            },
+            Expression::Infix { left, operator, right, .. } if operator == "??" && is_literal(left) => match left.as_ref() {
+                Expression::Null { .. } => Some(right.as_ref().clone()),
+                left => Some(left.clone()),
+            },
+            Expression::Infix { left, operator, right, .. } => match (left.as_ref(), right.as_ref()) {
+                (Expression::Integer { value: left, .. }, Expression::Integer { value: right, .. }) => match operator.as_str() {
+                    "+" => left.checked_add(*right).map(Expression::construct_integer_expression),
+                    "-" => left.checked_sub(*right).map(Expression::construct_integer_expression),
+                    "*" => left.checked_mul(*right).map(Expression::construct_integer_expression),
+                    "/" => left.checked_div(*right).map(Expression::construct_integer_expression),
+                    "**" => u32::try_from(*right).ok().and_then(|right| left.checked_pow(right)).map(Expression::construct_integer_expression),
+                    "&" => Some(Expression::construct_integer_expression(left & right)),
+                    "|" => Some(Expression::construct_integer_expression(left | right)),
+                    "^" => Some(Expression::construct_integer_expression(left ^ right)),
+                    "<<" => shift_amount(*right).map(|amount| Expression::construct_integer_expression(left << amount)),
+                    ">>" => shift_amount(*right).map(|amount| Expression::construct_integer_expression(left >> amount)),
+                    "<" => Some(Expression::construct_boolean_expression(left < right)),
+                    ">" => Some(Expression::construct_boolean_expression(left > right)),
+                    "==" => Some(Expression::construct_boolean_expression(left == right)),
+                    "!=" => Some(Expression::construct_boolean_expression(left != right)),
+                    _ => None,
+                },
+                (Expression::Boolean { value: left, .. }, Expression::Boolean { value: right, .. }) => match operator.as_str() {
+                    "==" => Some(Expression::construct_boolean_expression(left == right)),
+                    "!=" => Some(Expression::construct_boolean_expression(left != right)),
+                    "&&" => Some(Expression::construct_boolean_expression(*left && *right)),
+                    "||" => Some(Expression::construct_boolean_expression(*left || *right)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            // A branch of a single expression can stand in for the `if` wherever it is
+            Expression::If { condition, consequence, alternative, .. } => match taken(condition, consequence, alternative) {
+                Some(Statement::Block { statements, .. }) => match statements.as_slice() {
+                    [Statement::ExpressionStatement { expression, .. }] => Some(expression.clone()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            *expression = with_span(folded, span);
+        }
+        Ok(())
+    }
+}
+
+/// Shifts out of range fail at runtime, so aren't folded.
+fn shift_amount(amount: isize) -> Option<u32> {
+    u32::try_from(amount).ok().filter(|amount| *amount < isize::BITS)
+}
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(expression, Expression::Integer { .. } | Expression::Boolean { .. } | Expression::Null { .. } | Expression::String { .. })
+}
+
+/// Keeps errors pointing at the source the literal replaced.
+fn with_span(mut literal: Expression, span: Option<Span>) -> Expression {
+    if let Expression::Integer { token, .. } | Expression::Boolean { token, .. } | Expression::Null { token } | Expression::String { token, .. } = &mut literal {
+        token.span = span;
+    }
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::Lexer, Parser};
+
+    use super::*;
+
+    fn optimized(src: &str) -> String {
+        let mut program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+        optimize(&mut program);
+        let printed = program.statements.iter().map(Statement::dbg).collect::<Vec<String>>().join(" ");
+        printed.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    #[test]
+    fn test_optimize() {
+        assert_eq!(optimized("if (true) { a } else { b }"), "a");
+        assert_eq!(optimized("let debug = false; let x = if (debug) { 1 } else { 2 * 3 + 1 }; x"), "let debug = false let x = 7 7");
+        // A block with lets keeps its scope, and its literals aren't propagated past it
+        assert_eq!(optimized("if (1 < 2) { let y = 1; y } else { 0 }; z"), "if true { let y = 1 1 } else { 0 } z");
+        assert_eq!(optimized("if (x) { let y = 1; }; y"), "if x { let y = 1 } y");
+        // Nothing runs but the last `if` still evaluates to null
+        assert_eq!(optimized("if (false) { 1 }; if (false) { 2 }"), "if false { 2 }");
+
+        // Rebound names, params and globals used in fns keep being looked up
+        assert_eq!(optimized("let x = 1; let x = 2; x"), "let x = 1 let x = 2 x");
+        assert_eq!(optimized("let n = 1; let f = fn(n) { n }; f(n)"), "let n = 1 let f = fn(n) { n } f(n)");
+        assert_eq!(optimized("let k = 2; let f = fn() { let m = 3; fn() { k * m } }; k"), "let k = 2 let f = fn() { let m = 3 fn() { (k * 3) } } 2");
+        assert_eq!(optimized(r#"let v = 1; with({"v": 2}, fn() { v }); v"#), r#"let v = 1 with({ v : 2 }, fn() { v }) v"#);
+
+        // Failing operations are left to fail at runtime
+        assert_eq!(optimized("1 / 0; true > false; 1 + true; 1 << 64"), "(1 / 0) (true > false) (1 + true) (1 << 64)");
+        assert_eq!(optimized("0xF0 | 0b1111 ^ 1 << 2"), "251");
+        assert_eq!(optimized("2 * 3 ** 2; 2 ** -1; 2 ** 64"), "18 (2 ** -1) (2 ** 64)");
+        assert_eq!(optimized("let d = null; [d ?? x, 1 ?? x, y ?? 2]"), "let d = null [x,1,(y ?? 2)]");
+        assert_eq!(optimized("[true && false, false || 1 < 2, x && true]"), "[false,true,(x && true)]");
+    }
+}
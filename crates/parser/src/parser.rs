@@ -1,109 +1,296 @@
+use std::rc::Rc;
+
 use ast::{Expression, Statement};
 
-use crate::lexer::{Lexer, token::{Token, TokenType}};
+use crate::{analysis, intern::Interner, lexer::{Lexer, token::{Span, Token, TokenType}}};
 
 mod arena_tree;
 pub mod ast;
 
 #[allow(dead_code)]
 #[derive(Debug)]
-pub struct ParseError(String);
+pub enum ParseError {
+    Syntax(String),
+    LimitExceeded(LimitError),
+}
 
 impl ParseError {
     pub fn not_implemented() -> Self {
-        Self("Not implemented".to_string())
+        Self::Syntax("Not implemented".to_string())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LimitError {
+    SourceBytes { limit: usize, actual: usize },
+    Tokens { limit: usize },
+    AstNodes { limit: usize },
+    Depth { limit: usize },
+}
+
+/// Keeps parsing the deepest accepted program within a 2MiB thread stack in debug builds.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Upper bounds on the work done by a single parse, so embedders can reject adversarial input
+/// with a typed error instead of growing memory without bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserLimits {
+    pub max_source_bytes: usize,
+    pub max_tokens: usize,
+    pub max_ast_nodes: usize,
+    /// Deepest expression nesting, parsing (and dropping) the AST recurses once per level.
+    pub max_depth: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_source_bytes: 16 * 1024 * 1024,
+            max_tokens: 4_000_000,
+            max_ast_nodes: 4_000_000,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub statements: Vec<ast::Statement>
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
-enum Precedence {
+/// How tightly an operator binds, loosest first. [`ParserExtension`]s pick one of these for the operators they add.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
     Lowest = 0,
-    EqualTo = 1, // ==
-    GTLT = 2, // >, <
-    Sum = 3, // +
-    Mult = 4, // *,
-    Prefix = 5, // -x, !x
-    Call = 6, // x()
+    Pair = 1, // k : v, a[x:y]
+    Coalesce = 2, // x ?? y
+    Or = 3, // ||
+    And = 4, // &&
+    EqualTo = 5, // ==
+    GTLT = 6, // >, <
+    Pipe = 7, // x |> f
+    Range = 8, // a..b
+    BitOr = 9, // |
+    BitXor = 10, // ^
+    BitAnd = 11, // &
+    Shift = 12, // <<, >>
+    Sum = 13, // +
+    Mult = 14, // *,
+    Power = 15, // **
+    Prefix = 16, // -x, !x
+    Call = 17, // x()
 }
 
 impl Precedence {
-    fn get_precedence(token_type: TokenType) -> Self {
+    pub fn get_precedence(token_type: TokenType) -> Self {
         match token_type {
             TokenType::Eq | TokenType::NEq => Precedence::EqualTo,
             TokenType::LT | TokenType::GT => Precedence::GTLT,
+            TokenType::Bar => Precedence::BitOr,
+            TokenType::Caret => Precedence::BitXor,
+            TokenType::Ampersand => Precedence::BitAnd,
+            TokenType::ShiftL | TokenType::ShiftR => Precedence::Shift,
             TokenType::Plus | TokenType::Dash => Precedence::Sum,
             TokenType::FSlash | TokenType::Star => Precedence::Mult,
-            TokenType::LParen | TokenType::LBracket | TokenType::Colon => Precedence::Call,
+            TokenType::StarStar => Precedence::Power,
+            TokenType::LParen | TokenType::LBracket => Precedence::Call,
+            TokenType::Colon => Precedence::Pair,
+            TokenType::Pipe => Precedence::Pipe,
+            TokenType::DotDot => Precedence::Range,
+            TokenType::Coalesce => Precedence::Coalesce,
+            TokenType::Or => Precedence::Or,
+            TokenType::And => Precedence::And,
             _ => Precedence::Lowest,
         }
     }
 }
 
-pub struct Parser {
-    lexer: Lexer,
+/// Adds syntax to the parser without forking it: expressions starting with tokens the grammar doesn't know, or infix
+/// operators. The parser asks its extensions first, in the order they were registered, so an extension can take over
+/// a token the grammar already parses as well.
+pub trait ParserExtension {
+    /// Whether [`ParserExtension::parse_prefix`] parses the expressions starting with `token`.
+    fn is_prefix(&self, token: &Token) -> bool {
+        let _ = token;
+        false
+    }
+
+    /// Parses the expression starting at the current token, leaving the parser on its last token.
+    fn parse_prefix(&self, parser: &mut Parser<'_>) -> Result<Expression, ParseError> {
+        let _ = parser;
+        Err(ParseError::not_implemented())
+    }
+
+    /// How tightly `token` binds as an infix operator, `None` when [`ParserExtension::parse_infix`] doesn't parse it.
+    fn infix_precedence(&self, token: &Token) -> Option<Precedence> {
+        let _ = token;
+        None
+    }
+
+    /// Parses the infix expression whose operator is the current token, `left` being its left operand.
+    fn parse_infix(&self, parser: &mut Parser<'_>, left: Expression) -> Result<Expression, ParseError> {
+        let _ = (parser, left);
+        Err(ParseError::not_implemented())
+    }
+}
+
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
     cur_token: Token,
     peek_token: Token,
+    limits: ParserLimits,
+    num_tokens: usize,
+    num_nodes: usize,
+    depth: usize,
+    extensions: Vec<Rc<dyn ParserExtension>>,
+    illegal: Option<Token>, // the first char the lexer couldn't make a token of
+    interner: Interner,
 }
 
 #[allow(dead_code)]
-impl Parser {
-    pub fn new(mut lexer: Lexer) -> Self {
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Self::with_limits(lexer, ParserLimits::default())
+    }
+
+    pub fn with_limits(mut lexer: Lexer<'a>, limits: ParserLimits) -> Self {
+        let cur_token = lexer.next_token();
+        let peek_token = lexer.next_token();
+        let illegal = [&cur_token, &peek_token].into_iter().find(|token| token.typ == TokenType::Illegal).cloned();
         Self {
-            cur_token: lexer.next_token(),
-            peek_token: lexer.next_token(),
+            cur_token,
+            peek_token,
             lexer,
+            limits,
+            num_tokens: 2,
+            num_nodes: 0,
+            depth: 0,
+            extensions: Vec::new(),
+            illegal,
+            interner: Interner::new(),
         }
     }
 
-    fn next_token(&mut self) {
+    /// Shares the names of the identifiers parsed with those of earlier parses with `interner`.
+    pub fn set_interner(&mut self, interner: Interner) {
+        self.interner = interner;
+    }
+
+    /// Parses the syntax `extension` adds as well, see [`ParserExtension`].
+    pub fn register_extension(&mut self, extension: impl ParserExtension + 'static) {
+        self.extensions.push(Rc::new(extension));
+    }
+
+    /// The identifier the current token is.
+    fn identifier(&self) -> ast::Expression {
+        let token = self.cur_token.clone();
+        ast::Expression::Identifier { value: token.literal.to_string(), symbol: self.interner.intern(&token.literal), local: None, token }
+    }
+
+    pub fn cur_token(&self) -> &Token {
+        &self.cur_token
+    }
+
+    pub fn peek_token(&self) -> &Token {
+        &self.peek_token
+    }
+
+    /// Where the parser is in the source, after a failed `parse_program` that's the token the error is about.
+    pub fn span(&self) -> Option<Span> {
+        self.illegal.as_ref().map_or(self.cur_token.span, |token| token.span)
+    }
+
+    pub fn next_token(&mut self) {
         self.cur_token = std::mem::replace(&mut self.peek_token, self.lexer.next_token());
+        self.num_tokens += 1;
+        if self.peek_token.typ == TokenType::Illegal && self.illegal.is_none() {
+            self.illegal = Some(self.peek_token.clone());
+        }
+    }
+
+    fn count_node(&mut self) -> Result<(), ParseError> {
+        self.num_nodes += 1;
+        if self.num_nodes > self.limits.max_ast_nodes {
+            return Err(ParseError::LimitExceeded(LimitError::AstNodes { limit: self.limits.max_ast_nodes }));
+        }
+        if self.num_tokens > self.limits.max_tokens {
+            return Err(ParseError::LimitExceeded(LimitError::Tokens { limit: self.limits.max_tokens }));
+        }
+        // Readers have no length up front, so their size is checked as they're consumed
+        if self.lexer.bytes_read() > self.limits.max_source_bytes {
+            return Err(ParseError::LimitExceeded(LimitError::SourceBytes { limit: self.limits.max_source_bytes, actual: self.lexer.bytes_read() }));
+        }
+        Ok(())
     }
 
     pub fn parse_program(&mut self) -> Result<Program, ParseError> {
+        if let Some(src_len) = self.lexer.src_len().filter(|len| *len > self.limits.max_source_bytes) {
+            return Err(ParseError::LimitExceeded(LimitError::SourceBytes { limit: self.limits.max_source_bytes, actual: src_len }));
+        }
+
         let mut statements: Vec<ast::Statement> = Vec::new();
+        let mut result = Ok(());
         
         while self.cur_token.typ != TokenType::Eof {
-            let statement = self.parse_statement()?;
-            // println!("{statement:#?}                   ## parse_program");
-            statements.push(statement);
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                },
+            }
         }
 
-        Ok(Program {
-            statements
-        })
+        // A failed read ends the input early, that's the real error rather than whatever the parser tripped on
+        if let Some(err) = self.lexer.take_error() {
+            return Err(ParseError::Syntax(format!("Unable to read source: {err}")));
+        }
+        // Whatever the parser tripped on after an illegal char, the char is what's wrong
+        if let (Err(ParseError::Syntax(_)), Some(token)) = (&result, &self.illegal) {
+            return Err(illegal_char(token));
+        }
+        let mut program = result.map(|()| Program { statements })?;
+        analysis::resolve_locals(&mut program);
+        Ok(program)
     }
 
     fn parse_statement(&mut self) -> Result<ast::Statement, ParseError>  {
+        self.count_node()?;
         match self.cur_token.typ {
-            TokenType::Let => self.parse_let_statement(),
+            TokenType::Let | TokenType::Const => self.parse_let_statement(),
             TokenType::Return => self.parse_return_statement(),
+            TokenType::Import => self.parse_import_statement(),
+            TokenType::Function if self.peek_token.typ == TokenType::Identifier => self.parse_fn_declaration(),
             _ => self.parse_expression_statement(),
         }
     }
 
+    /// `let name = value`, or `const name = value`: a `let` with the `const` token, whose name can't be bound again in
+    /// the same scope.
     fn parse_let_statement(&mut self) -> Result<ast::Statement, ParseError> {
         let let_token = self.cur_token.clone();
 
-        if self.peek_token.typ != TokenType::Identifier {
-            return Err(ParseError(format!("Invlaid `let` statement, expected Identifier, got: {:?}", self.peek_token.typ)));
-        }
-
-        self.next_token();
-
-        let name = ast::Expression::Identifier {
-            value: self.cur_token.literal.to_string(),
-            token: self.cur_token.clone(),
+        let name = match self.peek_token.typ {
+            TokenType::Identifier => {
+                self.next_token();
+                self.identifier()
+            },
+            TokenType::LParen => {
+                self.next_token();
+                let pattern = self.parse_grouped_expression()?;
+                match &pattern {
+                    ast::Expression::Tuple { elements, .. } if elements.iter().all(|element| matches!(element, ast::Expression::Identifier { .. })) => pattern,
+                    _ => return Err(ParseError::Syntax(format!("Invlaid `let` statement, expected a tuple of Identifiers, got: {}", pattern.dbg()))),
+                }
+            },
+            _ => return Err(ParseError::Syntax(format!("Invlaid `let` statement, expected Identifier, got: {:?}", self.peek_token.typ))),
         };
 
         self.next_token();
 
         if self.cur_token.typ != TokenType::Assign {
-            return Err(ParseError(format!("Invlaid `let` statement, expected Assign, got: {:?}", self.peek_token.typ)));
+            return Err(ParseError::Syntax(format!("Invlaid `let` statement, expected Assign, got: {:?}", self.peek_token.typ)));
         }
 
         self.next_token();
@@ -120,6 +307,27 @@ impl Parser {
         )
     }
 
+    /// `fn name(params) { body }`, parsed as `let name = fn(params) { body }` with the `fn` token in place of the
+    /// `let`, which is what marks it as a declaration to hoist.
+    fn parse_fn_declaration(&mut self) -> Result<ast::Statement, ParseError> {
+        let fn_token = self.cur_token.clone();
+        self.next_token();
+        let name = self.identifier();
+
+        self.expect_next(TokenType::LParen)?;
+        let params = self.parse_fn_params()?;
+        self.expect_next(TokenType::LBrace)?;
+        let body = self.parse_block_statement()?;
+
+        self.end_line();
+
+        Ok(ast::Statement::Let {
+            token: fn_token.clone(),
+            name,
+            value: ast::Expression::Function { token: fn_token, locals: analysis::fn_locals(&params, &body, &self.interner), params, body: Rc::new(body) },
+        })
+    }
+
     fn parse_return_statement(&mut self) -> Result<ast::Statement, ParseError> {
         let return_token = self.cur_token.clone();
         self.next_token();
@@ -134,6 +342,20 @@ impl Parser {
         )
     }
 
+    fn parse_import_statement(&mut self) -> Result<ast::Statement, ParseError> {
+        let import_token = self.cur_token.clone();
+
+        self.expect_next(TokenType::String)?;
+        let path = self.cur_token.literal.to_string();
+
+        self.end_line();
+
+        Ok(ast::Statement::Import {
+            token: import_token,
+            path,
+        })
+    }
+
     fn parse_expression_statement(&mut self) -> Result<ast::Statement, ParseError> {
         let expression_token = self.cur_token.clone();
         let expression = self.parse_expression(Precedence::Lowest)?;
@@ -142,27 +364,55 @@ impl Parser {
 
         Ok(ast::Statement::ExpressionStatement {
             token: expression_token,
-            expression: expression,
+            expression,
         })
     }
 
-    fn parse_expression(&mut self, precedence: Precedence) -> Result<ast::Expression, ParseError> {
+    /// Parses the expression starting at the current token, up to an operator binding no tighter than `precedence`.
+    pub fn parse_expression(&mut self, precedence: Precedence) -> Result<ast::Expression, ParseError> {
+        let depth = self.depth;
+        let result = self.parse_expression_inner(precedence);
+        self.depth = depth;
+        result
+    }
+
+    fn parse_expression_inner(&mut self, precedence: Precedence) -> Result<ast::Expression, ParseError> {
+        self.enter()?;
         let mut left = self.parse_prefix()?;
-        while self.peek_token.typ != TokenType::Semicolon && precedence < Precedence::get_precedence(self.peek_token.typ) { // works with if ??
-            match self.parse_infix(left.clone())? {
-                Some(right) => left = right,
-                None => return Ok(left),
-            }
+        while self.peek_token.typ != TokenType::Semicolon && precedence < self.peek_precedence() { // works with if ??
+            // each infix wraps `left` one level deeper, so long chains count towards the depth as well
+            self.enter()?;
+            left = self.parse_infix(left)?;
         }
 
         Ok(left)
     }
 
+    fn peek_precedence(&self) -> Precedence {
+        self.extensions
+            .iter()
+            .find_map(|extension| extension.infix_precedence(&self.peek_token))
+            .unwrap_or_else(|| Precedence::get_precedence(self.peek_token.typ))
+    }
+
+    fn enter(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(ParseError::LimitExceeded(LimitError::Depth { limit: self.limits.max_depth }));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
     fn parse_prefix(&mut self) -> Result<ast::Expression, ParseError> {
+         self.count_node()?;
+         if let Some(extension) = self.extensions.iter().find(|extension| extension.is_prefix(&self.cur_token)).cloned() {
+             return extension.parse_prefix(self);
+         }
          match self.cur_token.typ {
             TokenType::Identifier => self.parse_identifier_expression(),
             TokenType::Int => self.parse_integer_expression(),
             TokenType::True | TokenType::False => self.parse_boolean_expression(),
+            TokenType::Null => Ok(ast::Expression::Null { token: self.cur_token.clone() }),
             TokenType::String => self.parse_string_expression(),
             TokenType::Dash | TokenType::Exclam => self.parse_prefix_expression(),
             TokenType::LParen => self.parse_grouped_expression(),
@@ -170,46 +420,60 @@ impl Parser {
             TokenType::LBrace => self.parse_hash_expression(),
             TokenType::If => self.parse_if_expression(),
             TokenType::Function => self.parse_fn_expression(),
-            _ => Err(ParseError(format!("Unable to parse token in prefix position: {:?}", self.cur_token)))
+            TokenType::Illegal => Err(illegal_char(&self.cur_token)),
+            // Reached as the statement after the name being assigned to
+            TokenType::PlusAssign | TokenType::DashAssign | TokenType::StarAssign | TokenType::FSlashAssign => Err(ParseError::Syntax(format!(
+                "`{}` would reassign a binding and bindings can't be reassigned, shadow it with a new `let` instead",
+                self.cur_token.literal,
+            ))),
+            _ => Err(ParseError::Syntax(format!("Unable to parse token in prefix position: {:?}", self.cur_token)))
         }
     }
 
-    fn parse_infix(&mut self, left: ast::Expression) -> Result<Option<ast::Expression>, ParseError> {
+    fn parse_infix(&mut self, left: ast::Expression) -> Result<ast::Expression, ParseError> {
+        self.count_node()?;
+        if let Some(extension) = self.extensions.iter().find(|extension| extension.infix_precedence(&self.peek_token).is_some()).cloned() {
+            self.next_token();
+            return extension.parse_infix(self, left);
+        }
         match self.peek_token.typ {
-            TokenType::Eq | TokenType::NEq | TokenType::LT | TokenType::GT | TokenType::Plus | TokenType::Dash | TokenType::FSlash | TokenType::Star => {
+            TokenType::Eq | TokenType::NEq | TokenType::LT | TokenType::GT | TokenType::Plus | TokenType::Dash | TokenType::FSlash | TokenType::Star | TokenType::StarStar
+            | TokenType::Ampersand | TokenType::Bar | TokenType::Caret | TokenType::ShiftL | TokenType::ShiftR | TokenType::DotDot
+            | TokenType::Coalesce | TokenType::And | TokenType::Or => {
                 self.next_token();
-                Ok(Some(self.parse_infix_expression(left)?))
+                self.parse_infix_expression(left)
             },
             TokenType::LParen => {
                 self.next_token();
-                Ok(Some(self.parse_call_expression(left)?))
+                self.parse_call_expression(left)
+            },
+            TokenType::Pipe => {
+                self.next_token();
+                self.parse_pipe_expression(left)
             },
             TokenType::LBracket => {
                 self.next_token();
-                Ok(Some(self.parse_array_index_expression(left)?))
+                self.parse_array_index_expression(left)
             },
             TokenType::Colon => {
                 self.next_token();
                 self.next_token();
-                Ok(Some(ast::Expression::KVPair { key: Box::new(left), value: Box::new(self.parse_expression(Precedence::Lowest)?) }))
+                Ok(ast::Expression::KVPair { key: Box::new(left), value: Box::new(self.parse_expression(Precedence::Lowest)?) })
             }
-            _ => Ok(None),
+            _ => Err(ParseError::Syntax(format!("Unable to parse token in infix position: {:?}", self.peek_token))),
         }
     }
 
     fn parse_identifier_expression(&mut self) -> Result<ast::Expression, ParseError> {
-        Ok(ast::Expression::Identifier { 
-            token: self.cur_token.clone(), 
-            value: self.cur_token.literal.to_string(),
-        })
+        Ok(self.identifier())
     }
 
     fn parse_integer_expression(&mut self) -> Result<ast::Expression, ParseError> {
         Ok(ast::Expression::Integer { 
             token: self.cur_token.clone(), 
-            value: match self.cur_token.literal.parse::<isize>() {
+            value: match parse_int(&self.cur_token.literal) {
                 Ok(val) => val,
-                _ => return Err(ParseError(format!("Unable to convert {} to int!", self.cur_token.literal)))
+                _ => return Err(ParseError::Syntax(format!("Unable to convert {} to int!", self.cur_token.literal)))
             }
         })
     }
@@ -220,7 +484,7 @@ impl Parser {
             value: match self.cur_token.literal.as_str() {
                 "true" => true,
                 "false" => false,
-                _ => return Err(ParseError(format!("Unable to convert {} to bool!", self.cur_token.literal)))
+                _ => return Err(ParseError::Syntax(format!("Unable to convert {} to bool!", self.cur_token.literal)))
             }
         })
     }
@@ -245,13 +509,25 @@ impl Parser {
     }
 
     fn parse_grouped_expression(&mut self) -> Result<ast::Expression, ParseError> {
+        let l_paren_token = self.cur_token.clone();
         self.next_token();
         let expression = self.parse_expression(Precedence::Lowest)?;
 
+        if self.peek_token.typ == TokenType::Comma {
+            let mut elements = vec![expression];
+            while self.peek_token.typ == TokenType::Comma {
+                self.next_token();
+                self.next_token();
+                elements.push(self.parse_expression(Precedence::Lowest)?);
+            }
+            self.expect_next(TokenType::RParen)?;
+            return Ok(ast::Expression::Tuple { token: l_paren_token, elements });
+        }
+
         self.next_token();
         
         if self.cur_token.typ != TokenType::RParen {
-            return Err(ParseError(format!("Expected ')', got {:?}", self.cur_token)));
+            return Err(ParseError::Syntax(format!("Expected ')', got {:?}", self.cur_token)));
         }
 
         Ok(expression)
@@ -283,6 +559,52 @@ impl Parser {
         Ok(vals)
     }
 
+    /// The params of a fn up to its `)`, where `name = value` gives a param a default value. Params with defaults
+    /// come last, so calls can leave them out, followed only by a `...rest` param taking any arguements past them.
+    fn parse_fn_params(&mut self) -> Result<Vec<ast::Expression>, ParseError> {
+        let mut params = Vec::new();
+        if self.peek_token.typ == TokenType::RParen {
+            self.next_token();
+            return Ok(params);
+        }
+
+        loop {
+            self.next_token();
+            if self.cur_token.typ == TokenType::Ellipsis {
+                let token = self.cur_token.clone();
+                self.expect_next(TokenType::Identifier)?;
+                let name = self.identifier();
+                if self.peek_token.typ != TokenType::RParen {
+                    return Err(ParseError::Syntax(format!("Rest param ...{} must be the last param", name.dbg())));
+                }
+                params.push(ast::Expression::Prefix { token, operator: "...".to_string(), right: Box::new(name) });
+                break;
+            }
+            let param = self.parse_expression(Precedence::Lowest)?;
+            let param = match (self.peek_token.typ, param) {
+                (TokenType::Assign, ast::Expression::Identifier { token, value, .. }) => {
+                    self.next_token();
+                    self.next_token();
+                    ast::Expression::NamedArg { token, name: value, value: Box::new(self.parse_expression(Precedence::Lowest)?) }
+                },
+                (TokenType::Assign, param) => return Err(ParseError::Syntax(format!("Only Identifier params can have a default value, got: {}", param.dbg()))),
+                (_, param) if params.iter().any(|param| param.param_default().is_some()) => {
+                    return Err(ParseError::Syntax(format!("Param {} without a default value after params with one", param.dbg())));
+                },
+                (_, param) => param,
+            };
+            params.push(param);
+
+            if self.peek_token.typ != TokenType::Comma {
+                break;
+            }
+            self.next_token();
+        }
+
+        self.expect_next(TokenType::RParen)?;
+        Ok(params)
+    }
+
     fn parse_array_expression(&mut self) -> Result<ast::Expression, ParseError> {
         Ok(ast::Expression::Array { token: self.cur_token.clone(), elements: self.parse_comma_separated(TokenType::RBracket)? })
     }
@@ -323,14 +645,15 @@ impl Parser {
         let fn_token = self.cur_token.clone();
 
         self.expect_next(TokenType::LParen)?;
-        let params = self.parse_comma_separated(TokenType::RParen)?;
+        let params = self.parse_fn_params()?;
         self.expect_next(TokenType::LBrace)?;
         let body = self.parse_block_statement()?;
 
         Ok(ast::Expression::Function { 
             token: fn_token, 
+            locals: analysis::fn_locals(&params, &body, &self.interner),
             params, 
-            body: Box::new(body)
+            body: Rc::new(body),
         })
     }
 
@@ -342,7 +665,7 @@ impl Parser {
 
         while self.cur_token.typ != TokenType::RBrace {
             if let TokenType::Eof = self.cur_token.typ {
-                return Err(ParseError("Unexpected EOF while parsing block statement".to_string()))
+                return Err(ParseError::Syntax("Unexpected EOF while parsing block statement".to_string()))
             }
             statements.push(self.parse_statement()?);
         }
@@ -355,7 +678,11 @@ impl Parser {
 
     fn parse_infix_expression(&mut self, left: ast::Expression) -> Result<ast::Expression, ParseError> {
         let operator_token = self.cur_token.clone();
-        let precedence = Precedence::get_precedence(operator_token.typ);
+        let precedence = match operator_token.typ {
+            // Parsing the right side one level looser takes in a `**` after it, so `a ** b ** c` is `a ** (b ** c)`
+            TokenType::StarStar => Precedence::Mult,
+            typ => Precedence::get_precedence(typ),
+        };
 
         self.next_token();
 
@@ -381,16 +708,52 @@ impl Parser {
         })
     }
 
+    /// `x |> f` is sugar for `f(x)`, the right side can be any expression evaluating to a function.
+    fn parse_pipe_expression(&mut self, arguement: ast::Expression) -> Result<ast::Expression, ParseError> {
+        let pipe_token = self.cur_token.clone();
+        self.next_token();
+
+        let function = self.parse_expression(Precedence::Pipe)?;
+
+        Ok(ast::Expression::Call {
+            token: pipe_token,
+            function: Box::new(function),
+            arguements: vec![arguement],
+        })
+    }
+
     fn parse_array_index_expression(&mut self, name: ast::Expression) -> Result<ast::Expression, ParseError> {
         let array_idx_token = self.cur_token.clone();
         self.next_token();
-        let i = self.parse_expression(Precedence::Lowest)?;
+
+        let mut start = None;
+        if self.cur_token.typ != TokenType::Colon {
+            let i = self.parse_expression(Precedence::Pair)?;
+            if self.peek_token.typ != TokenType::Colon {
+                self.expect_next(TokenType::RBracket)?;
+                return Ok(ast::Expression::Index { 
+                    token: array_idx_token, 
+                    name: Box::new(name), 
+                    i: Box::new(i)
+                })
+            }
+            start = Some(Box::new(i));
+            self.next_token();
+        }
+
+        // cur_token is now the ':'
+        let mut end = None;
+        if self.peek_token.typ != TokenType::RBracket {
+            self.next_token();
+            end = Some(Box::new(self.parse_expression(Precedence::Pair)?));
+        }
         self.expect_next(TokenType::RBracket)?;
 
-        Ok(ast::Expression::Index { 
-            token: array_idx_token, 
-            name: Box::new(name), 
-            i: Box::new(i)
+        Ok(ast::Expression::Slice {
+            token: array_idx_token,
+            name: Box::new(name),
+            start,
+            end,
         })
     }
 
@@ -404,7 +767,20 @@ impl Parser {
         }
 
         loop {
-            let expression = self.parse_expression(Precedence::Lowest)?;
+            let expression = if self.cur_token.typ == TokenType::Identifier && self.peek_token.typ == TokenType::Colon {
+                let name_token = self.cur_token.clone();
+                self.next_token();
+                self.next_token();
+                ast::Expression::NamedArg {
+                    name: name_token.literal.to_string(),
+                    token: name_token,
+                    value: Box::new(self.parse_expression(Precedence::Lowest)?),
+                }
+            } else if matches!(args.last(), Some(ast::Expression::NamedArg { .. })) {
+                return Err(ParseError::Syntax(format!("Positional arguement after named arguements: {:?}", self.cur_token)));
+            } else {
+                self.parse_expression(Precedence::Lowest)?
+            };
             args.push(expression);
             if self.peek_token.typ != TokenType::Comma {
                 break;
@@ -431,7 +807,7 @@ impl Parser {
 
     fn expect_next(&mut self, token_type: TokenType) -> Result<(), ParseError> {
         if self.peek_token.typ != token_type {
-            return Err(ParseError(format!("Expected {:?}, got: {:?}", token_type, self.peek_token)));
+            return Err(ParseError::Syntax(format!("Expected {:?}, got: {:?}", token_type, self.peek_token)));
         }
 
         self.next_token();
@@ -439,6 +815,23 @@ impl Parser {
     }
 }
 
+fn illegal_char(token: &Token) -> ParseError {
+    match token.span {
+        Some(span) => ParseError::Syntax(format!("Illegal character `{}` at {span}", token.literal)),
+        None => ParseError::Syntax(format!("Illegal character `{}`", token.literal)),
+    }
+}
+
+/// Parses an int literal, decimal or prefixed with `0x` (hex) or `0b` (binary).
+fn parse_int(literal: &str) -> Result<isize, std::num::ParseIntError> {
+    match (literal.strip_prefix("0x"), literal.strip_prefix("0b")) {
+        (Some(hex), _) => isize::from_str_radix(hex, 16),
+        (_, Some(binary)) => isize::from_str_radix(binary, 2),
+        _ => literal.parse::<isize>(),
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use ast::Statement;
 
@@ -452,8 +845,8 @@ mod tests {
         let parsed = parser.parse_program().unwrap();
 
         assert_eq!(parsed.statements.len(), expected.len(), "Expected {} statements, got {}", expected.len(), parsed.statements.len());
-        for i in 0..expected.len() {
-            assert_eq!(parsed.statements[i], expected[i]);
+        for (statement, expected) in parsed.statements.iter().zip(&expected) {
+            assert_eq!(statement, expected);
         }
     }
 
@@ -569,7 +962,7 @@ mod tests {
             !(true == true)
         "#.to_string();
 
-    let expected = vec![
+    let expected = [
         "((1 + (2 + 3)) + 4)",
         "((5 + 5) * 2)",
         "(2 / (5 + 5))",
@@ -586,8 +979,8 @@ mod tests {
     }
 
     assert_eq!(parsed.statements.len(), expected.len(), "Expected {} statements, got {}", expected.len(), parsed.statements.len());
-    for i in 0..expected.len() {
-        assert_eq!(parsed.statements[i].dbg(), expected[i]);
+    for (statement, expected) in parsed.statements.iter().zip(expected) {
+        assert_eq!(statement.dbg(), expected);
     }
     }
 
@@ -599,7 +992,7 @@ mod tests {
             add(a + b + c * d / f + g)
         "#.to_string();
 
-        let expected = vec![
+        let expected = [
             "((a + add((b * c))) + d)",
             "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
             "add((((a + b) + ((c * d) / f)) + g))",
@@ -611,11 +1004,436 @@ mod tests {
         let parsed = parser.parse_program().unwrap();
 
         assert_eq!(parsed.statements.len(), expected.len(), "Expected {} statements, got {}", expected.len(), parsed.statements.len());
-        for i in 0..expected.len() {
-            assert_eq!(parsed.statements[i].dbg(), expected[i]);
+        for (statement, expected) in parsed.statements.iter().zip(expected) {
+            assert_eq!(statement.dbg(), expected);
+        }
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let program = r#"
+            a | b ^ c & d;
+            1 << 2 + 3 == x >> 1;
+            0x1F & 0b1010 |> f
+        "#.to_string();
+
+        let expected = [
+            "(a | (b ^ (c & d)))",
+            "((1 << (2 + 3)) == (x >> 1))",
+            "f((31 & 10))",
+        ];
+
+        let parsed = Parser::new(Lexer::new(program)).parse_program().unwrap();
+        assert_eq!(parsed.statements.len(), expected.len());
+        for (statement, expected) in parsed.statements.iter().zip(expected) {
+            assert_eq!(statement.dbg(), expected);
+        }
+
+        for invalid in ["0b102", "0x", "0xG"] {
+            assert!(Parser::new(Lexer::new(invalid.to_string())).parse_program().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_index_and_slice() {
+        let program = r#"
+            a[-1];
+            a[1:3];
+            a[:n - 1];
+            a[i + 1:];
+            a[:];
+            {"a" + "b": 1}
+        "#.to_string();
+
+        let expected = [
+            "a[(-1)]",
+            "a[1:3]",
+            "a[:(n - 1)]",
+            "a[(i + 1):]",
+            "a[:]",
+            "{ (a + b) : 1 }",
+        ];
+
+        let mut parser = Parser::new(Lexer::new(program));
+        let parsed = parser.parse_program().unwrap();
+
+        assert_eq!(parsed.statements.len(), expected.len());
+        for (statement, expected) in parsed.statements.iter().zip(expected) {
+            assert_eq!(statement.dbg(), expected);
+        }
+
+        let mut parser = Parser::new(Lexer::new("a[1:2:3]".to_string()));
+        assert!(parser.parse_program().is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        let program = r#"
+            (1, a + b, "c");
+            (1 + 2) * 3;
+            let (q, r) = divmod(7, 2);
+        "#.to_string();
+
+        let expected = [
+            "(1, (a + b), c)",
+            "((1 + 2) * 3)",
+            "let (q, r) = divmod(7, 2)",
+        ];
+
+        let mut parser = Parser::new(Lexer::new(program));
+        let parsed = parser.parse_program().unwrap();
+
+        assert_eq!(parsed.statements.len(), expected.len());
+        for (statement, expected) in parsed.statements.iter().zip(expected) {
+            assert_eq!(statement.dbg(), expected);
+        }
+
+        for invalid in ["let (a, 1) = x;", "let (a) = x;", "(1, 2"] {
+            let mut parser = Parser::new(Lexer::new(invalid.to_string()));
+            assert!(parser.parse_program().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_named_args() {
+        let mut parser = Parser::new(Lexer::new("draw(shape, x: 1 + 2, y: f(z: 3));".to_string()));
+        let parsed = parser.parse_program().unwrap();
+        assert_eq!(parsed.statements[0].dbg(), "draw(shape, x: (1 + 2), y: f(z: 3))");
+
+        let mut parser = Parser::new(Lexer::new("draw(x: 1, 2);".to_string()));
+        assert!(parser.parse_program().is_err());
+    }
+
+    #[test]
+    fn test_pipe() {
+        for (src, expected) in [
+            ("x |> f |> g;", "g(f(x))"),
+            ("a + 1 |> bind(f, 2) == b;", "(bind(f, 2)((a + 1)) == b)"),
+            ("xs |> len > 2 * 3;", "(len(xs) > (2 * 3))"),
+            ("[1, 2] |> fn(xs) { len(xs) };", "fn(xs) {\n\tlen(xs)\n }([1,2])"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
+        }
+
+        let mut parser = Parser::new(Lexer::new("x |> ;".to_string()));
+        assert!(parser.parse_program().is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        for (src, expected) in [
+            ("0..n + 1;", "(0 .. (n + 1))"),
+            ("1..10 |> len == 9;", "(len((1 .. 10)) == 9)"),
+            ("xs[0]..-1;", "(xs[0] .. (-1))"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn test_null_and_coalesce() {
+        for (src, expected) in [
+            ("null;", "null"),
+            ("a ?? b == null;", "(a ?? (b == null))"),
+            ("h[k] ?? 0 + 1 ?? 2;", "((h[k] ?? (0 + 1)) ?? 2)"),
+            ("let x = y ?? z", "let x = (y ?? z)"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
+        }
+        assert!(Parser::new(Lexer::new("a ? b".to_string())).parse_program().is_err());
+    }
+
+    #[test]
+    fn test_const_statement() {
+        let program = Parser::new(Lexer::new("const limit = 10; let x = limit".to_string())).parse_program().unwrap();
+        assert!(program.statements[0].is_const() && !program.statements[1].is_const());
+        assert_eq!(program.statements[0].dbg(), "const limit = 10");
+        assert_eq!(program.statements[0].to_source(), "const limit = 10;");
+        assert!(Parser::new(Lexer::new("const = 1".to_string())).parse_program().is_err());
+    }
+
+    #[test]
+    fn test_power() {
+        for (src, expected) in [
+            ("2 * 3 ** 2;", "(2 * (3 ** 2))"),
+            ("2 ** 3 ** 2;", "(2 ** (3 ** 2))"),
+            ("a ** b * c;", "((a ** b) * c)"),
+            ("-2 ** 2;", "((-2) ** 2)"),
+            ("a[0] ** f(b);", "(a[0] ** f(b))"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        for (src, expected) in [
+            ("a || b && c;", "(a || (b && c))"),
+            ("a == b && c | d;", "((a == b) && (c | d))"),
+            ("a ?? b || c;", "(a ?? (b || c))"),
+            ("!a && b;", "((!a) && b)"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
         }
     }
 
+    /// `a ** b`, binding tighter than `*` and grouping to the right, like the grammar's own `**`.
+    struct Power;
+
+    impl ParserExtension for Power {
+        fn infix_precedence(&self, token: &Token) -> Option<Precedence> {
+            (token.typ == TokenType::StarStar).then_some(Precedence::Prefix)
+        }
+
+        fn parse_infix(&self, parser: &mut Parser<'_>, left: Expression) -> Result<Expression, ParseError> {
+            let token = parser.cur_token().clone();
+            parser.next_token();
+            // Parsed one level looser than `**` itself, so a `**` on the right takes the operand after it
+            let right = parser.parse_expression(Precedence::Mult)?;
+            Ok(Expression::Infix { operator: token.literal.clone(), token, left: Box::new(left), right: Box::new(right) })
+        }
+    }
+
+    #[test]
+    fn test_extension() {
+        for (src, expected) in [
+            ("2 * 3 ** 2 ** 2;", "(2 * (3 ** (2 ** 2)))"),
+            ("a ** b + c;", "((a ** b) + c)"),
+            ("f(x) ** [1][0];", "(f(x) ** [1][0])"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            parser.register_extension(Power);
+            assert_eq!(parser.parse_program().unwrap().statements[0].dbg(), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn test_fn_declaration() {
+        let program = Parser::new(Lexer::new("fn add(a, b) { a + b }; fn() { 1 }; fn noop() {}".to_string())).parse_program().unwrap();
+        assert_eq!(program.statements.len(), 3);
+        assert!(program.statements[0].is_fn_declaration());
+        assert!(!program.statements[1].is_fn_declaration());
+        assert_eq!(program.statements[0].to_source(), "fn add(a, b) { (a + b); }");
+        assert_eq!(program.statements[2].dbg(), "fn noop() {\n }");
+        assert_eq!(program.statements[0].span().map(|span| span.col), Some(1));
+
+        let program = Parser::new(Lexer::new("let add = fn(a, b) { a + b };".to_string())).parse_program().unwrap();
+        assert!(!program.statements[0].is_fn_declaration());
+        assert!(Parser::new(Lexer::new("fn add { 1 }".to_string())).parse_program().is_err());
+    }
+
+    #[test]
+    fn test_default_params() {
+        let program = Parser::new(Lexer::new("fn(x, y = x + 1, z = \"z\") { x }".to_string())).parse_program().unwrap();
+        let Statement::ExpressionStatement { expression: Expression::Function { params, .. }, .. } = &program.statements[0] else {
+            panic!("expected a fn, got {:?}", program.statements[0]);
+        };
+        assert_eq!(params.iter().map(|param| param.param_name().unwrap()).collect::<Vec<&str>>(), ["x", "y", "z"]);
+        assert_eq!(params[0].param_default(), None);
+        assert_eq!(program.statements[0].to_source(), "fn(x, y = (x + 1), z = \"z\") { x; };");
+
+        for src in ["fn(x = 1, y) { x }", "fn([x] = 1) { x }"] {
+            assert!(Parser::new(Lexer::new(src.to_string())).parse_program().is_err(), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_rest_param() {
+        let program = Parser::new(Lexer::new("fn(first, n = 1, ...rest) { rest }".to_string())).parse_program().unwrap();
+        let Statement::ExpressionStatement { expression: Expression::Function { params, .. }, .. } = &program.statements[0] else {
+            panic!("expected a fn, got {:?}", program.statements[0]);
+        };
+        assert!(params[2].is_rest_param() && !params[0].is_rest_param());
+        assert_eq!(params[2].param_name(), Some("rest"));
+        assert_eq!(program.statements[0].to_source(), "fn(first, n = 1, ...rest) { rest; };");
+        assert_eq!(params.iter().map(Expression::dbg_param).collect::<Vec<String>>(), ["first", "n = 1", "...rest"]);
+
+        for src in ["fn(...rest, x) { x }", "fn(...[x]) { x }", "fn(...rest = 1) { rest }", "...x"] {
+            assert!(Parser::new(Lexer::new(src.to_string())).parse_program().is_err(), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_import_statement() {
+        let program = r#"
+            import "lib/math.mk";
+        "#.to_string();
+
+        let expected = vec![
+            ast::Statement::construct_import_statement("lib/math.mk"),
+        ];
+
+        do_test(program, expected);
+
+        let mut parser = Parser::new(Lexer::new("import math;".to_string()));
+        assert!(parser.parse_program().is_err());
+    }
+
+    #[test]
+    fn test_visitors() {
+        struct IdentCounter(usize);
+        impl ast::Visitor for IdentCounter {
+            type Error = ();
+            fn visit_expression(&mut self, expression: &Expression) -> Result<(), ()> {
+                if let Expression::Identifier { .. } = expression {
+                    self.0 += 1;
+                }
+                ast::walk_expression(self, expression)
+            }
+        }
+
+        struct Renamer;
+        impl ast::VisitorMut for Renamer {
+            type Error = ();
+            fn visit_expression_mut(&mut self, expression: &mut Expression) -> Result<(), ()> {
+                if let Expression::Identifier { value, .. } = expression {
+                    *value = value.to_uppercase();
+                }
+                ast::walk_expression_mut(self, expression)
+            }
+        }
+
+        let mut parser = Parser::new(Lexer::new("let f = fn(x) { if (x > y) { g(x) } }; f(1)".to_string()));
+        let mut program = parser.parse_program().unwrap();
+
+        let mut counter = IdentCounter(0);
+        ast::Visitor::visit_program(&mut counter, &program).unwrap();
+        assert_eq!(counter.0, 7);
+
+        ast::VisitorMut::visit_program_mut(&mut Renamer, &mut program).unwrap();
+        assert_eq!(program.statements[1].dbg(), "F(1)");
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = ParserLimits { max_source_bytes: 8, ..ParserLimits::default() };
+        let mut parser = Parser::with_limits(Lexer::new("let x = 5;".to_string()), limits);
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::SourceBytes { limit: 8, actual: 10 }))));
+        let src = "let x = 5; ".repeat(10);
+        let mut parser = Parser::with_limits(Lexer::from_reader(src.as_bytes()), ParserLimits { max_source_bytes: 32, ..ParserLimits::default() });
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::SourceBytes { limit: 32, .. }))));
+        let mut parser = Parser::with_limits(Lexer::from_reader(src.as_bytes()), ParserLimits { max_source_bytes: 110, ..ParserLimits::default() });
+        assert_eq!(parser.parse_program().unwrap().statements.len(), 10);
+        let mut parser = Parser::new(Lexer::from_reader(&b"let x = \xC3"[..]));
+        assert!(matches!(parser.parse_program(), Err(ParseError::Syntax(msg)) if msg.starts_with("Unable to read source")));
+
+
+        let limits = ParserLimits { max_tokens: 10, ..ParserLimits::default() };
+        let mut parser = Parser::with_limits(Lexer::new("1 + 2 + 3 + 4 + 5 + 6 + 7;".to_string()), limits);
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::Tokens { limit: 10 }))));
+
+        let limits = ParserLimits { max_ast_nodes: 3, ..ParserLimits::default() };
+        let mut parser = Parser::with_limits(Lexer::new("[1, 2, 3];".to_string()), limits);
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::AstNodes { limit: 3 }))));
+
+        let mut parser = Parser::with_limits(Lexer::new("[1, 2, 3];".to_string()), ParserLimits::default());
+        assert!(parser.parse_program().is_ok());
+
+        let limits = ParserLimits { max_depth: 4, ..ParserLimits::default() };
+        let mut parser = Parser::with_limits(Lexer::new("((((1))));".to_string()), limits);
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::Depth { limit: 4 }))));
+        let mut parser = Parser::with_limits(Lexer::new("1 + 2 + 3 + 4 + 5;".to_string()), limits);
+        assert!(matches!(parser.parse_program(), Err(ParseError::LimitExceeded(LimitError::Depth { limit: 4 }))));
+        let mut parser = Parser::with_limits(Lexer::new("(1 + 2) * 3;".to_string()), limits);
+        assert!(parser.parse_program().is_ok());
+    }
+
+    #[test]
+    fn test_illegal_char() {
+        for (src, expected) in [
+            ("let x = 5 @ 3;", "Illegal character `@` at line 1, column 11"),
+            ("let x # = 5;", "Illegal character `#` at line 1, column 7"),
+            ("let f = fn(a) {\n  a $ 1\n};", "Illegal character `$` at line 2, column 5"),
+            ("€", "Illegal character `€` at line 1, column 1"),
+        ] {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            match parser.parse_program() {
+                Err(ParseError::Syntax(msg)) => assert_eq!(msg, expected, "{src}"),
+                other => panic!("{src}: expected a syntax error, got {other:?}"),
+            }
+            assert_eq!(parser.span().map(|span| span.to_string()), expected.split_once(" at ").map(|(_, span)| span.to_string()));
+        }
+        // An error before the illegal char is the one reported
+        assert!(matches!(Parser::new(Lexer::new("let = 1; @".to_string())).parse_program(), Err(ParseError::Syntax(msg)) if msg.starts_with("Invlaid `let`")));
+    }
+
+    #[test]
+    fn test_compound_assignment_rejected() {
+        let nested = [("let x = 1; if (true) { x += 1; }", "+="), ("let n = 0; let bump = fn() { n -= 1; n }", "-=")];
+        for (src, operator) in [("let x = 1; x += 2", "+="), ("x -= 1", "-="), ("x *= 2;", "*="), ("x/=2", "/=")].into_iter().chain(nested) {
+            let mut parser = Parser::new(Lexer::new(src.to_string()));
+            let Err(ParseError::Syntax(msg)) = parser.parse_program() else { panic!("{src} parsed") };
+            assert!(msg.starts_with(&format!("`{operator}` would reassign a binding")), "{msg}");
+            assert_eq!(parser.span().map(|span| span.col), src.find(operator).map(|col| col as u32 + 1));
+        }
+        assert_eq!(Parser::new(Lexer::new("x - -1 + (2 / 1)".to_string())).parse_program().unwrap().statements.len(), 1);
+    }
+
+    #[test]
+    fn test_ast_builders() {
+        let (x, one) = (ast::Expression::construct_identifier_expression("x"), ast::Expression::construct_integer_expression(1));
+        let shifted = ast::Expression::try_infix_expression("<<", x.clone(), one.clone()).unwrap();
+        assert_eq!(shifted.to_source(), "(x << 1)");
+        assert_eq!(ast::Expression::try_prefix_expression("!", shifted).unwrap().to_source(), "(!(x << 1))");
+
+        assert_eq!(ast::Expression::try_prefix_expression("+", one.clone()), Err(ast::AstError("Cannot use + as a prefix operator".to_string())));
+        assert!(ast::Expression::try_infix_expression("|>", x.clone(), one.clone()).is_err());
+
+        let block = Statement::construct_block_statement(vec![]);
+        assert!(ast::Expression::try_if_expression(x.clone(), block.clone(), Some(block.clone())).is_ok());
+        assert!(ast::Expression::try_if_expression(x.clone(), block, Some(Statement::construct_return_statement(one))).is_err());
+    }
+
+    #[test]
+    fn test_to_source() {
+        let print = |src: &str| {
+            let program = Parser::new(Lexer::new(src.to_string())).parse_program().unwrap();
+            program.statements.iter().map(Statement::to_source).collect::<Vec<String>>().join(" ")
+        };
+
+        let src = r#"let f = fn(a, b) { let (x, y) = (a, -b); if (x < y) { return "lt" } else { x |> g(n: 1) } };
+f([1, 2][0:1], {"k": true}[x]); import "lib"; fn() {}"#;
+        let printed = print(src);
+        assert_eq!(printed, r#"let f = fn(a, b) { let (x, y) = (a, (-b)); if ((x < y)) { return "lt"; } else { g(n: 1)(x); }; }; f([1, 2][0:1], {"k": true}[x]); import "lib"; fn() {};"#);
+        assert_eq!(print(&printed), printed);
+    }
+
+    #[test]
+    fn test_malformed_input_does_not_panic() {
+        let nested = |open: &str, close: &str| format!("{}1{}", open.repeat(100_000), close.repeat(100_000));
+        let inputs = [
+            "\"".to_string(), "\"abc".to_string(), "let".to_string(), "let x =".to_string(), "fn(".to_string(),
+            "if (".to_string(), "a[".to_string(), "[1:".to_string(), "{".to_string(), "¥é🕴".to_string(),
+            nested("(", ")"), nested("[", "]"), nested("-", ""), nested("fn() {", "}"), nested("1 + ", ""),
+        ];
+
+        for src in inputs {
+            let _ = Parser::new(Lexer::new(src)).parse_program();
+        }
+    }
+
+    // Every lexeme the lexer knows plus a few it doesn't, so token soups hit the error paths of every parse fn
+    const LEXEMES: &[&str] = &[
+        "let", "fn", "if", "else", "return", "true", "false", "import", "x", "add", "0", "42", "\"s\"", "\"",
+        "=", "==", "!=", "!", "|>", "|", "+", "-", "*", "/", "<", ">", ",", ";", ":", "(", ")", "{", "}", "[", "]", "@", "é",
+    ];
+
+    proptest::proptest! {
+        #[test]
+        fn prop_parse_arbitrary_strings(src in "\\PC{0,64}") {
+            let _ = Parser::new(Lexer::new(src)).parse_program();
+        }
+
+        #[test]
+        fn prop_parse_token_soup(lexemes in proptest::collection::vec(proptest::sample::select(LEXEMES), 0..48)) {
+            let _ = Parser::new(Lexer::new(lexemes.join(" "))).parse_program();
+        }
+    }
 }
         // println!("Expression: {:#?}", expression);
 
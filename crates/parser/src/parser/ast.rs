@@ -1,11 +1,23 @@
-use std::fmt::Debug;
-use crate::lexer::token::{Token, TokenType};
+use std::{fmt::{self, Debug}, rc::Rc};
+use crate::{intern::Symbol, lexer::token::{Span, Token, TokenType}};
+
+/// An AST node that can't be built from the parts it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AstError(pub String);
+
+impl fmt::Display for AstError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
     Identifier {
         token: Token,
         value: String,
+        symbol: Symbol, // `value`, shared with the other identifiers of the parse that spell it
+        local: Option<(usize, usize)>, // scopes out and slot of the fn local it names, from `analysis::resolve_locals`
     },
     Integer {
         token: Token,
@@ -15,6 +27,9 @@ pub enum Expression {
         token: Token,
         value: bool,
     },
+    Null {
+        token: Token,
+    },
     String {
         token: Token,
         value: String,
@@ -23,6 +38,10 @@ pub enum Expression {
         token: Token, // '['
         elements: Vec<Self>
     },
+    Tuple {
+        token: Token, // '('
+        elements: Vec<Self>,
+    },
     KVPair {
         key: Box<Expression>,
         value: Box<Expression>,
@@ -35,6 +54,12 @@ pub enum Expression {
         name: Box<Self>,
         i: Box<Self>,
     },
+    Slice {
+        token: Token, // '['
+        name: Box<Self>,
+        start: Option<Box<Self>>,
+        end: Option<Box<Self>>,
+    },
     Prefix {
         token: Token,
         operator: String,
@@ -54,24 +79,76 @@ pub enum Expression {
     },
     Function {
         token: Token, // 'fn'
-        params: Vec<Self>,
-        body: Box<Statement> // Block statement
+        params: Vec<Self>, // Identifiers, then NamedArgs for the params with a default value, then maybe a `...rest` Prefix
+        body: Rc<Statement>, // Block statement, shared with the fns evaluating this creates
+        locals: Rc<[Symbol]>, // what the params and lets of the body bind, from `analysis::fn_locals`
     },
     Call {
         token: Token, // '('
         function: Box<Self>, // Identifier or Function
-        arguements: Vec<Self>,
+        arguements: Vec<Self>, // positional arguements, then NamedArgs
+    },
+    NamedArg {
+        token: Token, // the name Identifier
+        name: String,
+        value: Box<Self>,
     }
 }
 
 impl Expression {
-    pub fn construct_identifier_expression(identifier: &str) -> Self {
-        Expression::Identifier {
-            token: Token::new_identifier(identifier),
-            value: identifier.to_string()
+    /// Where the expression starts, or its operator for infix expressions, when it was parsed from source.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Identifier { token, .. } | Self::Integer { token, .. } | Self::Boolean { token, .. } | Self::Null { token }
+            | Self::String { token, .. } | Self::Array { token, .. } | Self::Tuple { token, .. }
+            | Self::Index { token, .. } | Self::Slice { token, .. } | Self::Prefix { token, .. }
+            | Self::Infix { token, .. } | Self::If { token, .. } | Self::Function { token, .. }
+            | Self::Call { token, .. } | Self::NamedArg { token, .. } => token.span,
+            Self::KVPair { key, .. } => key.span(),
+            Self::Hash { kv_pairs } => kv_pairs.first().and_then(Self::span),
         }
     }
 
+    /// The name a fn param binds, whether or not it has a default value or is the rest param.
+    pub fn param_name(&self) -> Option<&str> {
+        match self {
+            Self::Identifier { value: name, .. } | Self::NamedArg { name, .. } => Some(name),
+            Self::Prefix { operator, right, .. } if operator == "..." => right.param_name(),
+            _ => None,
+        }
+    }
+
+    /// Whether this is the `...rest` param of a fn, bound to an array of the arguements past the other params.
+    pub fn is_rest_param(&self) -> bool {
+        matches!(self, Self::Prefix { operator, .. } if operator == "...")
+    }
+
+    /// The default value of a fn param, `y` of `fn(x, y = 10)` defaults to `10`.
+    pub fn param_default(&self) -> Option<&Self> {
+        match self {
+            Self::NamedArg { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A fn param as `dbg` prints it, `name = value` for one with a default value.
+    pub fn dbg_param(&self) -> String {
+        match self {
+            Self::NamedArg { name, value, .. } => format!("{name} = {}", value.dbg()),
+            Self::Prefix { operator, right, .. } if operator == "..." => format!("...{}", right.dbg()),
+            param => param.dbg(),
+        }
+    }
+
+    pub fn construct_identifier_expression(identifier: &str) -> Self {
+        Self::identifier(Token::new_identifier(identifier))
+    }
+
+    /// The identifier `token` is.
+    pub fn identifier(token: Token) -> Self {
+        Expression::Identifier { value: token.literal.to_string(), symbol: Symbol::from(token.literal.as_str()), local: None, token }
+    }
+
     pub fn construct_integer_expression(value: isize) -> Self {
         Expression::Integer { 
             token: Token::new_int_i(value), 
@@ -86,6 +163,10 @@ impl Expression {
         }
     }
 
+    pub fn construct_null_expression() -> Self {
+        Expression::Null { token: Token::new_null() }
+    }
+
     pub fn construct_string_expression(value: &str) -> Self {
         Expression::String { 
             token: Token::new_string(value), 
@@ -93,55 +174,69 @@ impl Expression {
         }
     }
 
-    pub fn construct_prefix_expression(operator: &str, right: Self) -> Self {
-        Expression::Prefix { 
-            token: match operator {
-                "-" => Token::new_dash(),
-                "!" => Token::new_exclam(),
-                _ => panic!("{}", format!("Cannot use {operator} as a prefix!"))
-            }, 
-            operator: operator.to_string(), 
-            right: Box::new(right)
-        }
+    /// `operator right`, failing unless `operator` is `-` or `!`.
+    pub fn try_prefix_expression(operator: &str, right: Self) -> Result<Self, AstError> {
+        let token = match operator {
+            "-" => Token::new_dash(),
+            "!" => Token::new_exclam(),
+            _ => return Err(AstError(format!("Cannot use {operator} as a prefix operator"))),
+        };
+        Ok(Expression::Prefix { token, operator: operator.to_string(), right: Box::new(right) })
     }
 
-    pub fn construct_infix_expression(operator: &str, left: Self, right: Self) -> Self {
-        Expression::Infix { 
-            token: match operator {
-                "+" => Token::new_plus(),
-                "-" => Token::new_dash(),
-                "*" => Token::new_star(),
-                "/" => Token::new_f_slash(),
-                ">" => Token::new_g_t(),
-                "<" => Token::new_l_t(),
-                "==" => Token::new_eq(),
-                "!=" => Token::new_n_eq(),
-                _ => panic!("{}", format!("Cannot use {operator} as a prefix!"))
-            }, 
-            left: Box::new(left), 
-            operator: operator.to_string(), 
-            right: Box::new(right),
-        }
+    /// `left operator right`, failing unless `operator` is a binary operator.
+    pub fn try_infix_expression(operator: &str, left: Self, right: Self) -> Result<Self, AstError> {
+        let token = match operator {
+            "+" => Token::new_plus(),
+            "-" => Token::new_dash(),
+            "*" => Token::new_star(),
+            "/" => Token::new_f_slash(),
+            ">" => Token::new_g_t(),
+            "<" => Token::new_l_t(),
+            "==" => Token::new_eq(),
+            "!=" => Token::new_n_eq(),
+            "&" => Token::new_ampersand(),
+            "|" => Token::new_bar(),
+            "&&" => Token::new_and(),
+            "||" => Token::new_or(),
+            "^" => Token::new_caret(),
+            "<<" => Token::new_shift_l(),
+            ">>" => Token::new_shift_r(),
+            ".." => Token::new_dot_dot(),
+            "??" => Token::new_coalesce(),
+            _ => return Err(AstError(format!("Cannot use {operator} as an infix operator"))),
+        };
+        Ok(Expression::Infix { token, left: Box::new(left), operator: operator.to_string(), right: Box::new(right) })
     }
 
-    pub fn construct_if_expression(condition: Expression, consequence: Statement, alternative: Option<Statement>) -> Self {
-        if let Statement::Block { .. } = &consequence {
-            if let Some(alt) = &alternative {
-                if let Statement::Block { .. } = &alt {
-                } else {
-                    panic!("Alternative must be a Block statement, got: {:?}", alternative);
-                }
+    /// `if (condition) consequence else alternative`, failing unless both branches are Block statements.
+    pub fn try_if_expression(condition: Expression, consequence: Statement, alternative: Option<Statement>) -> Result<Self, AstError> {
+        for branch in std::iter::once(&consequence).chain(&alternative) {
+            if !matches!(branch, Statement::Block { .. }) {
+                return Err(AstError(format!("Branches of an if must be Block statements, got: {}", branch.dbg())));
             }
-        } else {
-            panic!("Consequence must be a Block statement, got: {:?}", consequence);
         }
-
-        Self::If { 
+        Ok(Self::If {
             token: Token::new_if(),
             condition: Box::new(condition),
             consequence: Box::new(consequence),
-            alternative: alternative.map(|alt| Box::new(alt)) // if let Some(alt) = alternative { Some(Box::new(alt)) } else { None }
-        }
+            alternative: alternative.map(Box::new),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn construct_prefix_expression(operator: &str, right: Self) -> Self {
+        Self::try_prefix_expression(operator, right).unwrap()
+    }
+
+    #[cfg(test)]
+    pub fn construct_infix_expression(operator: &str, left: Self, right: Self) -> Self {
+        Self::try_infix_expression(operator, left, right).unwrap()
+    }
+
+    #[cfg(test)]
+    pub fn construct_if_expression(condition: Expression, consequence: Statement, alternative: Option<Statement>) -> Self {
+        Self::try_if_expression(condition, consequence, alternative).unwrap()
     }
 
     pub fn dbg(&self) -> String {
@@ -149,6 +244,7 @@ impl Expression {
             Self::Identifier { value, .. } => value.to_string(),
             Self::Integer { value, .. } => value.to_string(),
             Self::Boolean { value, .. } => value.to_string(),
+            Self::Null { .. } => "null".to_string(),
             Self::String { value, .. } => value.to_string(),
             Self::Array { elements, .. } => {
                 let elements = elements
@@ -158,6 +254,14 @@ impl Expression {
                     .join(",");
                 format!("[{}]", elements)
             },
+            Self::Tuple { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| element.dbg())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("({})", elements)
+            },
             Self::KVPair { key, value } => format!("{} : {}", key.dbg(), value.dbg()),
             Self::Hash { kv_pairs } => {
                 let elements = kv_pairs
@@ -168,6 +272,11 @@ impl Expression {
                 format!("{{ {elements} }}")
             }
             Self::Index { name, i, .. } => format!("{}[{}]", name.dbg(), i.dbg()),
+            Self::Slice { name, start, end, .. } => {
+                let start = start.as_ref().map(|start| start.dbg()).unwrap_or_default();
+                let end = end.as_ref().map(|end| end.dbg()).unwrap_or_default();
+                format!("{}[{}:{}]", name.dbg(), start, end)
+            },
             Self::Prefix { operator, right, .. } => format!("({}{})", operator, right.dbg()),
             Self::Infix { left, operator, right, .. } => format!("({} {} {})", left.dbg(), operator, right.dbg()),
             Self::If { token, condition, consequence, alternative } => {
@@ -178,10 +287,10 @@ impl Expression {
 
                 out
             },
-            Self::Function { token, params, body } => {
+            Self::Function { token, params, body, .. } => {
                 let params = params
                                         .iter()
-                                        .map(|param| param.dbg())
+                                        .map(|param| param.dbg_param())
                                         .collect::<Vec<String>>()
                                         .join(",");
                 format!("{}({}) {}", token.literal, params, body.dbg())
@@ -191,9 +300,45 @@ impl Expression {
                                             .iter()
                                             .map(|param| param.dbg())
                                             .collect::<Vec<String>>()
-                                            .join(",");
+                                            .join(", ");
                 format!("{}({})", function.dbg(), arguements)
-            }
+            },
+            Self::NamedArg { name, value, .. } => format!("{}: {}", name, value.dbg()),
+        }
+    }
+
+    /// Prints the expression back as Monkey source that parses to the same tree, unlike `dbg` which doesn't quote
+    /// strings or end statements.
+    pub fn to_source(&self) -> String {
+        let list = |elements: &[Self]| elements.iter().map(Self::to_source).collect::<Vec<String>>().join(", ");
+        match self {
+            Self::Identifier { value, .. } => value.to_string(),
+            Self::Integer { value, .. } => value.to_string(),
+            Self::Boolean { value, .. } => value.to_string(),
+            Self::Null { .. } => "null".to_string(),
+            Self::String { value, .. } => format!("\"{value}\""),
+            Self::Array { elements, .. } => format!("[{}]", list(elements)),
+            Self::Tuple { elements, .. } => format!("({})", list(elements)),
+            Self::KVPair { key, value } => format!("{}: {}", key.to_source(), value.to_source()),
+            Self::Hash { kv_pairs } => format!("{{{}}}", list(kv_pairs)),
+            Self::Index { name, i, .. } => format!("{}[{}]", name.to_source(), i.to_source()),
+            Self::Slice { name, start, end, .. } => {
+                let start = start.as_ref().map(|start| start.to_source()).unwrap_or_default();
+                let end = end.as_ref().map(|end| end.to_source()).unwrap_or_default();
+                format!("{}[{}:{}]", name.to_source(), start, end)
+            },
+            Self::Prefix { operator, right, .. } => format!("({}{})", operator, right.to_source()),
+            Self::Infix { left, operator, right, .. } => format!("({} {} {})", left.to_source(), operator, right.to_source()),
+            Self::If { condition, consequence, alternative, .. } => {
+                let mut out = format!("if ({}) {}", condition.to_source(), consequence.to_source());
+                if let Some(alt) = alternative {
+                    out += &format!(" else {}", alt.to_source());
+                }
+                out
+            },
+            Self::Function { params, body, .. } => format!("fn({}) {}", params_source(params), body.to_source()),
+            Self::Call { function, arguements, .. } => format!("{}({})", function.to_source(), list(arguements)),
+            Self::NamedArg { name, value, .. } => format!("{}: {}", name, value.to_source()),
         }
     }
 }
@@ -216,20 +361,40 @@ pub enum Statement {
     Block {
         token: Token, // '{'
         statements: Vec<Statement>
+    },
+    Import {
+        token: Token, // 'import'
+        path: String,
     }
 }
 
 impl Statement {
+    /// Where the statement's first token is, when it was parsed from source.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::ExpressionStatement { token, .. } | Self::Let { token, .. } | Self::Return { token, .. }
+            | Self::Block { token, .. } | Self::Import { token, .. } => token.span,
+        }
+    }
+
+    /// Whether this is a `fn name(params) { body }` declaration, which is a `let` of a fn whose token is the `fn`.
+    /// Declarations are bound before anything else of the block they're in runs.
+    pub fn is_fn_declaration(&self) -> bool {
+        matches!(self, Self::Let { token, value: Expression::Function { .. }, .. } if token.typ == TokenType::Function)
+    }
+
+    /// Whether this is a `const name = value`, which is a `let` whose token is the `const`.
+    pub fn is_const(&self) -> bool {
+        matches!(self, Self::Let { token, .. } if token.typ == TokenType::Const)
+    }
+
     pub fn construct_expression_statement(first_token: Token, expression: Expression) -> Self {
         Self::ExpressionStatement { token: first_token, expression }
     }
 
     pub fn construct_let_statement(identifier: String, value: isize) -> Self {
         Self::Let { 
-            token: Token {
-                typ: TokenType::Let, 
-                literal: "let".to_string()
-            }, 
+            token: Token::new_let(), 
             name: Expression::construct_identifier_expression(&identifier), 
             value: Expression::construct_integer_expression(value)
         }
@@ -237,14 +402,18 @@ impl Statement {
 
     pub fn construct_return_statement(return_value: Expression) -> Self {
         Self::Return { 
-            token: Token {
-                typ: TokenType::Return,
-                literal: "return".to_string(),
-            },
+            token: Token::new_return(),
             return_value
         }
     }
 
+    pub fn construct_import_statement(path: &str) -> Self {
+        Self::Import {
+            token: Token::new_import(),
+            path: path.to_string(),
+        }
+    }
+
     pub fn construct_block_statement(statements: Vec<Self>) -> Self {
         Self::Block { 
             token: Token::new_l_brace(), 
@@ -254,14 +423,258 @@ impl Statement {
 
     pub fn dbg(&self) -> String {
         match self {
+            Self::Let { name, value: Expression::Function { token, params, body, .. }, .. } if self.is_fn_declaration() => {
+                let params = params.iter().map(|param| param.dbg_param()).collect::<Vec<String>>().join(",");
+                format!("{} {}({}) {}", token.literal, name.dbg(), params, body.dbg())
+            },
             Self::Let { token, name, value } => format!("{} {} = {}", token.literal, name.dbg(), value.dbg()),
             Self::Return { token, return_value } => format!("{} {}", token.literal, return_value.dbg()),
             Self::ExpressionStatement { expression, .. } => expression.dbg(),
             Self::Block { statements, .. } => {
                 let mut out = "{\n".to_string();
                 for s in statements { out += &format!("\t{}\n", s.dbg()) }
-                return out + " }"
-            }
+                out + " }"
+            },
+            Self::Import { token, path } => format!("{} \"{}\"", token.literal, path),
         }
     }
+
+    /// Prints the statement back as Monkey source, see [`Expression::to_source`].
+    pub fn to_source(&self) -> String {
+        match self {
+            Self::Let { name, value: Expression::Function { params, body, .. }, .. } if self.is_fn_declaration() => {
+                format!("fn {}({}) {}", name.to_source(), params_source(params), body.to_source())
+            },
+            Self::Let { token, name, value } => format!("{} {} = {};", token.literal, name.to_source(), value.to_source()),
+            Self::Return { return_value, .. } => format!("return {};", return_value.to_source()),
+            Self::ExpressionStatement { expression, .. } => format!("{};", expression.to_source()),
+            Self::Block { statements, .. } if statements.is_empty() => "{}".to_string(),
+            Self::Block { statements, .. } => {
+                format!("{{ {} }}", statements.iter().map(Self::to_source).collect::<Vec<String>>().join(" "))
+            },
+            Self::Import { path, .. } => format!("import \"{path}\";"),
+        }
+    }
+}
+
+fn params_source(params: &[Expression]) -> String {
+    let param = |param: &Expression| match param {
+        Expression::NamedArg { name, value, .. } => format!("{name} = {}", value.to_source()),
+        Expression::Prefix { operator, right, .. } if operator == "..." => format!("...{}", right.to_source()),
+        param => param.to_source(),
+    };
+    params.iter().map(param).collect::<Vec<String>>().join(", ")
+}
+
+/// Read-only AST traversal. Override the `visit_*` hooks you care about and call the matching `walk_*`
+/// function to keep descending into children.
+pub trait Visitor {
+    type Error;
+
+    fn visit_program(&mut self, program: &crate::Program) -> Result<(), Self::Error> {
+        walk_program(self, program)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) -> Result<(), Self::Error> {
+        walk_statement(self, statement)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) -> Result<(), Self::Error> {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &crate::Program) -> Result<(), V::Error> {
+    for statement in &program.statements {
+        visitor.visit_statement(statement)?;
+    }
+    Ok(())
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) -> Result<(), V::Error> {
+    match statement {
+        Statement::ExpressionStatement { expression, .. } => visitor.visit_expression(expression),
+        Statement::Let { name, value, .. } => {
+            visitor.visit_expression(name)?;
+            visitor.visit_expression(value)
+        },
+        Statement::Return { return_value, .. } => visitor.visit_expression(return_value),
+        Statement::Block { statements, .. } => {
+            for statement in statements {
+                visitor.visit_statement(statement)?;
+            }
+            Ok(())
+        },
+        Statement::Import { .. } => Ok(()),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) -> Result<(), V::Error> {
+    match expression {
+        Expression::Identifier { .. } | Expression::Integer { .. } | Expression::Boolean { .. } | Expression::Null { .. }
+        | Expression::String { .. } => Ok(()),
+        Expression::Array { elements, .. } | Expression::Tuple { elements, .. } => {
+            for element in elements {
+                visitor.visit_expression(element)?;
+            }
+            Ok(())
+        },
+        Expression::KVPair { key, value } => {
+            visitor.visit_expression(key)?;
+            visitor.visit_expression(value)
+        },
+        Expression::Hash { kv_pairs } => {
+            for kv_pair in kv_pairs {
+                visitor.visit_expression(kv_pair)?;
+            }
+            Ok(())
+        },
+        Expression::Index { name, i, .. } => {
+            visitor.visit_expression(name)?;
+            visitor.visit_expression(i)
+        },
+        Expression::Slice { name, start, end, .. } => {
+            visitor.visit_expression(name)?;
+            if let Some(start) = start {
+                visitor.visit_expression(start)?;
+            }
+            if let Some(end) = end {
+                visitor.visit_expression(end)?;
+            }
+            Ok(())
+        },
+        Expression::Prefix { right, .. } => visitor.visit_expression(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression(left)?;
+            visitor.visit_expression(right)
+        },
+        Expression::If { condition, consequence, alternative, .. } => {
+            visitor.visit_expression(condition)?;
+            visitor.visit_statement(consequence)?;
+            if let Some(alternative) = alternative {
+                visitor.visit_statement(alternative)?;
+            }
+            Ok(())
+        },
+        Expression::Function { params, body, .. } => {
+            for param in params {
+                visitor.visit_expression(param)?;
+            }
+            visitor.visit_statement(body)
+        },
+        Expression::Call { function, arguements, .. } => {
+            visitor.visit_expression(function)?;
+            for arguement in arguements {
+                visitor.visit_expression(arguement)?;
+            }
+            Ok(())
+        },
+        Expression::NamedArg { value, .. } => visitor.visit_expression(value),
+    }
+}
+
+/// Mutable counterpart of `Visitor`, for passes that rewrite the tree in place.
+pub trait VisitorMut {
+    type Error;
+
+    fn visit_program_mut(&mut self, program: &mut crate::Program) -> Result<(), Self::Error> {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) -> Result<(), Self::Error> {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) -> Result<(), Self::Error> {
+        walk_expression_mut(self, expression)
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut crate::Program) -> Result<(), V::Error> {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement)?;
+    }
+    Ok(())
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) -> Result<(), V::Error> {
+    match statement {
+        Statement::ExpressionStatement { expression, .. } => visitor.visit_expression_mut(expression),
+        Statement::Let { name, value, .. } => {
+            visitor.visit_expression_mut(name)?;
+            visitor.visit_expression_mut(value)
+        },
+        Statement::Return { return_value, .. } => visitor.visit_expression_mut(return_value),
+        Statement::Block { statements, .. } => {
+            for statement in statements {
+                visitor.visit_statement_mut(statement)?;
+            }
+            Ok(())
+        },
+        Statement::Import { .. } => Ok(()),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) -> Result<(), V::Error> {
+    match expression {
+        Expression::Identifier { .. } | Expression::Integer { .. } | Expression::Boolean { .. } | Expression::Null { .. }
+        | Expression::String { .. } => Ok(()),
+        Expression::Array { elements, .. } | Expression::Tuple { elements, .. } => {
+            for element in elements {
+                visitor.visit_expression_mut(element)?;
+            }
+            Ok(())
+        },
+        Expression::KVPair { key, value } => {
+            visitor.visit_expression_mut(key)?;
+            visitor.visit_expression_mut(value)
+        },
+        Expression::Hash { kv_pairs } => {
+            for kv_pair in kv_pairs {
+                visitor.visit_expression_mut(kv_pair)?;
+            }
+            Ok(())
+        },
+        Expression::Index { name, i, .. } => {
+            visitor.visit_expression_mut(name)?;
+            visitor.visit_expression_mut(i)
+        },
+        Expression::Slice { name, start, end, .. } => {
+            visitor.visit_expression_mut(name)?;
+            if let Some(start) = start {
+                visitor.visit_expression_mut(start)?;
+            }
+            if let Some(end) = end {
+                visitor.visit_expression_mut(end)?;
+            }
+            Ok(())
+        },
+        Expression::Prefix { right, .. } => visitor.visit_expression_mut(right),
+        Expression::Infix { left, right, .. } => {
+            visitor.visit_expression_mut(left)?;
+            visitor.visit_expression_mut(right)
+        },
+        Expression::If { condition, consequence, alternative, .. } => {
+            visitor.visit_expression_mut(condition)?;
+            visitor.visit_statement_mut(consequence)?;
+            if let Some(alternative) = alternative {
+                visitor.visit_statement_mut(alternative)?;
+            }
+            Ok(())
+        },
+        Expression::Function { params, body, .. } => {
+            for param in params {
+                visitor.visit_expression_mut(param)?;
+            }
+            visitor.visit_statement_mut(Rc::make_mut(body))
+        },
+        Expression::Call { function, arguements, .. } => {
+            visitor.visit_expression_mut(function)?;
+            for arguement in arguements {
+                visitor.visit_expression_mut(arguement)?;
+            }
+            Ok(())
+        },
+        Expression::NamedArg { value, .. } => visitor.visit_expression_mut(value),
+    }
 }
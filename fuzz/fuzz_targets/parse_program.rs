@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use parser::{lexer::Lexer, Parser};
+
+// parse_program must return Ok or Err for any input, never panic, overflow the stack or hang
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = Parser::new(Lexer::new(src.to_string())).parse_program();
+    }
+});